@@ -0,0 +1,213 @@
+//! Throughput of the shared protocol layers: command encoding, frame
+//! reassembly, response parsing, and batching/coalescing.
+//!
+//! Run with `cargo bench`; compare against a saved baseline with
+//! `cargo bench -- --save-baseline main` and `--baseline main`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use serialtest::framing::{append_crc, Codec, FrameDecoder, StreamFraming};
+use serialtest::mock::MockTransport;
+use serialtest::protocol::{self, Command};
+use serialtest::stream::{AckWindow, CommandStream, SendQueue};
+use serialtest::transport::CoalescingWriter;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Commands per batch, a full table upload
+const BATCH: usize = 256;
+
+const FRAMINGS: [(&str, bool, StreamFraming); 4] = [
+    ("raw", false, StreamFraming::Raw),
+    ("crc", true, StreamFraming::Raw),
+    ("crc+cobs", true, StreamFraming::Cobs),
+    ("crc+slip", true, StreamFraming::Slip),
+];
+
+/// A table upload's worth of commands, ramp values so every byte value occurs
+fn table_commands() -> Vec<u8> {
+    let values: Vec<u16> = (0..BATCH as u16).map(|i| i.wrapping_mul(257)).collect();
+    protocol::table_upload(0, &values)
+}
+
+/// `count` status-0 responses as the device sends them with `crc` and `framing`
+fn responses(count: usize, crc: bool, framing: StreamFraming) -> Vec<u8> {
+    (0..count)
+        .flat_map(|_| {
+            let mut frame = vec![0x00, 0x00];
+            if crc {
+                append_crc(&mut frame);
+            }
+            framing.encode(&frame)
+        })
+        .collect()
+}
+
+fn encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(BATCH as u64));
+
+    group.bench_function("command", |b| {
+        b.iter(|| {
+            (0..BATCH as u16)
+                .map(|value| {
+                    Command::DacWrite {
+                        channel: (value % 8) as u8,
+                        value,
+                    }
+                    .encode()
+                })
+                .fold(0u8, |acc, bytes| acc ^ black_box(bytes)[3])
+        })
+    });
+    group.bench_function("table_upload", |b| {
+        let values: Vec<u16> = (0..BATCH as u16).collect();
+        b.iter(|| protocol::table_upload(0, black_box(&values)))
+    });
+
+    let data = table_commands();
+    for (name, crc, framing) in FRAMINGS {
+        let codec = Codec::new(crc, framing);
+        group.bench_function(format!("codec/{}", name), |b| {
+            b.iter(|| codec.encode_commands(black_box(&data)).unwrap())
+        });
+    }
+    let strict = Codec::default().with_strict(true);
+    group.bench_function("codec/strict", |b| {
+        b.iter(|| strict.encode_commands(black_box(&data)).unwrap())
+    });
+    group.finish();
+}
+
+fn reassembly(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reassemble");
+    group.throughput(Throughput::Elements(BATCH as u64));
+
+    for (name, framing) in [("cobs", StreamFraming::Cobs), ("slip", StreamFraming::Slip)] {
+        let stream = Codec::new(true, framing)
+            .encode_commands(&table_commands())
+            .unwrap();
+        // Whole batches and the small reads a serial port tends to deliver
+        for (reads, chunk) in [("whole", stream.len()), ("7B_reads", 7)] {
+            group.bench_function(format!("{}/{}", name, reads), |b| {
+                b.iter_batched_ref(
+                    || FrameDecoder::new(framing),
+                    |decoder| {
+                        stream
+                            .chunks(chunk)
+                            .map(|piece| decoder.push(black_box(piece)).len())
+                            .sum::<usize>()
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(BATCH as u64));
+
+    for (name, crc, framing) in FRAMINGS {
+        let data = responses(BATCH, crc, framing);
+        group.bench_function(format!("decode_responses/{}", name), |b| {
+            b.iter_batched_ref(
+                || Codec::new(crc, framing),
+                |codec| codec.decode_responses(black_box(&data)).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    let data = responses(BATCH, false, StreamFraming::Raw);
+    group.bench_function("check_status", |b| {
+        b.iter(|| {
+            data.chunks_exact(2)
+                .try_for_each(|r| protocol::check_status(black_box(r)))
+                .unwrap()
+        })
+    });
+    let commands = table_commands();
+    group.bench_function("ack_window", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut window = AckWindow::new(BATCH);
+                for cmd in commands.chunks_exact(4) {
+                    window.sent(cmd);
+                }
+                window
+            },
+            |window| window.received(black_box(&data)).len(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn batching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    let commands = table_commands();
+
+    group.bench_function("send_queue", |b| {
+        b.iter_batched_ref(
+            SendQueue::new,
+            |queue| {
+                for cmd in commands.chunks_exact(4) {
+                    queue.push(cmd);
+                }
+                let mut popped = 0;
+                while queue.pop().is_some() {
+                    popped += 1;
+                }
+                popped
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    for (name, delay) in [
+        ("passthrough", Duration::ZERO),
+        ("coalesce", Duration::from_secs(1)),
+    ] {
+        group.bench_function(format!("coalescing_writer/{}", name), |b| {
+            b.iter_batched_ref(
+                || CoalescingWriter::new(io::sink(), delay),
+                |writer| {
+                    for cmd in commands.chunks_exact(4) {
+                        writer.write_all(black_box(cmd)).unwrap();
+                    }
+                    writer.flush().unwrap()
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    // The whole client path against an instantly answering device
+    for window in [1, 16] {
+        group.bench_function(format!("command_stream/window{}", window), |b| {
+            b.iter_batched_ref(
+                || {
+                    CommandStream::new(
+                        MockTransport::new().with_auto_ack(),
+                        Codec::default(),
+                        window,
+                    )
+                },
+                |stream| {
+                    stream.enqueue(black_box(&commands));
+                    stream.flush_queue().unwrap();
+                    stream.drain().unwrap();
+                    stream.take_responses().len()
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encoding, reassembly, parsing, batching);
+criterion_main!(benches);
@@ -17,7 +17,7 @@ echo "📊 Table Control:"
 echo "   0 1 2 3 4 5 6 7 8 9 : Set table offset 0-9"
 echo
 echo "⚙️  System:"
-echo "   ESC or q : Quit application"
+echo "   ESC or Ctrl+Q : Quit application (plain q records a macro)"
 echo "   Auto keepalive every 5 seconds"
 echo "   Status display shows last command + device response"
 echo
@@ -63,7 +63,7 @@ echo "4. Press numbers 0-9 to change table offset"
 echo "5. Press Z,X,C,V,B,N,M,comma to toggle GPIO pins 0-7"
 echo "6. Watch the visual sliders and GPIO indicators update in real-time"
 echo "7. Monitor the status window for command/response display"
-echo "8. Press ESC or 'q' to quit when done"
+echo "8. Press ESC or Ctrl+Q to quit when done"
 echo
 echo "Press Enter to launch the TUI..."
 read
@@ -0,0 +1,161 @@
+//! Per-channel alarm thresholds.
+//!
+//! A DAC channel whose value leaves its `min..=max` window is in alarm.
+//! Displays highlight such channels; a [`DacClient`](crate::client::DacClient)
+//! can also refuse or clamp the writes that would put one there, guarding
+//! sensitive hardware downstream of the outputs. Thresholds enforced that
+//! way are the client's soft limits.
+
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, DAC_CHANNELS};
+use clap::ValueEnum;
+
+/// What a client does with a write outside a channel's thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum AlarmPolicy {
+    /// Send it anyway; the channel is only flagged
+    #[default]
+    Warn,
+    /// Fail the write with [`DacError::OutOfLimits`] without sending it
+    Refuse,
+    /// Send the nearest value inside the thresholds instead
+    Clamp,
+}
+
+/// Inclusive range of values a channel may carry without raising an alarm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl Threshold {
+    pub fn contains(&self, value: u16) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+
+    pub fn clamp(&self, value: u16) -> u16 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelAlarms {
+    thresholds: [Option<Threshold>; DAC_CHANNELS],
+    policy: AlarmPolicy,
+}
+
+impl ChannelAlarms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build alarms from `(channel, threshold)` pairs
+    pub fn from_thresholds(thresholds: &[(u8, Threshold)]) -> Result<Self> {
+        let mut alarms = Self::new();
+        for &(channel, threshold) in thresholds {
+            alarms.set(channel, threshold.min, threshold.max)?;
+        }
+        Ok(alarms)
+    }
+
+    /// Apply `policy` to writes outside the thresholds
+    pub fn with_policy(mut self, policy: AlarmPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> AlarmPolicy {
+        self.policy
+    }
+
+    /// Raise an alarm when `channel` leaves `min..=max`
+    pub fn set(&mut self, channel: u8, min: u16, max: u16) -> Result<()> {
+        if channel as usize >= DAC_CHANNELS {
+            return Err(DacError::InvalidArgument(format!(
+                "DAC channel {} out of range 0-{}",
+                channel,
+                DAC_CHANNELS - 1
+            )));
+        }
+        if min > max {
+            return Err(DacError::InvalidArgument(format!(
+                "DAC{} alarm minimum {} is above its maximum {}",
+                channel, min, max
+            )));
+        }
+        self.thresholds[channel as usize] = Some(Threshold { min, max });
+        Ok(())
+    }
+
+    /// Remove the thresholds of `channel`
+    pub fn clear(&mut self, channel: u8) {
+        if let Some(threshold) = self.thresholds.get_mut(channel as usize) {
+            *threshold = None;
+        }
+    }
+
+    pub fn threshold(&self, channel: u8) -> Option<Threshold> {
+        self.thresholds.get(channel as usize).copied().flatten()
+    }
+
+    /// Whether `value` on `channel` is outside its thresholds
+    pub fn in_alarm(&self, channel: u8, value: u16) -> bool {
+        self.threshold(channel)
+            .is_some_and(|threshold| !threshold.contains(value))
+    }
+
+    /// The command to send in place of `cmd` under the policy
+    ///
+    /// Only direct DAC writes are checked; everything else passes unchanged.
+    pub fn check(&self, cmd: Command) -> Result<Command> {
+        let Command::DacWrite { channel, value } = cmd else {
+            return Ok(cmd);
+        };
+        let Some(threshold) = self.threshold(channel) else {
+            return Ok(cmd);
+        };
+        if threshold.contains(value) {
+            return Ok(cmd);
+        }
+        match self.policy {
+            AlarmPolicy::Warn => Ok(cmd),
+            AlarmPolicy::Refuse => Err(DacError::OutOfLimits {
+                channel,
+                value,
+                min: threshold.min,
+                max: threshold.max,
+            }),
+            AlarmPolicy::Clamp => Ok(Command::DacWrite {
+                channel,
+                value: threshold.clamp(value),
+            }),
+        }
+    }
+}
+
+/// Parse a `channel=min:max` threshold, e.g. `3=1000:50000`, for command-line options
+pub fn parse_threshold(s: &str) -> std::result::Result<(u8, Threshold), String> {
+    let (channel, range) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected CHANNEL=MIN:MAX, got '{}'", s))?;
+    let (min, max) = range
+        .split_once(':')
+        .ok_or_else(|| format!("expected MIN:MAX, got '{}'", range))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u16>()
+            .map_err(|e| format!("invalid value '{}': {}", v, e))
+    };
+    let channel = channel
+        .trim()
+        .parse::<u8>()
+        .map_err(|e| format!("invalid channel '{}': {}", channel, e))?;
+    Ok((
+        channel,
+        Threshold {
+            min: parse(min)?,
+            max: parse(max)?,
+        },
+    ))
+}
@@ -0,0 +1,56 @@
+//! Append-only audit log of the commands a bridge forwards.
+//!
+//! One line per command: when it was forwarded, the client it came from, its
+//! bytes and what it means, so "who set channel 5 to full scale at 3am?" can
+//! be answered with `grep`:
+//!
+//! ```text
+//! 2024-05-01T03:00:12Z 192.168.1.20:51544 [05, 00, FF, FF] Direct DAC write: channel=5, value=0xFFFF
+//! ```
+//!
+//! The file rotates by a [`Rotation`] policy, 10 MiB and five old files
+//! unless told otherwise.
+
+use crate::error::Result;
+use crate::logfile::{RotatingFile, Rotation};
+use crate::protocol::Command;
+use crate::report;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Rotation policy used when none is given
+pub const DEFAULT_ROTATION: &str = "size=10M,keep=5";
+
+/// Audit file shared between client handlers
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Mutex<RotatingFile>,
+}
+
+impl AuditLog {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path, rotation: Rotation) -> Result<Self> {
+        Ok(Self {
+            file: Mutex::new(RotatingFile::open(path, rotation)?),
+        })
+    }
+
+    /// Record the 4-byte `commands` forwarded for `client`
+    pub fn record<'a>(
+        &self,
+        client: &str,
+        commands: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<()> {
+        let timestamp = report::utc_timestamp(SystemTime::now());
+        let lines: String = commands
+            .into_iter()
+            .map(|cmd| {
+                let meaning = Command::decode(cmd)
+                    .map_or_else(|| "Unknown command".to_string(), |cmd| cmd.to_string());
+                format!("{} {} {:02X?} {}\n", timestamp, client, cmd, meaning)
+            })
+            .collect();
+        self.file.lock().unwrap().write(lines.as_bytes())
+    }
+}
@@ -1,20 +1,47 @@
 use std::io::{self, Write};
 use std::time::Duration;
 use std::env;
+use serialtest::diagnose;
+use serialtest::target::{Target, DEFAULT_BAUD};
+use serialtest::version;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("-V") => {
+            println!("cdc {}", version::short_version());
+            return;
+        }
+        Some("--version" | "--version-full") => {
+            println!("cdc {}", version::long_version());
+            return;
+        }
+        _ => {}
+    }
     //let port_name = "/dev/cu.usbmodemcsv1_00011";
-    let port_name = args[1].clone();
+    let (port_name, baud_rate) = match args[1].parse::<Target>() {
+        Ok(Target::Serial { path, baud }) => (path, baud),
+        Ok(Target::Pty { path }) => (path, DEFAULT_BAUD),
+        Ok(other) => {
+            eprintln!("{} is not a serial device", other);
+            ::std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ::std::process::exit(1);
+        }
+    };
     println!("serial {port_name}");
     //let port_name = "/dev/cu.usbserial-1410";
-    let baud_rate = 115200;
     let rate = 10;
 
     let builder = serialport::new(&port_name, baud_rate).timeout(Duration::from_millis(10));
     println!("{:?}", &builder);
     let mut port = builder.open().unwrap_or_else(|e| {
         eprintln!("Failed to open \"{}\". Error: {}", port_name, e);
+        if let Some(advice) = diagnose::advise(&io::Error::from(e)) {
+            eprintln!("{}", advice);
+        }
         ::std::process::exit(1);
     });
 
@@ -0,0 +1,1486 @@
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Parser, Subcommand};
+use serialtest::cancel::CancellationToken;
+use serialtest::client::DacClient;
+use serialtest::control::{ClosedLoop, Pid};
+use serialtest::desired::DesiredState;
+use serialtest::diagnose::Failure;
+use serialtest::doctor;
+use serialtest::error::DacError;
+use serialtest::events::{self, EventLog};
+use serialtest::feedback::FeedbackRegistry;
+use serialtest::framing::{Codec, Padding, StreamFraming};
+use serialtest::group::{DeviceGroup, Trigger};
+use serialtest::protocol::{self, Command, Features, DAC_CHANNELS, GPIO_PINS, TABLES, TABLE_LEN};
+use serialtest::report::{utc_timestamp, Value};
+use serialtest::schedule::{self, Job};
+use serialtest::sequencer::{self, Sequencer};
+use serialtest::shell::{self, Line};
+use serialtest::snapshot::Snapshot;
+use serialtest::tables::TableSpec;
+use serialtest::target::Target;
+use serialtest::transport::LinkOptions;
+use serialtest::units::VoltageRange;
+use serialtest::version;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// One-shot commands for a csv1-ol8 device
+#[derive(Parser, Debug)]
+#[command(name = "csv1")]
+#[command(about = "One-shot commands for a csv1-ol8 device")]
+struct Cli {
+    #[command(subcommand)]
+    command: Cmd,
+
+    #[command(flatten)]
+    link: LinkArgs,
+}
+
+/// How commands go over the link, for every subcommand that connects to a device
+/// (`doctor` checks the raw link and ignores them)
+#[derive(clap::Args, Debug)]
+struct LinkArgs {
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long, global = true)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, global = true, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Trailing bytes that do not fill a 4-byte command: reject the batch, or zero-fill it
+    #[arg(long, value_enum, global = true, default_value = "reject")]
+    padding: Padding,
+
+    /// Validate channels, tables, GPIO pins and value fields before sending
+    #[arg(long, global = true)]
+    strict: bool,
+}
+
+impl LinkArgs {
+    fn codec(&self) -> Codec {
+        Codec::new(self.crc, self.framing)
+            .with_padding(self.padding)
+            .with_strict(self.strict)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Save the outputs of a state source as a snapshot file
+    Snapshot {
+        /// twin:HOST:PORT (a bridge started with --twin), a snapshot or a TUI session file
+        source: Source,
+
+        /// Write the snapshot here instead of to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List the channels and GPIOs that differ between two states (exit code 1 if any do)
+    Diff {
+        /// twin:HOST:PORT, a snapshot or a TUI session file
+        before: Source,
+
+        /// twin:HOST:PORT, a snapshot or a TUI session file
+        after: Source,
+    },
+    /// Run the jobs of a job file on their schedules until interrupted
+    Scheduler {
+        /// Job file: one `NAME SCHEDULE COMMAND` per line
+        jobs: PathBuf,
+
+        /// Directory for the per-job logs and the status file
+        #[arg(long, default_value = "scheduler-logs")]
+        log_dir: PathBuf,
+
+        /// Print the status of a running (or finished) scheduler and exit
+        #[arg(long)]
+        status: bool,
+    },
+    /// Load a state's DAC values into several devices, then latch them on all at once
+    /// (exit code 1 if the LDAC writes are spread wider than --window)
+    Sync {
+        /// TARGET=STATE: a device and the twin:HOST:PORT, snapshot or TUI session file
+        /// whose DAC values it gets (repeatable)
+        #[arg(long = "device", value_name = "TARGET=STATE", required = true, value_parser = parse_device)]
+        devices: Vec<(Target, Source)>,
+
+        /// Pulse this GPIO of one device instead of sending LDAC to each; its pin must
+        /// be wired to the LDAC inputs of all boards
+        #[arg(long, value_name = "DEVICE:PIN", value_parser = parse_gpio_trigger)]
+        gpio_trigger: Option<(usize, u8)>,
+
+        /// Length of the GPIO pulse in milliseconds
+        #[arg(long, default_value = "10")]
+        pulse: u64,
+
+        /// Widest accepted spread of the LDAC writes, in microseconds
+        #[arg(long)]
+        window: Option<u64>,
+    },
+    /// Play the tables back by stepping the table offset once per frame
+    Play {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// First table offset
+        #[arg(long, default_value = "0")]
+        first: u8,
+
+        /// Last table offset
+        #[arg(long, default_value = "255")]
+        last: u8,
+
+        /// Frame period in milliseconds
+        #[arg(long, default_value = "10")]
+        frame: u64,
+
+        /// Play the sweep this many times, 0 for until Ctrl-C
+        #[arg(long, default_value = "1")]
+        repeat: usize,
+
+        /// Start on the next multiple of this many milliseconds of wall-clock time and time
+        /// the frames by the system clock, keeping instances on NTP-synced hosts in step
+        #[arg(long, value_name = "MS")]
+        align: Option<u64>,
+    },
+    /// Scan the tables by stepping the table offset at a fixed rate, optionally back and forth
+    SweepOffset {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// First table offset
+        #[arg(long, default_value = "0")]
+        first: u8,
+
+        /// Last table offset
+        #[arg(long, default_value = "255")]
+        last: u8,
+
+        /// Offset steps per second
+        #[arg(long, default_value = "100", value_parser = parse_rate)]
+        rate: f64,
+
+        /// Step back down to the first offset after reaching the last
+        #[arg(long)]
+        ping_pong: bool,
+
+        /// Sweeps (up and down counts as one with --ping-pong), 0 for until Ctrl-C
+        #[arg(long, default_value = "0")]
+        cycles: usize,
+    },
+    /// Generate a table's contents and upload all 256 entries, e.g. `fill TARGET 0 "sine 2 | scale 0.5"`
+    Fill {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// Table to write
+        table: u8,
+
+        /// Generator and `|`-separated transforms: ramp, triangle, sine, gamma, scurve,
+        /// steps; scale, offset, invert, reverse, smooth
+        spec: TableSpec,
+
+        /// Print the entries instead of uploading them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Read the table back after the upload (exit code 1 if entries stay wrong);
+        /// needs firmware with table reads
+        #[arg(long)]
+        verify: bool,
+
+        /// With --verify, rewrite entries that read back wrong up to this many times
+        #[arg(long, default_value = "3")]
+        retries: usize,
+    },
+    /// Hold a measured value at a setpoint by adjusting one DAC channel (PID) until Ctrl-C
+    Hold {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// DAC channel to adjust
+        #[arg(long)]
+        channel: u8,
+
+        /// Value the feedback should read
+        #[arg(long, allow_negative_numbers = true)]
+        setpoint: f64,
+
+        /// file://PATH, tcp://HOST:PORT (one value per line) or scpi://HOST:PORT[/QUERY]
+        #[arg(long)]
+        feedback: String,
+
+        /// Proportional gain, in DAC codes per unit of error
+        #[arg(long, default_value = "0", allow_negative_numbers = true)]
+        kp: f64,
+
+        /// Integral gain, in DAC codes per unit of error and second
+        #[arg(long, default_value = "0", allow_negative_numbers = true)]
+        ki: f64,
+
+        /// Derivative gain, in DAC codes per unit of error per second
+        #[arg(long, default_value = "0", allow_negative_numbers = true)]
+        kd: f64,
+
+        /// Control period in milliseconds
+        #[arg(long, default_value = "100")]
+        period: u64,
+    },
+    /// Write one DAC channel as a code, percent or voltage, e.g. `set TARGET dac3 45%`
+    Set {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// DAC channel: dac3 or 3
+        #[arg(value_parser = parse_channel)]
+        channel: u8,
+
+        /// Code (decimal or 0x hex), percent of full scale (45%) or voltage (2500mV, 2.5V)
+        #[arg(allow_hyphen_values = true)]
+        value: Level,
+
+        /// Output voltage at code 0, for voltages
+        #[arg(long, default_value = "0", allow_negative_numbers = true)]
+        vmin: f64,
+
+        /// Output voltage at code 65535, for voltages
+        #[arg(long, default_value = "10", allow_negative_numbers = true)]
+        vmax: f64,
+
+        /// Send an LDAC after the write
+        #[arg(long)]
+        ldac: bool,
+    },
+    /// Switch one GPIO pin, e.g. `gpio TARGET 2 on`
+    Gpio {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// GPIO pin
+        #[arg(value_parser = parse_pin)]
+        pin: u8,
+
+        /// on or off (1 and 0 work too)
+        #[arg(value_parser = parse_on_off, action = ArgAction::Set)]
+        state: bool,
+    },
+    /// Latch the loaded DAC values onto the outputs
+    Ldac {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+    },
+    /// Apply, save or list named device states (snapshot files in a scene directory)
+    Scene {
+        #[command(subcommand)]
+        action: SceneCmd,
+
+        /// Directory holding the scenes, one NAME.snap file each
+        #[arg(long, global = true, default_value = "scenes", env = "CSV1_SCENES")]
+        dir: PathBuf,
+    },
+    /// Bring a device to the outputs a state file names, sending only what is needed
+    Apply {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// State file (TOML, or YAML if .yaml/.yml): table_offset, dac, gpio and attach
+        file: PathBuf,
+
+        /// The device's current state (twin:HOST:PORT, a snapshot or a TUI session
+        /// file); outputs it already has are not sent
+        #[arg(long)]
+        from: Option<Source>,
+
+        /// Print the commands instead of sending them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Read and write device registers, in the order given
+    Regs {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// REG to read a register, REG=VALUE to write one (decimal or 0x hex)
+        #[arg(required = true, value_name = "REG[=VALUE]")]
+        registers: Vec<RegisterAccess>,
+    },
+    /// Send raw frames unchecked and print the responses, e.g. to try out
+    /// undocumented firmware commands
+    Raw {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// Hex bytes of one frame each, e.g. "fb 10 be ef" or 0xf8100000; a
+        /// partial last command is zero-filled
+        #[arg(required = true, value_name = "HEX", value_parser = parse_frame)]
+        frames: Vec<Vec<u8>>,
+    },
+    /// Offer protocol features in a hello and show what the device agreed to
+    Hello {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// Features to offer: crc, extended, readback (comma-separated) or none
+        #[arg(long, default_value = "crc,extended,readback")]
+        features: Features,
+    },
+    /// Send line commands (dac, gpio, reg, raw, ...; see help) read from stdin
+    Shell {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// Pipe mode for programs: no prompt, one JSON result per input line on stdout
+        #[arg(long)]
+        stdin: bool,
+    },
+    /// Print the tools' version and build details, and the device's if a target is given
+    Version {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Option<Target>,
+    },
+    /// Check a target before real use: connection, keepalives, latency, padding,
+    /// hello and extended responses (exit code 1 if a check fails)
+    Doctor {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// Keepalives to send for the latency check
+        #[arg(long, default_value_t = doctor::DEFAULT_PROBES)]
+        probes: usize,
+
+        /// Print the report as one JSON object
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SceneCmd {
+    /// Bring a device to a scene's outputs
+    Apply {
+        /// Connection target: serial device path, network address or serial:, pty:, tcp:,
+        /// udp:, tls: or scheme:// target
+        target: Target,
+
+        /// Scene name
+        name: String,
+    },
+    /// Save a state source as a scene
+    Save {
+        /// Scene name
+        name: String,
+
+        /// twin:HOST:PORT, a snapshot or a TUI session file
+        source: Source,
+    },
+    /// List the saved scenes
+    List,
+}
+
+/// One register operation of `csv1 regs`
+#[derive(Debug, Clone, Copy)]
+enum RegisterAccess {
+    Read(u8),
+    Write(u8, u16),
+}
+
+/// Parse a decimal or `0x` hex number
+fn parse_number<T: FromStr + TryFrom<u32>>(s: &str) -> Result<T, String> {
+    let s = s.trim();
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("invalid number '{}': {}", s, e))?;
+    T::try_from(value).map_err(|_| format!("{} is out of range", s))
+}
+
+impl FromStr for RegisterAccess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((reg, value)) => Ok(RegisterAccess::Write(
+                parse_number(reg)?,
+                parse_number(value)?,
+            )),
+            None => Ok(RegisterAccess::Read(parse_number(s)?)),
+        }
+    }
+}
+
+/// Parse a GPIO pin number
+fn parse_pin(s: &str) -> Result<u8, String> {
+    let pin: u8 = s
+        .parse()
+        .map_err(|e| format!("invalid pin '{}': {}", s, e))?;
+    if pin as usize >= GPIO_PINS {
+        return Err(format!("pin {} is not in 0-{}", pin, GPIO_PINS - 1));
+    }
+    Ok(pin)
+}
+
+/// Parse `on`/`off` or `1`/`0`
+fn parse_on_off(s: &str) -> Result<bool, String> {
+    match s {
+        "on" | "1" => Ok(true),
+        "off" | "0" => Ok(false),
+        other => Err(format!("expected on or off, got '{}'", other)),
+    }
+}
+
+/// Parse a DAC channel as `dac3` or `3`
+fn parse_channel(s: &str) -> Result<u8, String> {
+    let number = s
+        .strip_prefix("dac")
+        .or_else(|| s.strip_prefix("DAC"))
+        .unwrap_or(s);
+    let channel: u8 = number
+        .parse()
+        .map_err(|e| format!("invalid channel '{}': {}", s, e))?;
+    if channel as usize >= DAC_CHANNELS {
+        return Err(format!(
+            "channel {} is not in 0-{}",
+            channel,
+            DAC_CHANNELS - 1
+        ));
+    }
+    Ok(channel)
+}
+
+/// A DAC value of `csv1 set`
+#[derive(Debug, Clone, Copy)]
+enum Level {
+    Code(u16),
+    /// Percent of full scale
+    Percent(f64),
+    /// Output voltage, converted through the range given
+    Volts(f64),
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let float = |number: &str| {
+            number
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| format!("invalid value '{}': {}", s, e))
+        };
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent = float(percent)?;
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("{} is not in 0-100%", s));
+            }
+            Ok(Level::Percent(percent))
+        } else if let Some(millivolts) = s.strip_suffix("mV").or_else(|| s.strip_suffix("mv")) {
+            Ok(Level::Volts(float(millivolts)? / 1000.0))
+        } else if let Some(volts) = s.strip_suffix('V').or_else(|| s.strip_suffix('v')) {
+            Ok(Level::Volts(float(volts)?))
+        } else {
+            Ok(Level::Code(parse_number(s)?))
+        }
+    }
+}
+
+impl Level {
+    /// The code to write; voltages must lie within `range`
+    fn code(self, range: &VoltageRange) -> Result<u16> {
+        match self {
+            Level::Code(code) => Ok(code),
+            Level::Percent(percent) => Ok((percent / 100.0 * 65535.0).round() as u16),
+            Level::Volts(volts) => range.to_code(volts).with_context(|| {
+                format!(
+                    "{} V is outside the range {} V to {} V (see --vmin and --vmax)",
+                    volts,
+                    range.min(),
+                    range.max()
+                )
+            }),
+        }
+    }
+}
+
+fn parse_frame(s: &str) -> Result<Vec<u8>, String> {
+    protocol::parse_frame(s).map_err(|e| e.to_string())
+}
+
+/// Parse a step rate in Hz; above zero and at most one step per microsecond
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s
+        .parse()
+        .map_err(|e| format!("invalid rate '{}': {}", s, e))?;
+    if !(rate > 0.0 && rate <= 1_000_000.0) {
+        return Err(format!("rate {} is not in (0, 1000000] Hz", s));
+    }
+    Ok(rate)
+}
+
+/// Parse `TARGET=STATE`; split at the last `=`, as target options use `=` too
+fn parse_device(s: &str) -> Result<(Target, Source), String> {
+    let (target, state) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected TARGET=STATE, got '{}'", s))?;
+    Ok((
+        target.parse().map_err(|e: DacError| e.to_string())?,
+        state.parse()?,
+    ))
+}
+
+/// Parse `DEVICE:PIN`, the device counted from 0 in --device order
+fn parse_gpio_trigger(s: &str) -> Result<(usize, u8), String> {
+    let (device, pin) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected DEVICE:PIN, got '{}'", s))?;
+    Ok((
+        device
+            .parse()
+            .map_err(|e| format!("invalid device '{}': {}", device, e))?,
+        pin.parse()
+            .map_err(|e| format!("invalid pin '{}': {}", pin, e))?,
+    ))
+}
+
+/// Where a device state comes from
+#[derive(Debug, Clone)]
+enum Source {
+    /// The digital twin of a `tcp_server --twin` bridge
+    Twin(String),
+    /// A snapshot or TUI session file
+    File(PathBuf),
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("twin:") {
+            Some("") => Err("twin: needs HOST:PORT".to_string()),
+            Some(addr) => Ok(Source::Twin(addr.to_string())),
+            None => Ok(Source::File(PathBuf::from(s))),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Twin(addr) => write!(f, "twin:{}", addr),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// `GET /snapshot` from a bridge's twin server
+fn fetch_twin(addr: &str) -> Result<Snapshot> {
+    let socket_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .with_context(|| format!("Cannot resolve {}", addr))?;
+    let mut stream = TcpStream::connect_timeout(&socket_addr, Duration::from_secs(5))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "GET /snapshot HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        addr
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .context("Malformed HTTP response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if !status.contains(" 200 ") {
+        bail!("Twin answered '{}'", status);
+    }
+    Ok(body.parse()?)
+}
+
+impl Source {
+    fn load(&self) -> Result<Snapshot> {
+        let snapshot = match self {
+            Source::Twin(addr) => fetch_twin(addr)
+                .with_context(|| format!("Failed to read the twin at {}", addr))?
+                .with_source(self.to_string()),
+            Source::File(path) => Snapshot::load(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?,
+        };
+        Ok(snapshot)
+    }
+}
+
+/// Name of the status file in the log directory
+const STATUS_FILE: &str = "status.txt";
+
+/// What the scheduler knows about a job
+struct JobStatus {
+    job: Job,
+    next: Option<SystemTime>,
+    running_since: Option<SystemTime>,
+    /// Outcome of the last run, e.g. `2024-05-01T02:00:00Z ok (12.3s)`
+    last: Option<String>,
+    runs: u64,
+    failures: u64,
+    skipped: u64,
+}
+
+fn status_table(statuses: &[JobStatus]) -> String {
+    let rows: Vec<[String; 6]> = statuses
+        .iter()
+        .map(|s| {
+            let last = match s.running_since {
+                Some(since) => format!("running since {}", utc_timestamp(since)),
+                None => s.last.clone().unwrap_or_else(|| "-".to_string()),
+            };
+            [
+                s.job.name.clone(),
+                s.job.spec.clone(),
+                s.runs.to_string(),
+                format!("{}/{}", s.failures, s.skipped),
+                last,
+                s.next.map_or_else(|| "never".to_string(), utc_timestamp),
+            ]
+        })
+        .collect();
+    let header = ["NAME", "SCHEDULE", "RUNS", "FAILED/SKIPPED", "LAST", "NEXT"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut out = format!(
+        "# csv1 scheduler status, updated {}\n",
+        utc_timestamp(SystemTime::now())
+    );
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn write_status(log_dir: &Path, statuses: &[JobStatus]) {
+    let path = log_dir.join(STATUS_FILE);
+    if let Err(e) = std::fs::write(&path, status_table(statuses)) {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+/// Run `job` through the shell, appending its output to `log`
+///
+/// Returns whether it succeeded and a one-line outcome.
+fn run_job(job: &Job, log: &Path) -> Result<(bool, String)> {
+    let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+    let started = SystemTime::now();
+    writeln!(
+        file,
+        "=== {} start: {}",
+        utc_timestamp(started),
+        job.command
+    )?;
+
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = process::Command::new("cmd");
+        command.arg("/C");
+        command
+    };
+    #[cfg(not(windows))]
+    let mut command = {
+        let mut command = process::Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    let timer = Instant::now();
+    let status = command
+        .arg(&job.command)
+        .env("CSV1_JOB", &job.name)
+        .stdin(process::Stdio::null())
+        .stdout(file.try_clone()?)
+        .stderr(file.try_clone()?)
+        .status()?;
+    let outcome = if status.success() {
+        "ok".to_string()
+    } else {
+        match status.code() {
+            Some(code) => format!("exit {}", code),
+            None => "killed".to_string(),
+        }
+    };
+    let outcome = format!(
+        "{} {} ({:.1}s)",
+        utc_timestamp(started),
+        outcome,
+        timer.elapsed().as_secs_f64()
+    );
+    writeln!(file, "=== {}", outcome)?;
+    Ok((status.success(), outcome))
+}
+
+fn run_scheduler(jobs_path: &Path, log_dir: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(jobs_path)
+        .with_context(|| format!("Failed to read {}", jobs_path.display()))?;
+    let jobs = schedule::parse_jobs(&text)
+        .with_context(|| format!("Invalid job file {}", jobs_path.display()))?;
+    if jobs.is_empty() {
+        bail!("{} has no jobs", jobs_path.display());
+    }
+    std::fs::create_dir_all(log_dir)
+        .with_context(|| format!("Failed to create {}", log_dir.display()))?;
+
+    let now = SystemTime::now();
+    let statuses: Vec<JobStatus> = jobs
+        .into_iter()
+        .map(|job| JobStatus {
+            next: job.schedule.next_after(now),
+            job,
+            running_since: None,
+            last: None,
+            runs: 0,
+            failures: 0,
+            skipped: 0,
+        })
+        .collect();
+    print!("{}", status_table(&statuses));
+    write_status(log_dir, &statuses);
+    let statuses = Arc::new(Mutex::new(statuses));
+
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    let shutdown_flag_clone = shutdown_flag.clone();
+    ctrlc::set_handler(move || {
+        println!("\nReceived interrupt signal, shutting down...");
+        shutdown_flag_clone.store(true, Ordering::Relaxed);
+    })?;
+
+    let mut running = Vec::new();
+    loop {
+        thread::sleep(Duration::from_millis(250));
+        if shutdown_flag.load(Ordering::Relaxed) {
+            break;
+        }
+        let now = SystemTime::now();
+        let mut list = statuses.lock().unwrap();
+        let mut changed = false;
+        for (i, status) in list.iter_mut().enumerate() {
+            if status.next.is_none_or(|next| next > now) {
+                continue;
+            }
+            changed = true;
+            status.next = status.job.schedule.next_after(now);
+            if status.running_since.is_some() {
+                println!("{}: still running, skipping this run", status.job.name);
+                status.skipped += 1;
+                continue;
+            }
+            println!("{}: starting {}", status.job.name, status.job.command);
+            status.running_since = Some(now);
+
+            let job = status.job.clone();
+            let log = log_dir.join(format!("{}.log", job.name));
+            let statuses = statuses.clone();
+            let log_dir = log_dir.to_path_buf();
+            running.push(thread::spawn(move || {
+                let outcome = run_job(&job, &log);
+                let mut list = statuses.lock().unwrap();
+                let status = &mut list[i];
+                status.running_since = None;
+                status.runs += 1;
+                let (ok, outcome) =
+                    outcome.unwrap_or_else(|e| (false, format!("failed to start: {}", e)));
+                if !ok {
+                    status.failures += 1;
+                }
+                println!("{}: {}", job.name, outcome);
+                status.last = Some(outcome);
+                write_status(&log_dir, &list);
+            }));
+        }
+        if changed {
+            write_status(log_dir, &list);
+        }
+        running.retain(|handle| !handle.is_finished());
+    }
+
+    if !running.is_empty() {
+        println!("Waiting for {} running job(s)", running.len());
+    }
+    for handle in running {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+/// Timeouts of the device connections
+const LINK_OPTIONS: LinkOptions = LinkOptions {
+    read_timeout: Duration::from_millis(200),
+    write_timeout: Duration::from_millis(1000),
+};
+
+/// Connect to `target` with the codec the link arguments ask for
+fn connect(target: &Target, link: &LinkArgs) -> Result<DacClient> {
+    DacClient::connect(vec![target.clone()], LINK_OPTIONS, link.codec())
+        .with_context(|| format!("Failed to connect to {}", target))
+}
+
+/// Arm every device with its state and trigger them; returns the LDAC spread
+fn run_sync(
+    devices: &[(Target, Source)],
+    gpio_trigger: Option<(usize, u8)>,
+    pulse: Duration,
+    link: &LinkArgs,
+) -> Result<Duration> {
+    let sequences = devices
+        .iter()
+        .map(|(_, source)| {
+            let snapshot = source.load()?;
+            Ok(snapshot
+                .dac
+                .iter()
+                .enumerate()
+                .map(|(channel, &value)| Command::DacWrite {
+                    channel: channel as u8,
+                    value,
+                })
+                .collect())
+        })
+        .collect::<Result<Vec<Vec<Command>>>>()?;
+    let targets: Vec<Target> = devices.iter().map(|(target, _)| target.clone()).collect();
+    let mut group =
+        DeviceGroup::connect(&targets, LINK_OPTIONS, link.codec()).context("Failed to connect")?;
+    group.arm(&sequences).context("Failed to arm")?;
+    println!("Armed {} devices", group.len());
+
+    let trigger = match gpio_trigger {
+        Some((device, pin)) => Trigger::Gpio { device, pin, pulse },
+        None => Trigger::Software(Command::Ldac),
+    };
+    let report = group.trigger(trigger).context("Failed to trigger")?;
+    match trigger {
+        Trigger::Gpio { device, pin, .. } => {
+            println!("Pulsed GPIO{} of {}", pin, targets[device])
+        }
+        Trigger::Software(_) => {
+            for (target, sent) in targets.iter().zip(&report.sent) {
+                println!("{}: LDAC +{}us", target, sent.as_micros());
+            }
+            println!("Spread: {}us", report.spread().as_micros());
+        }
+    }
+    Ok(report.spread())
+}
+
+/// Play a table sweep until done or Ctrl-C
+fn run_play(
+    target: &Target,
+    sequencer: &Sequencer,
+    align: Option<Duration>,
+    link: &LinkArgs,
+) -> Result<()> {
+    let mut client = connect(target, link)?;
+    let cancel = CancellationToken::new();
+    let interrupt = cancel.clone();
+    ctrlc::set_handler(move || interrupt.cancel()).context("Failed to set the Ctrl-C handler")?;
+
+    match align {
+        Some(align) => println!(
+            "Playing {} frames every {} ms from {}",
+            sequencer.frames().len(),
+            sequencer.period().as_millis(),
+            utc_timestamp(sequencer::next_boundary(SystemTime::now(), align))
+        ),
+        None => println!(
+            "Playing {} frames every {} ms",
+            sequencer.frames().len(),
+            sequencer.period().as_millis()
+        ),
+    }
+    match sequencer.run(&mut client, &cancel) {
+        Ok(playback) => println!(
+            "Played {} frames, skipped {}",
+            playback.played, playback.skipped
+        ),
+        Err(DacError::Cancelled) => println!("Stopped"),
+        Err(e) => return Err(e).context("Playback failed"),
+    }
+    Ok(())
+}
+
+/// Upload the entries `spec` generates to `table`, or only print them
+///
+/// With `verify` (the number of retries), returns whether the table read back right.
+fn run_fill(
+    target: &Target,
+    table: u8,
+    spec: &TableSpec,
+    dry_run: bool,
+    verify: Option<usize>,
+    link: &LinkArgs,
+) -> Result<bool> {
+    if table as usize >= TABLES {
+        bail!("Table {} is not in 0-{}", table, TABLES - 1);
+    }
+    if dry_run {
+        for (index, chunk) in spec.generate().chunks(16).enumerate() {
+            let entries: Vec<String> = chunk.iter().map(|v| format!("{:04X}", v)).collect();
+            println!("{:3}: {}", index * 16, entries.join(" "));
+        }
+        return Ok(true);
+    }
+    let mut client = connect(target, link)?;
+    let Some(retries) = verify else {
+        for cmd in spec.commands(table) {
+            client
+                .send(cmd)
+                .with_context(|| format!("Failed to send {}", cmd))?;
+        }
+        println!("Table {} = {} ({} entries)", table, spec, TABLE_LEN);
+        return Ok(true);
+    };
+    client
+        .negotiate(Features::EXTENDED | Features::READBACK)
+        .context("Hello failed")?;
+    let verification = client
+        .upload_table(table, &spec.generate(), retries)
+        .with_context(|| format!("Failed to upload and verify table {}", table))?;
+    println!(
+        "Table {} = {} ({} entries, {} rewritten after reading back wrong)",
+        table, spec, TABLE_LEN, verification.rewritten
+    );
+    for mismatch in &verification.mismatches {
+        println!("Mismatch at {}", mismatch);
+    }
+    if verification.passed() {
+        println!("Verified");
+    }
+    Ok(verification.passed())
+}
+
+/// Hold the feedback at `setpoint` until Ctrl-C, printing every step
+fn run_hold(
+    target: &Target,
+    channel: u8,
+    setpoint: f64,
+    feedback: &str,
+    pid: Pid,
+    period: Duration,
+    link: &LinkArgs,
+) -> Result<()> {
+    let source = FeedbackRegistry::with_builtins()
+        .open(feedback, &LINK_OPTIONS)
+        .with_context(|| format!("Failed to open the feedback {}", feedback))?;
+    let mut control = ClosedLoop::new(channel, setpoint, pid, source)?.with_period(period);
+    let mut client = connect(target, link)?;
+    let cancel = CancellationToken::new();
+    let interrupt = cancel.clone();
+    ctrlc::set_handler(move || interrupt.cancel()).context("Failed to set the Ctrl-C handler")?;
+
+    println!("Holding {} at {} with DAC{}", feedback, setpoint, channel);
+    let result = control.run(&mut client, &cancel, |step| {
+        println!(
+            "{:8.2}s  measured {:<12.6}  error {:<12.6}  DAC{} = {}",
+            step.elapsed.as_secs_f64(),
+            step.measured,
+            step.error,
+            channel,
+            step.value
+        )
+    });
+    match result {
+        Err(DacError::Cancelled) => {
+            println!("Stopped");
+            Ok(())
+        }
+        result => result.context("Control loop failed"),
+    }
+}
+
+/// Write `level` to one DAC channel, then latch it with `ldac`
+fn run_set(
+    target: &Target,
+    channel: u8,
+    level: Level,
+    range: &VoltageRange,
+    ldac: bool,
+    link: &LinkArgs,
+) -> Result<()> {
+    let value = level.code(range)?;
+    let mut client = connect(target, link)?;
+    let cmd = Command::DacWrite { channel, value };
+    client
+        .send(cmd)
+        .with_context(|| format!("Failed to send {}", cmd))?;
+    println!(
+        "DAC{} = 0x{:04X} ({}, {:.1}%, {:.3} V)",
+        channel,
+        value,
+        value,
+        value as f64 / 65535.0 * 100.0,
+        range.to_volts(value)
+    );
+    if ldac {
+        client.send(Command::Ldac).context("Failed to send LDAC")?;
+        println!("LDAC sent");
+    }
+    Ok(())
+}
+
+/// Connect, send each command and check its status
+fn run_commands(target: &Target, commands: &[Command], link: &LinkArgs) -> Result<()> {
+    let mut client = connect(target, link)?;
+    for &cmd in commands {
+        client
+            .send(cmd)
+            .with_context(|| format!("Failed to send {}", cmd))?;
+    }
+    Ok(())
+}
+
+/// Extension of the scene files in the scene directory
+const SCENE_EXTENSION: &str = "snap";
+
+/// File of the scene `name` in `dir`
+fn scene_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}", name, SCENE_EXTENSION))
+}
+
+/// Apply, save or list scenes
+fn run_scene(action: &SceneCmd, dir: &Path, link: &LinkArgs) -> Result<()> {
+    match action {
+        SceneCmd::Apply { target, name } => {
+            let path = scene_path(dir, name);
+            let scene = Snapshot::load(&path)
+                .with_context(|| format!("Failed to read scene {} ({})", name, path.display()))?;
+            run_commands(target, &scene.commands(), link)?;
+            println!("Applied scene {} to {}", name, target);
+        }
+        SceneCmd::Save { name, source } => {
+            let mut snapshot = source.load()?;
+            if snapshot.source.is_none() {
+                snapshot = snapshot.with_source(source.to_string());
+            }
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            let path = scene_path(dir, name);
+            snapshot
+                .save(&path)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Saved {} as scene {}", source, name);
+        }
+        SceneCmd::List => {
+            let entries = std::fs::read_dir(dir)
+                .with_context(|| format!("Failed to read {}", dir.display()))?;
+            let mut names = Vec::new();
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|e| e == SCENE_EXTENSION) {
+                    if let Some(name) = path.file_stem() {
+                        names.push(name.to_string_lossy().into_owned());
+                    }
+                }
+            }
+            names.sort();
+            for name in names {
+                println!("{}", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send the commands that realize the state in `file`, or only print them
+fn run_apply(
+    target: &Target,
+    file: &Path,
+    from: Option<&Source>,
+    dry_run: bool,
+    link: &LinkArgs,
+) -> Result<()> {
+    let desired =
+        DesiredState::load(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let current = from.map(Source::load).transpose()?;
+    let commands = desired.commands(current.as_ref());
+    if commands.is_empty() {
+        println!("Nothing to change");
+        return Ok(());
+    }
+    if dry_run {
+        for cmd in &commands {
+            println!("{}", cmd);
+        }
+        return Ok(());
+    }
+    run_commands(target, &commands, link)?;
+    println!(
+        "Applied {} to {} ({} commands)",
+        file.display(),
+        target,
+        commands.len()
+    );
+    Ok(())
+}
+
+/// Carry out register reads and writes, printing each
+fn run_regs(target: &Target, registers: &[RegisterAccess], link: &LinkArgs) -> Result<()> {
+    let mut client = connect(target, link)?;
+    if registers
+        .iter()
+        .any(|access| matches!(access, RegisterAccess::Read(_)))
+    {
+        // Only firmware that says so in a hello has register reads
+        client
+            .negotiate(Features::EXTENDED | Features::READBACK)
+            .context("Hello failed")?;
+    }
+    for access in registers {
+        match *access {
+            RegisterAccess::Read(reg) => {
+                let value = client
+                    .read_register(reg)
+                    .with_context(|| format!("Failed to read register {}", reg))?;
+                println!("REG{} = 0x{:04X} ({})", reg, value, value);
+            }
+            RegisterAccess::Write(reg, value) => {
+                client
+                    .write_register(reg, value)
+                    .with_context(|| format!("Failed to write register {}", reg))?;
+                println!("REG{} <- 0x{:04X} ({})", reg, value, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send each frame as is, printing what the device answered to it
+fn run_raw(target: &Target, frames: &[Vec<u8>], link: &LinkArgs) -> Result<()> {
+    let mut client = connect(target, link)?;
+    for frame in frames {
+        let responses = client
+            .send_raw(frame)
+            .with_context(|| format!("No answer to {:02X?}", frame))?;
+        println!("{:02X?} -> {:02X?}", frame, responses);
+    }
+    Ok(())
+}
+
+/// Negotiate, then check the link still works with the features agreed
+fn run_hello(target: &Target, features: Features, link: &LinkArgs) -> Result<()> {
+    let mut client = connect(target, link)?;
+    let hello = client.negotiate(features).context("Hello failed")?;
+    if hello.version == 0 {
+        println!("No hello support (legacy firmware), features: none");
+    } else {
+        println!(
+            "Protocol version {}, features: {}",
+            hello.version, hello.features
+        );
+    }
+    client
+        .send(Command::KeepAlive)
+        .context("Keepalive after the hello failed")?;
+    println!("Keepalive OK");
+    Ok(())
+}
+
+/// Run shell lines from stdin until `quit` or the end of input
+///
+/// In pipe mode (`json`) every line but blank and `#` comment lines gets one
+/// `result` line on stdout, with `ok` and `error` or the command's fields.
+fn run_shell(target: &Target, json: bool, link: &LinkArgs) -> Result<()> {
+    let log = EventLog::new(json);
+    let mut client = match connect(target, link) {
+        Ok(client) => client,
+        Err(e) if json => {
+            log.record("error", Value::object().with("error", format!("{:#}", e)));
+            process::exit(1);
+        }
+        Err(e) => return Err(e),
+    };
+    log.info(
+        "ready",
+        Value::object()
+            .with("target", target.to_string())
+            .with("transport", client.kind()),
+        format!(
+            "Connected to {} via {}; help lists the commands",
+            target,
+            client.kind()
+        ),
+    );
+
+    let prompt = !json && io::stdin().is_terminal();
+    let mut lines = io::stdin().lock().lines();
+    let mut number = 0u64;
+    loop {
+        if prompt {
+            print!("csv1> ");
+            io::stdout().flush()?;
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line.context("Failed to read stdin")?;
+        number += 1;
+        let input = line.trim();
+        if input.is_empty() || input.starts_with('#') {
+            continue;
+        }
+        let result = input.parse::<Line>().and_then(|line| {
+            let reply = shell::execute(&mut client, &line)?;
+            Ok((line, reply))
+        });
+        let quit = matches!(result, Ok((Line::Quit, _)));
+        if json {
+            let head = Value::object()
+                .with("line", number)
+                .with("input", input)
+                .with("ok", result.is_ok());
+            let fields = match result {
+                Ok((_, reply)) => head.merge(reply.fields),
+                Err(e) => head.with("error", e.to_string()),
+            };
+            println!("{}", events::line("result", fields));
+        } else {
+            match result {
+                Ok((_, reply)) if !reply.text.is_empty() => println!("{}", reply.text),
+                Ok(_) => {}
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        if quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Build details, then the protocol version the device reports in a hello
+fn run_version(target: Option<&Target>, link: &LinkArgs) -> Result<()> {
+    println!("csv1 {}", version::long_version());
+    let Some(target) = target else {
+        return Ok(());
+    };
+    let mut client = connect(target, link)?;
+    let hello = client.negotiate(Features::ALL).context("Hello failed")?;
+    println!("Device: {}", version::device_text(&hello));
+    Ok(())
+}
+
+/// Run the pre-flight checks and print the report; false if a check failed
+fn run_doctor(target: &Target, probes: usize, json: bool) -> bool {
+    let report = doctor::examine(target, &LINK_OPTIONS, probes);
+    if json {
+        println!("{}", report.to_value());
+    } else {
+        println!("{}", report);
+    }
+    report.passed()
+}
+
+fn main() -> Result<(), Failure> {
+    let cli = version::parse_args::<Cli>();
+    let link = &cli.link;
+    match cli.command {
+        Cmd::Snapshot { source, output } => {
+            let mut snapshot = source.load()?;
+            if snapshot.source.is_none() {
+                snapshot = snapshot.with_source(source.to_string());
+            }
+            match output {
+                Some(path) => {
+                    snapshot
+                        .save(&path)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    println!("Saved snapshot of {} to {}", source, path.display());
+                }
+                None => print!("{}", snapshot),
+            }
+        }
+        Cmd::Diff { before, after } => {
+            let differences = before.load()?.diff(&after.load()?);
+            if differences.is_empty() {
+                println!("No differences");
+            } else {
+                for difference in &differences {
+                    println!("{}", difference);
+                }
+                process::exit(1);
+            }
+        }
+        Cmd::Scheduler {
+            jobs,
+            log_dir,
+            status,
+        } => {
+            if status {
+                let path = log_dir.join(STATUS_FILE);
+                let table = std::fs::read_to_string(&path).with_context(|| {
+                    format!(
+                        "No status at {}; has the scheduler run there?",
+                        path.display()
+                    )
+                })?;
+                print!("{}", table);
+            } else {
+                run_scheduler(&jobs, &log_dir)?;
+            }
+        }
+        Cmd::Sync {
+            devices,
+            gpio_trigger,
+            pulse,
+            window,
+        } => {
+            let spread = run_sync(&devices, gpio_trigger, Duration::from_millis(pulse), link)?;
+            if window.is_some_and(|window| spread > Duration::from_micros(window)) {
+                process::exit(1);
+            }
+        }
+        Cmd::Play {
+            target,
+            first,
+            last,
+            frame,
+            repeat,
+            align,
+        } => {
+            let align = align.map(Duration::from_millis);
+            let mut sequencer = Sequencer::table_sweep(first, last, Duration::from_millis(frame))?
+                .with_repeat(repeat);
+            if let Some(align) = align {
+                sequencer = sequencer.with_wall_clock(align);
+            }
+            run_play(&target, &sequencer, align, link)?;
+        }
+        Cmd::SweepOffset {
+            target,
+            first,
+            last,
+            rate,
+            ping_pong,
+            cycles,
+        } => {
+            let period = Duration::from_secs_f64(1.0 / rate);
+            let sequencer = if ping_pong {
+                Sequencer::table_ping_pong(first, last, period)?
+            } else {
+                Sequencer::table_sweep(first, last, period)?
+            };
+            run_play(&target, &sequencer.with_repeat(cycles), None, link)?;
+        }
+        Cmd::Fill {
+            target,
+            table,
+            spec,
+            dry_run,
+            verify,
+            retries,
+        } => {
+            if !run_fill(
+                &target,
+                table,
+                &spec,
+                dry_run,
+                verify.then_some(retries),
+                link,
+            )? {
+                process::exit(1);
+            }
+        }
+        Cmd::Hold {
+            target,
+            channel,
+            setpoint,
+            feedback,
+            kp,
+            ki,
+            kd,
+            period,
+        } => run_hold(
+            &target,
+            channel,
+            setpoint,
+            &feedback,
+            Pid::new(kp, ki, kd),
+            Duration::from_millis(period),
+            link,
+        )?,
+        Cmd::Set {
+            target,
+            channel,
+            value,
+            vmin,
+            vmax,
+            ldac,
+        } => run_set(
+            &target,
+            channel,
+            value,
+            &VoltageRange::new(vmin, vmax).context("Bad --vmin/--vmax")?,
+            ldac,
+            link,
+        )?,
+        Cmd::Gpio { target, pin, state } => {
+            run_commands(&target, &[Command::Gpio { pin, on: state }], link)?;
+            println!("GPIO{} = {}", pin, if state { "on" } else { "off" });
+        }
+        Cmd::Ldac { target } => {
+            run_commands(&target, &[Command::Ldac], link)?;
+            println!("LDAC sent");
+        }
+        Cmd::Scene { action, dir } => run_scene(&action, &dir, link)?,
+        Cmd::Apply {
+            target,
+            file,
+            from,
+            dry_run,
+        } => run_apply(&target, &file, from.as_ref(), dry_run, link)?,
+        Cmd::Regs { target, registers } => run_regs(&target, &registers, link)?,
+        Cmd::Raw { target, frames } => run_raw(&target, &frames, link)?,
+        Cmd::Hello { target, features } => run_hello(&target, features, link)?,
+        Cmd::Shell { target, stdin } => run_shell(&target, stdin, link)?,
+        Cmd::Version { target } => run_version(target.as_ref(), link)?,
+        Cmd::Doctor {
+            target,
+            probes,
+            json,
+        } => {
+            if !run_doctor(&target, probes, json) {
+                process::exit(1);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+use anyhow::{Context, Result};
+use clap::builder::BoolishValueParser;
+use clap::{Parser, ValueEnum};
+use serialtest::alarms::{parse_threshold, AlarmPolicy, ChannelAlarms, Threshold};
+use serialtest::client::{DacClient, SharedClient};
+use serialtest::device::DeviceState;
+use serialtest::diagnose::Failure;
+use serialtest::framing::{Codec, StreamFraming};
+use serialtest::protocol::{Command, DAC_CHANNELS, GPIO_PINS};
+use serialtest::repeats;
+use serialtest::target::Target;
+use serialtest::transport::LinkOptions;
+use serialtest::version;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use zbus::blocking::connection;
+use zbus::fdo;
+use zbus::interface;
+use zbus::object_server::SignalEmitter;
+
+/// Well-known bus name and interface of the service
+const SERVICE_NAME: &str = "org.csv1.Dac";
+
+/// Object path of the device
+const OBJECT_PATH: &str = "/org/csv1/Dac";
+
+/// Headless D-Bus service for a DAC
+#[derive(Parser, Debug)]
+#[command(name = "dbus_server")]
+#[command(about = "Expose a DAC device over D-Bus as org.csv1.Dac")]
+struct Args {
+    /// Connection target: serial device path, network address (IPv4:port, [IPv6]:port) or
+    /// serial:, pty:, tcp:, udp:, tls: or scheme:// target
+    #[arg(env = "CSV1_TARGET")]
+    target: Target,
+
+    /// Backup target to switch to when the connection dies, restoring the device state
+    /// (repeatable, tried in order)
+    #[arg(long = "failover", value_name = "TARGET")]
+    failover: Vec<Target>,
+
+    /// Bus to register on
+    #[arg(long, value_enum, default_value = "session")]
+    bus: Bus,
+
+    /// Read timeout in milliseconds
+    #[arg(long, default_value = "200", env = "CSV1_READ_TIMEOUT")]
+    read_timeout: u64,
+
+    /// Write timeout in milliseconds
+    #[arg(long, default_value = "1000", env = "CSV1_WRITE_TIMEOUT")]
+    write_timeout: u64,
+
+    /// Keepalive interval in seconds
+    #[arg(long, default_value = "5")]
+    keepalive_interval: u64,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Alarm when a DAC channel leaves MIN..=MAX, e.g. 3=1000:50000 (repeatable)
+    #[arg(long = "alarm", value_name = "CHANNEL=MIN:MAX", value_parser = parse_threshold)]
+    alarms: Vec<(u8, Threshold)>,
+
+    /// What to do with a write outside its channel's alarm thresholds
+    #[arg(long, value_enum, default_value = "warn")]
+    alarm_policy: AlarmPolicy,
+
+    /// Log every method call
+    #[arg(short, long, env = "CSV1_VERBOSE", value_parser = BoolishValueParser::new())]
+    verbose: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Bus {
+    Session,
+    System,
+}
+
+/// The org.csv1.Dac object
+struct Dac {
+    client: SharedClient,
+    verbose: bool,
+}
+
+impl Dac {
+    fn apply(&mut self, cmd: Command) -> fdo::Result<()> {
+        if self.verbose {
+            println!("{}", cmd);
+        }
+        self.client
+            .lock()
+            .unwrap()
+            .send(cmd)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Device state from every command sent through this service
+    fn state(&self) -> DeviceState {
+        self.client.lock().unwrap().state().clone()
+    }
+}
+
+fn check_index(what: &str, index: u8, count: usize) -> fdo::Result<()> {
+    if (index as usize) < count {
+        Ok(())
+    } else {
+        Err(fdo::Error::InvalidArgs(format!(
+            "{} {} out of range (0-{})",
+            what,
+            index,
+            count - 1
+        )))
+    }
+}
+
+#[interface(name = "org.csv1.Dac")]
+impl Dac {
+    /// Write `value` to DAC `channel`
+    async fn set_dac(
+        &mut self,
+        channel: u8,
+        value: u16,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        check_index("DAC channel", channel, DAC_CHANNELS)?;
+        self.apply(Command::DacWrite { channel, value })?;
+        Self::dac_changed(&emitter, channel, self.state().dac[channel as usize]).await?;
+        self.dac_values_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Switch GPIO `pin` on or off
+    async fn set_gpio(
+        &mut self,
+        pin: u8,
+        on: bool,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        check_index("GPIO pin", pin, GPIO_PINS)?;
+        self.apply(Command::Gpio { pin, on })?;
+        Self::gpio_changed(&emitter, pin, on).await?;
+        self.gpio_states_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Load entry `offset` of the attached tables into their channels
+    async fn use_table(
+        &mut self,
+        offset: u8,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let before = self.state().dac;
+        self.apply(Command::UseTable { offset })?;
+        self.emit_dac_changes(&emitter, before).await?;
+        self.table_offset_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Update the outputs with the loaded values
+    async fn ldac(
+        &mut self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let before = self.state().dac;
+        self.apply(Command::Ldac)?;
+        self.emit_dac_changes(&emitter, before).await
+    }
+
+    /// Current output of every DAC channel
+    #[zbus(property)]
+    fn dac_values(&self) -> Vec<u16> {
+        self.state().dac.to_vec()
+    }
+
+    /// State of every GPIO pin
+    #[zbus(property)]
+    fn gpio_states(&self) -> Vec<bool> {
+        self.state().gpio.to_vec()
+    }
+
+    #[zbus(property)]
+    fn table_offset(&self) -> u8 {
+        self.state().table_offset
+    }
+
+    /// The device this service talks to (the failover target after a failover)
+    #[zbus(property)]
+    fn target(&self) -> String {
+        self.client
+            .lock()
+            .unwrap()
+            .target()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
+
+    #[zbus(signal)]
+    async fn dac_changed(emitter: &SignalEmitter<'_>, channel: u8, value: u16) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn gpio_changed(emitter: &SignalEmitter<'_>, pin: u8, on: bool) -> zbus::Result<()>;
+}
+
+impl Dac {
+    /// Signal every channel whose output differs from `before`
+    async fn emit_dac_changes(
+        &self,
+        emitter: &SignalEmitter<'_>,
+        before: [u16; DAC_CHANNELS],
+    ) -> fdo::Result<()> {
+        let mut changed = false;
+        for (channel, (&old, &new)) in before.iter().zip(&self.state().dac).enumerate() {
+            if old != new {
+                Self::dac_changed(emitter, channel as u8, new).await?;
+                changed = true;
+            }
+        }
+        if changed {
+            self.dac_values_changed(emitter).await?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(args.read_timeout),
+        write_timeout: Duration::from_millis(args.write_timeout),
+    };
+    let alarms = ChannelAlarms::from_thresholds(&args.alarms)?.with_policy(args.alarm_policy);
+    let targets = std::iter::once(args.target.clone()).chain(args.failover.iter().cloned());
+    let client = DacClient::connect(
+        targets.collect(),
+        options,
+        Codec::new(args.crc, args.framing),
+    )
+    .with_context(|| format!("Failed to connect to {}", args.target))?
+    .with_on_failover(|target, e| eprintln!("Connection lost ({}), failed over to {}", e, target))
+    .with_alarms(alarms);
+    println!(
+        "Connected via {} to {}",
+        client.kind(),
+        client.target().unwrap_or(&args.target)
+    );
+    let client: SharedClient = Arc::new(Mutex::new(client));
+
+    let dac = Dac {
+        client: client.clone(),
+        verbose: args.verbose,
+    };
+    let builder = match args.bus {
+        Bus::Session => connection::Builder::session()?,
+        Bus::System => connection::Builder::system()?,
+    };
+    let _connection = builder
+        .name(SERVICE_NAME)?
+        .serve_at(OBJECT_PATH, dac)?
+        .build()
+        .with_context(|| {
+            format!(
+                "Failed to register {} on the {:?} bus",
+                SERVICE_NAME, args.bus
+            )
+        })?;
+    println!("Serving {} at {}", SERVICE_NAME, OBJECT_PATH);
+
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    let shutdown_flag_clone = shutdown_flag.clone();
+    ctrlc::set_handler(move || {
+        println!("\nReceived interrupt signal, shutting down...");
+        shutdown_flag_clone.store(true, Ordering::Relaxed);
+    })?;
+
+    // Method calls run on the connection's own thread; this one keeps the device alive
+    let interval = Duration::from_secs(args.keepalive_interval.max(1));
+    let mut last_keepalive = Instant::now();
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(100));
+        repeats::eprint_due();
+        if last_keepalive.elapsed() >= interval {
+            last_keepalive = Instant::now();
+            if let Err(e) = client.lock().unwrap().send(Command::KeepAlive) {
+                repeats::eprint(format!("Keepalive failed: {}", e));
+            }
+        }
+    }
+    repeats::eprint_flush();
+    Ok(())
+}
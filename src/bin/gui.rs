@@ -0,0 +1,497 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use eframe::egui;
+use egui_plot::{Legend, Line, Plot};
+use serialtest::cancel::CancellationToken;
+use serialtest::device::DeviceState;
+use serialtest::diagnose::Failure;
+use serialtest::error::DacError;
+use serialtest::framing::{Codec, StreamFraming};
+use serialtest::progress::Progress;
+use serialtest::protocol::{self, Command, DAC_CHANNELS, GPIO_PINS, TABLES, TABLE_LEN};
+use serialtest::stream::CommandStream;
+use serialtest::target::Target;
+use serialtest::transport::{self, Link, LinkOptions};
+use serialtest::version;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Desktop DAC control panel
+#[derive(Parser, Debug)]
+#[command(name = "gui")]
+#[command(
+    about = "Desktop DAC control panel with sliders, GPIO switches, tables and a waveform preview"
+)]
+struct Args {
+    /// Connection target: serial device path, network address (IPv4:port, [IPv6]:port) or
+    /// serial:, pty:, tcp:, udp:, tls: or scheme:// target
+    target: Target,
+
+    /// Read timeout in milliseconds
+    #[arg(long, default_value = "200")]
+    read_timeout: u64,
+
+    /// Write timeout in milliseconds
+    #[arg(long, default_value = "1000")]
+    write_timeout: u64,
+
+    /// Keepalive interval in seconds
+    #[arg(long, default_value = "5")]
+    keepalive_interval: u64,
+
+    /// Keep up to N commands awaiting their response
+    #[arg(long, value_name = "N", default_value = "1")]
+    window: usize,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+}
+
+/// Work for the link thread
+enum Request {
+    Send(Vec<u8>),
+    /// Table upload, stopped through `token` and then undone with `cleanup`
+    Upload {
+        commands: Vec<u8>,
+        cleanup: Vec<u8>,
+        token: CancellationToken,
+    },
+}
+
+/// News from the link thread
+enum Report {
+    Response(Vec<u8>),
+    Progress(Progress),
+    /// An upload ended; `Err(Cancelled)` after its cleanup was sent
+    UploadDone(std::result::Result<(), DacError>),
+    Error(String),
+}
+
+/// Table contents the upload buttons generate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Ramp,
+    Sine,
+    Triangle,
+}
+
+impl Shape {
+    const ALL: [Shape; 3] = [Shape::Ramp, Shape::Sine, Shape::Triangle];
+
+    fn name(self) -> &'static str {
+        match self {
+            Shape::Ramp => "Ramp",
+            Shape::Sine => "Sine",
+            Shape::Triangle => "Triangle",
+        }
+    }
+
+    fn table(self) -> Vec<u16> {
+        (0..TABLE_LEN)
+            .map(|i| {
+                let x = i as f64 / TABLE_LEN as f64;
+                let y = match self {
+                    Shape::Ramp => x,
+                    Shape::Sine => 0.5 - 0.5 * (x * std::f64::consts::TAU).cos(),
+                    Shape::Triangle => 1.0 - (2.0 * x - 1.0).abs(),
+                };
+                (y * 65535.0).round() as u16
+            })
+            .collect()
+    }
+}
+
+/// Upload in progress
+struct Upload {
+    table: u8,
+    token: CancellationToken,
+    /// Commands restoring the previous contents, applied to the shadow on cancel
+    cleanup: Vec<u8>,
+    progress: Option<Progress>,
+}
+
+struct GuiApp {
+    target: String,
+    requests: mpsc::Sender<Request>,
+    reports: mpsc::Receiver<Report>,
+    /// What the device should be doing, from every command sent
+    shadow: DeviceState,
+    /// Slider positions; differ from the shadow for table-driven channels
+    dac: [u16; DAC_CHANNELS],
+    selected_table: u8,
+    shape: Shape,
+    upload: Option<Upload>,
+    last_response: String,
+    status: String,
+}
+
+impl GuiApp {
+    /// Send `commands` and apply them to the shadow state
+    fn send(&mut self, commands: &[Command]) {
+        for cmd in commands {
+            self.shadow.apply(cmd);
+        }
+        let bytes = commands.iter().flat_map(Command::encode).collect();
+        if self.requests.send(Request::Send(bytes)).is_err() {
+            self.status = "Link thread stopped".to_string();
+        }
+    }
+
+    fn start_upload(&mut self) {
+        let table = self.selected_table;
+        let previous = self.shadow.tables[table as usize];
+        let token = CancellationToken::new();
+        let cleanup = protocol::table_upload(table, &previous);
+        let request = Request::Upload {
+            commands: protocol::table_upload(table, &self.shape.table()),
+            cleanup: cleanup.clone(),
+            token: token.clone(),
+        };
+        if self.requests.send(request).is_ok() {
+            self.status = format!("Uploading {} to table {}", self.shape.name(), table);
+            self.upload = Some(Upload {
+                table,
+                token,
+                cleanup,
+                progress: None,
+            });
+        }
+    }
+
+    fn handle_reports(&mut self) {
+        while let Ok(report) = self.reports.try_recv() {
+            match report {
+                Report::Response(response) => {
+                    self.last_response = match protocol::check_status(&response) {
+                        Ok(()) => format!("{:02x?}", response),
+                        Err(e) => format!("{:02x?} ({})", response, e),
+                    };
+                }
+                Report::Progress(progress) => {
+                    // Table entries reach the shadow as they are sent; other
+                    // commands were applied when they were requested
+                    if let Some(upload) = &mut self.upload {
+                        if let Some(cmd) = &progress.current {
+                            self.shadow.apply(cmd);
+                        }
+                        upload.progress = Some(progress);
+                    }
+                }
+                Report::UploadDone(result) => {
+                    let Some(upload) = self.upload.take() else {
+                        continue;
+                    };
+                    self.status = match result {
+                        Ok(()) => format!("Table {} uploaded", upload.table),
+                        Err(DacError::Cancelled) => {
+                            for cmd in upload.cleanup.chunks(4).filter_map(Command::decode) {
+                                self.shadow.apply(&cmd);
+                            }
+                            format!("Upload cancelled, table {} restored", upload.table)
+                        }
+                        Err(e) => format!("Upload to table {} failed: {}", upload.table, e),
+                    };
+                }
+                Report::Error(e) => self.status = format!("Error: {}", e),
+            }
+        }
+    }
+
+    fn dac_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for ch in 0..DAC_CHANNELS {
+                ui.vertical(|ui| {
+                    ui.label(format!("DAC{}", ch));
+                    let slider = egui::Slider::new(&mut self.dac[ch], 0..=u16::MAX)
+                        .vertical()
+                        .show_value(false);
+                    let mut changed = ui.add_sized([40.0, 160.0], slider).changed();
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut self.dac[ch]).hexadecimal(4, false, true))
+                        .changed();
+                    if changed {
+                        let value = self.dac[ch];
+                        self.send(&[Command::DacWrite {
+                            channel: ch as u8,
+                            value,
+                        }]);
+                    }
+
+                    let attached = self.shadow.attached[ch];
+                    let mut choice = attached;
+                    egui::ComboBox::from_id_salt(("attach", ch))
+                        .width(60.0)
+                        .selected_text(match choice {
+                            Some(table) => format!("T{}", table),
+                            None => "direct".to_string(),
+                        })
+                        .show_ui(ui, |ui| {
+                            for table in 0..TABLES as u8 {
+                                ui.selectable_value(
+                                    &mut choice,
+                                    Some(table),
+                                    format!("T{}", table),
+                                );
+                            }
+                        });
+                    if choice != attached {
+                        if let Some(table) = choice {
+                            self.send(&[Command::AttachTable {
+                                channel: ch as u8,
+                                table,
+                            }]);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    fn gpio_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for pin in 0..GPIO_PINS {
+                let mut on = self.shadow.gpio[pin];
+                if ui.toggle_value(&mut on, format!("GPIO{}", pin)).changed() {
+                    self.send(&[Command::Gpio { pin: pin as u8, on }]);
+                }
+            }
+            ui.separator();
+            if ui.button("Keepalive").clicked() {
+                self.send(&[Command::KeepAlive]);
+            }
+            if ui.button("LDAC").clicked() {
+                self.send(&[Command::Ldac]);
+            }
+        });
+    }
+
+    fn table_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut offset = self.shadow.table_offset;
+            if ui
+                .add(egui::Slider::new(&mut offset, 0..=u8::MAX).text("Table offset"))
+                .changed()
+            {
+                self.send(&[Command::UseTable { offset }]);
+            }
+            ui.separator();
+
+            egui::ComboBox::from_id_salt("table")
+                .selected_text(format!("Table {}", self.selected_table))
+                .show_ui(ui, |ui| {
+                    for table in 0..TABLES as u8 {
+                        ui.selectable_value(
+                            &mut self.selected_table,
+                            table,
+                            format!("Table {}", table),
+                        );
+                    }
+                });
+            egui::ComboBox::from_id_salt("shape")
+                .selected_text(self.shape.name())
+                .show_ui(ui, |ui| {
+                    for shape in Shape::ALL {
+                        ui.selectable_value(&mut self.shape, shape, shape.name());
+                    }
+                });
+            match &self.upload {
+                Some(upload) => {
+                    if ui.button("Cancel").clicked() {
+                        upload.token.cancel();
+                    }
+                    let (ratio, text) = match &upload.progress {
+                        Some(progress) => (progress.ratio() as f32, progress.to_string()),
+                        None => (0.0, "Waiting".to_string()),
+                    };
+                    ui.add(egui::ProgressBar::new(ratio).text(text));
+                }
+                None => {
+                    if ui.button("Upload").clicked() {
+                        self.start_upload();
+                    }
+                }
+            }
+        });
+
+        Plot::new("tables")
+            .height(160.0)
+            .include_y(0.0)
+            .include_y(65535.0)
+            .legend(Legend::default())
+            .show(ui, |plot| {
+                for (table, values) in self.shadow.tables.iter().enumerate() {
+                    let points: Vec<[f64; 2]> = values
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &v)| [i as f64, v as f64])
+                        .collect();
+                    plot.line(Line::new(format!("Table {}", table), points));
+                }
+            });
+    }
+
+    /// Every channel's output as the table offset sweeps 0-255
+    fn waveform_panel(&self, ui: &mut egui::Ui) {
+        Plot::new("waveform")
+            .height(160.0)
+            .include_y(0.0)
+            .include_y(65535.0)
+            .legend(Legend::default())
+            .show(ui, |plot| {
+                for ch in 0..DAC_CHANNELS {
+                    let points: Vec<[f64; 2]> = (0..TABLE_LEN)
+                        .map(|offset| {
+                            let value = match self.shadow.attached[ch] {
+                                Some(table) => self.shadow.tables[table as usize][offset],
+                                None => self.shadow.dac[ch],
+                            };
+                            [offset as f64, value as f64]
+                        })
+                        .collect();
+                    plot.line(Line::new(format!("DAC{}", ch), points));
+                }
+            });
+    }
+}
+
+impl eframe::App for GuiApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_reports();
+
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(&self.target);
+                ui.separator();
+                ui.label(format!("Response: {}", self.last_response));
+                ui.separator();
+                ui.label(&self.status);
+            });
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.heading("DAC outputs");
+                self.dac_panel(ui);
+                ui.separator();
+                ui.heading("GPIO");
+                self.gpio_panel(ui);
+                ui.separator();
+                ui.heading("Tables");
+                self.table_panel(ui);
+                ui.separator();
+                ui.heading("Waveform preview (output per table offset)");
+                self.waveform_panel(ui);
+            });
+        });
+    }
+}
+
+/// Send requests in order, with a keepalive whenever one is due
+fn run_link_thread(
+    mut stream: CommandStream<Box<dyn Link>>,
+    requests: mpsc::Receiver<Request>,
+    reports: mpsc::Sender<Report>,
+    ctx: egui::Context,
+    keepalive: Duration,
+) {
+    let mut last_keepalive = Instant::now();
+    loop {
+        let request =
+            match requests.recv_timeout(keepalive.saturating_sub(last_keepalive.elapsed())) {
+                Ok(request) => request,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    Request::Send(Command::KeepAlive.encode().to_vec())
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+
+        let result = match request {
+            Request::Send(commands) => {
+                if commands
+                    .chunks(4)
+                    .any(|cmd| cmd.first() == Some(&protocol::CMD_KEEPALIVE))
+                {
+                    last_keepalive = Instant::now();
+                }
+                stream.enqueue(&commands);
+                stream.flush_queue()
+            }
+            Request::Upload {
+                commands,
+                cleanup,
+                token,
+            } => {
+                stream.enqueue(&commands);
+                let result = stream.flush_queue_cancellable(&token, &cleanup);
+                let _ = reports.send(Report::UploadDone(result));
+                Ok(())
+            }
+        };
+
+        for response in stream.take_responses() {
+            let _ = reports.send(Report::Response(response));
+        }
+        if let Err(e) = result {
+            let _ = reports.send(Report::Error(e.to_string()));
+        }
+        ctx.request_repaint();
+    }
+}
+
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(args.read_timeout),
+        write_timeout: Duration::from_millis(args.write_timeout),
+    };
+    let link = transport::open_target(&args.target, &options)
+        .with_context(|| format!("Failed to connect to {}", args.target))?;
+    let target = format!("{} via {}", args.target, link.kind());
+    let keepalive = Duration::from_secs(args.keepalive_interval.max(1));
+
+    let (request_tx, request_rx) = mpsc::channel();
+    let (report_tx, report_rx) = mpsc::channel();
+
+    let app = GuiApp {
+        target,
+        requests: request_tx,
+        reports: report_rx,
+        shadow: DeviceState::new(),
+        dac: [0; DAC_CHANNELS],
+        selected_table: 0,
+        shape: Shape::Sine,
+        upload: None,
+        last_response: "none yet".to_string(),
+        status: "Connected".to_string(),
+    };
+
+    let native_options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([900.0, 820.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "csv1-ol8 DAC control",
+        native_options,
+        Box::new(move |cc| {
+            // The link thread needs the context to wake the window on news
+            let ctx = cc.egui_ctx.clone();
+            let progress_tx = report_tx.clone();
+            let progress_ctx = ctx.clone();
+            let stream = CommandStream::new(link, Codec::new(args.crc, args.framing), args.window)
+                .with_keepalive(keepalive)
+                .with_progress(move |progress| {
+                    let _ = progress_tx.send(Report::Progress(progress.clone()));
+                    progress_ctx.request_repaint();
+                });
+            thread::spawn(move || run_link_thread(stream, request_rx, report_tx, ctx, keepalive));
+            Ok(Box::new(app))
+        }),
+    )
+    .map_err(|e| anyhow!("GUI failed: {}", e).into())
+}
@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use clap::builder::BoolishValueParser;
+use clap::Parser;
+use serialtest::client::DacClient;
+use serialtest::diagnose::Failure;
+use serialtest::framing::{Codec, StreamFraming};
+use serialtest::modbus::{Exception, Frame, Request};
+use serialtest::protocol::Command;
+use serialtest::repeats;
+use serialtest::target::Target;
+use serialtest::transport::LinkOptions;
+use serialtest::version;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Modbus TCP server for a DAC
+#[derive(Parser, Debug)]
+#[command(name = "modbus_server")]
+#[command(about = "Expose a DAC device as a Modbus TCP server")]
+struct Args {
+    /// Connection target: serial device path, network address (IPv4:port, [IPv6]:port) or
+    /// serial:, pty:, tcp:, udp:, tls: or scheme:// target
+    #[arg(env = "CSV1_TARGET")]
+    target: Target,
+
+    /// Backup target to switch to when the connection dies, restoring the device state
+    /// (repeatable, tried in order)
+    #[arg(long = "failover", value_name = "TARGET")]
+    failover: Vec<Target>,
+
+    /// Address to accept Modbus TCP connections on
+    #[arg(short, long, default_value = "0.0.0.0:502", env = "CSV1_LISTEN")]
+    listen: SocketAddr,
+
+    /// Only answer requests for this unit id (default: any)
+    #[arg(long)]
+    unit: Option<u8>,
+
+    /// Read timeout in milliseconds
+    #[arg(long, default_value = "200", env = "CSV1_READ_TIMEOUT")]
+    read_timeout: u64,
+
+    /// Write timeout in milliseconds
+    #[arg(long, default_value = "1000", env = "CSV1_WRITE_TIMEOUT")]
+    write_timeout: u64,
+
+    /// Keepalive interval in seconds
+    #[arg(long, default_value = "5")]
+    keepalive_interval: u64,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Log every request
+    #[arg(short, long, env = "CSV1_VERBOSE", value_parser = BoolishValueParser::new())]
+    verbose: bool,
+}
+
+type SharedDevice = Arc<Mutex<DacClient>>;
+
+/// The response PDU to request `pdu`
+fn handle(device: &mut DacClient, pdu: &[u8], verbose: bool) -> Vec<u8> {
+    let function = pdu.first().copied().unwrap_or(0);
+    let request = match Request::decode(pdu) {
+        Ok(request) => request,
+        Err(e) => return e.response(function),
+    };
+    if verbose {
+        println!("{:?}", request);
+    }
+    let commands = match request.commands() {
+        Ok(commands) => commands,
+        Err(e) => return e.response(function),
+    };
+    for cmd in commands {
+        if let Err(e) = device.send(cmd) {
+            repeats::eprint(format!("{} failed: {}", cmd, e));
+            return Exception::ServerDeviceFailure.response(function);
+        }
+    }
+    request.response(device.state())
+}
+
+fn handle_client(mut socket: TcpStream, device: SharedDevice, args: &Args) -> Result<()> {
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let n = socket.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&buf[..n]);
+        while let Some(frame) = Frame::take(&mut pending)? {
+            if args.unit.is_some_and(|unit| unit != frame.unit) {
+                continue;
+            }
+            let pdu = handle(&mut device.lock().unwrap(), &frame.pdu, args.verbose);
+            socket.write_all(&frame.reply(pdu).encode())?;
+        }
+    }
+}
+
+fn main() -> Result<(), Failure> {
+    let args = Arc::new(version::parse_args::<Args>());
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(args.read_timeout),
+        write_timeout: Duration::from_millis(args.write_timeout),
+    };
+    let targets = std::iter::once(args.target.clone()).chain(args.failover.iter().cloned());
+    let client = DacClient::connect(
+        targets.collect(),
+        options,
+        Codec::new(args.crc, args.framing),
+    )
+    .with_context(|| format!("Failed to connect to {}", args.target))?
+    .with_on_failover(|target, e| eprintln!("Connection lost ({}), failed over to {}", e, target));
+    println!(
+        "Connected via {} to {}",
+        client.kind(),
+        client.target().unwrap_or(&args.target)
+    );
+    let device: SharedDevice = Arc::new(Mutex::new(client));
+
+    let listener = TcpListener::bind(args.listen)
+        .with_context(|| format!("Failed to bind to {}", args.listen))?;
+    println!("Modbus TCP server listening on {}", args.listen);
+    {
+        let device = device.clone();
+        let args = args.clone();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let socket = match socket {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        repeats::eprint(format!("Accept failed: {}", e));
+                        continue;
+                    }
+                };
+                let peer = socket
+                    .peer_addr()
+                    .map_or_else(|_| "?".to_string(), |a| a.to_string());
+                println!("Modbus client {} connected", peer);
+                let device = device.clone();
+                let args = args.clone();
+                thread::spawn(move || match handle_client(socket, device, &args) {
+                    Ok(()) => println!("Modbus client {} disconnected", peer),
+                    Err(e) => eprintln!("Modbus client {} dropped: {}", peer, e),
+                });
+            }
+        });
+    }
+
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    let shutdown_flag_clone = shutdown_flag.clone();
+    ctrlc::set_handler(move || {
+        println!("\nReceived interrupt signal, shutting down...");
+        shutdown_flag_clone.store(true, Ordering::Relaxed);
+    })?;
+
+    // Requests are served on their own threads; this one keeps the device alive
+    let interval = Duration::from_secs(args.keepalive_interval.max(1));
+    let mut last_keepalive = Instant::now();
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(100));
+        repeats::eprint_due();
+        if last_keepalive.elapsed() >= interval {
+            last_keepalive = Instant::now();
+            if let Err(e) = device.lock().unwrap().send(Command::KeepAlive) {
+                repeats::eprint(format!("Keepalive failed: {}", e));
+            }
+        }
+    }
+    repeats::eprint_flush();
+    Ok(())
+}
@@ -0,0 +1,236 @@
+use anyhow::{Context, Result};
+use clap::builder::BoolishValueParser;
+use clap::Parser;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serialtest::client::DacClient;
+use serialtest::diagnose::Failure;
+use serialtest::framing::{Codec, StreamFraming};
+use serialtest::mqtt::{self, Topics};
+use serialtest::protocol::Command;
+use serialtest::repeats;
+use serialtest::target::Target;
+use serialtest::transport::LinkOptions;
+use serialtest::version;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// MQTT bridge for a DAC
+#[derive(Parser, Debug)]
+#[command(name = "mqtt_bridge")]
+#[command(about = "Publish a DAC device over MQTT, with Home Assistant discovery")]
+struct Args {
+    /// Connection target: serial device path, network address (IPv4:port, [IPv6]:port) or
+    /// serial:, pty:, tcp:, udp:, tls: or scheme:// target
+    #[arg(env = "CSV1_TARGET")]
+    target: Target,
+
+    /// Backup target to switch to when the connection dies, restoring the device state
+    /// (repeatable, tried in order)
+    #[arg(long = "failover", value_name = "TARGET")]
+    failover: Vec<Target>,
+
+    /// MQTT broker host
+    #[arg(long, default_value = "localhost", env = "CSV1_MQTT_BROKER")]
+    broker: String,
+
+    /// MQTT broker port
+    #[arg(long, default_value = "1883")]
+    port: u16,
+
+    /// Broker user name
+    #[arg(long, env = "CSV1_MQTT_USERNAME")]
+    username: Option<String>,
+
+    /// Broker password or access token; prefer the variable, the command line is visible
+    /// to other users
+    #[arg(long, env = "CSV1_MQTT_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Topic prefix; topics are <prefix>/<device id>/...
+    #[arg(long, default_value = "csv1")]
+    prefix: String,
+
+    /// Device id, unique per device on the broker
+    #[arg(long, default_value = "ol8")]
+    device_id: String,
+
+    /// Home Assistant discovery prefix
+    #[arg(long, default_value = "homeassistant")]
+    discovery_prefix: String,
+
+    /// Do not publish Home Assistant discovery configs
+    #[arg(long)]
+    no_discovery: bool,
+
+    /// Read timeout in milliseconds
+    #[arg(long, default_value = "200", env = "CSV1_READ_TIMEOUT")]
+    read_timeout: u64,
+
+    /// Write timeout in milliseconds
+    #[arg(long, default_value = "1000", env = "CSV1_WRITE_TIMEOUT")]
+    write_timeout: u64,
+
+    /// Keepalive interval in seconds
+    #[arg(long, default_value = "5")]
+    keepalive_interval: u64,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Log every command received
+    #[arg(short, long, env = "CSV1_VERBOSE", value_parser = BoolishValueParser::new())]
+    verbose: bool,
+}
+
+/// Events from the MQTT connection thread
+enum Incoming {
+    /// (Re)connected to the broker
+    Connected,
+    Message(String, Vec<u8>),
+}
+
+fn publish_all(client: &Client, messages: Vec<(String, String)>) -> Result<()> {
+    for (topic, payload) in messages {
+        client.publish(topic, QoS::AtLeastOnce, true, payload)?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(args.read_timeout),
+        write_timeout: Duration::from_millis(args.write_timeout),
+    };
+    let targets = std::iter::once(args.target.clone()).chain(args.failover.iter().cloned());
+    let mut device = DacClient::connect(
+        targets.collect(),
+        options,
+        Codec::new(args.crc, args.framing),
+    )
+    .with_context(|| format!("Failed to connect to {}", args.target))?
+    .with_on_failover(|target, e| eprintln!("Connection lost ({}), failed over to {}", e, target));
+    println!(
+        "Connected via {} to {}",
+        device.kind(),
+        device.target().unwrap_or(&args.target)
+    );
+
+    let topics = Topics::new(&args.prefix, &args.device_id, &args.discovery_prefix);
+    let mut mqtt_options = MqttOptions::new(
+        format!("serialtest-{}", args.device_id),
+        &args.broker,
+        args.port,
+    );
+    mqtt_options
+        .set_keep_alive(Duration::from_secs(30))
+        .set_last_will(LastWill::new(
+            topics.availability(),
+            mqtt::OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+    if let Some(username) = &args.username {
+        mqtt_options.set_credentials(username, args.password.clone().unwrap_or_default());
+    }
+    let (client, mut connection) = Client::new(mqtt_options, 64);
+
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    let shutdown_flag_clone = shutdown_flag.clone();
+    ctrlc::set_handler(move || {
+        println!("\nReceived interrupt signal, shutting down...");
+        shutdown_flag_clone.store(true, Ordering::Relaxed);
+    })?;
+
+    // The connection only makes progress while it is iterated, so it gets its own thread
+    let (event_tx, event_rx) = mpsc::channel();
+    let broker = format!("{}:{}", args.broker, args.port);
+    let stopping = shutdown_flag.clone();
+    thread::spawn(move || {
+        for event in connection.iter() {
+            let incoming = match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => Incoming::Connected,
+                Ok(Event::Incoming(Packet::Publish(p))) => {
+                    Incoming::Message(p.topic, p.payload.to_vec())
+                }
+                Ok(_) => continue,
+                // The broker closes the connection after our disconnect
+                Err(_) if stopping.load(Ordering::Relaxed) => break,
+                Err(e) => {
+                    repeats::eprint(format!("MQTT connection to {} failed: {}", broker, e));
+                    thread::sleep(Duration::from_secs(2));
+                    continue;
+                }
+            };
+            if event_tx.send(incoming).is_err() {
+                break;
+            }
+        }
+    });
+
+    let interval = Duration::from_secs(args.keepalive_interval.max(1));
+    let mut last_keepalive = Instant::now();
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        match event_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(Incoming::Connected) => {
+                println!("Connected to MQTT broker {}:{}", args.broker, args.port);
+                client.subscribe(topics.commands(), QoS::AtLeastOnce)?;
+                client.subscribe(topics.ha_status(), QoS::AtLeastOnce)?;
+                client.publish(topics.availability(), QoS::AtLeastOnce, true, mqtt::ONLINE)?;
+                if !args.no_discovery {
+                    publish_all(&client, topics.discovery())?;
+                }
+                publish_all(&client, topics.states(device.state()))?;
+            }
+            Ok(Incoming::Message(topic, payload)) if topic == topics.ha_status() => {
+                // Home Assistant asks for discovery again whenever it restarts
+                if payload == mqtt::ONLINE.as_bytes() && !args.no_discovery {
+                    publish_all(&client, topics.discovery())?;
+                }
+            }
+            Ok(Incoming::Message(topic, payload)) => {
+                match topics.parse_command(&topic, &payload) {
+                    Some(Ok(cmd)) => {
+                        if args.verbose {
+                            println!("{}: {}", topic, cmd);
+                        }
+                        if let Err(e) = device.send(cmd) {
+                            repeats::eprint(format!("{} failed: {}", cmd, e));
+                        }
+                        // Publish even on failure, so the entity snaps back to the real state
+                        publish_all(
+                            &client,
+                            topics.state_of(&cmd, device.state()).into_iter().collect(),
+                        )?;
+                    }
+                    Some(Err(e)) => eprintln!("{}", e),
+                    None => {}
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        repeats::eprint_due();
+        if last_keepalive.elapsed() >= interval {
+            last_keepalive = Instant::now();
+            if let Err(e) = device.send(Command::KeepAlive) {
+                repeats::eprint(format!("Keepalive failed: {}", e));
+            }
+        }
+    }
+
+    repeats::eprint_flush();
+    // A clean disconnect does not trigger the last will
+    client.publish(topics.availability(), QoS::AtLeastOnce, true, mqtt::OFFLINE)?;
+    client.disconnect()?;
+    thread::sleep(Duration::from_millis(200));
+    Ok(())
+}
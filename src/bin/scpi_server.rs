@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use clap::builder::BoolishValueParser;
+use clap::Parser;
+use serialtest::client::DacClient;
+use serialtest::diagnose::Failure;
+use serialtest::framing::{Codec, StreamFraming};
+use serialtest::protocol::Command;
+use serialtest::repeats;
+use serialtest::scpi::{Scpi, ScpiError};
+use serialtest::target::Target;
+use serialtest::transport::LinkOptions;
+use serialtest::units::VoltageRange;
+use serialtest::version;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Errors kept per connection before `SYSTem:ERRor?` reports an overflow
+const ERROR_QUEUE_LEN: usize = 16;
+
+/// SCPI text command server for a DAC
+#[derive(Parser, Debug)]
+#[command(name = "scpi_server")]
+#[command(about = "Accept SCPI-style text commands for a DAC device")]
+struct Args {
+    /// Connection target: serial device path, network address (IPv4:port, [IPv6]:port) or
+    /// serial:, pty:, tcp:, udp:, tls: or scheme:// target
+    #[arg(env = "CSV1_TARGET")]
+    target: Target,
+
+    /// Backup target to switch to when the connection dies, restoring the device state
+    /// (repeatable, tried in order)
+    #[arg(long = "failover", value_name = "TARGET")]
+    failover: Vec<Target>,
+
+    /// Address to accept connections on (5025 is the usual SCPI socket port)
+    #[arg(short, long, default_value = "0.0.0.0:5025", env = "CSV1_LISTEN")]
+    listen: SocketAddr,
+
+    /// Output voltage at code 0
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    vmin: f64,
+
+    /// Output voltage at code 65535
+    #[arg(long, default_value = "10", allow_negative_numbers = true)]
+    vmax: f64,
+
+    /// Read timeout in milliseconds
+    #[arg(long, default_value = "200", env = "CSV1_READ_TIMEOUT")]
+    read_timeout: u64,
+
+    /// Write timeout in milliseconds
+    #[arg(long, default_value = "1000", env = "CSV1_WRITE_TIMEOUT")]
+    write_timeout: u64,
+
+    /// Keepalive interval in seconds
+    #[arg(long, default_value = "5")]
+    keepalive_interval: u64,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Log every command line
+    #[arg(short, long, env = "CSV1_VERBOSE", value_parser = BoolishValueParser::new())]
+    verbose: bool,
+}
+
+type SharedDevice = Arc<Mutex<DacClient>>;
+
+/// One connection: its own error queue
+struct Session {
+    device: SharedDevice,
+    range: VoltageRange,
+    errors: VecDeque<ScpiError>,
+}
+
+impl Session {
+    fn push_error(&mut self, error: ScpiError) {
+        if self.errors.len() >= ERROR_QUEUE_LEN {
+            self.errors.pop_back();
+            self.errors.push_back(ScpiError::QUEUE_OVERFLOW);
+        } else {
+            self.errors.push_back(error);
+        }
+    }
+
+    /// Run one command; `Some` answer for queries
+    fn execute(&mut self, command: &str) -> Result<Option<String>, ScpiError> {
+        let cmd = Scpi::parse(command)?;
+        match cmd {
+            Scpi::ClearErrors => self.errors.clear(),
+            Scpi::NextError => {
+                let error = self.errors.pop_front().unwrap_or(ScpiError::NONE);
+                return Ok(Some(error.to_string()));
+            }
+            _ => {}
+        }
+        let commands = cmd.commands(&self.range)?;
+        let mut device = self.device.lock().unwrap();
+        for command in commands {
+            if let Err(e) = device.send(command) {
+                repeats::eprint(format!("{} failed: {}", command, e));
+                return Err(ScpiError::HARDWARE);
+            }
+        }
+        Ok(cmd.response(device.state(), &self.range))
+    }
+
+    /// Run every command of `line`; the answers to its queries, joined by `;`
+    fn execute_line(&mut self, line: &str) -> Option<String> {
+        let mut answers = Vec::new();
+        for command in line.split(';').filter(|c| !c.trim().is_empty()) {
+            match self.execute(command) {
+                Ok(Some(answer)) => answers.push(answer),
+                Ok(None) => {}
+                Err(e) => self.push_error(e),
+            }
+        }
+        (!answers.is_empty()).then(|| answers.join(";"))
+    }
+}
+
+fn handle_client(
+    socket: TcpStream,
+    device: SharedDevice,
+    range: VoltageRange,
+    args: &Args,
+) -> Result<()> {
+    let mut session = Session {
+        device,
+        range,
+        errors: VecDeque::new(),
+    };
+    let mut writer = socket.try_clone()?;
+    for line in BufReader::new(socket).lines() {
+        let line = line?;
+        if args.verbose {
+            println!("> {}", line.trim());
+        }
+        if let Some(answer) = session.execute_line(&line) {
+            if args.verbose {
+                println!("< {}", answer);
+            }
+            writeln!(writer, "{}", answer)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Failure> {
+    let args = Arc::new(version::parse_args::<Args>());
+    let range = VoltageRange::new(args.vmin, args.vmax).context("Bad --vmin/--vmax")?;
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(args.read_timeout),
+        write_timeout: Duration::from_millis(args.write_timeout),
+    };
+    let targets = std::iter::once(args.target.clone()).chain(args.failover.iter().cloned());
+    let client = DacClient::connect(
+        targets.collect(),
+        options,
+        Codec::new(args.crc, args.framing),
+    )
+    .with_context(|| format!("Failed to connect to {}", args.target))?
+    .with_on_failover(|target, e| eprintln!("Connection lost ({}), failed over to {}", e, target));
+    println!(
+        "Connected via {} to {}",
+        client.kind(),
+        client.target().unwrap_or(&args.target)
+    );
+    let device: SharedDevice = Arc::new(Mutex::new(client));
+
+    let listener = TcpListener::bind(args.listen)
+        .with_context(|| format!("Failed to bind to {}", args.listen))?;
+    println!("SCPI server listening on {}", args.listen);
+    {
+        let device = device.clone();
+        let args = args.clone();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let socket = match socket {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        repeats::eprint(format!("Accept failed: {}", e));
+                        continue;
+                    }
+                };
+                let peer = socket
+                    .peer_addr()
+                    .map_or_else(|_| "?".to_string(), |a| a.to_string());
+                println!("SCPI client {} connected", peer);
+                let device = device.clone();
+                let args = args.clone();
+                thread::spawn(move || match handle_client(socket, device, range, &args) {
+                    Ok(()) => println!("SCPI client {} disconnected", peer),
+                    Err(e) => eprintln!("SCPI client {} dropped: {}", peer, e),
+                });
+            }
+        });
+    }
+
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    let shutdown_flag_clone = shutdown_flag.clone();
+    ctrlc::set_handler(move || {
+        println!("\nReceived interrupt signal, shutting down...");
+        shutdown_flag_clone.store(true, Ordering::Relaxed);
+    })?;
+
+    // Connections are served on their own threads; this one keeps the device alive
+    let interval = Duration::from_secs(args.keepalive_interval.max(1));
+    let mut last_keepalive = Instant::now();
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(100));
+        repeats::eprint_due();
+        if last_keepalive.elapsed() >= interval {
+            last_keepalive = Instant::now();
+            if let Err(e) = device.lock().unwrap().send(Command::KeepAlive) {
+                repeats::eprint(format!("Keepalive failed: {}", e));
+            }
+        }
+    }
+    repeats::eprint_flush();
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use serialtest::channels::ChannelLinks;
+use serialtest::diagnose::Failure;
+use serialtest::framing::{Codec, StreamFraming};
+use serialtest::protocol::{self, DAC_CHANNELS, TABLE_LEN};
+use serialtest::sim::{self, SimConfig, SimState};
+use serialtest::stream::CommandStream;
+use serialtest::target::Target;
+use serialtest::transport::{self, LinkOptions};
+use serialtest::version;
+use std::io::Write;
+use std::net::TcpListener;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Sanity check of the whole crate without hardware
+#[derive(Parser, Debug)]
+#[command(name = "selftest")]
+#[command(
+    about = "Run the standard init and ramp sequence against an in-process simulator and check its state"
+)]
+struct Args {
+    /// Main-loop DAC writes to send
+    #[arg(long, default_value = "256")]
+    loops: u32,
+
+    /// Keep up to N commands awaiting their response
+    #[arg(long, value_name = "N", default_value = "1")]
+    window: usize,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Log every command the simulator receives
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// GPIO setup, init1 (table 0), init2 (table 1), init3 (table attachments)
+/// and three keepalives, as sent by `unified_test`
+const INIT: &[[u8; 4]] = &[
+    [0xfe, 0, 0, 1],
+    [0xfe, 1, 0, 1],
+    [16, 49, 0, 0],
+    [16, 50, 64, 0],
+    [16, 51, 128, 0],
+    [17, 49, 64, 0],
+    [17, 50, 128, 0],
+    [17, 51, 0, 0],
+    [0, 16, 0, 0],
+    [1, 17, 0, 0],
+    [2, 16, 0, 0],
+    [3, 17, 0, 0],
+    [4, 16, 0, 0],
+    [5, 17, 0, 0],
+    [6, 16, 0, 0],
+    [7, 17, 0, 0],
+    [0xfd, 0, 0, 0],
+    [0xfd, 0, 0, 0],
+    [0xfd, 0, 0, 0],
+];
+
+/// Prints each check as it is made and counts the failures
+#[derive(Default)]
+struct Checks {
+    run: usize,
+    failed: usize,
+}
+
+impl Checks {
+    fn check<T: PartialEq + std::fmt::Debug>(&mut self, name: &str, expected: T, actual: T) {
+        self.run += 1;
+        if expected == actual {
+            println!("  ok    {}", name);
+        } else {
+            self.failed += 1;
+            println!(
+                "  FAIL  {}: expected {:?}, got {:?}",
+                name, expected, actual
+            );
+        }
+    }
+}
+
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
+
+    // Simulator on an ephemeral local port
+    let listener = TcpListener::bind("127.0.0.1:0").context("Failed to start the simulator")?;
+    let address = listener.local_addr()?.to_string();
+    let config = SimConfig {
+        verbose: args.verbose,
+        crc: args.crc,
+        framing: args.framing,
+        behaviors: Arc::new(Vec::new()),
+        state: Arc::new(Mutex::new(SimState::default())),
+        dashboard: false,
+    };
+    let state = config.state.clone();
+    thread::spawn(move || sim::serve(listener, config, Arc::new(AtomicBool::new(true))));
+    println!("Simulator listening on {}", address);
+
+    // Client side: target parsing, transport and flow-controlled stream
+    let target: Target = format!("tcp:{}", address).parse()?;
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(200),
+        write_timeout: Duration::from_millis(1000),
+    };
+    let link = transport::open_target(&target, &options)?;
+    let codec = Codec::new(args.crc, args.framing);
+    let mut stream = CommandStream::new(link, codec, args.window).with_progress(|progress| {
+        print!("\r  {:<72}", progress.to_string());
+        let _ = std::io::stdout().flush();
+    });
+
+    println!("Sending init sequence ({} commands)...", INIT.len());
+    for cmd in INIT {
+        stream.send(cmd)?;
+    }
+
+    // Table 2 through the priority queue, as a bulk upload
+    println!("Uploading table 2 ({} entries)...", TABLE_LEN);
+    let table: Vec<u16> = (0..TABLE_LEN).map(|i| (i * 257) as u16).collect();
+    stream.enqueue(&protocol::table_upload(2, &table));
+    stream.flush_queue()?;
+    println!();
+
+    println!("Sending ramp ({} steps)...", args.loops);
+    let links = ChannelLinks::from_pairs(&[(4, 0), (5, 1), (6, 2), (7, 3)])?;
+    let mut expected_dac = [0u16; DAC_CHANNELS];
+    let mut dac_writes = 0u64;
+    let mut v: u16 = 0;
+    let mut c: u8 = 0;
+    for _ in 0..args.loops {
+        c = (c + 1) % DAC_CHANNELS as u8;
+        v = if v == u16::MAX {
+            0
+        } else {
+            v.saturating_add(511)
+        };
+        for (channel, value) in links.resolve(c, v) {
+            expected_dac[channel as usize] = value;
+        }
+        let batch = links.apply(&[c, 0, (v >> 8) as u8, v as u8]);
+        dac_writes += (batch.len() / 4) as u64;
+        stream.send(&batch)?;
+    }
+    stream
+        .drain()
+        .map_err(|e| anyhow!("Waiting for the last responses failed: {}", e))?;
+
+    println!("Checking...");
+    let sent = INIT.len() as u64 + TABLE_LEN as u64 + dac_writes;
+    let responses = stream.take_responses();
+    let mut checks = Checks::default();
+    checks.check("every command answered", sent, responses.len() as u64);
+    checks.check(
+        "every response OK",
+        0,
+        responses
+            .iter()
+            .filter(|r| protocol::check_status(r).is_err())
+            .count(),
+    );
+
+    let state = state.lock().unwrap();
+    let device = &state.device;
+    checks.check("commands applied", sent, device.commands);
+    checks.check(
+        "GPIO 0 and 1 on",
+        [true, true],
+        [device.gpio[0], device.gpio[1]],
+    );
+    checks.check(
+        "table 0 entries 49-51",
+        [0x0000, 0x4000, 0x8000],
+        [
+            device.tables[0][49],
+            device.tables[0][50],
+            device.tables[0][51],
+        ],
+    );
+    checks.check(
+        "table 1 entries 49-51",
+        [0x4000, 0x8000, 0x0000],
+        [
+            device.tables[1][49],
+            device.tables[1][50],
+            device.tables[1][51],
+        ],
+    );
+    checks.check("table 2 upload", table, device.tables[2].to_vec());
+    checks.check(
+        "table attachments",
+        [0, 1, 0, 1, 0, 1, 0, 1].map(Some),
+        device.attached,
+    );
+    checks.check("keepalives", 3, device.keepalives);
+    checks.check("DAC outputs", expected_dac, device.dac);
+
+    if checks.failed > 0 {
+        return Err(anyhow!("{} of {} checks failed", checks.failed, checks.run).into());
+    }
+    println!("Self-test passed ({} checks)", checks.run);
+    Ok(())
+}
@@ -1,17 +1,31 @@
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use std::collections::HashSet;
+use serialtest::channels::{parse_pair, ChannelLinks};
+use serialtest::desired::ExitState;
+use serialtest::diagnose::{self, Failure};
+use serialtest::events::EventLog;
+use serialtest::framing::{Codec, Padding, StreamFraming};
+use serialtest::logfile::Rotation;
+use serialtest::protocol::Command;
+use serialtest::report::{self, ReportFormat, Value};
+use serialtest::stats::{LatencyStats, TransportStats};
+use serialtest::stream::AckWindow;
+use serialtest::target::Target;
+use serialtest::transport::CoalescingWriter;
+use serialtest::version;
+use std::collections::{HashSet, VecDeque};
 use std::io::{Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
-use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 /// Robust TCP test program optimized for real device communication
 #[derive(Parser, Debug)]
 #[command(name = "tcp_robust_test")]
 #[command(about = "Robust TCP test program for real DAC devices")]
 struct Args {
-    /// TCP address (IPv4:port or [IPv6]:port)
-    address: String,
+    /// TCP address (IPv4:port, [IPv6]:port or tcp:host:port)
+    address: Target,
 
     /// Test rate in Hz
     #[arg(short, long, default_value = "10")]
@@ -41,6 +55,11 @@ struct Args {
     #[arg(long)]
     no_responses: bool,
 
+    /// Keep up to N commands awaiting their response, waiting for responses only when the device
+    /// falls behind (replaces --command-delay and the read after every command in the main loop)
+    #[arg(long, value_name = "N", conflicts_with_all = ["no_responses", "response_commands"])]
+    window: Option<usize>,
+
     /// Only read responses for specific commands (comma-separated hex values, e.g., "0xfe,0xfd")
     #[arg(long)]
     response_commands: Option<String>,
@@ -52,6 +71,80 @@ struct Args {
     /// Test duration in seconds (0 = infinite)
     #[arg(short, long, default_value = "0")]
     duration: u64,
+
+    /// Write a machine-readable report (counters, latency percentiles, configuration,
+    /// timestamps, version) to FILE; `.csv` files get one row per run appended
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Report format (default: from the file extension, JSON unless `.csv`)
+    #[arg(long, value_enum, requires = "report")]
+    report_format: Option<ReportFormat>,
+
+    /// Rotate CSV reports: size=N[K|M|G], age=N[s|m|h|d], keep=N, compress
+    #[arg(long, value_name = "SPEC", requires = "report")]
+    report_rotation: Option<Rotation>,
+
+    /// Main-loop commands sent before measuring starts; reported as their own phase
+    #[arg(long, value_name = "N", default_value = "0")]
+    warmup: u64,
+
+    /// After the main loop, return the device to idle (every DAC at 0, complements at
+    /// full scale, GPIO 0-1 off) and report it as its own phase
+    #[arg(long)]
+    cooldown: bool,
+
+    /// State to leave the device in when the test stops, even after a failure: zero
+    /// (every DAC at 0, every GPIO off), hold (as the test left it) or a state file
+    /// as read by `csv1 apply`
+    #[arg(long, value_name = "FILE|zero|hold", default_value = "hold")]
+    on_exit: ExitState,
+
+    /// Fail (exit code 3) if errors exceed PCT percent of the main phase's writes
+    #[arg(long, value_name = "PCT")]
+    max_error_rate: Option<f64>,
+
+    /// Fail (exit code 3) if timeouts exceed PCT percent of the main phase's writes
+    #[arg(long, value_name = "PCT")]
+    max_timeout_rate: Option<f64>,
+
+    /// Fail (exit code 3) if responses fall below PCT percent of the main phase's writes
+    #[arg(long, value_name = "PCT")]
+    min_response_rate: Option<f64>,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Trailing bytes that do not fill a 4-byte command: reject the batch, or zero-fill it
+    #[arg(long, value_enum, default_value = "reject")]
+    padding: Padding,
+
+    /// Validate channels, tables, GPIO pins and value fields before sending
+    #[arg(long)]
+    strict: bool,
+
+    /// Coalesce writes and send them at most this many milliseconds later (0 = send each at once);
+    /// pending writes always go out before a response is read
+    #[arg(long, value_name = "MS", default_value = "0")]
+    coalesce: u64,
+
+    /// Channel pairs where COMPLEMENT carries 65535 - MASTER (repeatable)
+    #[arg(
+        long = "complement",
+        value_name = "COMPLEMENT=MASTER",
+        value_parser = parse_pair,
+        default_values = ["4=0", "5=1", "6=2", "7=3"]
+    )]
+    complements: Vec<(u8, u8)>,
+
+    /// Print one JSON object per line for every event, command and result instead of text
+    #[arg(long)]
+    json: bool,
 }
 
 // Protocol documentation - same as unified test
@@ -68,26 +161,98 @@ struct Args {
  * + -----------------------------------------------+
  */
 
+/// Counters of one test phase
+#[derive(Clone)]
+struct PhaseStats {
+    name: &'static str,
+    elapsed: Duration,
+    stats: TransportStats,
+    frame_errors: u64,
+    latency: LatencyStats,
+}
+
+impl PhaseStats {
+    /// `count` as a percentage of the writes in this phase
+    fn rate(&self, count: u64) -> f64 {
+        if self.stats.writes > 0 {
+            (count as f64 / self.stats.writes as f64) * 100.0
+        } else {
+            0.0
+        }
+    }
+
+    fn response_rate(&self) -> f64 {
+        self.rate(self.stats.reads)
+    }
+
+    fn error_rate(&self) -> f64 {
+        self.rate(self.stats.errors)
+    }
+
+    fn timeout_rate(&self) -> f64 {
+        self.rate(self.stats.timeouts)
+    }
+
+    fn report(&mut self) -> Value {
+        let s = &self.stats;
+        let counters = Value::object()
+            .with("elapsed_s", self.elapsed.as_secs_f64())
+            .with("writes", s.writes)
+            .with("reads", s.reads)
+            .with("bytes_out", s.bytes_out)
+            .with("bytes_in", s.bytes_in)
+            .with("timeouts", s.timeouts)
+            .with("errors", s.errors)
+            .with("reconnects", s.reconnects)
+            .with("frame_errors", self.frame_errors)
+            .with("response_rate_pct", self.response_rate())
+            .with("error_rate_pct", self.error_rate())
+            .with("timeout_rate_pct", self.timeout_rate());
+        let latency = Value::object()
+            .with("count", self.latency.count())
+            .with("min_ms", self.latency.min())
+            .with("mean_ms", self.latency.mean())
+            .with("p50_ms", self.latency.percentile(50.0))
+            .with("p90_ms", self.latency.percentile(90.0))
+            .with("p99_ms", self.latency.percentile(99.0))
+            .with("max_ms", self.latency.max());
+        counters.with("latency", latency)
+    }
+}
+
 struct RobustTcpClient {
-    stream: TcpStream,
+    stream: CoalescingWriter<TcpStream>,
     response_commands: HashSet<u8>,
-    stats: ConnectionStats,
+    codec: Codec,
+    /// Counters of the current phase
+    stats: TransportStats,
+    /// Responses rejected by the CRC or stream framing in the current phase
+    frame_errors: u64,
+    /// Response times in the current phase
+    latency: LatencyStats,
+    phase: &'static str,
+    phase_start: Instant,
+    /// Phases finished so far, in order
+    phases: Vec<PhaseStats>,
+    /// Unanswered commands in `--window` mode
+    acks: AckWindow,
+    /// Send time of every unanswered command in `--window` mode, oldest first
+    sent_at: VecDeque<Instant>,
+    /// Commands of the `--on-exit` state
+    exit_commands: Vec<Command>,
+    started: SystemTime,
+    log: EventLog,
     args: Args,
 }
 
-#[derive(Debug, Default)]
-struct ConnectionStats {
-    commands_sent: u64,
-    responses_received: u64,
-    timeouts: u64,
-    errors: u64,
-    bytes_sent: u64,
-    bytes_received: u64,
-}
-
 impl RobustTcpClient {
     fn new(args: Args) -> Result<Self> {
-        println!("Connecting to {}...", args.address);
+        let log = EventLog::new(args.json);
+        log.info(
+            "connecting",
+            Value::object().with("address", args.address.to_string()),
+            format!("Connecting to {}...", args.address),
+        );
 
         // Parse response commands if specified
         let response_commands = if args.no_responses {
@@ -99,13 +264,23 @@ impl RobustTcpClient {
             [0xfe, 0xfd, 0xfc].iter().cloned().collect()
         };
 
+        let exit_commands = args
+            .on_exit
+            .commands()
+            .with_context(|| format!("Failed to read the exit state {}", args.on_exit))?;
+
         // Connect with timeout
-        let addr = args
-            .address
+        let Target::Tcp { address } = &args.address else {
+            return Err(anyhow!(
+                "{} is not a TCP target; use IP:PORT (e.g., 192.168.1.100:8080)",
+                args.address
+            ));
+        };
+        let addr = address
             .to_socket_addrs()
-            .with_context(|| format!("Invalid address format: {}", args.address))?
+            .with_context(|| format!("Invalid address format: {}", address))?
             .next()
-            .ok_or_else(|| anyhow!("Could not resolve address: {}", args.address))?;
+            .ok_or_else(|| anyhow!("Could not resolve address: {}", address))?;
 
         let stream = TcpStream::connect_timeout(&addr, Duration::from_millis(args.connect_timeout))
             .with_context(|| format!("Failed to connect to {}", addr))?;
@@ -115,26 +290,66 @@ impl RobustTcpClient {
         stream.set_write_timeout(Some(Duration::from_millis(args.write_timeout)))?;
         stream.set_nodelay(true)?; // Disable Nagle's algorithm for low latency
 
-        println!("Connected successfully to {}", addr);
-        println!("Configuration:");
-        println!("  Read timeout: {}ms", args.read_timeout);
-        println!("  Write timeout: {}ms", args.write_timeout);
-        println!("  Command delay: {}ms", args.command_delay);
-        println!(
-            "  Response mode: {}",
-            if args.no_responses {
-                "No responses"
-            } else if response_commands.is_empty() {
-                "All responses"
-            } else {
-                "Selective responses"
+        let response_mode = if args.no_responses {
+            "No responses"
+        } else if args.window.is_some() {
+            "Windowed"
+        } else if response_commands.is_empty() {
+            "All responses"
+        } else {
+            "Selective responses"
+        };
+        if log.is_json() {
+            log.record(
+                "connected",
+                Value::object()
+                    .with("address", addr.to_string())
+                    .with("read_timeout_ms", args.read_timeout)
+                    .with("write_timeout_ms", args.write_timeout)
+                    .with("command_delay_ms", args.command_delay)
+                    .with("crc", args.crc)
+                    .with("framing", format!("{:?}", args.framing).to_lowercase())
+                    .with("window", args.window)
+                    .with("coalesce_ms", args.coalesce)
+                    .with("response_mode", response_mode),
+            );
+        } else {
+            println!("Connected successfully to {}", addr);
+            println!("Configuration:");
+            println!("  Read timeout: {}ms", args.read_timeout);
+            println!("  Write timeout: {}ms", args.write_timeout);
+            println!("  Command delay: {}ms", args.command_delay);
+            println!(
+                "  CRC framing: {}",
+                if args.crc { "enabled" } else { "disabled" }
+            );
+            println!("  Stream framing: {:?}", args.framing);
+            if let Some(window) = args.window {
+                println!("  Window: {} commands", window);
             }
-        );
+            if args.coalesce > 0 {
+                println!("  Write coalescing: {}ms", args.coalesce);
+            }
+            println!("  Response mode: {}", response_mode);
+        }
 
         Ok(RobustTcpClient {
-            stream,
+            stream: CoalescingWriter::new(stream, Duration::from_millis(args.coalesce)),
             response_commands,
-            stats: ConnectionStats::default(),
+            codec: Codec::new(args.crc, args.framing)
+                .with_padding(args.padding)
+                .with_strict(args.strict),
+            stats: TransportStats::default(),
+            frame_errors: 0,
+            latency: LatencyStats::default(),
+            phase: "setup",
+            phase_start: Instant::now(),
+            phases: Vec::new(),
+            acks: AckWindow::new(args.window.unwrap_or(1)),
+            sent_at: VecDeque::new(),
+            exit_commands,
+            started: SystemTime::now(),
+            log,
             args,
         })
     }
@@ -154,24 +369,26 @@ impl RobustTcpClient {
     }
 
     fn write_command(&mut self, data: &[u8]) -> Result<()> {
-        // Pad to 4-byte boundary
-        let mut padded_data = data.to_vec();
-        while padded_data.len() % 4 != 0 {
-            padded_data.push(0);
-        }
+        // Pad to 4-byte boundary (and apply CRC/stream framing when enabled)
+        let padded_data = self.codec.encode_commands(data)?;
 
         if self.args.verbose {
-            println!("→ Sending {} bytes: {:02x?}", padded_data.len(), data);
+            self.log.info(
+                "sent",
+                Value::object()
+                    .with("command", data.to_vec())
+                    .with("bytes", padded_data.len()),
+                format!("→ Sending {} bytes: {:02x?}", padded_data.len(), data),
+            );
         }
 
         match self.stream.write_all(&padded_data) {
             Ok(()) => {
-                self.stats.commands_sent += 1;
-                self.stats.bytes_sent += padded_data.len() as u64;
+                self.stats.record_write(padded_data.len());
                 Ok(())
             }
             Err(e) => {
-                self.stats.errors += 1;
+                self.stats.record_error();
                 Err(anyhow!("Write failed: {}", e))
             }
         }
@@ -184,7 +401,11 @@ impl RobustTcpClient {
                 && !self.response_commands.contains(&command_type))
         {
             if self.args.verbose {
-                println!("← Skipping response for command 0x{:02x}", command_type);
+                self.log.info(
+                    "response_skipped",
+                    Value::object().with("command_type", command_type),
+                    format!("← Skipping response for command 0x{:02x}", command_type),
+                );
             }
             return Ok(Vec::new());
         }
@@ -197,21 +418,23 @@ impl RobustTcpClient {
             match self.stream.read(&mut buffer[total_bytes..]) {
                 Ok(0) => {
                     if self.args.verbose {
-                        println!("← Connection closed by remote");
+                        self.log
+                            .info("closed", Value::Null, "← Connection closed by remote");
                     }
                     break;
                 }
                 Ok(n) => {
                     total_bytes += n;
-                    self.stats.responses_received += 1;
-                    self.stats.bytes_received += n as u64;
+                    self.stats.record_read(n);
 
                     if self.args.verbose {
-                        println!(
-                            "← Received {} bytes (retry {}): {:02x?}",
-                            n,
-                            retry,
-                            &buffer[total_bytes - n..total_bytes]
+                        let bytes = &buffer[total_bytes - n..total_bytes];
+                        self.log.info(
+                            "received",
+                            Value::object()
+                                .with("bytes", bytes.to_vec())
+                                .with("retry", retry),
+                            format!("← Received {} bytes (retry {}): {:02x?}", n, retry, bytes),
                         );
                     }
                     break;
@@ -226,30 +449,44 @@ impl RobustTcpClient {
 
                     if retry < self.args.read_retries {
                         if self.args.verbose {
-                            println!(
-                                "← Read timeout (retry {}/{})",
-                                retry + 1,
-                                self.args.read_retries
+                            self.log.info(
+                                "read_timeout",
+                                Value::object().with("retry", retry + 1),
+                                format!(
+                                    "← Read timeout (retry {}/{})",
+                                    retry + 1,
+                                    self.args.read_retries
+                                ),
                             );
                         }
                         std::thread::sleep(Duration::from_millis(50));
                         continue;
                     } else {
-                        self.stats.timeouts += 1;
+                        self.stats.record_timeout();
                         if self.args.verbose {
-                            println!(
-                                "← No response after {} retries ({:.1}ms)",
-                                self.args.read_retries,
-                                start_time.elapsed().as_millis()
+                            self.log.info(
+                                "no_response",
+                                Value::object()
+                                    .with("retries", self.args.read_retries)
+                                    .with("waited_ms", start_time.elapsed()),
+                                format!(
+                                    "← No response after {} retries ({:.1}ms)",
+                                    self.args.read_retries,
+                                    start_time.elapsed().as_millis()
+                                ),
                             );
                         }
                         return Ok(Vec::new());
                     }
                 }
                 Err(e) => {
-                    self.stats.errors += 1;
+                    self.stats.record_error();
                     if self.args.verbose {
-                        println!("← Read error: {} (continuing)", e);
+                        self.log.info(
+                            "read_error",
+                            Value::object().with("error", e.to_string()),
+                            format!("← Read error: {} (continuing)", e),
+                        );
                     }
                     return Ok(Vec::new()); // Continue operation
                 }
@@ -257,13 +494,32 @@ impl RobustTcpClient {
         }
 
         buffer.truncate(total_bytes);
+
+        if (self.args.crc || self.args.framing != StreamFraming::Raw) && !buffer.is_empty() {
+            match self.codec.decode_responses(&buffer) {
+                Ok(decoded) => return Ok(decoded),
+                Err(e) => {
+                    self.frame_errors += 1;
+                    if self.args.verbose {
+                        self.log.info(
+                            "response_rejected",
+                            Value::object().with("error", e.to_string()),
+                            format!("← Response rejected: {}", e),
+                        );
+                    }
+                    return Ok(Vec::new());
+                }
+            }
+        }
+
         Ok(buffer)
     }
 
     fn send_command_with_response(&mut self, data: &[u8]) -> Result<Vec<u8>> {
-        let command_type = data.get(0).copied().unwrap_or(0);
+        let command_type = data.first().copied().unwrap_or(0);
 
         // Send command
+        let sent = Instant::now();
         self.write_command(data)?;
 
         // Add delay between command and response
@@ -272,24 +528,382 @@ impl RobustTcpClient {
         }
 
         // Read response
-        self.read_response(command_type)
+        let response = self.read_response(command_type)?;
+        let latency = (!response.is_empty()).then(|| sent.elapsed());
+        if let Some(latency) = latency {
+            self.latency.record(latency);
+        }
+        self.log.record(
+            "command",
+            Value::object()
+                .with("phase", self.phase)
+                .with("command", data.to_vec())
+                .with("response", response.clone())
+                .with("latency_ms", latency),
+        );
+        Ok(response)
     }
 
+    /// Send without waiting for this command's response, blocking only while
+    /// `--window` commands are still unanswered
+    fn send_windowed(&mut self, data: &[u8]) -> Result<()> {
+        self.wait_for_acks(|acks| acks.has_room(data))?;
+        self.write_command(data)?;
+        let before = self.acks.in_flight();
+        self.acks.sent(data);
+        let now = Instant::now();
+        self.sent_at
+            .extend(std::iter::repeat_n(now, self.acks.in_flight() - before));
+        self.log.record(
+            "command",
+            Value::object()
+                .with("phase", self.phase)
+                .with("command", data.to_vec())
+                .with("in_flight", self.acks.in_flight()),
+        );
+        Ok(())
+    }
+
+    /// Read responses until `ready`, giving up after the read timeout and
+    /// retries pass without any response
+    fn wait_for_acks(&mut self, ready: impl Fn(&AckWindow) -> bool) -> Result<()> {
+        let stall = Duration::from_millis(self.args.read_timeout) * (self.args.read_retries + 1);
+        let mut last_progress = Instant::now();
+
+        while !ready(&self.acks) {
+            if self.poll_responses()? > 0 {
+                last_progress = Instant::now();
+            } else if last_progress.elapsed() >= stall {
+                self.stats.record_timeout();
+                if self.args.verbose {
+                    self.log.info(
+                        "window_reset",
+                        Value::object()
+                            .with("missing", self.acks.in_flight())
+                            .with("stall_ms", stall),
+                        format!(
+                            "← {} responses missing after {}ms, resetting window",
+                            self.acks.in_flight(),
+                            stall.as_millis()
+                        ),
+                    );
+                }
+                self.acks.reset();
+                self.sent_at.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait `interval` between commands; in `--window` mode responses are read
+    /// as they arrive meanwhile so their latency is measured
+    fn pace(&mut self, interval: Duration) -> Result<()> {
+        let deadline = Instant::now() + interval;
+        if self.args.window.is_some() {
+            while self.acks.in_flight() > 0 {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    break;
+                }
+                self.stream.get_ref().set_read_timeout(Some(left))?;
+                let polled = self.poll_responses();
+                self.stream
+                    .get_ref()
+                    .set_read_timeout(Some(Duration::from_millis(self.args.read_timeout)))?;
+                polled?;
+            }
+        }
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        Ok(())
+    }
+
+    /// Read once and account for the responses received
+    fn poll_responses(&mut self) -> Result<usize> {
+        let mut buffer = [0u8; 1024];
+        let n = match self.stream.read(&mut buffer) {
+            Ok(0) => return Err(anyhow!("Connection closed by remote")),
+            Ok(n) => n,
+            Err(e)
+                if e.kind() == std::io::ErrorKind::TimedOut
+                    || e.kind() == std::io::ErrorKind::WouldBlock =>
+            {
+                return Ok(0)
+            }
+            Err(e) => {
+                self.stats.record_error();
+                return Err(anyhow!("Read failed: {}", e));
+            }
+        };
+        self.stats.record_read(n);
+
+        let decoded = match self.codec.decode_responses(&buffer[..n]) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                self.frame_errors += 1;
+                if self.args.verbose {
+                    self.log.info(
+                        "response_rejected",
+                        Value::object().with("error", e.to_string()),
+                        format!("← Response rejected: {}", e),
+                    );
+                }
+                return Ok(0);
+            }
+        };
+        let responses = self.acks.received(&decoded);
+        for response in &responses {
+            let latency = self.sent_at.pop_front().map(|sent| sent.elapsed());
+            if let Some(latency) = latency {
+                self.latency.record(latency);
+            }
+            self.log.record(
+                "response",
+                Value::object()
+                    .with("phase", self.phase)
+                    .with("response", response.clone())
+                    .with("latency_ms", latency),
+            );
+            if self.args.verbose && !self.log.is_json() {
+                println!("← Response: {:02x?}", response);
+            }
+        }
+        Ok(responses.len())
+    }
+
+    /// Close the current phase and count everything from now on towards `name`
+    fn begin_phase(&mut self, name: &'static str) {
+        self.phases.push(PhaseStats {
+            name: self.phase,
+            elapsed: self.phase_start.elapsed(),
+            stats: std::mem::take(&mut self.stats),
+            frame_errors: std::mem::take(&mut self.frame_errors),
+            latency: std::mem::take(&mut self.latency),
+        });
+        self.phase = name;
+        self.phase_start = Instant::now();
+        self.log
+            .record("phase", Value::object().with("phase", name));
+    }
+
+    /// Per-phase summary, then the full statistics of the main phase
+    ///
+    /// Before the main phase starts (e.g. a failed setup) the current phase
+    /// gets the full statistics instead.
     fn print_stats(&self) {
-        println!("\n=== Connection Statistics ===");
-        println!("Commands sent:     {}", self.stats.commands_sent);
-        println!("Responses received: {}", self.stats.responses_received);
-        println!("Timeouts:          {}", self.stats.timeouts);
-        println!("Errors:            {}", self.stats.errors);
-        println!("Bytes sent:        {}", self.stats.bytes_sent);
-        println!("Bytes received:    {}", self.stats.bytes_received);
-
-        let success_rate = if self.stats.commands_sent > 0 {
-            (self.stats.responses_received as f64 / self.stats.commands_sent as f64) * 100.0
-        } else {
-            0.0
+        let mut phases = self.all_phases();
+        if self.log.is_json() {
+            for phase in &mut phases {
+                self.log
+                    .record("phase_stats", phase.report().with("phase", phase.name));
+            }
+            return;
+        }
+
+        println!("\n=== Phase Statistics ===");
+        for phase in &phases {
+            let s = &phase.stats;
+            println!(
+                "{:<9} {:>7.1}s {:>7} writes {:>7} reads {:>5} timeouts {:>5} errors",
+                phase.name,
+                phase.elapsed.as_secs_f64(),
+                s.writes,
+                s.reads,
+                s.timeouts,
+                s.errors
+            );
+        }
+
+        let mut main = Self::main_phase(&phases).clone();
+        println!("\n=== Connection Statistics ({}) ===", main.name);
+        println!("{}", main.stats);
+        if self.args.crc || self.args.framing != StreamFraming::Raw {
+            println!("Frame errors:      {}", main.frame_errors);
+        }
+        println!("Response rate:     {:.1}%", main.response_rate());
+        if let (Some(p50), Some(p99), Some(max)) = (
+            main.latency.percentile(50.0),
+            main.latency.percentile(99.0),
+            main.latency.max(),
+        ) {
+            println!(
+                "Latency:           p50 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+                p50.as_secs_f64() * 1000.0,
+                p99.as_secs_f64() * 1000.0,
+                max.as_secs_f64() * 1000.0
+            );
+        }
+    }
+
+    /// Finished phases followed by the current one
+    fn all_phases(&self) -> Vec<PhaseStats> {
+        let mut phases = self.phases.clone();
+        phases.push(PhaseStats {
+            name: self.phase,
+            elapsed: self.phase_start.elapsed(),
+            stats: self.stats,
+            frame_errors: self.frame_errors,
+            latency: self.latency.clone(),
+        });
+        phases
+    }
+
+    /// The main phase, or the last one if the test never got there
+    fn main_phase(phases: &[PhaseStats]) -> &PhaseStats {
+        phases
+            .iter()
+            .find(|phase| phase.name == "main")
+            .unwrap_or(&phases[phases.len() - 1])
+    }
+
+    /// Threshold violations in the main phase, as messages
+    fn check_thresholds(&self) -> Vec<String> {
+        let phases = self.all_phases();
+        let main = Self::main_phase(&phases);
+        let mut violations = Vec::new();
+        let mut check = |name: &str, option: &str, value: f64, limit: Option<f64>, max: bool| {
+            if let Some(limit) = limit {
+                if (max && value > limit) || (!max && value < limit) {
+                    let side = if max { "above" } else { "below" };
+                    violations.push(format!(
+                        "{} {:.2}% {} {} {}%",
+                        name, value, side, option, limit
+                    ));
+                }
+            }
         };
-        println!("Response rate:     {:.1}%", success_rate);
+        check(
+            "Error rate",
+            "--max-error-rate",
+            main.error_rate(),
+            self.args.max_error_rate,
+            true,
+        );
+        check(
+            "Timeout rate",
+            "--max-timeout-rate",
+            main.timeout_rate(),
+            self.args.max_timeout_rate,
+            true,
+        );
+        check(
+            "Response rate",
+            "--min-response-rate",
+            main.response_rate(),
+            self.args.min_response_rate,
+            false,
+        );
+        violations
+    }
+
+    /// Configuration, timestamps, outcome and every phase's counters
+    fn report(&self, outcome: &Result<()>, violations: &[String]) -> Value {
+        let args = &self.args;
+        let mut response_commands: Vec<u8> = self.response_commands.iter().copied().collect();
+        response_commands.sort_unstable();
+        let response_commands = response_commands
+            .iter()
+            .map(|c| format!("0x{:02x}", c))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let config = Value::object()
+            .with("address", args.address.to_string())
+            .with("rate_hz", args.rate)
+            .with("duration_s", args.duration)
+            .with("connect_timeout_ms", args.connect_timeout)
+            .with("read_timeout_ms", args.read_timeout)
+            .with("write_timeout_ms", args.write_timeout)
+            .with("command_delay_ms", args.command_delay)
+            .with("read_retries", args.read_retries)
+            .with("no_responses", args.no_responses)
+            .with("response_commands", response_commands)
+            .with("window", args.window)
+            .with("crc", args.crc)
+            .with("framing", format!("{:?}", args.framing).to_lowercase())
+            .with("padding", format!("{:?}", args.padding).to_lowercase())
+            .with("strict", args.strict)
+            .with("coalesce_ms", args.coalesce)
+            .with("warmup", args.warmup)
+            .with("cooldown", args.cooldown)
+            .with("max_error_rate_pct", args.max_error_rate)
+            .with("max_timeout_rate_pct", args.max_timeout_rate)
+            .with("min_response_rate_pct", args.min_response_rate);
+
+        let mut phases = self.all_phases();
+        let main = Self::main_phase(&phases).name;
+        let mut phase_values = Value::object();
+        for phase in &mut phases {
+            phase_values = phase_values.with(phase.name, phase.report());
+        }
+
+        Value::object()
+            .with("tool", "tcp_robust_test")
+            .with("version", report::VERSION)
+            .with("git", report::GIT_VERSION)
+            .with("build", version::build_value())
+            .with("started", report::utc_timestamp(self.started))
+            .with("finished", report::utc_timestamp(SystemTime::now()))
+            .with(
+                "duration_s",
+                self.started.elapsed().unwrap_or_default().as_secs_f64(),
+            )
+            .with("passed", outcome.is_ok() && violations.is_empty())
+            .with("error", outcome.as_ref().err().map(|e| e.to_string()))
+            .with("violations", violations.join("; "))
+            .with("config", config)
+            .with("main_phase", main)
+            .with("phases", phase_values)
+    }
+
+    fn write_report(&self, path: &Path, outcome: &Result<()>, violations: &[String]) -> Result<()> {
+        let format = self
+            .args
+            .report_format
+            .unwrap_or_else(|| ReportFormat::for_path(path));
+        let rotation = self.args.report_rotation.clone().unwrap_or_default();
+        self.report(outcome, violations)
+            .write_rotated(path, format, &rotation)
+            .with_context(|| format!("Failed to write report {}", path.display()))?;
+        self.log.info(
+            "report",
+            Value::object().with("path", path.display().to_string()),
+            format!("Report written to {}", path.display()),
+        );
+        Ok(())
+    }
+
+    /// Every DAC back to 0 (complements to full scale) and the setup GPIOs off
+    fn cool_down(&mut self, links: &ChannelLinks) -> Result<()> {
+        self.step("cooldown", "Cooling down...");
+        for c in (0..8).filter(|&c| links.complement_of(c).is_none()) {
+            self.send_command_with_response(&links.apply(&[c, 0, 0, 0]))?;
+        }
+        self.send_command_with_response(&[0xfe, 0, 0, 0])?;
+        self.send_command_with_response(&[0xfe, 1, 0, 0])?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Bring the device to the `--on-exit` state, as its own phase
+    fn leave_device(&mut self) -> Result<()> {
+        if self.exit_commands.is_empty() {
+            return Ok(());
+        }
+        self.begin_phase("exit");
+        self.step(
+            "exit",
+            &format!("Leaving the device at {}...", self.args.on_exit),
+        );
+        for cmd in self.exit_commands.clone() {
+            self.send_command_with_response(&cmd.encode())?;
+        }
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Announce the next part of the test sequence
+    fn step(&self, name: &str, text: &str) {
+        self.log
+            .info("step", Value::object().with("step", name), text);
     }
 
     fn run_test(&mut self) -> Result<()> {
@@ -303,33 +917,41 @@ impl RobustTcpClient {
         // Set up Ctrl+C handler
         let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
         let r = running.clone();
+        let log = self.log;
         ctrlc::set_handler(move || {
-            println!("\nReceived Ctrl+C, shutting down gracefully...");
+            log.info(
+                "interrupted",
+                Value::Null,
+                "\nReceived Ctrl+C, shutting down gracefully...",
+            );
             r.store(false, std::sync::atomic::Ordering::SeqCst);
         })
         .context("Error setting Ctrl+C handler")?;
 
-        println!("\nStarting test sequence...");
+        if !self.log.is_json() {
+            println!("\nStarting test sequence...");
+        }
 
         // GPIO setup
-        println!("Setting up GPIO...");
+        self.step("gpio", "Setting up GPIO...");
         self.send_command_with_response(&[0xfe, 0, 0, 1])?;
         self.send_command_with_response(&[0xfe, 1, 0, 1])?;
 
         // Init1
-        println!("Sending init1...");
+        self.begin_phase("init");
+        self.step("init1", "Sending init1...");
         self.send_command_with_response(&[16, 49, 0, 0])?;
         self.send_command_with_response(&[16, 50, 64, 0])?;
         self.send_command_with_response(&[16, 51, 128, 0])?;
 
         // Init2
-        println!("Sending init2...");
+        self.step("init2", "Sending init2...");
         self.send_command_with_response(&[17, 49, 64, 0])?;
         self.send_command_with_response(&[17, 50, 128, 0])?;
         self.send_command_with_response(&[17, 51, 0, 0])?;
 
         // Init3 - in chunks
-        println!("Sending init3 (chunked)...");
+        self.step("init3", "Sending init3 (chunked)...");
         let init3_commands = [
             [0, 16, 0, 0],
             [1, 17, 0, 0],
@@ -343,23 +965,32 @@ impl RobustTcpClient {
 
         for (i, cmd) in init3_commands.iter().enumerate() {
             self.send_command_with_response(cmd)?;
-            if self.args.verbose {
+            if self.args.verbose && !self.log.is_json() {
                 println!("Init3 chunk {} completed", i + 1);
             }
         }
 
         // Keepalive test
-        println!("Testing keepalive...");
+        self.step("keepalive", "Testing keepalive...");
         for i in 0..3 {
             self.send_command_with_response(&[0xfd, 0, 0, 0])?;
-            if self.args.verbose {
+            if self.args.verbose && !self.log.is_json() {
                 println!("Keepalive {} completed", i + 1);
             }
             std::thread::sleep(Duration::from_millis(500));
         }
 
         // Main loop
-        println!("Starting main data loop (Ctrl+C to stop)...");
+        if self.args.warmup > 0 {
+            self.begin_phase("warmup");
+            if !self.log.is_json() {
+                println!("Warming up ({} commands)...", self.args.warmup);
+            }
+        } else {
+            self.begin_phase("main");
+        }
+        self.step("main", "Starting main data loop (Ctrl+C to stop)...");
+        let links = ChannelLinks::from_pairs(&self.args.complements)?;
         let mut msg: Vec<u8> = vec![0, 0, 0, 0];
         let mut v: u16 = 0;
         let mut c: u8 = 0;
@@ -369,7 +1000,11 @@ impl RobustTcpClient {
             // Check test duration
             if let Some(duration) = test_duration {
                 if test_start.elapsed() >= duration {
-                    println!("Test duration reached, stopping...");
+                    self.log.info(
+                        "duration_reached",
+                        Value::object().with("duration_s", duration.as_secs()),
+                        "Test duration reached, stopping...",
+                    );
                     break;
                 }
             }
@@ -386,17 +1021,19 @@ impl RobustTcpClient {
 
             msg[0] = c;
             msg[1] = 0; // Direct write mode
-            if c < 4 {
-                msg[2] = ((v & 0xff00) >> 8) as u8;
-                msg[3] = (v & 0xff) as u8;
-            } else {
-                msg[2] = (((65535 - v) & 0xff00) >> 8) as u8;
-                msg[3] = ((65535 - v) & 0xff) as u8;
-            }
+            msg[2] = ((v & 0xff00) >> 8) as u8;
+            msg[3] = (v & 0xff) as u8;
 
-            match self.send_command_with_response(&msg) {
-                Ok(_) => {
-                    if self.args.verbose || loop_count % 100 == 0 {
+            // The write and the rest of its complement pair
+            let batch = links.apply(&msg);
+            let result = if self.args.window.is_some() {
+                self.send_windowed(&batch)
+            } else {
+                self.send_command_with_response(&batch).map(|_| ())
+            };
+            match result {
+                Ok(()) => {
+                    if !self.log.is_json() && (self.args.verbose || loop_count % 100 == 0) {
                         let value = ((msg[2] as u16) << 8) | (msg[3] as u16);
                         println!(
                             "Loop {}: DAC {} = 0x{:04x} ({})",
@@ -405,50 +1042,122 @@ impl RobustTcpClient {
                     }
                 }
                 Err(e) => {
-                    eprintln!("Command failed: {}", e);
+                    self.log.warn(
+                        "command_failed",
+                        Value::object()
+                            .with("phase", self.phase)
+                            .with("command", msg.clone())
+                            .with("error", format!("{:#}", e)),
+                        format!("Command failed: {}", e),
+                    );
                     std::thread::sleep(Duration::from_millis(100));
                 }
             }
 
             loop_count += 1;
+            if loop_count == self.args.warmup {
+                // Warm-up responses still in flight count towards the warm-up
+                if self.args.window.is_some() {
+                    self.stream.flush()?;
+                    self.wait_for_acks(|acks| acks.in_flight() == 0)?;
+                }
+                self.begin_phase("main");
+                if !self.log.is_json() {
+                    println!("Warm-up done, measuring");
+                }
+            }
 
             if self.args.rate > 0 {
-                std::thread::sleep(Duration::from_millis(
+                self.pace(Duration::from_millis(
                     (1000.0 / self.args.rate as f32) as u64,
-                ));
+                ))?;
             }
         }
 
-        println!(
-            "Test completed after {:.1} seconds",
-            test_start.elapsed().as_secs_f64()
+        if let Err(e) = self.stream.flush() {
+            self.stats.record_error();
+            self.log.warn(
+                "flush_failed",
+                Value::object().with("error", e.to_string()),
+                format!("Final flush failed: {}", e),
+            );
+        }
+        if self.args.window.is_some() {
+            self.wait_for_acks(|acks| acks.in_flight() == 0)?;
+        }
+
+        if self.args.cooldown {
+            self.begin_phase("cooldown");
+            self.cool_down(&links)?;
+        }
+
+        self.log.info(
+            "completed",
+            Value::object().with("elapsed_s", test_start.elapsed().as_secs_f64()),
+            format!(
+                "Test completed after {:.1} seconds",
+                test_start.elapsed().as_secs_f64()
+            ),
         );
         Ok(())
     }
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
 
-    // Validate address format
-    if !args.address.contains(':') {
-        return Err(anyhow!(
-            "Address must be in format IP:PORT (e.g., 192.168.1.100:8080)"
-        ));
-    }
-
-    let mut client = RobustTcpClient::new(args)?;
+    let log = EventLog::new(args.json);
+    let report = args.report.clone();
+    let mut client = match RobustTcpClient::new(args) {
+        Ok(client) => client,
+        Err(e) if log.is_json() => {
+            log.record("error", Value::object().with("error", format!("{:#}", e)));
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e.into()),
+    };
 
-    match client.run_test() {
-        Ok(()) => {
-            client.print_stats();
-            println!("Test completed successfully.");
+    let mut outcome = client.run_test();
+    if let Err(e) = client.leave_device() {
+        let e = e.context("Failed to leave the device in the exit state");
+        match outcome {
+            Ok(()) => outcome = Err(e),
+            Err(_) => log.warn(
+                "exit_state_failed",
+                Value::object().with("error", format!("{:#}", e)),
+                format!("{:#}", e),
+            ),
         }
+    }
+    client.print_stats();
+    let violations = client.check_thresholds();
+    for violation in &violations {
+        log.warn(
+            "threshold_violated",
+            Value::object().with("violation", violation.as_str()),
+            format!("Threshold violated: {}", violation),
+        );
+    }
+    if let Some(path) = &report {
+        client.write_report(path, &outcome, &violations)?;
+    }
+    let finished = Value::object()
+        .with("passed", outcome.is_ok() && violations.is_empty())
+        .with("error", outcome.as_ref().err().map(|e| format!("{:#}", e)))
+        .with("violations", violations.len());
+    match outcome {
         Err(e) => {
-            client.print_stats();
-            eprintln!("Test failed: {}", e);
+            log.warn("finished", finished, format!("Test failed: {}", e));
+            if let Some(advice) = diagnose::advise(e.as_ref()).filter(|_| !log.is_json()) {
+                eprintln!("{}", advice);
+            }
             std::process::exit(1);
         }
+        Ok(()) if !violations.is_empty() => {
+            log.record("finished", finished);
+            std::process::exit(3)
+        }
+        Ok(()) => log.info("finished", finished, "Test completed successfully."),
     }
 
     Ok(())
@@ -1,20 +1,67 @@
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
-use serialport::SerialPort;
+use clap::builder::BoolishValueParser;
+use clap::{Parser, ValueEnum};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use mio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use mio::unix::pipe::{Receiver, Sender};
+#[cfg(unix)]
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout},
+    widgets::{Block, Borders, List, ListItem, Paragraph, Row, Sparkline, Table},
+    Frame, Terminal,
+};
+use serialtest::audit::{self, AuditLog};
+use serialtest::diagnose::Failure;
+use serialtest::discovery;
+use serialtest::error::DacError;
+use serialtest::filter::{BuiltinFilter, FilterChain};
+use serialtest::framing::{self, FrameDecoder, Padding, StreamFraming};
+use serialtest::health::{self, Health, Status};
+use serialtest::logfile::Rotation;
+use serialtest::protocol::{self, Command, Features, PROTOCOL_VERSION};
+use serialtest::repeats::Repeats;
+use serialtest::target::{Target, DEFAULT_BAUD};
+use serialtest::transport::{self, Link, LinkOptions};
+#[cfg(unix)]
+use serialtest::tuning::SerialTuning;
+use serialtest::twin::{self, Twin};
+use serialtest::version;
+use serialtest::wake::{self, Backoff, SleepDetector};
+use serialtest::widgets::Theme;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::{Read, Write};
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(unix)]
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, IntoRawFd};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// TCP server that bridges serial communication to TCP for csv1-ol8 devices
 #[derive(Parser, Debug)]
 #[command(name = "tcp_server")]
 #[command(about = "Serial-to-TCP bridge server for csv1-ol8 devices")]
 struct Args {
-    /// Serial device path (e.g., /dev/ttyACM0, /dev/cu.usbmodemcsv1_00011, COM5)
-    serial_device: String,
+    /// Serial device path (e.g., /dev/ttyACM0, /dev/cu.usbmodemcsv1_00011, COM5),
+    /// serial:PATH?baud=N or pty:PATH; or another bridge to relay to (tcp:HOST:PORT, tls:,
+    /// udp: or scheme:// target)
+    #[arg(env = "CSV1_TARGET")]
+    serial_device: Target,
+
+    /// Bridge a single client over stdin/stdout instead of listening on TCP
+    /// (for socat, SSH ForceCommand or `docker exec`); logs go to stderr
+    #[arg(long, conflicts_with_all = ["port", "bind"])]
+    stdio: bool,
 
     /// TCP port to listen on
     #[arg(short, long, default_value = "2012")]
@@ -24,9 +71,421 @@ struct Args {
     #[arg(short, long)]
     bind: Option<String>,
 
+    /// Advertise the bridge over mDNS as NAME, so clients started without a target list it
+    #[cfg(feature = "mdns")]
+    #[arg(long, value_name = "NAME", conflicts_with = "stdio")]
+    mdns: Option<String>,
+
     /// Enable verbose output for debugging
-    #[arg(short, long)]
+    #[arg(short, long, env = "CSV1_VERBOSE", value_parser = BoolishValueParser::new())]
+    verbose: bool,
+
+    /// CRC framing: validate command CRCs from clients and expect CRCs on device responses
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing used by TCP (or stdio) clients
+    #[arg(long, value_enum, default_value = "raw")]
+    tcp_framing: StreamFraming,
+
+    /// Stream framing used by the serial device (the upstream bridge's --tcp-framing when
+    /// relaying)
+    #[arg(long, value_enum, default_value = "raw")]
+    serial_framing: StreamFraming,
+
+    /// Partial commands from raw TCP clients: hold them until complete (reject), or zero-fill
+    /// them to 4 bytes as soon as they arrive
+    #[arg(long, value_enum, default_value = "reject")]
+    padding: Padding,
+
+    /// Drop client commands with out-of-range channels, tables, GPIO pins or value fields
+    /// instead of forwarding them to the device
+    #[arg(long)]
+    strict: bool,
+
+    /// Track the device state from forwarded commands and serve it as JSON over HTTP on ADDR
+    /// (e.g. 127.0.0.1:2013)
+    #[arg(long, value_name = "ADDR")]
+    twin: Option<SocketAddr>,
+
+    /// Let only one client (the controller) write; the others are read-only observers
+    /// until they send a takeover command
+    #[arg(long, conflicts_with = "stdio")]
+    roles: bool,
+
+    /// Append every forwarded command, with its client and meaning, to an audit log FILE
+    #[arg(long, value_name = "FILE")]
+    audit: Option<PathBuf>,
+
+    /// Close a client connection after SECS without any data from it, so half-open
+    /// connections (e.g. over NAT or Wi-Fi) are cleaned up; clients send heartbeats to stay
+    #[arg(
+        long,
+        value_name = "SECS",
+        conflicts_with = "stdio",
+        env = "CSV1_CLIENT_TIMEOUT"
+    )]
+    client_timeout: Option<u64>,
+
+    /// Show connections, command rates, serial errors and per-client activity in a live
+    /// terminal view instead of printing log lines
+    #[arg(long, conflicts_with = "stdio")]
+    dashboard: bool,
+
+    /// Audit log rotation: size=N[K|M|G], age=N[s|m|h|d], keep=N, compress
+    #[arg(long, value_name = "SPEC", default_value = audit::DEFAULT_ROTATION, requires = "audit")]
+    audit_rotation: Rotation,
+
+    /// Translate between the protocol clients speak and the device's: legacy-device answers
+    /// hellos and register reads for firmware that knows neither
+    #[arg(long, value_enum, value_name = "MODE")]
+    translate: Option<Translation>,
+
+    /// Run device responses through FILTER before passing them on; repeat for a chain,
+    /// applied in the order given
+    #[arg(long, value_enum, value_name = "FILTER")]
+    filter: Vec<BuiltinFilter>,
+
+    /// Half-duplex RS-485 adapter: assert RTS while sending to switch the transceiver to
+    /// transmit, and send one command at a time, each once the previous one is answered
+    #[arg(long)]
+    rs485: bool,
+
+    /// Time RTS stays asserted after the last byte has left the UART, in microseconds
+    #[arg(long, value_name = "MICROS", default_value = "0", requires = "rs485")]
+    rs485_turnaround: u64,
+
+    /// Set the FTDI latency timer of the serial adapter to MS milliseconds (Linux; the
+    /// default 16 ms delays every response)
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u8).range(1..))]
+    latency_timer: Option<u8>,
+
+    /// Have the serial driver pass received bytes on at once instead of buffering them
+    /// (the low_latency flag, Linux)
+    #[arg(long)]
+    no_buffering: bool,
+
+    /// Raw termios VMIN of the serial port: bytes a read waits for
+    #[arg(long, value_name = "BYTES")]
+    vmin: Option<u8>,
+
+    /// Raw termios VTIME of the serial port: tenths of a second a read waits between bytes
+    #[arg(long, value_name = "DECISECONDS")]
+    vtime: Option<u8>,
+
+    /// Drop every client when the serial device fails, instead of keeping them connected
+    /// while it is reopened (after the host slept or the adapter was replugged)
+    #[arg(long)]
+    no_reopen: bool,
+
+    /// Wait for the serial device node to appear before serving, for at most SECS (without
+    /// a value, until it does), e.g. while a container's device is not yet passed through
+    #[arg(long, value_name = "SECS", num_args = 0..=1)]
+    device_wait: Option<Option<u64>>,
+
+    /// Answer health probes over HTTP on ADDR: /health while alive, /ready while serving
+    /// (e.g. 0.0.0.0:8080)
+    #[arg(long, value_name = "ADDR", conflicts_with = "stdio")]
+    health: Option<SocketAddr>,
+}
+
+/// How the bridge insulates clients from the device's protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Translation {
+    /// The device only knows the legacy 4-byte commands: hellos, register and table
+    /// reads are answered by the bridge, reads from the writes it forwarded
+    LegacyDevice,
+}
+
+/// Bridge settings shared by every client handler
+#[derive(Debug, Clone)]
+struct BridgeConfig {
+    /// Serial device, or the upstream bridge in relay mode
+    device: Target,
     verbose: bool,
+    crc: bool,
+    tcp_framing: StreamFraming,
+    serial_framing: StreamFraming,
+    padding: Padding,
+    strict: bool,
+    /// stdout carries protocol data, so diagnostics go to stderr
+    stdio: bool,
+    /// Silence after which a client is disconnected
+    client_timeout: Option<Duration>,
+    /// Device state shared by all clients, with `--twin`, `--translate` or to
+    /// restore a reopened device
+    twin: Option<Arc<Twin>>,
+    translate: Option<Translation>,
+    /// Controller/observer arbitration, with `--roles`
+    roles: Option<Arc<Roles>>,
+    /// Record of forwarded commands, with `--audit`
+    audit: Option<Arc<AuditLog>>,
+    /// Response filters, with `--filter`
+    filters: Option<Arc<Mutex<FilterChain>>>,
+    /// RS-485 turnaround delay, with `--rs485`
+    rs485: Option<Duration>,
+    /// Latency settings of the serial port
+    #[cfg(unix)]
+    tuning: SerialTuning,
+    /// Reopen a serial device that went away, keeping the clients connected
+    reopen: bool,
+    /// Activity drawn by `--dashboard`, which also collects the log
+    activity: Option<Arc<Mutex<Activity>>>,
+    /// Bridge status served by `--health`
+    health: Option<Arc<Health>>,
+    /// Errors counted rather than logged again
+    repeats: Arc<Repeats>,
+}
+
+impl BridgeConfig {
+    /// Diagnostic output, kept off stdout in stdio mode
+    fn log(&self, message: impl std::fmt::Display) {
+        if let Some(activity) = &self.activity {
+            activity.lock().unwrap().log(message.to_string());
+        } else if self.stdio {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// Like `log`, but to stderr outside the dashboard; repeats of an error
+    /// within a few seconds are only counted
+    fn log_error(&self, message: impl std::fmt::Display) {
+        self.log_repeats();
+        if let Some(line) = self.repeats.check(&message.to_string()) {
+            self.print_error(line);
+        }
+    }
+
+    /// Log how often errors repeated that have not been summed up yet
+    fn flush_repeats(&self) {
+        for summary in self.repeats.flush() {
+            self.print_error(summary);
+        }
+    }
+
+    /// Log how often errors repeated, for the kinds whose window has passed
+    fn log_repeats(&self) {
+        for summary in self.repeats.due() {
+            self.print_error(summary);
+        }
+    }
+
+    fn print_error(&self, message: String) {
+        match &self.activity {
+            Some(activity) => activity.lock().unwrap().log(message),
+            None => eprintln!("{}", message),
+        }
+    }
+
+    /// Whether the device is another bridge rather than a serial port
+    fn is_relay(&self) -> bool {
+        !matches!(self.device, Target::Serial { .. } | Target::Pty { .. })
+    }
+
+    /// RS-485 turnaround delay if RTS switches the transceiver; a pty has no RTS,
+    /// so `--rs485` only sends one command at a time there
+    fn rts(&self) -> Option<Duration> {
+        self.rs485
+            .filter(|_| matches!(self.device, Target::Serial { .. }))
+    }
+
+    /// Serial device path, or the upstream bridge's target
+    fn device_name(&self) -> String {
+        match &self.device {
+            Target::Serial { path, .. } | Target::Pty { path } => path.clone(),
+            upstream => upstream.to_string(),
+        }
+    }
+
+    /// Update the dashboard's activity, if there is a dashboard
+    fn track(&self, f: impl FnOnce(&mut Activity)) {
+        if let Some(activity) = &self.activity {
+            f(&mut activity.lock().unwrap());
+        }
+    }
+}
+
+/// Number of log lines kept for the dashboard
+const LOG_CAPACITY: usize = 500;
+
+/// Serial round trips kept for the dashboard's latency figures
+const LATENCY_WINDOW: usize = 1000;
+
+/// Seconds of command rate history shown by the dashboard
+const RATE_HISTORY: usize = 120;
+
+/// One connected client, as shown by the dashboard
+#[derive(Debug)]
+struct ClientActivity {
+    connected: Instant,
+    commands: u64,
+    responses: u64,
+    /// When each command of the last second was forwarded
+    recent: VecDeque<Instant>,
+    last_activity: Instant,
+    last_command: String,
+}
+
+impl ClientActivity {
+    /// Commands forwarded in the last second
+    fn rate(&mut self) -> usize {
+        while self
+            .recent
+            .front()
+            .is_some_and(|t| t.elapsed() > Duration::from_secs(1))
+        {
+            self.recent.pop_front();
+        }
+        self.recent.len()
+    }
+}
+
+/// What the bridge is doing, drawn by `--dashboard`
+#[derive(Debug, Default)]
+struct Activity {
+    clients: BTreeMap<String, ClientActivity>,
+    commands: u64,
+    responses: u64,
+    serial_errors: u64,
+    /// Latest serial round trips, oldest first
+    latency: VecDeque<Duration>,
+    log: VecDeque<String>,
+}
+
+impl Activity {
+    fn connect(&mut self, client: &str) {
+        let now = Instant::now();
+        self.clients.insert(
+            client.to_string(),
+            ClientActivity {
+                connected: now,
+                commands: 0,
+                responses: 0,
+                recent: VecDeque::new(),
+                last_activity: now,
+                last_command: String::new(),
+            },
+        );
+    }
+
+    fn disconnect(&mut self, client: &str) {
+        self.clients.remove(client);
+    }
+
+    /// Count the 4-byte `commands` forwarded for `client`
+    fn forwarded<'a>(&mut self, client: &str, commands: impl Iterator<Item = &'a [u8]>) {
+        let now = Instant::now();
+        let Some(activity) = self.clients.get_mut(client) else {
+            return;
+        };
+        for cmd in commands {
+            self.commands += 1;
+            activity.commands += 1;
+            activity.recent.push_back(now);
+            activity.last_command = match Command::decode(cmd) {
+                Some(cmd) => cmd.to_string(),
+                None => format!("{:02X?}", cmd),
+            };
+        }
+        activity.last_activity = now;
+    }
+
+    /// Count a response relayed to `client`, `latency` after the command went out
+    fn answered(&mut self, client: &str, latency: Duration) {
+        self.responses += 1;
+        if self.latency.len() == LATENCY_WINDOW {
+            self.latency.pop_front();
+        }
+        self.latency.push_back(latency);
+        if let Some(activity) = self.clients.get_mut(client) {
+            activity.responses += 1;
+            activity.last_activity = Instant::now();
+        }
+    }
+
+    fn serial_error(&mut self) {
+        self.serial_errors += 1;
+    }
+
+    fn log(&mut self, message: String) {
+        if self.log.len() == LOG_CAPACITY {
+            self.log.pop_front();
+        }
+        self.log.push_back(message);
+    }
+}
+
+#[derive(Debug, Default)]
+struct RolesState {
+    /// Client allowed to write, if any
+    controller: Option<String>,
+    /// Controllers displaced by a takeover that have not been told yet
+    displaced: HashSet<String>,
+}
+
+/// Which client may write to the device
+///
+/// The first client to connect (or to write while nobody is in control)
+/// becomes the controller; everyone else is an observer that may only send
+/// keepalives until it sends [`Command::Takeover`].
+#[derive(Debug, Default)]
+struct Roles {
+    state: Mutex<RolesState>,
+}
+
+impl Roles {
+    /// Register a new client, making it the controller if there is none
+    fn join(&self, client: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.controller.is_none() {
+            state.controller = Some(client.to_string());
+        }
+        state.controller.as_deref() == Some(client)
+    }
+
+    /// The client in control, if any
+    fn controller(&self) -> Option<String> {
+        self.state.lock().unwrap().controller.clone()
+    }
+
+    /// Forget a client, returning whether it released control
+    fn leave(&self, client: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.displaced.remove(client);
+        if state.controller.as_deref() == Some(client) {
+            state.controller = None;
+            return true;
+        }
+        false
+    }
+
+    /// Make `client` the controller, returning the client it displaced
+    fn take_over(&self, client: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.displaced.remove(client);
+        let previous = state.controller.replace(client.to_string())?;
+        if previous == client {
+            return None;
+        }
+        state.displaced.insert(previous.clone());
+        Some(previous)
+    }
+
+    /// Whether `client` may write, or the status to refuse it with
+    fn check_write(&self, client: &str) -> std::result::Result<(), u8> {
+        let state = &mut *self.state.lock().unwrap();
+        match state.controller.as_deref() {
+            Some(controller) if controller == client => Ok(()),
+            None => {
+                state.controller = Some(client.to_string());
+                Ok(())
+            }
+            Some(_) if state.displaced.remove(client) => Err(protocol::STATUS_DISPLACED),
+            Some(_) => Err(protocol::STATUS_READ_ONLY),
+        }
+    }
 }
 
 /// Response format detector and handler
@@ -73,366 +532,2158 @@ fn parse_response_header(first_byte: u8, second_byte: Option<u8>) -> Result<Resp
     }
 }
 
-/// Handle a single TCP client connection
-fn handle_client(
-    mut tcp_stream: TcpStream,
-    serial_device: String,
-    verbose: bool,
-    shutdown_flag: Arc<AtomicBool>,
-) -> Result<()> {
-    let client_addr = tcp_stream.peer_addr()?;
+/// Take all complete commands out of `pending`, dropping frames with a bad CRC
+///
+/// A partial command stays in `pending` until the rest arrives, so a command
+/// split across TCP reads is never padded into a different one.
+fn take_commands(pending: &mut Vec<u8>, config: &BridgeConfig) -> Vec<u8> {
+    let frame_len = framing::command_frame_len(config.crc);
+    let complete = pending.len() - pending.len() % frame_len;
+    let mut valid = Vec::with_capacity(complete);
 
-    if verbose {
-        println!("Client connected: {}", client_addr);
+    for frame in pending[..complete].chunks(frame_len) {
+        if !config.crc {
+            valid.extend_from_slice(frame);
+            continue;
+        }
+        match framing::check_crc(frame) {
+            Ok(_) => valid.extend_from_slice(frame),
+            Err(e) => config.log_error(format!("Dropping client frame: {}", e)),
+        }
     }
+    pending.drain(..complete);
 
-    // Set TCP stream timeouts
-    tcp_stream.set_read_timeout(Some(Duration::from_millis(100)))?;
-    tcp_stream.set_write_timeout(Some(Duration::from_millis(1000)))?;
-
-    // Open serial port
-    let mut serial_port = serialport::new(&serial_device, 115_200)
-        .timeout(Duration::from_millis(200))
-        .data_bits(serialport::DataBits::Eight)
-        .stop_bits(serialport::StopBits::One)
-        .parity(serialport::Parity::None)
-        .flow_control(serialport::FlowControl::None)
-        .open()
-        .with_context(|| format!("Failed to open serial port: {}", serial_device))?;
-
-    if verbose {
-        println!("Opened serial port: {} at 115200 8N1", serial_device);
+    if config.verbose && !pending.is_empty() {
+        config.log(format!(
+            "Holding {} bytes of partial {}",
+            pending.len(),
+            if config.crc { "CRC frame" } else { "command" }
+        ));
     }
 
-    let mut tcp_buffer = [0u8; 1024];
-    let mut serial_buffer = [0u8; 1024];
+    valid
+}
 
-    while !shutdown_flag.load(Ordering::Relaxed) {
-        // Read from TCP client
-        match tcp_stream.read(&mut tcp_buffer) {
-            Ok(0) => {
-                // Client disconnected
-                if verbose {
-                    println!("Client {} disconnected", client_addr);
-                }
-                break;
-            }
-            Ok(bytes_read) => {
-                let request_data = &tcp_buffer[..bytes_read];
+/// Drop commands that fail strict validation, reporting why
+fn drop_invalid_commands(commands: Vec<u8>, config: &BridgeConfig) -> Vec<u8> {
+    let mut valid = Vec::with_capacity(commands.len());
+    for frame in commands.chunks(framing::command_frame_len(config.crc)) {
+        match Command::validate(&frame[..framing::COMMAND_LEN]) {
+            Ok(_) => valid.extend_from_slice(frame),
+            Err(e) => config.log_error(format!("Dropping client command: {}", e)),
+        }
+    }
+    valid
+}
 
-                if verbose {
-                    println!("TCP → Serial: {} bytes: {:02X?}", bytes_read, request_data);
-                }
+/// Answer heartbeats and, with `--roles`, takeovers and writes from observers
+///
+/// Hellos, from observers too, are forwarded with the CRC feature removed; with
+/// `--translate legacy-device` they and register reads are answered here.
+///
+/// Returns the commands to forward and the responses to send the client
+/// directly; none of the commands answered here reach the device.
+fn answer_locally(
+    commands: Vec<u8>,
+    client_addr: &str,
+    config: &BridgeConfig,
+) -> (Vec<u8>, Vec<u8>) {
+    let mut forward = Vec::with_capacity(commands.len());
+    let mut responses = Vec::new();
+    let mut respond = |response: &[u8]| {
+        let mut response = response.to_vec();
+        if config.crc {
+            framing::append_crc(&mut response);
+        }
+        responses.extend(config.tcp_framing.encode(&response));
+    };
 
-                // Forward request to serial device (with padding to 4-byte boundary)
-                let mut padded_data = request_data.to_vec();
-                while padded_data.len() % 4 != 0 {
-                    padded_data.push(0);
+    for frame in commands.chunks(framing::command_frame_len(config.crc)) {
+        let command = Command::decode(&frame[..framing::COMMAND_LEN]);
+        if command == Some(Command::Heartbeat) {
+            respond(&protocol::HEARTBEAT_RESPONSE);
+            continue;
+        }
+        if let (Some(Translation::LegacyDevice), Some(twin)) = (config.translate, &config.twin) {
+            match command {
+                Some(Command::Hello { features, .. }) => {
+                    let [hi, lo] = features
+                        .intersection(Features::EXTENDED | Features::READBACK)
+                        .bits()
+                        .to_be_bytes();
+                    respond(&[0x01, 0x03, PROTOCOL_VERSION, hi, lo]);
+                    continue;
                 }
-
-                match serial_port.write_all(&padded_data) {
-                    Ok(_) => {
-                        if verbose && padded_data.len() != bytes_read {
-                            println!(
-                                "Serial write: {} bytes (padded from {}): {:02X?}",
-                                padded_data.len(),
-                                bytes_read,
-                                padded_data
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Serial write error: {}", e);
-                        continue;
-                    }
+                Some(Command::RegisterRead { reg }) => {
+                    let [hi, lo] = twin.register(reg).unwrap_or(0).to_be_bytes();
+                    respond(&[0x01, 0x02, hi, lo]);
+                    continue;
                 }
-
-                // Read response from serial device
-                match read_serial_response(&mut *serial_port, &mut serial_buffer, verbose) {
-                    Ok(response_data) => {
-                        if !response_data.is_empty() {
-                            if verbose {
-                                println!(
-                                    "Serial → TCP: {} bytes: {:02X?}",
-                                    response_data.len(),
-                                    response_data
-                                );
-                            }
-
-                            // Forward response to TCP client
-                            if let Err(e) = tcp_stream.write_all(&response_data) {
-                                eprintln!("TCP write error to {}: {}", client_addr, e);
-                                break;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        if verbose {
-                            eprintln!("Serial read error: {}", e);
-                        }
-                        // Continue operation even on read errors
-                    }
+                Some(Command::TableRead { table, index }) => {
+                    let [hi, lo] = twin.table_entry(table, index).to_be_bytes();
+                    respond(&[0x01, 0x02, hi, lo]);
+                    continue;
                 }
+                _ => {}
             }
-            Err(e)
-                if e.kind() == std::io::ErrorKind::WouldBlock
-                    || e.kind() == std::io::ErrorKind::TimedOut =>
-            {
-                // Timeout, continue listening
-                continue;
+        }
+        // The bridge's framing is fixed, so CRC cannot be switched on through it
+        if let Some(Command::Hello { version, features }) = command {
+            let mut hello = Command::Hello {
+                version,
+                features: features.without(Features::CRC),
             }
-            Err(e) => {
-                eprintln!("TCP read error from {}: {}", client_addr, e);
-                break;
+            .encode()
+            .to_vec();
+            if config.crc {
+                framing::append_crc(&mut hello);
             }
+            forward.extend(hello);
+            continue;
         }
-    }
-
-    if verbose {
-        println!("Connection to {} closed", client_addr);
-    }
-
-    Ok(())
-}
-
-/// Read complete response from serial device, handling both legacy and extended formats
-fn read_serial_response(
-    serial_port: &mut dyn SerialPort,
-    buffer: &mut [u8],
-    verbose: bool,
-) -> Result<Vec<u8>> {
-    // First, try to read at least 2 bytes for header
-    let mut response_data = Vec::new();
-    let mut bytes_needed = 2; // Start by reading header
-    let mut total_read = 0;
-
-    while total_read < bytes_needed && total_read < buffer.len() {
-        match serial_port.read(&mut buffer[total_read..]) {
-            Ok(0) => break, // No more data
-            Ok(n) => {
-                response_data.extend_from_slice(&buffer[total_read..total_read + n]);
-                total_read += n;
-
-                // Once we have at least 2 bytes, determine the response format
-                if total_read >= 2 && bytes_needed == 2 {
-                    match parse_response_header(response_data[0], Some(response_data[1])) {
-                        Ok(response_type) => {
-                            bytes_needed = response_type.expected_length();
-                            if verbose {
-                                match response_type {
-                                    ResponseType::Stadard => {
-                                        println!("Detected Stadard response format (2 bytes)");
-                                    }
-                                    ResponseType::Extended { payload_length } => {
-                                        println!("Detected extended response format ({} bytes payload, {} total)",
-                                               payload_length, bytes_needed);
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            if verbose {
-                                eprintln!("Response format error: {}, treating as legacy", e);
-                            }
-                            bytes_needed = 2; // Fall back to legacy format
-                        }
-                    }
+        let Some(roles) = &config.roles else {
+            forward.extend_from_slice(frame);
+            continue;
+        };
+        match command {
+            Some(Command::Takeover) => {
+                match roles.take_over(client_addr) {
+                    Some(previous) => config.log(format!(
+                        "Client {} took control from {}",
+                        client_addr, previous
+                    )),
+                    None => config.log(format!("Client {} is the controller", client_addr)),
                 }
+                respond(&[0x00, 0x00]);
             }
-            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                // Timeout - return what we have if anything
-                break;
-            }
-            Err(e) => {
-                return Err(anyhow!("Serial read error: {}", e));
+            // Reads change nothing, so observers may send them
+            Some(Command::KeepAlive | Command::RegisterRead { .. } | Command::TableRead { .. }) => {
+                forward.extend_from_slice(frame)
             }
+            _ => match roles.check_write(client_addr) {
+                Ok(()) => forward.extend_from_slice(frame),
+                Err(status) => {
+                    if config.verbose {
+                        config.log(format!(
+                            "Refusing command from observer {}: {:02X?}",
+                            client_addr, frame
+                        ));
+                    }
+                    respond(&[0x00, status]);
+                }
+            },
         }
     }
 
-    Ok(response_data)
+    (forward, responses)
 }
 
-/// Start TCP server on IPv4
-fn start_ipv4_server(
-    bind_addr: Ipv4Addr,
-    port: u16,
-    serial_device: String,
-    verbose: bool,
-    shutdown_flag: Arc<AtomicBool>,
-) -> Result<()> {
-    let socket_addr = SocketAddr::from((bind_addr, port));
-    let listener = TcpListener::bind(socket_addr)
-        .with_context(|| format!("Failed to bind to {}", socket_addr))?;
-
-    // Set non-blocking mode to allow checking shutdown flag
-    listener
-        .set_nonblocking(true)
-        .with_context(|| "Failed to set listener to non-blocking mode")?;
-
-    println!("TCP server listening on {} (IPv4)", socket_addr);
-
-    while !shutdown_flag.load(Ordering::Relaxed) {
-        match listener.accept() {
-            Ok((tcp_stream, _addr)) => {
-                let serial_device_clone = serial_device.clone();
-                let shutdown_flag_clone = shutdown_flag.clone();
-
-                thread::spawn(move || {
-                    if let Err(e) = handle_client(
-                        tcp_stream,
-                        serial_device_clone,
-                        verbose,
-                        shutdown_flag_clone,
-                    ) {
-                        eprintln!("Client handler error: {}", e);
-                    }
-                });
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // No pending connections, sleep briefly and check shutdown flag again
-                thread::sleep(Duration::from_millis(100));
-                continue;
+/// Decode COBS/SLIP framed commands, dropping undecodable or malformed frames
+fn take_framed_commands(
+    decoder: &mut FrameDecoder,
+    data: &[u8],
+    config: &BridgeConfig,
+) -> Vec<Vec<u8>> {
+    let crc = config.crc;
+    let frame_len = framing::command_frame_len(crc);
+    let mut commands = Vec::new();
+
+    for frame in decoder.push(data) {
+        let command = frame.and_then(|frame| {
+            if frame.len() != frame_len {
+                return Err(DacError::Protocol(format!(
+                    "expected {} bytes, got {}: {:02X?}",
+                    frame_len,
+                    frame.len(),
+                    frame
+                )));
             }
-            Err(e) => {
-                eprintln!("Failed to accept connection: {}", e);
-                thread::sleep(Duration::from_millis(100));
+            if crc {
+                framing::check_crc(&frame)?;
             }
+            Ok(frame)
+        });
+        match command {
+            Ok(command) => commands.push(command),
+            Err(e) => config.log_error(format!("Dropping client frame: {}", e)),
         }
     }
 
-    if verbose {
-        println!("IPv4 server on {} shutting down", socket_addr);
-    }
+    commands
+}
 
-    Ok(())
+/// Turn bytes received from a client into commands to forward and local responses
+///
+/// Raw commands split across reads wait in `pending` and framed ones in
+/// `decoder`. Heartbeats and `--roles` refusals are answered here, and
+/// `--strict` drops invalid commands.
+fn client_commands(
+    data: &[u8],
+    pending: &mut Vec<u8>,
+    decoder: &mut FrameDecoder,
+    client_addr: &str,
+    config: &BridgeConfig,
+) -> (Vec<u8>, Vec<u8>) {
+    // Whole (CRC-checked) commands, or padded to a 4-byte boundary if explicitly requested
+    let commands = if config.tcp_framing == StreamFraming::Raw {
+        if !config.crc && config.padding == Padding::Zero {
+            framing::encode_commands(data, false)
+        } else {
+            pending.extend_from_slice(data);
+            take_commands(pending, config)
+        }
+    } else {
+        take_framed_commands(decoder, data, config).concat()
+    };
+    let (commands, responses) = answer_locally(commands, client_addr, config);
+    let commands = if config.strict {
+        drop_invalid_commands(commands, config)
+    } else {
+        commands
+    };
+    (commands, responses)
 }
 
-/// Start TCP server on IPv6
-fn start_ipv6_server(
-    bind_addr: Ipv6Addr,
-    port: u16,
-    serial_device: String,
-    verbose: bool,
-    shutdown_flag: Arc<AtomicBool>,
-) -> Result<()> {
-    let socket_addr = SocketAddr::from((bind_addr, port));
-    let listener = TcpListener::bind(socket_addr)
-        .with_context(|| format!("Failed to bind to {}", socket_addr))?;
-
-    // Set non-blocking mode to allow checking shutdown flag
-    listener
-        .set_nonblocking(true)
-        .with_context(|| "Failed to set listener to non-blocking mode")?;
-
-    println!("TCP server listening on {} (IPv6)", socket_addr);
-
-    while !shutdown_flag.load(Ordering::Relaxed) {
-        match listener.accept() {
-            Ok((tcp_stream, _addr)) => {
-                let serial_device_clone = serial_device.clone();
-                let shutdown_flag_clone = shutdown_flag.clone();
-
-                thread::spawn(move || {
-                    if let Err(e) = handle_client(
-                        tcp_stream,
-                        serial_device_clone,
-                        verbose,
-                        shutdown_flag_clone,
-                    ) {
-                        eprintln!("Client handler error: {}", e);
-                    }
-                });
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // No pending connections, sleep briefly and check shutdown flag again
-                thread::sleep(Duration::from_millis(100));
-                continue;
-            }
-            Err(e) => {
-                eprintln!("Failed to accept connection: {}", e);
-                thread::sleep(Duration::from_millis(100));
-            }
+/// The 4-byte commands of `commands`, without their CRCs
+fn command_bytes<'a>(commands: &'a [u8], config: &BridgeConfig) -> impl Iterator<Item = &'a [u8]> {
+    commands
+        .chunks(framing::command_frame_len(config.crc))
+        .map(|frame| &frame[..framing::COMMAND_LEN])
+}
+
+/// Count, twin and audit commands that reached the device
+fn record_forwarded(commands: &[u8], client_addr: &str, config: &BridgeConfig) {
+    config.track(|activity| activity.forwarded(client_addr, command_bytes(commands, config)));
+    if let Some(twin) = &config.twin {
+        twin.apply(command_bytes(commands, config));
+    }
+    if let Some(audit) = &config.audit {
+        if let Err(e) = audit.record(client_addr, command_bytes(commands, config)) {
+            config.log_error(format!("Audit log write failed: {}", e));
         }
     }
+}
 
-    if verbose {
-        println!("IPv6 server on {} shutting down", socket_addr);
+/// Run a device response to `command` through the `--filter` chain
+///
+/// With `--crc` the filters see the response without its CRC, which is
+/// recomputed afterwards; a response failing its CRC check passes unfiltered,
+/// so the client still sees the corruption. `None` if a filter dropped it.
+fn filter_response(
+    response: Vec<u8>,
+    command: &[u8],
+    client_addr: &str,
+    config: &BridgeConfig,
+) -> Option<Vec<u8>> {
+    let Some(filters) = &config.filters else {
+        return Some(response);
+    };
+    let payload = if config.crc {
+        match framing::check_crc(&response) {
+            Ok(payload) => payload.to_vec(),
+            Err(_) => return Some(response),
+        }
+    } else {
+        response.clone()
+    };
+    let Some(mut filtered) = filters.lock().unwrap().apply(client_addr, command, payload) else {
+        if config.verbose {
+            config.log(format!(
+                "Filtered out response to {}: {:02X?}",
+                client_addr, response
+            ));
+        }
+        return None;
+    };
+    if config.crc {
+        framing::append_crc(&mut filtered);
     }
-
-    Ok(())
+    Some(filtered)
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Apply the device's stream framing to every command
+fn serial_frames(commands: &[u8], config: &BridgeConfig) -> Vec<u8> {
+    commands
+        .chunks(framing::command_frame_len(config.crc))
+        .flat_map(|command| config.serial_framing.encode(command))
+        .collect()
+}
 
-    // Set up graceful shutdown handling
-    let shutdown_flag = Arc::new(AtomicBool::new(false));
-    let shutdown_flag_clone = shutdown_flag.clone();
+/// The twin's state as command frames, for a device that may have reset
+/// while it was gone
+fn restore_frames(config: &BridgeConfig) -> Vec<u8> {
+    let Some(twin) = &config.twin else {
+        return Vec::new();
+    };
+    let commands: Vec<u8> = twin
+        .restore_commands()
+        .iter()
+        .flat_map(|command| command.encode())
+        .collect();
+    framing::encode_commands(&commands, config.crc)
+}
 
-    ctrlc::set_handler(move || {
-        println!("\nReceived interrupt signal, shutting down...");
-        shutdown_flag_clone.store(true, Ordering::Relaxed);
-    })?;
+/// Whether the serial device node exists; on Windows, whether the port is listed
+fn device_present(path: &str) -> bool {
+    #[cfg(windows)]
+    return discovery::resolve_serial(path).is_ok_and(|port| {
+        let port = port.trim_start_matches(r"\\.\");
+        serialport::available_ports()
+            .is_ok_and(|ports| ports.iter().any(|p| p.port_name.eq_ignore_ascii_case(port)))
+    });
+    #[cfg(not(windows))]
+    std::path::Path::new(path).exists()
+}
 
-    if args.verbose {
-        println!(
-            "Starting TCP server for serial device: {}",
-            args.serial_device
-        );
-        println!("Server will listen on port {} (IPv4 and IPv6)", args.port);
+/// Wait until the serial device appears, for at most `timeout`
+///
+/// Returns false if the bridge was shut down meanwhile.
+fn wait_for_device(
+    config: &BridgeConfig,
+    timeout: Option<Duration>,
+    shutdown_flag: &AtomicBool,
+) -> Result<bool> {
+    let path = config.device_name();
+    if device_present(&path) {
+        return Ok(true);
+    }
+    config.log(format!("Waiting for {} to appear", path));
+    let started = Instant::now();
+    while !device_present(&path) {
+        if shutdown_flag.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            return Err(anyhow!(
+                "{} did not appear within {}s",
+                path,
+                started.elapsed().as_secs()
+            ));
+        }
+        thread::sleep(DEVICE_WAIT_CHECK);
     }
+    config.log(format!(
+        "{} appeared after {:.1}s",
+        path,
+        started.elapsed().as_secs_f64()
+    ));
+    Ok(true)
+}
 
-    // Determine bind addresses
-    let (ipv4_addr, ipv6_addr): (Option<Ipv4Addr>, Option<Ipv6Addr>) = match &args.bind {
-        Some(bind_str) => {
-            // Parse specific bind address
-            match bind_str.parse::<Ipv4Addr>() {
-                Ok(addr) => (Some(addr), None),
-                Err(_) => match bind_str.parse::<Ipv6Addr>() {
-                    Ok(addr) => (None, Some(addr)),
-                    Err(_) => {
-                        return Err(anyhow!("Invalid bind address: {}", bind_str));
-                    }
-                },
+/// Open the serial port, or connect to the upstream bridge in relay mode
+fn open_device(config: &BridgeConfig) -> Result<Box<dyn Link>> {
+    let (path, baud) = match &config.device {
+        Target::Serial { path, baud } => (path, *baud),
+        Target::Pty { path } => (path, DEFAULT_BAUD),
+        upstream => {
+            let options = LinkOptions {
+                read_timeout: Duration::from_millis(200),
+                write_timeout: Duration::from_millis(1000),
+            };
+            let link = transport::open_target(upstream, &options)
+                .with_context(|| format!("Failed to connect to upstream bridge {}", upstream))?;
+            if config.verbose {
+                config.log(format!("Relaying to upstream bridge {}", upstream));
             }
-        }
-        None => {
-            // Bind to all interfaces
-            (Some(Ipv4Addr::UNSPECIFIED), Some(Ipv6Addr::UNSPECIFIED))
+            return Ok(link);
         }
     };
 
-    // Start servers
-    let mut handles = Vec::new();
+    #[cfg(unix)]
+    let mut serial_port: Box<dyn serialport::SerialPort> =
+        Box::new(open_tuned(path, baud, config)?);
+    #[cfg(not(unix))]
+    let mut serial_port = serial_port(path, baud)
+        .open()
+        .with_context(|| format!("Failed to open serial port: {}", path))?;
 
-    // Start IPv4 server if requested
-    if let Some(addr) = ipv4_addr {
-        let serial_device = args.serial_device.clone();
-        let shutdown_flag = shutdown_flag.clone();
-        let handle = thread::spawn(move || {
-            start_ipv4_server(addr, args.port, serial_device, args.verbose, shutdown_flag)
-        });
-        handles.push(handle);
+    if config.verbose {
+        config.log(format!("Opened serial port: {} at {} 8N1", path, baud));
+    }
+    if let Some(turnaround) = config.rts() {
+        serial_port.write_request_to_send(false)?;
+        return Ok(Box::new(Rs485Link {
+            port: serial_port,
+            turnaround,
+        }));
     }
+    Ok(Box::new(serial_port))
+}
 
-    // Start IPv6 server if requested
-    if let Some(addr) = ipv6_addr {
-        thread::sleep(Duration::from_millis(100)); //sleep a little bit to allow the IPv4 server to start
-        let serial_device = args.serial_device.clone();
-        let shutdown_flag = shutdown_flag.clone();
-        let handle = thread::spawn(move || {
-            start_ipv6_server(addr, args.port, serial_device, args.verbose, shutdown_flag)
-        });
-        handles.push(handle);
+/// Send `data` through an RS-485 transceiver whose direction follows RTS
+///
+/// RTS is asserted for the transmission and released `turnaround` after the
+/// UART has sent the last byte, so the device's answer can be received.
+fn transmit(
+    port: &mut dyn serialport::SerialPort,
+    data: &[u8],
+    turnaround: Duration,
+) -> std::io::Result<()> {
+    port.write_request_to_send(true)?;
+    // Drains the UART, not only the kernel buffer
+    let sent = port.write_all(data).and_then(|_| port.flush());
+    thread::sleep(turnaround);
+    // Back to receive even after a failed write, not to hold the bus
+    let released = port.write_request_to_send(false);
+    sent?;
+    Ok(released?)
+}
+
+/// A serial port behind a half-duplex RS-485 transceiver, with `--rs485`
+///
+/// Every write is one transmission, see [`transmit`].
+struct Rs485Link {
+    port: Box<dyn serialport::SerialPort>,
+    turnaround: Duration,
+}
+
+impl Read for Rs485Link {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read(buffer)
+    }
+}
+
+impl Write for Rs485Link {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        transmit(&mut *self.port, data, self.turnaround)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Link for Rs485Link {
+    fn kind(&self) -> &'static str {
+        "RS-485"
+    }
+
+    fn try_clone_link(&self) -> std::io::Result<Box<dyn Link>> {
+        Ok(Box::new(Rs485Link {
+            port: self.port.try_clone()?,
+            turnaround: self.turnaround,
+        }))
+    }
+}
+
+/// Open the serial port with the `--latency-timer`, `--no-buffering`,
+/// `--vmin` and `--vtime` settings applied
+#[cfg(unix)]
+fn open_tuned(path: &str, baud: u32, config: &BridgeConfig) -> Result<serialport::TTYPort> {
+    let port = serial_port(path, baud)
+        .open_native()
+        .with_context(|| format!("Failed to open serial port: {}", path))?;
+    if !config.tuning.is_default() {
+        config
+            .tuning
+            .apply(path, port.as_raw_fd())
+            .with_context(|| format!("Failed to tune serial port {}", path))?;
+        if config.verbose {
+            config.log(format!("Serial port tuning: {}", config.tuning));
+        }
+    }
+    Ok(port)
+}
+
+/// Settings of the serial port: 8N1 without flow control
+fn serial_port(path: &str, baud: u32) -> serialport::SerialPortBuilder {
+    serialport::new(path, baud)
+        .timeout(Duration::from_millis(200))
+        .data_bits(serialport::DataBits::Eight)
+        .stop_bits(serialport::StopBits::One)
+        .parity(serialport::Parity::None)
+        .flow_control(serialport::FlowControl::None)
+}
+
+/// Whether a read error is only the read timeout expiring
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
+/// Poll token of the IPv4 listener
+const LISTENER_V4: Token = Token(0);
+
+/// Poll token of the IPv6 listener
+const LISTENER_V6: Token = Token(1);
+
+/// Wakes the event loop on Ctrl+C and when the threads of a device link have
+/// data or an error
+const WAKER: Token = Token(2);
+
+/// Poll token of a device polled through its descriptor: a serial port, a pty
+/// or an upstream bridge over TCP
+const DEVICE: Token = Token(3);
+
+/// Stands in for a client in the device's queues while its state is restored
+const RESTORE: Token = Token(4);
+
+/// Poll token of the first client
+const FIRST_CLIENT: usize = 16;
+
+/// Time the device has to answer a forwarded command before the bridge stops waiting
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Longest wait for outstanding responses when shutting down
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Interval at which `--device-wait` looks for the device node
+const DEVICE_WAIT_CHECK: Duration = Duration::from_millis(250);
+
+/// Unsent responses after which a client that does not read is disconnected
+const OUTBOX_LIMIT: usize = 64 * 1024;
+
+/// Unwritten commands after which clients are not read until the device has
+/// taken some of them
+const DEVICE_BACKLOG: usize = 16 * 1024;
+
+/// Write `outbox` until it is empty or `writer` would block, removing what was written
+fn write_out(writer: &mut dyn Write, outbox: &mut Vec<u8>) -> std::io::Result<()> {
+    while !outbox.is_empty() {
+        match writer.write(outbox) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                outbox.drain(..n);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// How the event loop reaches a client
+enum ClientIo {
+    Tcp(TcpStream),
+    /// stdin and stdout, with `--stdio`
+    #[cfg(unix)]
+    Stdio {
+        input: Receiver,
+        output: Sender,
+    },
+}
+
+impl ClientIo {
+    /// Non-blocking handles on stdin and stdout
+    #[cfg(unix)]
+    fn stdio() -> std::io::Result<Self> {
+        let input = Receiver::from(std::io::stdin().as_fd().try_clone_to_owned()?);
+        let output = Sender::from(std::io::stdout().as_fd().try_clone_to_owned()?);
+        input.set_nonblocking(true)?;
+        output.set_nonblocking(true)?;
+        Ok(Self::Stdio { input, output })
+    }
+
+    fn register(&mut self, registry: &Registry, token: Token) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(stream) => {
+                registry.register(stream, token, Interest::READABLE | Interest::WRITABLE)
+            }
+            // Both under the client's token; a plain file cannot be polled, but
+            // it never blocks either
+            #[cfg(unix)]
+            Self::Stdio { input, output } => {
+                allow_unpollable(registry.register(input, token, Interest::READABLE))?;
+                allow_unpollable(registry.register(output, token, Interest::WRITABLE))
+            }
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) {
+        match self {
+            Self::Tcp(stream) => {
+                let _ = registry.deregister(stream);
+            }
+            #[cfg(unix)]
+            Self::Stdio { input, output } => {
+                let _ = registry.deregister(input);
+                let _ = registry.deregister(output);
+                // The flag is shared with whoever else has stdin and stdout open
+                let _ = input.set_nonblocking(false);
+                let _ = output.set_nonblocking(false);
+            }
+        }
+    }
+}
+
+impl Read for ClientIo {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buffer),
+            #[cfg(unix)]
+            Self::Stdio { input, .. } => input.read(buffer),
+        }
+    }
+}
+
+impl Write for ClientIo {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(data),
+            #[cfg(unix)]
+            Self::Stdio { output, .. } => output.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Stdio { output, .. } => output.flush(),
+        }
+    }
+}
+
+/// Accept a failed registration of a plain file, which epoll refuses
+#[cfg(unix)]
+fn allow_unpollable(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => Ok(()),
+        result => result,
+    }
+}
+
+/// One client of the event loop
+struct Client {
+    io: ClientIo,
+    addr: String,
+    /// Partial command or CRC frame carried over between reads
+    pending: Vec<u8>,
+    decoder: FrameDecoder,
+    last_heard: Instant,
+    /// Responses the client has not taken yet
+    outbox: Vec<u8>,
+}
+
+impl Client {
+    /// Queue `data` for the client and write as much as it takes
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.outbox.extend_from_slice(data);
+        if self.outbox.len() > OUTBOX_LIMIT {
+            return Err(std::io::Error::other("client is not reading its responses"));
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        write_out(&mut self.io, &mut self.outbox)
+    }
+}
+
+/// How the event loop reaches the device
+enum DeviceIo {
+    /// A serial port or pty, registered with the poll through its file
+    /// descriptor, and a non-blocking handle on it for writing; without one,
+    /// RTS switches an RS-485 transceiver and every command is sent whole
+    #[cfg(unix)]
+    Port {
+        port: serialport::TTYPort,
+        writer: Option<Sender>,
+    },
+    /// An upstream bridge over TCP
+    Upstream(TcpStream),
+    /// Any other link (TLS, UDP, SSH, USB, a plugin, or a serial port off
+    /// Unix), which has no descriptor to poll; see [`spawn_link_threads`]
+    Link {
+        commands: mpsc::Sender<Vec<u8>>,
+        received: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+        stop: Arc<AtomicBool>,
+    },
+}
+
+/// The device shared by every client of the event loop
+struct Device {
+    io: DeviceIo,
+    /// Commands the device has not taken yet
+    outbox: Vec<u8>,
+    /// Raw response bytes that do not form a whole response yet
+    partial: Vec<u8>,
+    decoder: FrameDecoder,
+    /// Client, send time and bytes of every forwarded command awaiting its
+    /// response, oldest first
+    outstanding: VecDeque<(Token, Instant, Vec<u8>)>,
+    /// Send one command at a time, with `--rs485`
+    half_duplex: bool,
+    /// RS-485 turnaround delay, with `--rs485` on a serial port
+    rts: Option<Duration>,
+    /// Commands (CRC frames) waiting for the half-duplex bus, oldest first
+    waiting: VecDeque<(Token, Vec<u8>)>,
+}
+
+impl Device {
+    fn open(config: &BridgeConfig, poll: &Poll, waker: &Arc<Waker>) -> Result<Self> {
+        let io = match &config.device {
+            #[cfg(unix)]
+            Target::Serial { path, .. } | Target::Pty { path } => {
+                let baud = match config.device {
+                    Target::Serial { baud, .. } => baud,
+                    _ => DEFAULT_BAUD,
+                };
+                let mut port = open_tuned(path, baud, config)?;
+                let writer = if config.rts().is_some() {
+                    use serialport::SerialPort;
+                    port.write_request_to_send(false)?;
+                    None
+                } else {
+                    // SAFETY: the clone's descriptor is open, and handed over whole
+                    let writer =
+                        unsafe { Sender::from_raw_fd(port.try_clone_native()?.into_raw_fd()) };
+                    writer.set_nonblocking(true)?;
+                    Some(writer)
+                };
+                poll.registry().register(
+                    &mut SourceFd(&port.as_raw_fd()),
+                    DEVICE,
+                    Interest::READABLE | Interest::WRITABLE,
+                )?;
+                if config.verbose {
+                    config.log(format!("Opened serial port: {} at {} 8N1", path, baud));
+                }
+                DeviceIo::Port { port, writer }
+            }
+            Target::Tcp { address } => {
+                let stream = std::net::TcpStream::connect(address.as_str()).with_context(|| {
+                    format!("Failed to connect to upstream bridge {}", config.device)
+                })?;
+                stream.set_nodelay(true)?;
+                stream.set_nonblocking(true)?;
+                let mut stream = TcpStream::from_std(stream);
+                poll.registry().register(
+                    &mut stream,
+                    DEVICE,
+                    Interest::READABLE | Interest::WRITABLE,
+                )?;
+                if config.verbose {
+                    config.log(format!("Relaying to upstream bridge {}", config.device));
+                }
+                DeviceIo::Upstream(stream)
+            }
+            _ => spawn_link_threads(open_device(config)?, waker.clone())?,
+        };
+        Ok(Self {
+            io,
+            outbox: Vec::new(),
+            partial: Vec::new(),
+            decoder: FrameDecoder::new(config.serial_framing),
+            outstanding: VecDeque::new(),
+            half_duplex: config.rs485.is_some(),
+            rts: config.rts(),
+            waiting: VecDeque::new(),
+        })
+    }
+
+    /// Queue `data` for the device and write as much as it takes
+    ///
+    /// Through an RS-485 transceiver the data is sent whole instead, as RTS
+    /// has to be held until the UART has sent the last byte.
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match (&mut self.io, self.rts) {
+            #[cfg(unix)]
+            (DeviceIo::Port { port, writer: None }, Some(turnaround)) => {
+                transmit(port, data, turnaround)
+            }
+            (DeviceIo::Link { commands, .. }, _) => commands
+                .send(data.to_vec())
+                .map_err(|_| std::io::ErrorKind::BrokenPipe.into()),
+            _ => {
+                self.outbox.extend_from_slice(data);
+                self.flush()
+            }
+        }
+    }
+
+    /// Write queued commands until the device takes no more
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.io {
+            #[cfg(unix)]
+            DeviceIo::Port {
+                writer: Some(writer),
+                ..
+            } => write_out(writer, &mut self.outbox),
+            DeviceIo::Upstream(stream) => write_out(stream, &mut self.outbox),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether clients have to wait for the device to take queued commands
+    fn backed_up(&self) -> bool {
+        self.outbox.len() >= DEVICE_BACKLOG
+    }
+
+    /// Everything the device has sent since the last call
+    ///
+    /// An error from a link served by threads means they have stopped.
+    fn read(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match &mut self.io {
+            #[cfg(unix)]
+            DeviceIo::Port { port, .. } => {
+                use serialport::SerialPort;
+                let mut buffer = [0u8; 1024];
+                while port.bytes_to_read()? > 0 {
+                    match port.read(&mut buffer)? {
+                        0 => break,
+                        n => data.extend_from_slice(&buffer[..n]),
+                    }
+                }
+            }
+            DeviceIo::Upstream(stream) => {
+                let mut buffer = [0u8; 1024];
+                loop {
+                    match stream.read(&mut buffer) {
+                        Ok(0) if data.is_empty() => {
+                            return Err(std::io::ErrorKind::UnexpectedEof.into())
+                        }
+                        Ok(0) => break,
+                        Ok(n) => data.extend_from_slice(&buffer[..n]),
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            DeviceIo::Link { received, .. } => loop {
+                match received.try_recv() {
+                    Ok(received) => data.extend(received?),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        return Err(std::io::ErrorKind::BrokenPipe.into())
+                    }
+                }
+            },
+        }
+        Ok(data)
+    }
+
+    /// Split received bytes into whole responses
+    fn responses(&mut self, data: &[u8], config: &BridgeConfig) -> Vec<Vec<u8>> {
+        if config.serial_framing != StreamFraming::Raw {
+            return self
+                .decoder
+                .push(data)
+                .into_iter()
+                .filter_map(|frame| {
+                    frame
+                        .map_err(|e| config.log_error(format!("Dropping device frame: {}", e)))
+                        .ok()
+                })
+                .collect();
+        }
+
+        self.partial.extend_from_slice(data);
+        let mut responses = Vec::new();
+        while self.partial.len() >= 2 {
+            let mut len = match parse_response_header(self.partial[0], Some(self.partial[1])) {
+                Ok(response_type) => response_type.expected_length(),
+                Err(e) => {
+                    if config.verbose {
+                        config
+                            .log_error(format!("Response format error: {}, treating as legacy", e));
+                    }
+                    2
+                }
+            };
+            if config.crc {
+                len += framing::CRC_LEN;
+            }
+            if self.partial.len() < len {
+                break;
+            }
+            let response: Vec<u8> = self.partial.drain(..len).collect();
+            // Report corrupted device responses; the client validates them as well
+            if config.crc {
+                if let Err(e) = framing::check_crc(&response) {
+                    config.log_error(format!("Device response failed CRC check: {}", e));
+                }
+            }
+            responses.push(response);
+        }
+        responses
+    }
+
+    fn close(self, poll: &Poll) {
+        match self.io {
+            #[cfg(unix)]
+            DeviceIo::Port { port, .. } => {
+                let _ = poll.registry().deregister(&mut SourceFd(&port.as_raw_fd()));
+            }
+            DeviceIo::Upstream(mut stream) => {
+                let _ = poll.registry().deregister(&mut stream);
+            }
+            // The writer ends with its channel
+            DeviceIo::Link { stop, .. } => stop.store(true, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Serve a link that has no descriptor to poll from two threads: one reads
+/// it and passes the data on, the other writes the commands it is sent, and
+/// both wake the loop
+///
+/// The reader ends after passing on an error or end of file, or once `stop` is
+/// set; the writer after passing on an error, or once the device drops its
+/// sender.
+fn spawn_link_threads(mut link: Box<dyn Link>, waker: Arc<Waker>) -> Result<DeviceIo> {
+    let mut reader = link.try_clone_link()?;
+    let (sender, received) = mpsc::channel();
+    let (commands, queued) = mpsc::channel::<Vec<u8>>();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    {
+        let sender = sender.clone();
+        let waker = waker.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+            while !stop.load(Ordering::Relaxed) {
+                let result = match reader.read(&mut buffer) {
+                    Ok(0) => Err(std::io::ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => Ok(buffer[..n].to_vec()),
+                    Err(e) if is_timeout(&e) => continue,
+                    Err(e) => Err(e),
+                };
+                let ended = result.is_err();
+                if sender.send(result).is_err() || waker.wake().is_err() || ended {
+                    break;
+                }
+            }
+        });
+    }
+    thread::spawn(move || {
+        for data in queued {
+            if let Err(e) = link.write_all(&data).and_then(|_| link.flush()) {
+                let _ = sender.send(Err(e));
+                let _ = waker.wake();
+                break;
+            }
+        }
+    });
+
+    Ok(DeviceIo::Link {
+        commands,
+        received,
+        stop,
+    })
+}
+
+/// Every client and the device, served by one thread
+///
+/// The clients are TCP connections, or stdin and stdout with `--stdio`. The
+/// device is opened when the first client connects and closed after the last
+/// one leaves. Commands from all clients share it; the device answers
+/// in order, so each response goes to the client whose command is oldest.
+struct EventLoop {
+    poll: Poll,
+    waker: Arc<Waker>,
+    listeners: Vec<(Token, TcpListener)>,
+    clients: HashMap<Token, Client>,
+    next_token: usize,
+    device: Option<Device>,
+    /// Set while a device that went away is being reopened
+    reopen: Option<Backoff>,
+    sleep: SleepDetector,
+    /// Set once the stdio client's input ends; the responses due still go out
+    input_closed: bool,
+    /// Why the device was lost, which ends a stdio bridge with an error
+    lost: Option<String>,
+    config: BridgeConfig,
+}
+
+impl EventLoop {
+    /// Listen on the given addresses; an IPv6 listener next to an IPv4 one is optional
+    fn bind(
+        config: BridgeConfig,
+        ipv4_addr: Option<Ipv4Addr>,
+        ipv6_addr: Option<Ipv6Addr>,
+        port: u16,
+    ) -> Result<Self> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let mut listeners = Vec::new();
+
+        let addresses = [
+            (
+                LISTENER_V4,
+                ipv4_addr.map(|addr| SocketAddr::from((addr, port))),
+            ),
+            (
+                LISTENER_V6,
+                ipv6_addr.map(|addr| SocketAddr::from((addr, port))),
+            ),
+        ];
+        for (token, socket_addr) in addresses {
+            let Some(socket_addr) = socket_addr else {
+                continue;
+            };
+            let family = if socket_addr.is_ipv4() {
+                "IPv4"
+            } else {
+                "IPv6"
+            };
+            let mut listener = match TcpListener::bind(socket_addr) {
+                Ok(listener) => listener,
+                // Dual-stack systems may already serve IPv6 on the IPv4 port
+                Err(e) if !listeners.is_empty() => {
+                    if config.verbose {
+                        config.log(format!(
+                            "Not listening on {} ({}): {}",
+                            socket_addr, family, e
+                        ));
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to bind to {}", socket_addr))
+                }
+            };
+            poll.registry()
+                .register(&mut listener, token, Interest::READABLE)?;
+            config.log(format!(
+                "TCP server listening on {} ({})",
+                socket_addr, family
+            ));
+            listeners.push((token, listener));
+        }
+
+        Ok(Self::new(poll, waker, listeners, config))
+    }
+
+    /// Serve a single client on stdin and stdout, with `--stdio`
+    #[cfg(unix)]
+    fn stdio(config: BridgeConfig) -> Result<Self> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let mut server = Self::new(poll, waker, Vec::new(), config);
+        let io = ClientIo::stdio().context("Failed to set up stdin and stdout")?;
+        let token = server.add_client(io, "stdio".to_string())?;
+        // The poll does not announce input from a plain file
+        server.client_ready(token, true, false);
+        Ok(server)
+    }
+
+    fn new(
+        poll: Poll,
+        waker: Arc<Waker>,
+        listeners: Vec<(Token, TcpListener)>,
+        config: BridgeConfig,
+    ) -> Self {
+        Self {
+            poll,
+            waker,
+            listeners,
+            clients: HashMap::new(),
+            next_token: FIRST_CLIENT,
+            device: None,
+            reopen: None,
+            sleep: SleepDetector::new(),
+            input_closed: false,
+            lost: None,
+            config,
+        }
+    }
+
+    /// Serve until `shutdown_flag` is set (and the waker woken), or with
+    /// `--stdio` until the client is gone
+    fn run(mut self, shutdown_flag: &AtomicBool) -> Result<()> {
+        let mut events = Events::with_capacity(256);
+        self.report_health();
+        while !shutdown_flag.load(Ordering::Relaxed) && !self.finished() {
+            match self.poll.poll(&mut events, self.next_deadline()) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => result?,
+            }
+            self.check_sleep();
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER_V4 | LISTENER_V6 => self.accept(event.token()),
+                    WAKER => self.device_readable(false),
+                    DEVICE => self.device_ready(event),
+                    // A closed pipe only hangs up, without being readable
+                    token => self.client_ready(
+                        token,
+                        event.is_readable() || event.is_read_closed(),
+                        event.is_writable(),
+                    ),
+                }
+            }
+            self.expire();
+            self.reopen_device();
+            self.config.log_repeats();
+            self.report_health();
+        }
+
+        self.drain();
+        let tokens: Vec<Token> = self.clients.keys().copied().collect();
+        for token in tokens {
+            self.close_client(token);
+        }
+        if self.config.verbose {
+            for (_, listener) in &self.listeners {
+                if let Ok(addr) = listener.local_addr() {
+                    self.config.log(format!("Server on {} shutting down", addr));
+                }
+            }
+        }
+        match self.lost {
+            Some(reason) => Err(anyhow!(reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the stdio client is done; a TCP server runs until shut down
+    fn finished(&self) -> bool {
+        self.config.stdio && (self.input_closed || self.clients.is_empty())
+    }
+
+    /// Stop taking clients and commands, and pass on the responses still due for
+    /// at most [`DRAIN_TIMEOUT`], so a restart loses no answer to a sent command
+    fn drain(&mut self) {
+        if let Some(health) = &self.config.health {
+            health.set_status(Status::Draining);
+        }
+        for (_, listener) in &mut self.listeners {
+            let _ = self.poll.registry().deregister(listener);
+        }
+        if !self.has_pending() {
+            return;
+        }
+        // The normal end of a stdio session
+        let quiet = self.config.stdio && !self.config.verbose;
+        if let Some(device) = self.device.as_ref().filter(|_| !quiet) {
+            self.config.log(format!(
+                "Draining {} outstanding command(s)",
+                device.outstanding.len() + device.waiting.len()
+            ));
+        }
+
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        let mut events = Events::with_capacity(256);
+        while self.has_pending() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                self.config
+                    .log_error("Shutting down with responses still outstanding");
+                return;
+            }
+            let timeout = self.next_deadline().map_or(left, |next| next.min(left));
+            match self.poll.poll(&mut events, Some(timeout)) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.config.log_error(format!("Drain failed: {}", e));
+                    return;
+                }
+                Ok(()) => {}
+            }
+            for event in events.iter() {
+                match event.token() {
+                    LISTENER_V4 | LISTENER_V6 => {}
+                    WAKER => self.device_readable(false),
+                    DEVICE => self.device_ready(event),
+                    // Only flush: commands sent now are not forwarded
+                    token => self.client_ready(token, false, event.is_writable()),
+                }
+            }
+            self.expire();
+        }
+    }
+
+    /// Whether commands await their responses or responses their clients
+    fn has_pending(&self) -> bool {
+        let device = self
+            .device
+            .as_ref()
+            .is_some_and(|device| !device.outstanding.is_empty() || !device.waiting.is_empty());
+        device
+            || self
+                .clients
+                .values()
+                .any(|client| !client.outbox.is_empty())
+    }
+
+    /// Publish the clients and the device's state for `--health`
+    fn report_health(&self) {
+        let Some(health) = &self.config.health else {
+            return;
+        };
+        health.update(|state| {
+            state.clients = self.clients.len();
+            state.device_open = self.device.is_some();
+            if state.status != Status::Draining {
+                state.status = if self.reopen.is_some() {
+                    Status::Reopening
+                } else {
+                    Status::Serving
+                };
+            }
+        });
+    }
+
+    /// Time until the oldest command or the quietest client times out, or the
+    /// device is to be reopened
+    fn next_deadline(&self) -> Option<Duration> {
+        let response = self
+            .device
+            .as_ref()
+            .and_then(|device| device.outstanding.front())
+            .map(|(_, sent, _)| *sent + RESPONSE_TIMEOUT);
+        let silence = self.config.client_timeout.and_then(|timeout| {
+            self.clients
+                .values()
+                .map(|client| client.last_heard + timeout)
+                .min()
+        });
+        let reopen = self
+            .reopen
+            .as_ref()
+            .map(|backoff| Instant::now() + backoff.due_in());
+        response
+            .into_iter()
+            .chain(silence)
+            .chain(reopen)
+            .chain(self.config.repeats.next_due())
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn accept(&mut self, token: Token) {
+        loop {
+            let Some((_, listener)) = self.listeners.iter().find(|(t, _)| *t == token) else {
+                return;
+            };
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    if self.config.verbose {
+                        self.config.log(format!("Client connected: {}", addr));
+                    }
+                    if let Err(e) = self.add_client(ClientIo::Tcp(stream), addr.to_string()) {
+                        self.config
+                            .log_error(format!("Client handler error: {:#}", e));
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    self.config
+                        .log_error(format!("Failed to accept connection: {}", e));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn add_client(&mut self, mut io: ClientIo, addr: String) -> Result<Token> {
+        if self.device.is_none() {
+            self.device = Some(Device::open(&self.config, &self.poll, &self.waker)?);
+            if self.reopen.take().is_some() {
+                self.restore_device();
+            }
+        }
+        let config = &self.config;
+
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        io.register(self.poll.registry(), token)?;
+
+        config.track(|activity| activity.connect(&addr));
+        if let Some(roles) = &config.roles {
+            let role = if roles.join(&addr) {
+                "controller"
+            } else {
+                "observer"
+            };
+            config.log(format!("Client {} joined as {}", addr, role));
+        }
+        self.clients.insert(
+            token,
+            Client {
+                io,
+                addr,
+                pending: Vec::new(),
+                decoder: FrameDecoder::new(config.tcp_framing),
+                last_heard: Instant::now(),
+                outbox: Vec::new(),
+            },
+        );
+        Ok(token)
+    }
+
+    fn client_ready(&mut self, token: Token, readable: bool, writable: bool) {
+        let config = &self.config;
+        let Some(client) = self.clients.get_mut(&token) else {
+            return;
+        };
+        if writable {
+            if let Err(e) = client.flush() {
+                config.log_error(format!("TCP write error to {}: {}", client.addr, e));
+                self.close_client(token);
+                return;
+            }
+        }
+        if !readable {
+            return;
+        }
+
+        let mut buffer = [0u8; 1024];
+        loop {
+            // Left unread until the device catches up, see `device_ready`
+            if self.device.as_ref().is_some_and(Device::backed_up) {
+                return;
+            }
+            let Some(client) = self.clients.get_mut(&token) else {
+                return;
+            };
+            match client.io.read(&mut buffer) {
+                Ok(0) => {
+                    if self.config.verbose {
+                        self.config
+                            .log(format!("Client {} disconnected", client.addr));
+                    }
+                    // The stdio client still gets the responses due
+                    if self.config.stdio {
+                        self.input_closed = true;
+                    } else {
+                        self.close_client(token);
+                    }
+                    return;
+                }
+                Ok(n) => self.client_data(token, &buffer[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.config
+                        .log_error(format!("TCP read error from {}: {}", client.addr, e));
+                    self.close_client(token);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Answer or forward what a client sent
+    fn client_data(&mut self, token: Token, data: &[u8]) {
+        let config = &self.config;
+        let Some(client) = self.clients.get_mut(&token) else {
+            return;
+        };
+        client.last_heard = Instant::now();
+        if config.verbose {
+            config.log(format!("TCP → Serial: {} bytes: {:02X?}", data.len(), data));
+        }
+
+        let (commands, responses) = client_commands(
+            data,
+            &mut client.pending,
+            &mut client.decoder,
+            &client.addr,
+            config,
+        );
+        if !responses.is_empty() {
+            if let Err(e) = client.send(&responses) {
+                config.log_error(format!("TCP write error to {}: {}", client.addr, e));
+                self.close_client(token);
+                return;
+            }
+        }
+        let Some(device) = &mut self.device else {
+            return;
+        };
+        if commands.is_empty() {
+            return;
+        }
+        if device.half_duplex {
+            device.waiting.extend(
+                commands
+                    .chunks(framing::command_frame_len(config.crc))
+                    .map(|frame| (token, frame.to_vec())),
+            );
+            self.send_waiting();
+            return;
+        }
+
+        let serial_data = serial_frames(&commands, config);
+        match device.write(&serial_data) {
+            Ok(()) => {
+                let now = Instant::now();
+                device.outstanding.extend(
+                    command_bytes(&commands, config).map(|command| (token, now, command.to_vec())),
+                );
+                record_forwarded(&commands, &client.addr, config);
+                if config.verbose && serial_data.len() != data.len() {
+                    config.log(format!(
+                        "Serial write: {} bytes (from {} received): {:02X?}",
+                        serial_data.len(),
+                        data.len(),
+                        serial_data
+                    ));
+                }
+            }
+            Err(e) => self.write_failed(e),
+        }
+    }
+
+    /// Log a failed device write, and reopen or close a device that is gone
+    fn write_failed(&mut self, e: std::io::Error) {
+        let config = &self.config;
+        config.track(Activity::serial_error);
+        if config.is_relay() {
+            self.lose_device(format!("Upstream bridge write error: {}", e));
+        } else if config.reopen && wake::is_link_lost(&e) {
+            self.suspend_device(format!("Serial write error: {}", e));
+        } else {
+            config.log_error(format!("Serial write error: {}", e));
+        }
+    }
+
+    /// Put the next waiting command on the half-duplex bus once the last is
+    /// answered (or given up on)
+    fn send_waiting(&mut self) {
+        loop {
+            let config = &self.config;
+            let Some(device) = &mut self.device else {
+                return;
+            };
+            if !device.outstanding.is_empty() {
+                return;
+            }
+            let Some((token, frame)) = device.waiting.pop_front() else {
+                return;
+            };
+            // The client may have left since
+            let client = self.clients.get(&token);
+            if client.is_none() && token != RESTORE {
+                continue;
+            }
+            if let Err(e) = device.write(&serial_frames(&frame, config)) {
+                self.write_failed(e);
+                continue;
+            }
+            device.outstanding.push_back((
+                token,
+                Instant::now(),
+                frame[..framing::COMMAND_LEN].to_vec(),
+            ));
+            if let Some(client) = client {
+                record_forwarded(&frame, &client.addr, config);
+            }
+        }
+    }
+
+    /// Write to and read from a device polled through its descriptor
+    fn device_ready(&mut self, event: &mio::event::Event) {
+        if event.is_writable() {
+            self.device_writable();
+        }
+        if event.is_readable() || event.is_read_closed() {
+            self.device_readable(event.is_read_closed());
+        }
+    }
+
+    /// Write the commands the device could not take before, and read the
+    /// clients that waited for it
+    fn device_writable(&mut self) {
+        let Some(device) = &mut self.device else {
+            return;
+        };
+        let backed_up = device.backed_up();
+        if let Err(e) = device.flush() {
+            return self.write_failed(e);
+        }
+        if backed_up && !device.backed_up() {
+            let tokens: Vec<Token> = self.clients.keys().copied().collect();
+            for token in tokens {
+                self.client_ready(token, true, false);
+            }
+        }
+    }
+
+    /// Pass the device's responses on, each to the client whose command is oldest
+    fn device_readable(&mut self, closed: bool) {
+        let config = &self.config;
+        let Some(device) = &mut self.device else {
+            return;
+        };
+        let data = match device.read() {
+            Ok(data) => data,
+            Err(e) => {
+                config.track(Activity::serial_error);
+                let reason = match e.kind() {
+                    std::io::ErrorKind::UnexpectedEof if config.is_relay() => {
+                        "Upstream bridge closed the connection".to_string()
+                    }
+                    _ => format!("Serial read error: {}", e),
+                };
+                if config.reopen && wake::is_link_lost(&e) {
+                    self.suspend_device(reason);
+                } else {
+                    self.lose_device(reason);
+                }
+                return;
+            }
+        };
+
+        let mut failed = Vec::new();
+        for response in device.responses(&data, config) {
+            if response.is_empty() {
+                continue;
+            }
+            let Some((token, sent, command)) = device.outstanding.pop_front() else {
+                if config.verbose {
+                    config.log(format!(
+                        "Dropping unexpected device response: {:02X?}",
+                        response
+                    ));
+                }
+                continue;
+            };
+            // The client may have left since
+            let Some(client) = self.clients.get_mut(&token) else {
+                continue;
+            };
+            let latency = sent.elapsed();
+            config.track(|activity| activity.answered(&client.addr, latency));
+            let Some(response) = filter_response(response, &command, &client.addr, config) else {
+                continue;
+            };
+            let response_data = config.tcp_framing.encode(&response);
+            if config.verbose {
+                config.log(format!(
+                    "Serial → TCP: {} bytes: {:02X?}",
+                    response_data.len(),
+                    response_data
+                ));
+            }
+            if let Err(e) = client.send(&response_data) {
+                config.log_error(format!("TCP write error to {}: {}", client.addr, e));
+                failed.push(token);
+            }
+        }
+        for token in failed {
+            self.close_client(token);
+        }
+        if closed && self.config.is_relay() {
+            self.lose_device("Upstream bridge closed the connection".to_string());
+        } else if closed && self.config.reopen {
+            self.suspend_device("Serial device closed".to_string());
+        } else if closed {
+            self.lose_device("Serial device closed".to_string());
+        }
+        self.send_waiting();
+    }
+
+    /// Give up on unanswered commands and on silent clients
+    fn expire(&mut self) {
+        if let Some(device) = &mut self.device {
+            while let Some(&(token, sent, _)) = device.outstanding.front() {
+                if sent.elapsed() < RESPONSE_TIMEOUT {
+                    break;
+                }
+                device.outstanding.pop_front();
+                if self.config.verbose {
+                    if let Some(client) = self.clients.get(&token) {
+                        self.config.log(format!(
+                            "No response from the device to {} within {} ms",
+                            client.addr,
+                            RESPONSE_TIMEOUT.as_millis()
+                        ));
+                    }
+                }
+            }
+        }
+        self.send_waiting();
+
+        let Some(timeout) = self.config.client_timeout else {
+            return;
+        };
+        let silent: Vec<Token> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.last_heard.elapsed() >= timeout)
+            .map(|(&token, _)| token)
+            .collect();
+        for token in silent {
+            self.config.log(format!(
+                "Client {} silent for {}s, closing the connection",
+                self.clients[&token].addr,
+                timeout.as_secs()
+            ));
+            self.close_client(token);
+        }
+    }
+
+    /// Close a device that went away (the host slept, the adapter was
+    /// replugged) and reopen it, keeping the clients connected
+    fn suspend_device(&mut self, reason: String) {
+        if self.clients.is_empty() {
+            return self.lose_device(reason);
+        }
+        self.config
+            .log_error(format!("{}; reopening the device", reason));
+        if let Some(device) = self.device.take() {
+            device.close(&self.poll);
+        }
+        self.reopen.get_or_insert_with(Backoff::default);
+    }
+
+    /// Try to reopen the device once it is due, and restore its state
+    fn reopen_device(&mut self) {
+        let Some(backoff) = &mut self.reopen else {
+            return;
+        };
+        if !backoff.due_in().is_zero() {
+            return;
+        }
+        match Device::open(&self.config, &self.poll, &self.waker) {
+            Ok(device) => {
+                self.config
+                    .log(format!("Reopened {}", self.config.device_name()));
+                self.reopen = None;
+                self.device = Some(device);
+                self.restore_device();
+            }
+            Err(e) => {
+                let wait = backoff.failed();
+                self.config.log_error(format!(
+                    "Reopening {} failed: {:#}; retrying in {}s",
+                    self.config.device_name(),
+                    e,
+                    wait.as_secs()
+                ));
+            }
+        }
+    }
+
+    /// Replay the twin's state to the device, which may have reset while it was
+    /// gone; the answers go to no client
+    fn restore_device(&mut self) {
+        let config = &self.config;
+        let Some(device) = &mut self.device else {
+            return;
+        };
+        let frames = restore_frames(config);
+        if frames.is_empty() {
+            return;
+        }
+        let frame_len = framing::command_frame_len(config.crc);
+        if device.half_duplex {
+            device.waiting.extend(
+                frames
+                    .chunks(frame_len)
+                    .map(|frame| (RESTORE, frame.to_vec())),
+            );
+        } else {
+            if let Err(e) = device.write(&serial_frames(&frames, config)) {
+                config.track(Activity::serial_error);
+                let reason = format!("Restoring the device state failed: {}", e);
+                if wake::is_link_lost(&e) {
+                    self.suspend_device(reason);
+                } else {
+                    config.log_error(reason);
+                }
+                return;
+            }
+            let now = Instant::now();
+            device.outstanding.extend(
+                command_bytes(&frames, config).map(|command| (RESTORE, now, command.to_vec())),
+            );
+        }
+        config.log(format!(
+            "Restored the state of {} ({} commands)",
+            config.device_name(),
+            frames.len() / frame_len
+        ));
+        self.send_waiting();
+    }
+
+    /// Restore the device's state after the host slept, in case it lost power
+    fn check_sleep(&mut self) {
+        let Some(slept) = self.sleep.check() else {
+            return;
+        };
+        self.config
+            .log(format!("Host slept for {:.0}s", slept.as_secs_f64()));
+        if self.config.reopen {
+            self.restore_device();
+        }
+    }
+
+    /// Close the device after it failed, and with it every client connection
+    ///
+    /// Clients reconnect (or fail over) as they would to a dead bridge; the
+    /// next connection opens the device again.
+    fn lose_device(&mut self, reason: String) {
+        self.config.log_error(format!(
+            "{}; closing {} client connection(s)",
+            reason,
+            self.clients.len()
+        ));
+        if self.config.stdio {
+            self.lost.get_or_insert(reason);
+        }
+        if let Some(device) = self.device.take() {
+            device.close(&self.poll);
+        }
+        let tokens: Vec<Token> = self.clients.keys().copied().collect();
+        for token in tokens {
+            self.close_client(token);
+        }
+    }
+
+    fn close_client(&mut self, token: Token) {
+        let Some(mut client) = self.clients.remove(&token) else {
+            return;
+        };
+        let config = &self.config;
+        client.io.deregister(self.poll.registry());
+        config.track(|activity| activity.disconnect(&client.addr));
+        if let Some(filters) = &config.filters {
+            filters.lock().unwrap().client_left(&client.addr);
+        }
+        if let Some(roles) = &config.roles {
+            if roles.leave(&client.addr) {
+                config.log(format!(
+                    "Controller {} left; the next client to write takes control",
+                    client.addr
+                ));
+            }
+        }
+        if config.verbose {
+            config.log(format!("Connection to {} closed", client.addr));
+        }
+
+        if self.clients.is_empty() {
+            if let Some(device) = self.device.take() {
+                device.close(&self.poll);
+            }
+            // The next client opens the device
+            self.reopen = None;
+        }
+    }
+}
+
+/// Draw the bridge's totals, command rate, clients and log
+fn render_dashboard(
+    f: &mut Frame,
+    config: &BridgeConfig,
+    listen: &str,
+    activity: &mut Activity,
+    rates: &VecDeque<u64>,
+) {
+    let theme = Theme::default();
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3), // Title
+            Constraint::Length(3), // Totals
+            Constraint::Length(6), // Command rate
+            Constraint::Min(5),    // Clients
+            Constraint::Min(6),    // Log
+        ])
+        .split(f.size());
+
+    let title = Paragraph::new(format!(
+        "{} {} on {} - {} client(s) - q/ESC to quit",
+        if config.is_relay() {
+            "Relay to"
+        } else {
+            "Serial bridge"
+        },
+        config.device_name(),
+        listen,
+        activity.clients.len()
+    ))
+    .style(theme.title)
+    .alignment(Alignment::Center)
+    .block(Block::default().borders(Borders::ALL));
+    f.render_widget(title, chunks[0]);
+
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let latency = match activity.latency.back() {
+        Some(&last) => {
+            let total: Duration = activity.latency.iter().sum();
+            let max = activity.latency.iter().max().copied().unwrap_or_default();
+            format!(
+                "last {:.1} / mean {:.1} / max {:.1} ms",
+                ms(last),
+                ms(total / activity.latency.len() as u32),
+                ms(max)
+            )
+        }
+        None => "-".to_string(),
+    };
+    let totals = Paragraph::new(format!(
+        "Commands: {} ({}/s) | Responses: {} | Serial errors: {} | Latency: {}",
+        activity.commands,
+        rates.back().copied().unwrap_or(0),
+        activity.responses,
+        activity.serial_errors,
+        latency
+    ))
+    .style(if activity.serial_errors > 0 {
+        theme.alert
+    } else {
+        theme.info
+    })
+    .block(Block::default().borders(Borders::ALL).title("Totals"));
+    f.render_widget(totals, chunks[1]);
+
+    // Newest second on the right, as many seconds as fit
+    let width = chunks[2].width.saturating_sub(2) as usize;
+    let data: Vec<u64> = rates
+        .iter()
+        .skip(rates.len().saturating_sub(width))
+        .copied()
+        .collect();
+    let sparkline = Sparkline::default()
+        .block(Block::default().borders(Borders::ALL).title(format!(
+            "Commands/s (last {}s, peak {})",
+            data.len(),
+            data.iter().max().copied().unwrap_or(0)
+        )))
+        .data(&data)
+        .style(theme.status);
+    f.render_widget(sparkline, chunks[2]);
+
+    let controller = config.roles.as_ref().and_then(|roles| roles.controller());
+    let rows: Vec<Row> = activity
+        .clients
+        .iter_mut()
+        .map(|(addr, client)| {
+            let role = match &controller {
+                Some(controller) if controller == addr => "controller",
+                _ if config.roles.is_some() => "observer",
+                _ => "-",
+            };
+            Row::new(vec![
+                addr.clone(),
+                role.to_string(),
+                format!("{}s", client.connected.elapsed().as_secs()),
+                client.commands.to_string(),
+                client.rate().to_string(),
+                client.responses.to_string(),
+                format!("{:.1}s", client.last_activity.elapsed().as_secs_f64()),
+                client.last_command.clone(),
+            ])
+        })
+        .collect();
+    let widths = [
+        Constraint::Length(24),
+        Constraint::Length(10),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(6),
+        Constraint::Length(9),
+        Constraint::Length(8),
+        Constraint::Min(20),
+    ];
+    let clients = Table::new(rows)
+        .header(
+            Row::new(vec![
+                "Client",
+                "Role",
+                "Connected",
+                "Commands",
+                "Cmd/s",
+                "Responses",
+                "Idle",
+                "Last command",
+            ])
+            .style(theme.title),
+        )
+        .widths(&widths)
+        .style(theme.text)
+        .block(Block::default().borders(Borders::ALL).title("Clients"));
+    f.render_widget(clients, chunks[3]);
+
+    let visible = chunks[4].height.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = activity
+        .log
+        .iter()
+        .skip(activity.log.len().saturating_sub(visible))
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+    let log = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Log"))
+        .style(theme.text);
+    f.render_widget(log, chunks[4]);
+}
+
+/// Draw the dashboard until the user quits
+fn run_dashboard(config: &BridgeConfig, listen: &str) -> Result<()> {
+    let Some(activity) = &config.activity else {
+        return Ok(());
+    };
+
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    // Commands per second, sampled from the running total once a second
+    let mut rates = VecDeque::with_capacity(RATE_HISTORY);
+    let mut last_total = 0;
+    let mut last_sample = Instant::now();
+
+    let result = (|| -> Result<()> {
+        loop {
+            {
+                let mut activity = activity.lock().unwrap();
+                if last_sample.elapsed() >= Duration::from_secs(1) {
+                    if rates.len() == RATE_HISTORY {
+                        rates.pop_front();
+                    }
+                    rates.push_back(activity.commands - last_total);
+                    last_total = activity.commands;
+                    last_sample = Instant::now();
+                }
+                terminal.draw(|f| render_dashboard(f, config, listen, &mut activity, &rates))?;
+            }
+
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if key.kind == KeyEventKind::Press
+                        && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c)
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    })();
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
+
+    let reopen = !args.no_reopen
+        && matches!(
+            args.serial_device,
+            Target::Serial { .. } | Target::Pty { .. }
+        );
+    let mut config = BridgeConfig {
+        device: args.serial_device.clone(),
+        verbose: args.verbose,
+        crc: args.crc,
+        tcp_framing: args.tcp_framing,
+        serial_framing: args.serial_framing,
+        padding: args.padding,
+        strict: args.strict,
+        stdio: args.stdio,
+        client_timeout: args.client_timeout.map(Duration::from_secs),
+        // Register reads are answered from the twin when translating, and a
+        // reopened device gets its state back from it
+        twin: (args.twin.is_some() || args.translate.is_some() || reopen)
+            .then(|| Arc::new(Twin::new())),
+        translate: args.translate,
+        roles: args.roles.then(|| Arc::new(Roles::default())),
+        audit: match &args.audit {
+            Some(path) => Some(Arc::new(
+                AuditLog::open(path, args.audit_rotation.clone())
+                    .with_context(|| format!("Failed to open audit log {}", path.display()))?,
+            )),
+            None => None,
+        },
+        filters: (!args.filter.is_empty()).then(|| {
+            let mut chain = FilterChain::new();
+            for filter in &args.filter {
+                chain.push(filter.build());
+            }
+            Arc::new(Mutex::new(chain))
+        }),
+        rs485: args
+            .rs485
+            .then(|| Duration::from_micros(args.rs485_turnaround)),
+        #[cfg(unix)]
+        tuning: SerialTuning {
+            latency_timer: args.latency_timer,
+            low_latency: args.no_buffering,
+            vmin: args.vmin,
+            vtime: args.vtime,
+        },
+        reopen,
+        activity: args
+            .dashboard
+            .then(|| Arc::new(Mutex::new(Activity::default()))),
+        health: args.health.map(|_| Arc::new(Health::new(Status::Waiting))),
+        repeats: Arc::default(),
+    };
+    if config.rs485.is_some() && config.is_relay() {
+        return Err(anyhow!("--rs485 needs a serial device, not an upstream bridge").into());
+    }
+    let tuned = args.latency_timer.is_some()
+        || args.no_buffering
+        || args.vmin.is_some()
+        || args.vtime.is_some();
+    if tuned && (config.is_relay() || cfg!(not(unix))) {
+        return Err(anyhow!("Serial port tuning needs a serial device on a Unix system").into());
+    }
+
+    if args.stdio && cfg!(not(unix)) {
+        return Err(anyhow!("--stdio needs a Unix system").into());
+    }
+
+    if args.device_wait.is_some() && config.is_relay() {
+        return Err(anyhow!("--device-wait needs a serial device, not an upstream bridge").into());
+    }
+
+    // Shared by the HTTP thread and the bridge; set on Ctrl+C or SIGTERM
+    let shutdown_flag = Arc::new(AtomicBool::new(false));
+    // The bridge's, once it listens
+    let waker: Arc<OnceLock<Arc<Waker>>> = Arc::default();
+
+    // Set up graceful shutdown handling; the stdio bridge keeps the defaults
+    if !args.stdio {
+        let shutdown_flag = shutdown_flag.clone();
+        let waker = waker.clone();
+        let health = config.health.clone();
+        ctrlc::set_handler(move || {
+            // A second signal does not wait for the drain
+            if shutdown_flag.swap(true, Ordering::Relaxed) {
+                std::process::exit(1);
+            }
+            println!("\nReceived termination signal, shutting down...");
+            if let Some(health) = &health {
+                health.set_status(Status::Draining);
+            }
+            if let Some(waker) = waker.get() {
+                let _ = waker.wake();
+            }
+        })?;
+    }
+
+    if let (Some(addr), Some(health)) = (args.health, config.health.clone()) {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("Failed to bind to {}", addr))?;
+        config.log(format!("Serving health probes on http://{}/health", addr));
+        thread::spawn(move || health::serve_http(listener, &health));
+    }
+
+    if let Some(timeout) = args.device_wait {
+        if !wait_for_device(&config, timeout.map(Duration::from_secs), &shutdown_flag)? {
+            println!("Server shutdown complete.");
+            return Ok(());
+        }
+    }
+    // A Windows friendly name is looked up once, so the log shows the port
+    if let Target::Serial { path, .. } = &mut config.device {
+        *path = discovery::resolve_serial(path)?;
+    }
+    let config = config;
+
+    if let (Some(addr), Some(twin)) = (args.twin, config.twin.clone()) {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("Failed to bind to {}", addr))?;
+        config.log(format!("Serving device twin on http://{}/state", addr));
+        let shutdown_flag = shutdown_flag.clone();
+        let config = config.clone();
+        thread::spawn(move || {
+            if let Err(e) = twin::serve_http(listener, &twin, &shutdown_flag) {
+                config.log_error(format!("Device twin server failed: {}", e));
+            }
+        });
+    }
+
+    // One client on stdin/stdout; it ends at EOF, and Ctrl+C keeps its default
+    #[cfg(unix)]
+    if args.stdio {
+        let result =
+            EventLoop::stdio(config.clone()).and_then(|server| server.run(&AtomicBool::new(false)));
+        config.flush_repeats();
+        return Ok(result?);
+    }
+
+    if args.verbose {
+        config.log(format!(
+            "Starting TCP server for serial device: {}",
+            config.device
+        ));
+        config.log(format!(
+            "Server will listen on port {} (IPv4 and IPv6)",
+            args.port
+        ));
+        if let Some(filters) = &config.filters {
+            let filters = filters.lock().unwrap();
+            config.log(format!(
+                "Response filters: {}",
+                filters.names().collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+
+    // Determine bind addresses
+    let (ipv4_addr, ipv6_addr): (Option<Ipv4Addr>, Option<Ipv6Addr>) = match &args.bind {
+        Some(bind_str) => {
+            // Parse specific bind address
+            match bind_str.parse::<Ipv4Addr>() {
+                Ok(addr) => (Some(addr), None),
+                Err(_) => match bind_str.parse::<Ipv6Addr>() {
+                    Ok(addr) => (None, Some(addr)),
+                    Err(_) => {
+                        return Err(anyhow!("Invalid bind address: {}", bind_str).into());
+                    }
+                },
+            }
+        }
+        None => {
+            // Bind to all interfaces
+            (Some(Ipv4Addr::UNSPECIFIED), Some(Ipv6Addr::UNSPECIFIED))
+        }
+    };
+    let server = EventLoop::bind(config.clone(), ipv4_addr, ipv6_addr, args.port)?;
+
+    #[cfg(feature = "mdns")]
+    let _advertisement = match &args.mdns {
+        Some(name) => {
+            let advertisement =
+                discovery::Advertisement::new(name, args.port, &config.device_name())?;
+            if args.verbose {
+                config.log(format!(
+                    "Advertising as '{}' ({})",
+                    name,
+                    discovery::SERVICE_TYPE
+                ));
+            }
+            Some(advertisement)
+        }
+        None => None,
+    };
+
+    let _ = waker.set(server.waker.clone());
+    // A signal during the wait for the device
+    if shutdown_flag.load(Ordering::Relaxed) {
+        server.waker.wake()?;
     }
 
-    // Wait for all server threads
-    for handle in handles {
-        if let Err(e) = handle.join() {
-            eprintln!("Server thread error: {:?}", e);
+    if args.dashboard {
+        // The dashboard owns the terminal and handles q/ESC/Ctrl+C itself
+        let listen = format!("{}:{}", args.bind.as_deref().unwrap_or("*"), args.port);
+        let waker = server.waker.clone();
+        let handle = {
+            let shutdown_flag = shutdown_flag.clone();
+            thread::spawn(move || server.run(&shutdown_flag))
+        };
+        let result = run_dashboard(&config, &listen);
+        shutdown_flag.store(true, Ordering::Relaxed);
+        waker.wake()?;
+        match handle.join() {
+            Ok(served) => served?,
+            Err(e) => eprintln!("Server thread error: {:?}", e),
         }
+        result?;
+    } else {
+        server.run(&shutdown_flag)?;
     }
 
+    config.flush_repeats();
     println!("Server shutdown complete.");
     Ok(())
 }
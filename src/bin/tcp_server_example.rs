@@ -1,8 +1,31 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, Paragraph, Sparkline},
+    Frame, Terminal,
+};
+use serialtest::device::DeviceState;
+use serialtest::diagnose::Failure;
+use serialtest::framing::StreamFraming;
+use serialtest::protocol::TABLES;
+use serialtest::sim::{load_behaviors, serve, SimConfig, SimState};
+use serialtest::version;
+use serialtest::waveform::WaveformRecorder;
+use serialtest::widgets::{self, Theme, ValueFormat};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
 /// TCP server example for testing unified_test TCP transport
 #[derive(Parser, Debug)]
@@ -20,162 +43,198 @@ struct Args {
     /// Enable verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// Expect a CRC16 after every command and append one to every response
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Behavior script mapping command patterns to canned responses and latencies
+    #[arg(long)]
+    behavior: Option<String>,
+
+    /// Show the simulated DAC/table/GPIO state in a live dashboard
+    #[arg(long)]
+    tui: bool,
+
+    /// Record the simulated analog outputs to this file (.csv or .wav)
+    #[arg(long)]
+    export: Option<PathBuf>,
+
+    /// Sample rate of the exported waveform in Hz
+    #[arg(long, default_value = "10000", value_parser = clap::value_parser!(u32).range(1..))]
+    sample_rate: u32,
+
+    /// Only update the DAC outputs on LDAC commands, like a device with LDAC held high
+    #[arg(long)]
+    hold_until_ldac: bool,
 }
 
-const STATUS_OK: u16 = 0x0000;
-const STATUS_ERROR: u16 = 0xFFFF;
+fn render_dashboard(f: &mut Frame, bind_addr: &str, state: &SimState) {
+    let device = &state.device;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3), // Title
+            Constraint::Min(8),    // DAC outputs
+            Constraint::Length(5), // Tables
+            Constraint::Length(3), // GPIO
+            Constraint::Length(3), // Counters
+            Constraint::Min(6),    // Log
+        ])
+        .split(f.size());
 
-fn handle_client(mut stream: TcpStream, verbose: bool) -> Result<()> {
-    let peer_addr = stream.peer_addr()?;
-    println!("Client connected: {}", peer_addr);
+    let title = Paragraph::new(format!(
+        "DAC Simulator on {} - {} client(s) - q/ESC to quit",
+        bind_addr, state.clients
+    ))
+    .style(
+        Style::default()
+            .fg(Color::Cyan)
+            .add_modifier(Modifier::BOLD),
+    )
+    .alignment(Alignment::Center)
+    .block(Block::default().borders(Borders::ALL));
+    f.render_widget(title, chunks[0]);
 
-    let mut buffer = [0u8; 1024];
+    widgets::render_dac_gauges(
+        f,
+        chunks[1],
+        &device.dac,
+        None,
+        &[],
+        &ValueFormat::default(),
+        &Theme::default(),
+    );
+    render_tables(f, chunks[2], device);
+    widgets::render_gpio_states(f, chunks[3], &device.gpio, &Theme::default());
 
-    loop {
-        match stream.read(&mut buffer) {
-            Ok(0) => {
-                // Client disconnected
-                println!("Client {} disconnected", peer_addr);
-                break;
-            }
-            Ok(bytes_read) => {
-                if verbose {
-                    println!(
-                        "Received {} bytes from {}: {:?}",
-                        bytes_read,
-                        peer_addr,
-                        &buffer[..bytes_read]
-                    );
-                }
+    let attached: Vec<String> = device
+        .attached
+        .iter()
+        .enumerate()
+        .filter_map(|(ch, table)| table.map(|t| format!("DAC{}→T{}", ch, t)))
+        .collect();
+    let registers: Vec<String> = device
+        .registers
+        .iter()
+        .map(|(reg, value)| format!("R{}=0x{:04X}", reg, value))
+        .collect();
+    let counters = Paragraph::new(format!(
+        "Commands: {} | Keepalives: {} | LDAC: {} | Offset: {} | Attached: {} | Registers: {}",
+        device.commands,
+        device.keepalives,
+        device.ldac_count,
+        device.table_offset,
+        if attached.is_empty() {
+            "-".to_string()
+        } else {
+            attached.join(" ")
+        },
+        if registers.is_empty() {
+            "-".to_string()
+        } else {
+            registers.join(" ")
+        },
+    ))
+    .style(Style::default().fg(Color::Yellow))
+    .block(Block::default().borders(Borders::ALL).title("Device"));
+    f.render_widget(counters, chunks[4]);
 
-                // Process commands in 4-byte chunks
-                let mut responses = Vec::new();
-                for chunk in buffer[..bytes_read].chunks(4) {
-                    if chunk.len() == 4 {
-                        let response = process_command(chunk, verbose);
-                        responses.extend_from_slice(&response.to_be_bytes());
-                    }
-                }
+    let visible = chunks[5].height.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = state
+        .log
+        .iter()
+        .skip(state.log.len().saturating_sub(visible))
+        .map(|line| ListItem::new(line.as_str()))
+        .collect();
+    let log = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Activity"))
+        .style(Style::default().fg(Color::White));
+    f.render_widget(log, chunks[5]);
+}
+
+/// Table contents as sparklines, with the current offset in the title
+fn render_tables(f: &mut Frame, area: Rect, device: &DeviceState) {
+    let table_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Ratio(1, TABLES as u32); TABLES])
+        .split(area);
+
+    for (i, chunk) in table_chunks.iter().enumerate() {
+        // One bar per column: downsample the 256 entries to the available width
+        let width = chunk.width.saturating_sub(2).max(1) as usize;
+        let table = &device.tables[i];
+        let data: Vec<u64> = (0..width)
+            .map(|x| table[x * table.len() / width] as u64)
+            .collect();
+        let sparkline = Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(format!(
+                "Table {} [{}]",
+                i, table[device.table_offset as usize]
+            )))
+            .data(&data)
+            .max(65535)
+            .style(Style::default().fg(Color::Magenta));
+        f.render_widget(sparkline, *chunk);
+    }
+}
 
-                // Send responses back
-                if !responses.is_empty() {
-                    stream.write_all(&responses)?;
-                    if verbose {
-                        println!(
-                            "Sent {} response bytes to {}: {:?}",
-                            responses.len(),
-                            peer_addr,
-                            responses
-                        );
+/// Draw the dashboard until the user quits
+fn run_dashboard(bind_addr: &str, state: Arc<Mutex<SimState>>) -> Result<()> {
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = (|| -> Result<()> {
+        loop {
+            terminal.draw(|f| render_dashboard(f, bind_addr, &state.lock().unwrap()))?;
+
+            if event::poll(Duration::from_millis(100))? {
+                if let Event::Key(key) = event::read()? {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if key.kind == KeyEventKind::Press
+                        && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c)
+                    {
+                        return Ok(());
                     }
                 }
             }
-            Err(e) => {
-                eprintln!("Error reading from {}: {}", peer_addr, e);
-                break;
-            }
         }
-    }
+    })();
 
-    Ok(())
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
 }
 
-fn process_command(cmd: &[u8], verbose: bool) -> u16 {
-    if cmd.len() != 4 {
-        return STATUS_ERROR;
-    }
+/// Write the rest of the recording to its file and close it
+fn write_export(state: &Mutex<SimState>, path: &Path) -> Result<()> {
+    let Some(recorder) = state.lock().unwrap().recorder.take() else {
+        return Ok(());
+    };
 
-    let cmd_type = cmd[0];
-    let param = cmd[1];
-    let value = ((cmd[2] as u16) << 8) | (cmd[3] as u16);
-
-    match cmd_type {
-        0..=7 => {
-            // Direct DAC write
-            if param == 0x00 {
-                if verbose {
-                    println!(
-                        "  -> Direct DAC write: channel={}, value=0x{:04X}",
-                        cmd_type, value
-                    );
-                }
-            } else if param >= 16 && param <= 19 {
-                let table = param - 16;
-                if verbose {
-                    println!("  -> Attach table: channel={}, table={}", cmd_type, table);
-                }
-            } else {
-                if verbose {
-                    println!(
-                        "  -> Unknown DAC command: channel={}, param={}",
-                        cmd_type, param
-                    );
-                }
-                return STATUS_ERROR;
-            }
-            STATUS_OK
-        }
-        16..=19 => {
-            // Table write
-            let table = cmd_type - 16;
-            if verbose {
-                println!(
-                    "  -> Table write: table={}, offset={}, value=0x{:04X}",
-                    table, param, value
-                );
-            }
-            STATUS_OK
-        }
-        0xFF => {
-            // Use table with offset
-            if verbose {
-                println!("  -> Use table: offset={}", param);
-            }
-            STATUS_OK
-        }
-        0xFE => {
-            // GPIO control
-            let state = if value == 0 { "OFF" } else { "ON" };
-            if verbose {
-                println!("  -> GPIO control: pin={}, state={}", param, state);
-            }
-            STATUS_OK
-        }
-        0xFD => {
-            // Keep alive
-            if verbose {
-                println!("  -> Keep alive");
-            }
-            STATUS_OK
-        }
-        0xFC => {
-            // LDAC update
-            if verbose {
-                println!("  -> LDAC update");
-            }
-            STATUS_OK
-        }
-        0xFB => {
-            // Register write
-            if verbose {
-                println!("  -> Register write: reg={}, value=0x{:04X}", param, value);
-            }
-            STATUS_OK
-        }
-        _ => {
-            if verbose {
-                println!(
-                    "  -> Unknown command: 0x{:02X} 0x{:02X} 0x{:04X}",
-                    cmd_type, param, value
-                );
-            }
-            STATUS_ERROR
-        }
-    }
+    let changes = recorder.changes();
+    let samples = recorder
+        .finish()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!(
+        "Exported {} samples ({} output changes) to {}",
+        samples,
+        changes,
+        path.display()
+    );
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
 
     let bind_addr = format!("{}:{}", args.address, args.port);
     let listener = TcpListener::bind(&bind_addr)
@@ -187,35 +246,80 @@ fn main() -> Result<()> {
     if args.verbose {
         println!("Verbose mode enabled - all commands will be logged");
     }
+    if args.crc {
+        println!("CRC framing enabled - commands are 6 bytes, responses carry a CRC16");
+    }
+    if args.framing != StreamFraming::Raw {
+        println!("Stream framing: {:?}", args.framing);
+    }
+
+    let behaviors = match &args.behavior {
+        Some(path) => {
+            let rules = load_behaviors(path)
+                .with_context(|| format!("Failed to load behavior script: {}", path))?;
+            println!("Loaded {} behavior rules from {}", rules.len(), path);
+            rules
+        }
+        None => Vec::new(),
+    };
+    let config = SimConfig {
+        verbose: args.verbose,
+        crc: args.crc,
+        framing: args.framing,
+        behaviors: Arc::new(behaviors),
+        state: Arc::new(Mutex::new(SimState::default())),
+        dashboard: args.tui,
+    };
+
+    {
+        let mut state = config.state.lock().unwrap();
+        state.device.hold_until_ldac = args.hold_until_ldac;
+        if let Some(path) = &args.export {
+            println!(
+                "Recording DAC outputs to {} at {} Hz",
+                path.display(),
+                args.sample_rate
+            );
+            let recorder = WaveformRecorder::create(path, args.sample_rate, state.device.dac)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            state.recorder = Some(recorder);
+        }
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+
+    if args.tui {
+        // The dashboard owns the terminal and handles q/ESC/Ctrl+C itself
+        let state = config.state.clone();
+        thread::spawn(move || serve(listener, config, running));
+        run_dashboard(&bind_addr, state.clone())?;
+        if let Some(path) = &args.export {
+            write_export(&state, path)?;
+        }
+        println!("Server shutdown complete");
+        return Ok(());
+    }
 
     // Set up Ctrl+C handler
-    let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
     let r = running.clone();
+    let state = config.state.clone();
+    let export = args.export.clone();
     ctrlc::set_handler(move || {
         println!("\nReceived Ctrl+C, shutting down server...");
-        r.store(false, std::sync::atomic::Ordering::SeqCst);
+        r.store(false, Ordering::SeqCst);
+        // The accept loop only notices the flag on the next connection, so
+        // the recording has to be written from here
+        if let Some(path) = &export {
+            if let Err(e) = write_export(&state, path) {
+                eprintln!("Waveform export failed: {:#}", e);
+            }
+            println!("Server shutdown complete");
+            std::process::exit(0);
+        }
     })
     .context("Error setting Ctrl+C handler")?;
 
-    for stream in listener.incoming() {
-        if !running.load(std::sync::atomic::Ordering::SeqCst) {
-            break;
-        }
-
-        match stream {
-            Ok(stream) => {
-                let verbose = args.verbose;
-                thread::spawn(move || {
-                    if let Err(e) = handle_client(stream, verbose) {
-                        eprintln!("Client handler error: {}", e);
-                    }
-                });
-            }
-            Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
-            }
-        }
-    }
+    serve(listener, config, running);
 
     println!("Server shutdown complete");
     Ok(())
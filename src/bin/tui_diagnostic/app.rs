@@ -0,0 +1,1856 @@
+//! State of the panel and what each key does to it.
+
+use crate::ui::{
+    keepalive_text, scroll_reference, stats_text, status_text, table_text, title_text,
+};
+#[cfg(not(feature = "clipboard"))]
+use anyhow::anyhow;
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent};
+use serialtest::alarms::ChannelAlarms;
+use serialtest::channels::ChannelLinks;
+use serialtest::clock::{self, SharedClock};
+use serialtest::expr::ChannelMappings;
+use serialtest::framing;
+#[cfg(feature = "lua")]
+use serialtest::keymap::Key;
+use serialtest::keymap::{Action, Keymap};
+use serialtest::progress::Progress;
+use serialtest::protocol::{
+    self, Command, Features, DAC_CHANNELS, GPIO_PINS, PROTOCOL_VERSION, TABLES,
+};
+use serialtest::ramp::Ramp;
+use serialtest::report::{self, UtcTime};
+#[cfg(feature = "lua")]
+use serialtest::script::ScriptHost;
+use serialtest::session::Session;
+use serialtest::stats::{LatencyStats, SharedStats};
+use serialtest::tables::{TableShadow, TableSpec};
+use serialtest::target::Target;
+use serialtest::version;
+use serialtest::widgets::{ThemeName, ValueDisplay, ValueFormat};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Heartbeat intervals without any data before the bridge is considered lost
+const HEARTBEAT_MISSES: u32 = 3;
+
+/// Keepalive interval change per `[`/`]` key press
+const KEEPALIVE_STEP: Duration = Duration::from_millis(500);
+
+/// How ganged channels follow the channel being adjusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GangMode {
+    /// Apply the same change in counts to every linked channel
+    Absolute,
+    /// Scale every linked channel by the same factor, keeping their ratios
+    Ratio,
+}
+
+impl GangMode {
+    /// New value of a linked channel at `value` when the adjusted one goes from `old` to `new`
+    fn follow(self, old: u16, new: u16, value: u16) -> u16 {
+        match self {
+            // A channel at 0 has no ratio to keep, so it moves like Absolute
+            GangMode::Ratio if old != 0 => (value as f64 * new as f64 / old as f64)
+                .round()
+                .min(65535.0) as u16,
+            _ => (value as i32 + new as i32 - old as i32).clamp(0, 65535) as u16,
+        }
+    }
+}
+
+/// DAC and GPIO outputs at one point in time, for undo/redo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub dac_values: [u16; DAC_CHANNELS],
+    pub gpio_states: [bool; GPIO_PINS],
+}
+
+/// Changes kept for undo
+const UNDO_LIMIT: usize = 100;
+
+/// Lines kept in the activity log
+const LOG_LIMIT: usize = 500;
+
+/// Newest log lines included in an exported report
+const REPORT_LOG_LINES: usize = 50;
+
+/// A key press recorded into a macro, with the time since the previous one
+pub type MacroKey = (Duration, KeyEvent);
+
+/// Macro recording in progress
+#[derive(Debug)]
+pub struct Recording {
+    pub keys: Vec<MacroKey>,
+    pub last_key: Instant,
+}
+
+/// A large step moving a channel gradually, with `--ramp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveRamp {
+    pub ramp: Ramp,
+    pub start: Instant,
+    /// When the next write is due
+    pub next: Instant,
+}
+
+/// Arrow presses closer together than this count as one held key
+const REPEAT_GAP: Duration = Duration::from_millis(150);
+
+/// Repeats of a held key before its step doubles
+const REPEATS_PER_DOUBLING: u32 = 8;
+
+/// Held keys step by at most `step << MAX_REPEAT_SHIFT`
+const MAX_REPEAT_SHIFT: u32 = 6;
+
+/// A held key writes to the device at most this often
+const REPEAT_WRITE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Step up or down held on one channel
+#[derive(Debug)]
+pub struct KeyRepeat {
+    pub action: Action,
+    pub channel: usize,
+    /// Repeats since the key went down
+    pub count: u32,
+    pub last_press: Instant,
+    pub last_write: Instant,
+    /// Latest write not sent yet; each one supersedes the previous
+    pub held: Option<Vec<u8>>,
+}
+
+/// Macro playback in progress
+#[derive(Debug, Clone, Copy)]
+pub struct Playback {
+    /// Index of the next key to replay
+    pub next: usize,
+    pub due: Instant,
+    pub started: Instant,
+}
+
+/// Keepalives sent by a latency probe
+pub const PROBE_COUNT: usize = 50;
+
+/// A probe keepalive unanswered for this long counts as lost
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Round trips (95th percentile) and jitter fine for interactive control
+const PROBE_GOOD: (Duration, Duration) = (Duration::from_millis(50), Duration::from_millis(20));
+
+/// Round trips beyond which adjustments lag too much to follow
+const PROBE_USABLE: Duration = Duration::from_millis(200);
+
+/// Keepalives sent one at a time, each timed until its answer arrives
+#[derive(Debug, Clone, Default)]
+pub struct LatencyProbe {
+    pub latency: LatencyStats,
+    /// Differences between consecutive round trips, summed
+    pub jitter_total: Duration,
+    pub previous: Option<Duration>,
+    pub sent: usize,
+    pub lost: usize,
+    /// When the keepalive waiting for its answer went out
+    pub waiting: Option<Instant>,
+}
+
+impl LatencyProbe {
+    pub fn running(&self) -> bool {
+        self.sent < PROBE_COUNT || self.waiting.is_some()
+    }
+
+    /// Time the keepalive waiting for an answer; false if none is
+    fn answered(&mut self) -> bool {
+        let Some(sent) = self.waiting.take() else {
+            return false;
+        };
+        let round_trip = sent.elapsed();
+        self.latency.record(round_trip);
+        if let Some(previous) = self.previous {
+            self.jitter_total += round_trip.abs_diff(previous);
+        }
+        self.previous = Some(round_trip);
+        true
+    }
+
+    /// Mean difference between consecutive round trips
+    fn jitter(&self) -> Option<Duration> {
+        let pairs = self.latency.count().checked_sub(1).filter(|&n| n > 0)?;
+        Some(self.jitter_total / pairs as u32)
+    }
+
+    /// Whether the path suits interactive control, once the probe is done
+    pub fn verdict(&self) -> Option<(&'static str, bool)> {
+        if self.running() {
+            return None;
+        }
+        let p95 = self.latency.clone().percentile(95.0)?;
+        let jitter = self.jitter().unwrap_or_default();
+        Some(
+            if self.lost == 0 && p95 <= PROBE_GOOD.0 && jitter <= PROBE_GOOD.1 {
+                ("Good for interactive control", true)
+            } else if self.lost == 0 && p95 <= PROBE_USABLE {
+                ("Usable: adjustments will lag noticeably", false)
+            } else {
+                ("Too slow or lossy for interactive control", false)
+            },
+        )
+    }
+
+    pub fn summary(&self) -> String {
+        let ms = |d: Option<Duration>| {
+            d.map_or("-".to_string(), |d| {
+                format!("{:.2} ms", d.as_secs_f64() * 1000.0)
+            })
+        };
+        format!(
+            "min {}, avg {}, max {}, jitter {}, {} of {} lost",
+            ms(self.latency.min()),
+            ms(self.latency.mean()),
+            ms(self.latency.max()),
+            ms(self.jitter()),
+            self.lost,
+            self.sent
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct AppState {
+    pub dac_values: [u16; DAC_CHANNELS],
+    pub gpio_states: [bool; GPIO_PINS],
+    pub selected_channel: usize,
+    pub step: u16,
+    pub table_offset: u8,
+    /// Offset being typed in offset input mode
+    pub offset_input: Option<String>,
+    /// Register access being typed: `REG` or `REG=VALUE`
+    pub register_input: Option<String>,
+    /// Register whose value the next response carries
+    pub register_read: Option<u8>,
+    /// Features the device agreed to in its last hello
+    pub features: Features,
+    /// Features offered in a hello the next response answers
+    pub hello_offered: Option<Features>,
+    /// Raw frame being typed as hex bytes
+    pub raw_input: Option<String>,
+    /// Table fill being typed: `TABLE SPEC`
+    pub fill_input: Option<String>,
+    /// Table entries the device acknowledged since the link was opened, so
+    /// fills only send changes
+    pub tables: TableShadow,
+    /// Table writes sent but not yet entered in `tables`
+    pub tables_pending: Vec<Command>,
+    /// `00 00` acknowledgements seen since the first pending table write
+    pub tables_acked: usize,
+    /// Raw frame waiting for `y` to send it past `--strict`
+    pub raw_confirm: Option<Vec<u8>>,
+    /// Raw frame for the main loop to send unchecked
+    pub raw_frame: Option<Vec<u8>>,
+    /// Auto-increment the table offset every `sweep_interval`
+    pub sweeping: bool,
+    pub sweep_interval: Duration,
+    pub last_sweep: Instant,
+    /// Hold DAC changes locally until LDAC is requested
+    pub deferred: bool,
+    /// Channels changed since the last LDAC in deferred mode
+    pub pending: [bool; DAC_CHANNELS],
+    pub theme: ThemeName,
+    /// How DAC values are shown in the gauges and the status line
+    pub format: ValueFormat,
+    /// Channels linked together; adjusting one adjusts the others
+    pub gang: [bool; DAC_CHANNELS],
+    pub gang_mode: GangMode,
+    /// Complementary channel pairs enforced on every write
+    pub links: ChannelLinks,
+    /// Thresholds outside which a channel is highlighted
+    pub alarms: ChannelAlarms,
+    /// Output formulas evaluated after every write
+    pub mappings: ChannelMappings,
+    /// Outputs before each change, most recent last
+    pub undo: Vec<Snapshot>,
+    /// Outputs undone, most recent last; cleared by any new change
+    pub redo: Vec<Snapshot>,
+    pub recording: Option<Recording>,
+    /// Last recorded macro
+    pub macro_keys: Vec<MacroKey>,
+    pub playback: Option<Playback>,
+    pub repeat: Option<KeyRepeat>,
+    /// Duration of large-step ramps, zero to jump at once
+    pub ramp_duration: Duration,
+    pub ramp: Option<ActiveRamp>,
+    pub last_command: String,
+    pub last_response: String,
+    /// Transport errors and overload warnings, shown after the response
+    pub status_message: String,
+    /// Commands, responses and errors, oldest first
+    pub log: VecDeque<String>,
+    /// Last command already written to the log
+    pub logged_command: String,
+    /// Show the log in place of the help pane
+    pub show_log: bool,
+    /// Newest log lines selected for copying
+    pub log_selection: usize,
+    /// First line shown of the key and protocol reference, while it is open
+    pub reference: Option<u16>,
+    /// Latency probe shown in a popup, running or done
+    pub probe: Option<LatencyProbe>,
+    /// Time source of the keepalive, heartbeat and silence timers
+    pub clock: SharedClock,
+    pub keepalive_count: u64,
+    pub keepalive_interval: Duration,
+    pub keepalive_paused: bool,
+    pub last_keepalive: Instant,
+    /// Bridge heartbeat interval, with `--heartbeat`
+    pub heartbeat_interval: Option<Duration>,
+    pub last_heartbeat: Instant,
+    /// Last time anything arrived from the target
+    pub last_received: Instant,
+}
+
+impl AppState {
+    fn new(step: u16, keepalive_interval: Duration, sweep_interval: Duration) -> Self {
+        let clock = clock::system();
+        Self {
+            dac_values: [0; DAC_CHANNELS],
+            gpio_states: [false; GPIO_PINS],
+            selected_channel: 0,
+            step,
+            table_offset: 0,
+            offset_input: None,
+            register_input: None,
+            register_read: None,
+            features: Features::NONE,
+            hello_offered: None,
+            raw_input: None,
+            fill_input: None,
+            tables: TableShadow::new(),
+            tables_pending: Vec::new(),
+            tables_acked: 0,
+            raw_confirm: None,
+            raw_frame: None,
+            sweeping: false,
+            sweep_interval,
+            last_sweep: Instant::now(),
+            deferred: false,
+            pending: [false; DAC_CHANNELS],
+            theme: ThemeName::Default,
+            format: ValueFormat::default(),
+            gang: [false; DAC_CHANNELS],
+            gang_mode: GangMode::Absolute,
+            links: ChannelLinks::new(),
+            alarms: ChannelAlarms::new(),
+            mappings: ChannelMappings::default(),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            recording: None,
+            macro_keys: Vec::new(),
+            playback: None,
+            repeat: None,
+            ramp_duration: Duration::ZERO,
+            ramp: None,
+            last_command: "Ready".to_string(),
+            last_response: "No response yet".to_string(),
+            status_message: String::new(),
+            log: VecDeque::new(),
+            logged_command: "Ready".to_string(),
+            show_log: false,
+            log_selection: 1,
+            reference: None,
+            probe: None,
+            keepalive_count: 0,
+            keepalive_interval,
+            keepalive_paused: false,
+            last_keepalive: clock.now(),
+            heartbeat_interval: None,
+            last_heartbeat: clock.now(),
+            last_received: clock.now(),
+            clock,
+        }
+    }
+}
+
+pub struct App {
+    pub state: AppState,
+    pub should_quit: bool,
+    /// Counters of the connected transport, shared with its threads
+    pub stats: SharedStats,
+    pub keymap: Keymap,
+    /// Target as given, for the state summary
+    pub target: String,
+    /// Where exported reports are written
+    pub report_dir: PathBuf,
+    /// Ask before sending a raw frame, which skips the `--strict` checks
+    pub strict: bool,
+    /// Kept open: on X11 the copied text lives only as long as its owner
+    #[cfg(feature = "clipboard")]
+    pub clipboard: Option<arboard::Clipboard>,
+    #[cfg(feature = "lua")]
+    pub script: Option<ScriptHost>,
+}
+
+impl App {
+    pub fn new(step: u16, keepalive_interval: Duration, sweep_interval: Duration) -> Self {
+        Self {
+            state: AppState::new(step, keepalive_interval, sweep_interval),
+            should_quit: false,
+            stats: SharedStats::new(),
+            keymap: Keymap::default(),
+            target: String::new(),
+            report_dir: PathBuf::from("."),
+            strict: false,
+            #[cfg(feature = "clipboard")]
+            clipboard: None,
+            #[cfg(feature = "lua")]
+            script: None,
+        }
+    }
+
+    /// Handle a key press from the user: macro control, then recording
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Vec<u8>> {
+        // Any key stops a running macro and is otherwise ignored
+        if self.state.playback.take().is_some() {
+            self.state.last_command = "Macro stopped".to_string();
+            return None;
+        }
+
+        if let Some(scroll) = self.state.reference {
+            self.state.reference = scroll_reference(&self.keymap, scroll, key.code);
+            return None;
+        }
+
+        if let Some(probe) = self.state.probe.take() {
+            if probe.running() {
+                self.state.last_command = "Latency probe stopped".to_string();
+            }
+            return None;
+        }
+
+        if !self.typing() {
+            match self.keymap.action(&key) {
+                Some(Action::HelpScreen) => {
+                    self.state.reference = Some(0);
+                    return None;
+                }
+                Some(Action::RecordMacro) => {
+                    self.toggle_recording();
+                    return None;
+                }
+                Some(Action::ReplayMacro) => {
+                    self.start_playback();
+                    return None;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(recording) = &mut self.state.recording {
+            recording.keys.push((recording.last_key.elapsed(), key));
+            recording.last_key = Instant::now();
+        }
+        self.apply_key(key)
+    }
+
+    /// Carry out a key press, first sending what a held key has not sent yet
+    ///
+    /// Any key but another large step stops a running ramp where it is.
+    fn apply_key(&mut self, key: KeyEvent) -> Option<Vec<u8>> {
+        let action = self.keymap.action(&key);
+        if action != Some(Action::LargeStep) {
+            if let Some(active) = self.state.ramp.take() {
+                let ch = active.ramp.channel as usize;
+                self.state.last_command = format!(
+                    "Ramp stopped at {}",
+                    self.dac_text(ch, self.state.dac_values[ch])
+                );
+            }
+        }
+        let held = match &mut self.state.repeat {
+            Some(repeat) if Some(repeat.action) != action => repeat.held.take(),
+            _ => None,
+        };
+        match (held, self.apply_undoable_key(key)) {
+            (Some(mut held), Some(command)) => {
+                held.extend(command);
+                Some(held)
+            }
+            (held, command) => held.or(command),
+        }
+    }
+
+    /// Carry out a key press, recording any DAC or GPIO change for undo
+    fn apply_undoable_key(&mut self, key: KeyEvent) -> Option<Vec<u8>> {
+        if !self.typing() {
+            match self.keymap.action(&key) {
+                Some(Action::Redo) => return self.redo(),
+                Some(Action::Undo) => return self.undo(),
+                _ => {}
+            }
+        }
+
+        // Starting a ramp changes nothing yet, but undo should return to its start
+        let before = self.snapshot();
+        let ramp_before = self.state.ramp;
+        let command = self.dispatch_key(key);
+        if self.snapshot() != before || self.state.ramp != ramp_before {
+            if self.state.undo.len() == UNDO_LIMIT {
+                self.state.undo.remove(0);
+            }
+            self.state.undo.push(before);
+            self.state.redo.clear();
+        }
+        command
+    }
+
+    fn dispatch_key(&mut self, key: KeyEvent) -> Option<Vec<u8>> {
+        if self.state.offset_input.is_some() {
+            return self.handle_offset_input(key.code);
+        }
+        if self.state.register_input.is_some() {
+            return self.handle_register_input(key.code);
+        }
+        if self.state.raw_input.is_some() {
+            return self.handle_raw_input(key.code);
+        }
+        if self.state.raw_confirm.is_some() {
+            return self.handle_raw_confirm(key.code);
+        }
+        if self.state.fill_input.is_some() {
+            return self.handle_fill_input(key.code);
+        }
+
+        // Script hotkeys take precedence over the built-in bindings
+        #[cfg(feature = "lua")]
+        if let KeyCode::Char(c) = key.code {
+            if !Key::from(&key).alt {
+                if let Some(command) = self.run_script(|script| script.handle_key(c)) {
+                    return Some(command);
+                }
+            }
+        }
+
+        match self.keymap.action(&key)? {
+            Action::Quit => {
+                self.should_quit = true;
+                None
+            }
+            Action::PrevChannel => {
+                self.state.selected_channel =
+                    (self.state.selected_channel + DAC_CHANNELS - 1) % DAC_CHANNELS;
+                None
+            }
+            Action::NextChannel => {
+                self.state.selected_channel = (self.state.selected_channel + 1) % DAC_CHANNELS;
+                None
+            }
+            action @ (Action::StepUp | Action::StepDown) => {
+                let ch = self.state.selected_channel;
+                let step = self.repeat_step(action);
+                let new_value = if action == Action::StepUp {
+                    self.state.dac_values[ch].saturating_add(step)
+                } else {
+                    self.state.dac_values[ch].saturating_sub(step)
+                };
+                self.state.last_command = self.dac_text(ch, new_value);
+                if step != self.state.step {
+                    self.state
+                        .last_command
+                        .push_str(&format!(" (step {})", step));
+                }
+                let command = self.write_dac(ch, new_value);
+                self.throttle_repeat(command)
+            }
+            Action::FineUp => {
+                let ch = self.state.selected_channel;
+                let new_value = self.state.dac_values[ch].saturating_add(16);
+                self.state.last_command = self.dac_text(ch, new_value);
+                self.write_dac(ch, new_value)
+            }
+            Action::FineDown => {
+                let ch = self.state.selected_channel;
+                let new_value = self.state.dac_values[ch].saturating_sub(16);
+                self.state.last_command = self.dac_text(ch, new_value);
+                self.write_dac(ch, new_value)
+            }
+            Action::TableOffset(offset) => Some(self.set_table_offset(offset)),
+            Action::NextTableOffset => {
+                Some(self.set_table_offset(self.state.table_offset.wrapping_add(1)))
+            }
+            Action::PrevTableOffset => {
+                Some(self.set_table_offset(self.state.table_offset.wrapping_sub(1)))
+            }
+            Action::EnterTableOffset => {
+                self.state.offset_input = Some(String::new());
+                self.state.last_command =
+                    "Enter table offset (decimal or 0x hex), Enter to send".to_string();
+                None
+            }
+            Action::Registers => {
+                self.state.register_input = Some(String::new());
+                self.state.last_command = register_prompt("");
+                None
+            }
+            Action::RawFrame => {
+                self.state.raw_input = Some(String::new());
+                self.state.last_command = raw_prompt("");
+                None
+            }
+            Action::FillTable => {
+                self.state.fill_input = Some(String::new());
+                self.state.last_command = fill_prompt("");
+                None
+            }
+            Action::LatencyProbe => {
+                self.state.probe = Some(LatencyProbe::default());
+                self.state.last_command = format!("Latency probe: {} keepalives", PROBE_COUNT);
+                None
+            }
+            Action::Deferred => {
+                self.state.deferred = !self.state.deferred;
+                if self.state.deferred {
+                    self.state.last_command = "Deferred mode: changes load on L (LDAC)".to_string();
+                    None
+                } else {
+                    // Leaving deferred mode must not strand pending changes
+                    let command = self.apply_pending();
+                    self.state.last_command = "Immediate mode".to_string();
+                    command
+                }
+            }
+            Action::Ldac => {
+                let command = self.apply_pending();
+                if command.is_none() {
+                    self.state.last_command = "LDAC: nothing pending".to_string();
+                }
+                command
+            }
+            Action::Gang => {
+                let ch = self.state.selected_channel;
+                self.state.gang[ch] = !self.state.gang[ch];
+                self.state.last_command = format!(
+                    "DAC {} {} gang",
+                    ch,
+                    if self.state.gang[ch] {
+                        "joined"
+                    } else {
+                        "left"
+                    }
+                );
+                None
+            }
+            Action::GangMode => {
+                self.state.gang_mode = match self.state.gang_mode {
+                    GangMode::Absolute => GangMode::Ratio,
+                    GangMode::Ratio => GangMode::Absolute,
+                };
+                self.state.last_command = format!("Gang mode: {:?}", self.state.gang_mode);
+                None
+            }
+            Action::Theme => {
+                self.state.theme = self.state.theme.next();
+                self.state.last_command = format!("Theme: {:?}", self.state.theme);
+                None
+            }
+            Action::Display => {
+                self.state.format.display = self.state.format.display.next();
+                let range = self.state.format.range;
+                self.state.last_command = match self.state.format.display {
+                    ValueDisplay::Volts => {
+                        format!("Display: Volts ({} V to {} V)", range.min(), range.max())
+                    }
+                    display => format!("Display: {:?}", display),
+                };
+                None
+            }
+            Action::Sweep => {
+                self.state.sweeping = !self.state.sweeping;
+                self.state.last_sweep = Instant::now();
+                self.state.last_command = if self.state.sweeping {
+                    "Table offset sweep started".to_string()
+                } else {
+                    "Table offset sweep stopped".to_string()
+                };
+                None
+            }
+            Action::Gpio(pin) => {
+                let state = !self.state.gpio_states[pin as usize];
+                self.state.gpio_states[pin as usize] = state;
+                self.state.last_command =
+                    format!("GPIO {} = {}", pin, if state { "ON" } else { "OFF" });
+                Some(self.build_gpio_command(pin, state))
+            }
+            Action::ShorterKeepalive => {
+                self.state.keepalive_interval = self
+                    .state
+                    .keepalive_interval
+                    .saturating_sub(KEEPALIVE_STEP)
+                    .max(KEEPALIVE_STEP);
+                self.state.last_command = format!(
+                    "Keepalive interval = {:.1}s",
+                    self.state.keepalive_interval.as_secs_f64()
+                );
+                None
+            }
+            Action::LongerKeepalive => {
+                self.state.keepalive_interval += KEEPALIVE_STEP;
+                self.state.last_command = format!(
+                    "Keepalive interval = {:.1}s",
+                    self.state.keepalive_interval.as_secs_f64()
+                );
+                None
+            }
+            Action::Takeover => {
+                self.state.last_command = "Take bridge control".to_string();
+                Some(Command::Takeover.encode().to_vec())
+            }
+            Action::PauseKeepalive => {
+                self.state.keepalive_paused = !self.state.keepalive_paused;
+                // Resuming starts a fresh interval rather than firing at once
+                self.state.last_keepalive = self.state.clock.now();
+                self.state.last_command = if self.state.keepalive_paused {
+                    "Keepalive paused".to_string()
+                } else {
+                    "Keepalive resumed".to_string()
+                };
+                None
+            }
+            Action::LargeStep => {
+                let ch = self.state.selected_channel;
+                // Another step during a ramp continues from where the ramp is heading
+                let value = match self.state.ramp {
+                    Some(active) if active.ramp.channel as usize == ch => active.ramp.to,
+                    _ => self.state.dac_values[ch],
+                };
+                let new_value = if value == 65535 {
+                    0 // Wrap to 0 only when already at maximum
+                } else {
+                    value.saturating_add(8192)
+                };
+                self.state.last_command = format!("{} (large step)", self.dac_text(ch, new_value));
+                if self.state.ramp_duration.is_zero() {
+                    return self.write_dac(ch, new_value);
+                }
+                self.start_ramp(ch, new_value);
+                None
+            }
+            Action::ToggleLog => {
+                self.state.show_log = !self.state.show_log;
+                self.state.log_selection = 1;
+                self.state.last_command = if self.state.show_log {
+                    "Log shown: PgUp/PgDn select lines, Y copies them".to_string()
+                } else {
+                    "Log hidden".to_string()
+                };
+                None
+            }
+            Action::SelectMore => {
+                self.state.log_selection = (self.state.log_selection + 1).min(self.state.log.len());
+                None
+            }
+            Action::SelectLess => {
+                self.state.log_selection = self.state.log_selection.saturating_sub(1).max(1);
+                None
+            }
+            Action::Copy => {
+                let (text, what) = match self.selected_log() {
+                    Some(lines) => (lines.join("\n"), format!("{} log line(s)", lines.len())),
+                    None => (self.state_summary(), "state summary".to_string()),
+                };
+                self.state.last_command = match self.set_clipboard(text) {
+                    Ok(()) => format!("Copied {} to the clipboard", what),
+                    Err(e) => format!("Copy failed: {}", e),
+                };
+                None
+            }
+            Action::Export => {
+                self.state.last_command = match self.export_report() {
+                    Ok(path) => format!("Report written to {}", path.display()),
+                    Err(e) => format!("Report failed: {}", e),
+                };
+                None
+            }
+            // Handled before recording and undo tracking
+            Action::Undo
+            | Action::Redo
+            | Action::RecordMacro
+            | Action::ReplayMacro
+            | Action::HelpScreen => None,
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        match self.state.recording.take() {
+            Some(recording) => {
+                self.state.last_command =
+                    format!("Macro recorded: {} keys, @ to replay", recording.keys.len());
+                self.state.macro_keys = recording.keys;
+            }
+            None => {
+                self.state.recording = Some(Recording {
+                    keys: Vec::new(),
+                    last_key: Instant::now(),
+                });
+                self.state.last_command = "Recording macro, q to stop".to_string();
+            }
+        }
+    }
+
+    /// Replay the recorded macro with its original timing (the first key at once)
+    fn start_playback(&mut self) {
+        if self.state.recording.is_some() {
+            self.state.last_command = "Stop recording (q) before replaying".to_string();
+        } else if self.state.macro_keys.is_empty() {
+            self.state.last_command = "No macro recorded (q to record)".to_string();
+        } else {
+            self.state.playback = Some(Playback {
+                next: 0,
+                due: Instant::now(),
+                started: Instant::now(),
+            });
+            self.state.last_command =
+                format!("Replaying macro ({} keys)", self.state.macro_keys.len());
+        }
+    }
+
+    /// How far macro playback has got, or `None` when not replaying
+    /// Append a line to the activity log, stamped with the UTC time of day
+    pub fn log(&mut self, line: String) {
+        let t = UtcTime::of(SystemTime::now());
+        if self.state.log.len() == LOG_LIMIT {
+            self.state.log.pop_front();
+        }
+        self.state.log.push_back(format!(
+            "{:02}:{:02}:{:02} {}",
+            t.hour, t.minute, t.second, line
+        ));
+    }
+
+    /// Log the last command once it changes
+    pub fn log_command(&mut self) {
+        // Input prompts change with every key; only what they lead to is logged
+        if !self.typing() && self.state.last_command != self.state.logged_command {
+            self.state.logged_command = self.state.last_command.clone();
+            self.log(format!("> {}", self.state.last_command));
+        }
+    }
+
+    /// Selected log lines, oldest first, or `None` while the log is hidden
+    fn selected_log(&self) -> Option<Vec<String>> {
+        if !self.state.show_log || self.state.log.is_empty() {
+            return None;
+        }
+        let first = self
+            .state
+            .log
+            .len()
+            .saturating_sub(self.state.log_selection);
+        Some(self.state.log.range(first..).cloned().collect())
+    }
+
+    /// Outputs, modes and link status as plain text for bug reports
+    fn state_summary(&self) -> String {
+        let state = &self.state;
+        let mut lines = vec![
+            title_text(self),
+            format!(
+                "Target: {} at {}",
+                self.target,
+                report::utc_timestamp(SystemTime::now())
+            ),
+        ];
+        for (ch, &value) in state.dac_values.iter().enumerate() {
+            let [raw, hex, percent, volts] = self.all_formats(value);
+            lines.push(format!(
+                "DAC{} = {} ({}, {}, {})",
+                ch, raw, hex, percent, volts
+            ));
+        }
+        let gpio: Vec<&str> = state
+            .gpio_states
+            .iter()
+            .map(|&on| if on { "ON" } else { "OFF" })
+            .collect();
+        lines.push(format!("GPIO 0-7 = {}", gpio.join(" ")));
+        lines.push(table_text(self));
+        lines.push(format!("Keepalive: {}", keepalive_text(self)));
+        lines.push(status_text(self));
+        lines.push(stats_text(self));
+        lines.join("\n")
+    }
+
+    /// `value` in every display mode: raw, hex, percent, volts
+    fn all_formats(&self, value: u16) -> [String; 4] {
+        [
+            ValueDisplay::Raw,
+            ValueDisplay::Hex,
+            ValueDisplay::Percent,
+            ValueDisplay::Volts,
+        ]
+        .map(|display| {
+            ValueFormat {
+                display,
+                range: self.state.format.range,
+            }
+            .format(value)
+        })
+    }
+
+    /// The panel state and the newest log lines as a Markdown report
+    fn markdown_report(&self, taken: SystemTime) -> String {
+        let state = &self.state;
+        let mut out = String::from("# DAC Control Panel Report\n\n");
+        out.push_str(&format!("- Taken: {}\n", report::utc_timestamp(taken)));
+        out.push_str(&format!("- Target: {}\n", self.target));
+        out.push_str(&format!(
+            "- Tool: tui_diagnostic {}, built {}, features: {}, protocol {}\n",
+            version::short_version(),
+            report::utc_timestamp(version::build_time()),
+            version::features_text(),
+            protocol::PROTOCOL_VERSION
+        ));
+        out.push_str(&format!("- Panel: {}\n", title_text(self)));
+        out.push_str(&format!("- {}\n", table_text(self)));
+        out.push_str(&format!("- Keepalive: {}\n", keepalive_text(self)));
+        out.push_str(&format!("- Last command: {}\n", state.last_command));
+        out.push_str(&format!("- Last response: {}\n", state.last_response));
+        out.push_str(&format!("- Traffic: {}\n", stats_text(self)));
+
+        out.push_str("\n## DAC Outputs\n\n");
+        out.push_str("| Channel | Code | Hex | Percent | Volts | Notes |\n");
+        out.push_str("|---------|------|-----|---------|-------|-------|\n");
+        for (ch, &value) in state.dac_values.iter().enumerate() {
+            let [raw, hex, percent, volts] = self.all_formats(value);
+            let mut notes = Vec::new();
+            if state.gang[ch] {
+                notes.push("ganged");
+            }
+            if state.pending[ch] {
+                notes.push("pending LDAC");
+            }
+            if state.mappings.is_mapped(ch as u8) {
+                notes.push("mapped");
+            }
+            out.push_str(&format!(
+                "| DAC{} | {} | {} | {} | {} | {} |\n",
+                ch,
+                raw,
+                hex,
+                percent,
+                volts,
+                notes.join(", ")
+            ));
+        }
+
+        out.push_str("\n## GPIO\n\n");
+        out.push_str("| Pin | State |\n|-----|-------|\n");
+        for (pin, &on) in state.gpio_states.iter().enumerate() {
+            out.push_str(&format!(
+                "| GPIO{} | {} |\n",
+                pin,
+                if on { "ON" } else { "OFF" }
+            ));
+        }
+
+        let first = state.log.len().saturating_sub(REPORT_LOG_LINES);
+        out.push_str(&format!(
+            "\n## Log\n\nLast {} of {} lines (UTC).\n\n```text\n",
+            state.log.len() - first,
+            state.log.len()
+        ));
+        for line in state.log.range(first..) {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str("```\n");
+        out
+    }
+
+    /// Write a report to a new timestamped file in the report directory
+    fn export_report(&self) -> Result<PathBuf> {
+        let taken = SystemTime::now();
+        let t = UtcTime::of(taken);
+        let stem = format!(
+            "tui-report-{:04}{:02}{:02}-{:02}{:02}{:02}",
+            t.year, t.month, t.day, t.hour, t.minute, t.second
+        );
+        std::fs::create_dir_all(&self.report_dir)?;
+        // Never overwrite: a second report in the same second gets a suffix
+        let mut path = self.report_dir.join(format!("{}.md", stem));
+        let mut n = 1;
+        while path.exists() {
+            n += 1;
+            path = self.report_dir.join(format!("{}-{}.md", stem, n));
+        }
+        std::fs::write(&path, self.markdown_report(taken))?;
+        Ok(path)
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn set_clipboard(&mut self, text: String) -> Result<()> {
+        let clipboard = match &mut self.clipboard {
+            Some(clipboard) => clipboard,
+            None => self.clipboard.insert(arboard::Clipboard::new()?),
+        };
+        clipboard.set_text(text)?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "clipboard"))]
+    fn set_clipboard(&mut self, _text: String) -> Result<()> {
+        Err(anyhow!("built without the clipboard feature"))
+    }
+
+    pub fn playback_progress(&self) -> Option<Progress> {
+        let playback = self.state.playback?;
+        Some(Progress {
+            done: playback.next,
+            total: self.state.macro_keys.len(),
+            elapsed: playback.started.elapsed(),
+            current: None,
+        })
+    }
+
+    /// Time left until the next macro key, or `None` when not replaying
+    pub fn macro_due_in(&self) -> Option<Duration> {
+        let playback = self.state.playback?;
+        Some(playback.due.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn handle_macro(&mut self) -> Option<Vec<u8>> {
+        let playback = self.state.playback?;
+        let (_, key) = self.state.macro_keys[playback.next];
+        let command = self.apply_key(key);
+        self.state.playback = match self.state.macro_keys.get(playback.next + 1) {
+            Some(&(delay, _)) => Some(Playback {
+                next: playback.next + 1,
+                due: Instant::now() + delay,
+                ..playback
+            }),
+            None => {
+                self.state.last_command.push_str(" (macro done)");
+                None
+            }
+        };
+        command
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            dac_values: self.state.dac_values,
+            gpio_states: self.state.gpio_states,
+        }
+    }
+
+    fn undo(&mut self) -> Option<Vec<u8>> {
+        let Some(previous) = self.state.undo.pop() else {
+            self.state.last_command = "Nothing to undo".to_string();
+            return None;
+        };
+        self.state.redo.push(self.snapshot());
+        let command = self.restore_snapshot(previous);
+        self.state.last_command = format!("Undo ({} left)", self.state.undo.len());
+        command
+    }
+
+    fn redo(&mut self) -> Option<Vec<u8>> {
+        let Some(next) = self.state.redo.pop() else {
+            self.state.last_command = "Nothing to redo".to_string();
+            return None;
+        };
+        self.state.undo.push(self.snapshot());
+        let command = self.restore_snapshot(next);
+        self.state.last_command = format!("Redo ({} left)", self.state.redo.len());
+        command
+    }
+
+    /// Send the channels and pins that differ from `snapshot` back to its values
+    ///
+    /// The snapshot already reflects gangs, complements and formulas, so the
+    /// values are written as they are. In deferred mode DAC changes wait for LDAC.
+    fn restore_snapshot(&mut self, snapshot: Snapshot) -> Option<Vec<u8>> {
+        let mut commands = Vec::new();
+        for ch in 0..DAC_CHANNELS {
+            let value = snapshot.dac_values[ch];
+            if self.state.dac_values[ch] != value {
+                self.state.dac_values[ch] = value;
+                if self.state.deferred {
+                    self.state.pending[ch] = true;
+                } else {
+                    commands.extend(self.build_dac_command(ch as u8, value));
+                }
+            }
+        }
+        for pin in 0..GPIO_PINS {
+            let on = snapshot.gpio_states[pin];
+            if self.state.gpio_states[pin] != on {
+                self.state.gpio_states[pin] = on;
+                commands.extend(self.build_gpio_command(pin as u8, on));
+            }
+        }
+        (!commands.is_empty()).then_some(commands)
+    }
+
+    fn build_dac_command(&self, channel: u8, value: u16) -> Vec<u8> {
+        Command::DacWrite { channel, value }.encode().to_vec()
+    }
+
+    fn build_ldac_command(&self) -> Vec<u8> {
+        Command::Ldac.encode().to_vec()
+    }
+
+    /// Send a DAC write now, or hold it for LDAC in deferred mode
+    fn write_dac(&mut self, channel: usize, value: u16) -> Option<Vec<u8>> {
+        let old = self.state.dac_values[channel];
+        let mut changed = vec![(channel, value)];
+        if self.state.gang[channel] {
+            for ch in (0..DAC_CHANNELS).filter(|&ch| ch != channel && self.state.gang[ch]) {
+                let follower = self.state.dac_values[ch];
+                changed.push((ch, self.state.gang_mode.follow(old, value, follower)));
+            }
+            if changed.len() > 1 {
+                self.state
+                    .last_command
+                    .push_str(&format!(" (+{} ganged)", changed.len() - 1));
+            }
+        }
+
+        // Complements follow whatever was written, ganged channels included
+        let mut writes: Vec<(usize, u16)> = Vec::new();
+        for (ch, value) in changed {
+            for (ch, value) in self.state.links.resolve(ch as u8, value) {
+                if !writes.iter().any(|&(written, _)| written == ch as usize) {
+                    writes.push((ch as usize, value));
+                }
+            }
+        }
+
+        // Formulas see the new values; their outputs keep complements intact too
+        let mut values = self.state.dac_values;
+        for &(ch, value) in &writes {
+            values[ch] = value;
+        }
+        for (ch, value) in self.state.mappings.evaluate(&values) {
+            for (ch, value) in self.state.links.resolve(ch, value) {
+                match writes
+                    .iter_mut()
+                    .find(|(written, _)| *written == ch as usize)
+                {
+                    Some(write) => write.1 = value,
+                    None => writes.push((ch as usize, value)),
+                }
+            }
+        }
+
+        let mut commands = Vec::new();
+        for (ch, value) in writes {
+            self.state.dac_values[ch] = value;
+            if self.state.deferred {
+                self.state.pending[ch] = true;
+            } else {
+                commands.extend(self.build_dac_command(ch as u8, value));
+            }
+        }
+
+        if self.state.deferred {
+            self.state.last_command.push_str(" (deferred)");
+            return None;
+        }
+        Some(commands)
+    }
+
+    /// Status line text for a write of `value` to `channel`
+    fn dac_text(&self, channel: usize, value: u16) -> String {
+        format!("DAC {} = {}", channel, self.state.format.format(value))
+    }
+
+    /// Load every pending channel followed by one LDAC, or `None` if nothing is pending
+    fn apply_pending(&mut self) -> Option<Vec<u8>> {
+        let mut commands = Vec::new();
+        for ch in 0..DAC_CHANNELS {
+            if std::mem::take(&mut self.state.pending[ch]) {
+                commands.extend(self.build_dac_command(ch as u8, self.state.dac_values[ch]));
+            }
+        }
+        if commands.is_empty() {
+            return None;
+        }
+
+        let channels = commands.len() / 4;
+        commands.extend(self.build_ldac_command());
+        self.state.last_command = format!("LDAC: applied {} channel(s)", channels);
+        Some(commands)
+    }
+
+    fn build_gpio_command(&self, pin: u8, state: bool) -> Vec<u8> {
+        Command::Gpio { pin, on: state }.encode().to_vec()
+    }
+
+    fn build_table_offset_command(&self, offset: u8) -> Vec<u8> {
+        Command::UseTable { offset }.encode().to_vec()
+    }
+
+    fn set_table_offset(&mut self, offset: u8) -> Vec<u8> {
+        self.state.table_offset = offset;
+        self.state.last_command = format!("Table offset = {} (0x{:02X})", offset, offset);
+        self.build_table_offset_command(offset)
+    }
+
+    /// Keys while typing an offset: digits/hex, Backspace, Enter to send, ESC to cancel
+    fn handle_offset_input(&mut self, key: KeyCode) -> Option<Vec<u8>> {
+        let input = self.state.offset_input.as_mut()?;
+        match key {
+            KeyCode::Char(c) if c.is_ascii_hexdigit() || c == 'x' || c == 'X' => {
+                if input.len() < 4 {
+                    input.push(c);
+                }
+                None
+            }
+            KeyCode::Backspace => {
+                input.pop();
+                None
+            }
+            KeyCode::Esc => {
+                self.state.offset_input = None;
+                self.state.last_command = "Offset input cancelled".to_string();
+                None
+            }
+            KeyCode::Enter => {
+                let input = self.state.offset_input.take()?;
+                let parsed = match input.strip_prefix("0x").or(input.strip_prefix("0X")) {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => input.parse::<u8>(),
+                };
+                match parsed {
+                    Ok(offset) => Some(self.set_table_offset(offset)),
+                    Err(_) => {
+                        self.state.last_command =
+                            format!("Invalid table offset '{}' (0-255 or 0x00-0xFF)", input);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether keys go to an input line rather than to their actions
+    fn typing(&self) -> bool {
+        self.state.offset_input.is_some()
+            || self.state.register_input.is_some()
+            || self.state.raw_input.is_some()
+            || self.state.raw_confirm.is_some()
+            || self.state.fill_input.is_some()
+    }
+
+    /// Keys while typing a register access: `REG` reads, `REG=VALUE` writes
+    fn handle_register_input(&mut self, key: KeyCode) -> Option<Vec<u8>> {
+        let input = self.state.register_input.as_mut()?;
+        match key {
+            KeyCode::Char(c) if c.is_ascii_hexdigit() || "xX=".contains(c) => {
+                if input.len() < 16 {
+                    input.push(c);
+                }
+                self.state.last_command = register_prompt(input);
+                None
+            }
+            KeyCode::Backspace => {
+                input.pop();
+                self.state.last_command = register_prompt(input);
+                None
+            }
+            KeyCode::Esc => {
+                self.state.register_input = None;
+                self.state.last_command = "Register input cancelled".to_string();
+                None
+            }
+            KeyCode::Enter => {
+                let input = self.state.register_input.take()?;
+                let (reg, value) = match input.split_once('=') {
+                    Some((reg, value)) => (reg, Some(value)),
+                    None => (input.as_str(), None),
+                };
+                let reg = parse_number(reg).and_then(|reg| u8::try_from(reg).ok());
+                let value =
+                    value.map(|value| parse_number(value).and_then(|v| u16::try_from(v).ok()));
+                match (reg, value) {
+                    (Some(_), None) if !self.state.features.contains(Features::READBACK) => {
+                        self.state.last_command =
+                            "Register reads need firmware that agrees to readback in its hello"
+                                .to_string();
+                        None
+                    }
+                    (Some(reg), None) => {
+                        self.state.register_read = Some(reg);
+                        self.state.last_command = format!("Register {} read", reg);
+                        Some(Command::RegisterRead { reg }.encode().to_vec())
+                    }
+                    (Some(reg), Some(Some(value))) => {
+                        self.state.last_command =
+                            format!("Register {} = 0x{:04X} ({})", reg, value, value);
+                        Some(Command::RegisterWrite { reg, value }.encode().to_vec())
+                    }
+                    _ => {
+                        self.state.last_command = format!(
+                            "Invalid register access '{}' (REG 0-255, VALUE 0-65535, decimal or 0x hex)",
+                            input
+                        );
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Hello offering register reads, sent on every (re)connect since only
+    /// firmware that agrees to them has them
+    pub fn hello(&mut self) -> Vec<u8> {
+        let features = Features::EXTENDED | Features::READBACK;
+        self.state.features = Features::NONE;
+        self.state.hello_offered = Some(features);
+        Command::Hello {
+            version: PROTOCOL_VERSION,
+            features,
+        }
+        .encode()
+        .to_vec()
+    }
+
+    /// Take `response` as the answer to the hello waiting for one; firmware
+    /// without hello agrees to nothing
+    pub fn hello_response(&mut self, response: &[u8]) -> bool {
+        let Some(offered) = self.state.hello_offered.take() else {
+            return false;
+        };
+        self.state.features = protocol::hello_answer(response)
+            .map_or(Features::NONE, |hello| hello.features.intersection(offered));
+        self.log(format!("< Hello: features {}", self.state.features));
+        true
+    }
+
+    /// Show the answer to a register read, if `response` is one
+    pub fn register_response(&mut self, response: &[u8]) -> Option<String> {
+        let reg = self.state.register_read.take()?;
+        Some(match protocol::register_value(response) {
+            Ok(value) => format!("Register {} = 0x{:04X} ({})", reg, value, value),
+            Err(e) => format!("Register {} read failed: {}", reg, e),
+        })
+    }
+
+    /// Keys while typing a raw frame: hex bytes, Enter to send (or confirm), ESC to cancel
+    fn handle_raw_input(&mut self, key: KeyCode) -> Option<Vec<u8>> {
+        let input = self.state.raw_input.as_mut()?;
+        match key {
+            KeyCode::Char(c) if c.is_ascii_hexdigit() || " xX".contains(c) => {
+                if input.len() < 64 {
+                    input.push(c);
+                }
+                self.state.last_command = raw_prompt(input);
+            }
+            KeyCode::Backspace => {
+                input.pop();
+                self.state.last_command = raw_prompt(input);
+            }
+            KeyCode::Esc => {
+                self.state.raw_input = None;
+                self.state.last_command = "Raw frame cancelled".to_string();
+            }
+            KeyCode::Enter => {
+                let input = self.state.raw_input.take()?;
+                match protocol::parse_frame(&input) {
+                    Ok(frame) if self.strict => {
+                        self.state.last_command =
+                            format!("Send {:02X?} past --strict? (y to send)", frame);
+                        self.state.raw_confirm = Some(frame);
+                    }
+                    Ok(frame) => self.send_raw(frame),
+                    Err(e) => {
+                        self.state.last_command = format!("Invalid raw frame '{}': {}", input, e)
+                    }
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// `y` sends the raw frame awaiting confirmation, any other key drops it
+    fn handle_raw_confirm(&mut self, key: KeyCode) -> Option<Vec<u8>> {
+        let frame = self.state.raw_confirm.take()?;
+        match key {
+            KeyCode::Char('y' | 'Y') => self.send_raw(frame),
+            _ => self.state.last_command = "Raw frame not sent".to_string(),
+        }
+        None
+    }
+
+    /// Keys while typing a table fill: `TABLE SPEC`, e.g. `2 sine | scale 0.5`
+    fn handle_fill_input(&mut self, key: KeyCode) -> Option<Vec<u8>> {
+        let input = self.state.fill_input.as_mut()?;
+        match key {
+            KeyCode::Char(c) => {
+                if input.len() < 64 {
+                    input.push(c);
+                }
+                self.state.last_command = fill_prompt(input);
+                None
+            }
+            KeyCode::Backspace => {
+                input.pop();
+                self.state.last_command = fill_prompt(input);
+                None
+            }
+            KeyCode::Esc => {
+                self.state.fill_input = None;
+                self.state.last_command = "Table fill cancelled".to_string();
+                None
+            }
+            KeyCode::Enter => {
+                let input = self.state.fill_input.take()?;
+                let (table, spec) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+                let table = parse_number(table)
+                    .and_then(|table| u8::try_from(table).ok())
+                    .filter(|&table| (table as usize) < TABLES);
+                match (table, spec.parse::<TableSpec>()) {
+                    (Some(table), Ok(spec)) => {
+                        let changes = self.state.tables.changes(table, &spec.generate());
+                        self.state.last_command = format!(
+                            "Table {} = {} ({} entries changed)",
+                            table,
+                            spec,
+                            changes.len()
+                        );
+                        let bytes = self.send_table_writes(changes);
+                        (!bytes.is_empty()).then_some(bytes)
+                    }
+                    (None, _) => {
+                        self.state.last_command = format!(
+                            "Invalid table fill '{}' (TABLE 0-{} first)",
+                            input,
+                            TABLES - 1
+                        );
+                        None
+                    }
+                    (_, Err(e)) => {
+                        self.state.last_command = format!("Invalid table fill: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Encode table writes, which enter the shadow only once acknowledged
+    fn send_table_writes(&mut self, writes: Vec<Command>) -> Vec<u8> {
+        let bytes = writes.iter().flat_map(|cmd| cmd.encode()).collect();
+        self.state.tables_pending.extend(writes);
+        bytes
+    }
+
+    /// Count the acknowledgements in `response` towards the pending table
+    /// writes and enter them in the shadow once there are as many as writes
+    ///
+    /// Any error status forgets the shadow instead, as the TUI cannot tell
+    /// which command it answers.
+    pub fn table_acks(&mut self, response: &[u8]) {
+        if self.state.tables_pending.is_empty() {
+            return;
+        }
+        let mut rest = response;
+        while let [first, second, ..] = *rest {
+            match (first, second) {
+                (0x00, 0x00) => self.state.tables_acked += 1,
+                (0x00, _) => return self.forget_tables(),
+                _ => {}
+            }
+            rest = &rest[framing::response_len(first, second).min(rest.len())..];
+        }
+        if self.state.tables_acked >= self.state.tables_pending.len() {
+            for cmd in std::mem::take(&mut self.state.tables_pending) {
+                self.state.tables.record(&cmd);
+            }
+            self.state.tables_acked = 0;
+        }
+    }
+
+    /// Count every table entry as unknown, including those still unacknowledged
+    pub fn forget_tables(&mut self) {
+        self.state.tables.clear();
+        self.state.tables_pending.clear();
+        self.state.tables_acked = 0;
+    }
+
+    /// Hand `frame` to the main loop, which sends it without any checks
+    fn send_raw(&mut self, frame: Vec<u8>) {
+        self.state.last_command = format!("Raw frame {:02X?}", frame);
+        self.state.raw_frame = Some(frame);
+    }
+
+    /// Step for a step up or down, doubling every few repeats while the key is held
+    fn repeat_step(&mut self, action: Action) -> u16 {
+        let channel = self.state.selected_channel;
+        let now = Instant::now();
+        match &mut self.state.repeat {
+            Some(repeat)
+                if repeat.action == action
+                    && repeat.channel == channel
+                    && now - repeat.last_press < REPEAT_GAP =>
+            {
+                repeat.count += 1;
+                repeat.last_press = now;
+            }
+            repeat => {
+                *repeat = Some(KeyRepeat {
+                    action,
+                    channel,
+                    count: 0,
+                    last_press: now,
+                    last_write: now,
+                    held: None,
+                });
+            }
+        }
+        let count = self.state.repeat.as_ref().map_or(0, |repeat| repeat.count);
+        let shift = (count / REPEATS_PER_DOUBLING).min(MAX_REPEAT_SHIFT);
+        self.state.step.saturating_mul(1 << shift)
+    }
+
+    /// Send a held key's writes at most every `REPEAT_WRITE_INTERVAL`, keeping the latest
+    fn throttle_repeat(&mut self, command: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        let repeat = self.state.repeat.as_mut()?;
+        if repeat.count == 0 || repeat.last_write.elapsed() >= REPEAT_WRITE_INTERVAL {
+            repeat.last_write = Instant::now();
+            repeat.held = None;
+            command
+        } else {
+            repeat.held = command;
+            None
+        }
+    }
+
+    /// Time left until a held key's latest write is due, or `None` if it was sent
+    pub fn repeat_due_in(&self) -> Option<Duration> {
+        let repeat = self.state.repeat.as_ref()?;
+        repeat.held.as_ref()?;
+        Some(REPEAT_WRITE_INTERVAL.saturating_sub(repeat.last_write.elapsed()))
+    }
+
+    pub fn handle_repeat(&mut self) -> Option<Vec<u8>> {
+        let repeat = self.state.repeat.as_mut()?;
+        repeat.last_write = Instant::now();
+        repeat.held.take()
+    }
+
+    fn start_ramp(&mut self, channel: usize, value: u16) {
+        let now = Instant::now();
+        let from = self.state.dac_values[channel];
+        self.state.ramp = Some(ActiveRamp {
+            ramp: Ramp::new(channel as u8, from, value, self.state.ramp_duration),
+            start: now,
+            next: now,
+        });
+        self.state.last_command.push_str(&format!(
+            " (ramping over {} ms)",
+            self.state.ramp_duration.as_millis()
+        ));
+    }
+
+    /// Time left until the next ramp write, or `None` without a ramp
+    pub fn ramp_due_in(&self) -> Option<Duration> {
+        let active = self.state.ramp?;
+        Some(active.next.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn handle_ramp(&mut self) -> Option<Vec<u8>> {
+        let mut active = self.state.ramp?;
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(active.start);
+        let ch = active.ramp.channel as usize;
+        let value = active.ramp.value_at(elapsed);
+
+        // Intermediate writes leave the status line (and the log) at the ramp's start
+        let status = self.state.last_command.clone();
+        let command = if value == self.state.dac_values[ch] {
+            None
+        } else {
+            self.write_dac(ch, value)
+        };
+        if active.ramp.is_done(elapsed) {
+            self.state.ramp = None;
+            self.state.last_command = format!("{} (ramp done)", self.dac_text(ch, value));
+        } else {
+            active.next = now + active.ramp.next_step_in(elapsed);
+            self.state.ramp = Some(active);
+            self.state.last_command = status;
+        }
+        command
+    }
+
+    /// Time left until the next sweep step, or `None` when not sweeping
+    pub fn sweep_due_in(&self) -> Option<Duration> {
+        if !self.state.sweeping {
+            return None;
+        }
+        Some(
+            self.state
+                .sweep_interval
+                .saturating_sub(self.state.last_sweep.elapsed()),
+        )
+    }
+
+    pub fn handle_sweep(&mut self) -> Vec<u8> {
+        self.state.last_sweep = Instant::now();
+        self.set_table_offset(self.state.table_offset.wrapping_add(1))
+    }
+
+    fn build_keepalive_command(&self) -> Vec<u8> {
+        Command::KeepAlive.encode().to_vec()
+    }
+
+    /// Time left until the next keepalive is due, or `None` while paused
+    ///
+    /// A latency probe keeps the link alive itself, and the answer to
+    /// another keepalive would pass for its own.
+    pub fn keepalive_due_in(&self) -> Option<Duration> {
+        if self.state.keepalive_paused || self.probe_due_in().is_some() {
+            return None;
+        }
+        Some(
+            self.state
+                .keepalive_interval
+                .saturating_sub(self.state.clock.elapsed(self.state.last_keepalive)),
+        )
+    }
+
+    pub fn handle_keepalive(&mut self) -> Vec<u8> {
+        self.state.last_keepalive = self.state.clock.now();
+        self.state.keepalive_count += 1;
+        self.state.last_command = format!("Keepalive #{}", self.state.keepalive_count);
+        self.build_keepalive_command()
+    }
+
+    /// Time left until the latency probe sends a keepalive or gives up
+    /// waiting for one, or `None` unless a probe runs
+    pub fn probe_due_in(&self) -> Option<Duration> {
+        let probe = self.state.probe.as_ref().filter(|probe| probe.running())?;
+        Some(match probe.waiting {
+            Some(sent) => PROBE_TIMEOUT.saturating_sub(sent.elapsed()),
+            None => Duration::ZERO,
+        })
+    }
+
+    /// Count an unanswered probe keepalive as lost and send the next one
+    pub fn handle_probe(&mut self) -> Option<Vec<u8>> {
+        let probe = self.state.probe.as_mut()?;
+        if probe.waiting.take().is_some() {
+            probe.lost += 1;
+        }
+        if probe.sent == PROBE_COUNT {
+            self.state.last_command = format!("Latency probe: {}", probe.summary());
+            return None;
+        }
+        probe.sent += 1;
+        probe.waiting = Some(Instant::now());
+        Some(self.build_keepalive_command())
+    }
+
+    /// Take a response as the answer to the probe keepalive waiting for one
+    pub fn probe_answer(&mut self) -> bool {
+        let answered = self
+            .state
+            .probe
+            .as_mut()
+            .is_some_and(LatencyProbe::answered);
+        if answered && self.probe_due_in().is_none() {
+            let summary = self.state.probe.as_ref().map(LatencyProbe::summary);
+            self.state.last_command = format!("Latency probe: {}", summary.unwrap_or_default());
+        }
+        answered
+    }
+
+    /// Time left until the next bridge heartbeat, or `None` without heartbeats
+    pub fn heartbeat_due_in(&self) -> Option<Duration> {
+        let interval = self.state.heartbeat_interval?;
+        Some(interval.saturating_sub(self.state.clock.elapsed(self.state.last_heartbeat)))
+    }
+
+    pub fn handle_heartbeat(&mut self) -> Vec<u8> {
+        self.state.last_heartbeat = self.state.clock.now();
+        Command::Heartbeat.encode().to_vec()
+    }
+
+    /// How long the bridge has been silent, once that is long enough to give up on it
+    pub fn bridge_lost(&self) -> Option<Duration> {
+        let interval = self.state.heartbeat_interval?;
+        let silent = self.state.clock.elapsed(self.state.last_received);
+        (silent >= interval * HEARTBEAT_MISSES).then_some(silent)
+    }
+
+    /// Time left until the next script timer, or `None` without a script
+    #[cfg(feature = "lua")]
+    pub fn script_due_in(&self) -> Option<Duration> {
+        self.script.as_ref()?.due_in()
+    }
+
+    /// Run a script hook against the current outputs and encode what it queued
+    ///
+    /// Returns `None` when there is no script or the hook did not apply;
+    /// script errors are shown in the status line.
+    #[cfg(feature = "lua")]
+    pub fn run_script(
+        &mut self,
+        hook: impl FnOnce(&ScriptHost) -> serialtest::error::Result<Option<Vec<Command>>>,
+    ) -> Option<Vec<u8>> {
+        let script = self.script.take()?;
+        script.sync(&self.state.dac_values, &self.state.gpio_states);
+        let result = hook(&script);
+        let message = script.take_log().pop();
+        self.script = Some(script);
+
+        let command = match result {
+            Ok(commands) => commands.map(|commands| self.apply_script_commands(commands)),
+            Err(e) => {
+                self.state.last_command = e.to_string();
+                return Some(Vec::new());
+            }
+        };
+        if let Some(message) = message {
+            self.state.last_command = format!("Script: {}", message);
+        }
+        command
+    }
+
+    /// Everything worth restoring on the next launch
+    pub fn session(&self, target: &Target) -> Session {
+        Session {
+            target: Some(target.clone()),
+            dac: self.state.dac_values,
+            gpio: self.state.gpio_states,
+            selected_channel: self.state.selected_channel,
+            step: self.state.step,
+            table_offset: self.state.table_offset,
+            deferred: self.state.deferred,
+            pending: self.state.pending,
+            gang: self.state.gang,
+            gang_ratio: self.state.gang_mode == GangMode::Ratio,
+            theme: self.state.theme,
+            display: self.state.format.display,
+            keepalive_interval: self.state.keepalive_interval,
+            keepalive_paused: self.state.keepalive_paused,
+            last_command: self.state.last_command.clone(),
+            last_response: self.state.last_response.clone(),
+        }
+    }
+
+    /// Take over a saved session and return the commands that replay it
+    pub fn restore(&mut self, session: &Session) -> Vec<u8> {
+        self.state.dac_values = session.dac;
+        self.state.gpio_states = session.gpio;
+        self.state.selected_channel = session.selected_channel;
+        self.state.step = session.step;
+        self.state.table_offset = session.table_offset;
+        self.state.deferred = session.deferred;
+        self.state.pending = session.pending;
+        self.state.gang = session.gang;
+        self.state.gang_mode = if session.gang_ratio {
+            GangMode::Ratio
+        } else {
+            GangMode::Absolute
+        };
+        self.state.theme = session.theme;
+        self.state.format.display = session.display;
+        self.state.keepalive_interval = session.keepalive_interval;
+        self.state.keepalive_paused = session.keepalive_paused;
+        self.state.undo.clear();
+        self.state.redo.clear();
+        self.state.last_response = session.last_response.clone();
+        self.state.last_command = format!("Session restored (last: {})", session.last_command);
+        session
+            .replay()
+            .iter()
+            .flat_map(|command| command.encode())
+            .collect()
+    }
+
+    /// Commands that bring a reopened device, which may have reset, back to
+    /// the outputs shown, after a new hello; its tables count as unknown from
+    /// then on
+    pub fn resync(&mut self, target: &Target) -> Vec<u8> {
+        self.forget_tables();
+        let mut bytes = self.hello();
+        bytes.extend(
+            self.session(target)
+                .replay()
+                .iter()
+                .flat_map(|command| command.encode()),
+        );
+        bytes
+    }
+
+    /// Script commands go through the same paths as key presses
+    #[cfg(feature = "lua")]
+    fn apply_script_commands(&mut self, commands: Vec<Command>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for command in commands {
+            match command {
+                Command::DacWrite { channel, value } => {
+                    self.state.last_command =
+                        format!("{} (script)", self.dac_text(channel as usize, value));
+                    bytes.extend(self.write_dac(channel as usize, value).unwrap_or_default());
+                }
+                Command::Gpio { pin, on } => {
+                    self.state.gpio_states[pin as usize] = on;
+                    bytes.extend(self.build_gpio_command(pin, on));
+                }
+                Command::UseTable { offset } => bytes.extend(self.set_table_offset(offset)),
+                // Fills send only the entries that changed, as from the prompt
+                Command::TableWrite {
+                    table,
+                    index,
+                    value,
+                } if self.state.tables.holds(table, index, value) => {}
+                Command::TableWrite { .. } => bytes.extend(self.send_table_writes(vec![command])),
+                Command::Ldac => match self.apply_pending() {
+                    Some(pending) => bytes.extend(pending),
+                    None => bytes.extend(self.build_ldac_command()),
+                },
+                other => bytes.extend(other.encode()),
+            }
+        }
+        bytes
+    }
+}
+
+/// Last-command line while typing a register access
+fn register_prompt(input: &str) -> String {
+    format!(
+        "Register: {}_ (REG to read, REG=VALUE to write, Enter to send, ESC to cancel)",
+        input
+    )
+}
+
+/// Last-command line while typing a raw frame
+fn raw_prompt(input: &str) -> String {
+    format!(
+        "Raw frame: {}_ (hex bytes, sent unchecked; Enter to send, ESC to cancel)",
+        input
+    )
+}
+
+/// Last-command line while typing a table fill
+fn fill_prompt(input: &str) -> String {
+    format!(
+        "Fill table: {}_ (TABLE then e.g. sine 2 | scale 0.5; Enter to send, ESC to cancel)",
+        input
+    )
+}
+
+/// Parse a decimal or `0x` hex number
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
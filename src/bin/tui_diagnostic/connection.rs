@@ -0,0 +1,157 @@
+//! Writer and reader threads between the panel and the link.
+
+use crate::AppEvent;
+use anyhow::Result;
+use serialtest::error::DacError;
+use serialtest::framing::Codec;
+use serialtest::mailbox::{self, CommandReceiver, CommandSender, Shed};
+use serialtest::transport::FramedLink;
+use serialtest::wake;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/// Commands queued for the writer before DAC updates are shed
+const COMMAND_CAPACITY: usize = 256;
+
+/// Whether a transport error means the port or connection is gone, e.g.
+/// after the host slept
+fn is_link_lost(error: &DacError) -> bool {
+    matches!(error, DacError::Transport(e) if wake::is_link_lost(e))
+}
+
+/// Send queued commands; blocks until a command arrives or the app exits
+fn run_writer_thread(
+    mut transport: FramedLink,
+    cmd_rx: CommandReceiver,
+    event_tx: mpsc::SyncSender<AppEvent>,
+) {
+    loop {
+        // Wake up when coalesced commands are due, otherwise wait for the next one
+        let command = match transport.flush_due_in() {
+            Some(due_in) => match cmd_rx.recv_timeout(due_in) {
+                Ok(command) => Some(command),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+            None => match cmd_rx.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            },
+        };
+
+        let result = match command {
+            Some(command) => transport.write_unchecked(&command).map(|_| ()),
+            None => transport.flush(),
+        };
+        match result {
+            Ok(()) => {}
+            Err(e) if is_link_lost(&e) => {
+                let _ = event_tx.send(AppEvent::LinkLost(format!("Write error: {:#}", e)));
+                return; // Every further write would fail the same way
+            }
+            Err(e) => {
+                let _ = event_tx.send(AppEvent::TransportError(format!("Write error: {:#}", e)));
+            }
+        }
+    }
+    let _ = transport.flush();
+}
+
+/// Read continuously and forward everything the device sends, solicited or not
+///
+/// Each read blocks for up to the read timeout, so an idle link costs no CPU.
+/// While the main loop is behind, reading waits for it instead of dropping
+/// responses: every status frame reaches the screen and the log.
+fn run_reader_thread(
+    mut transport: FramedLink,
+    event_tx: mpsc::SyncSender<AppEvent>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut buffer = [0u8; 256];
+
+    while !stop.load(Ordering::Relaxed) {
+        let event = match transport.read_responses(&mut buffer) {
+            Ok(0) => continue,
+            Ok(bytes_read) => AppEvent::Response(buffer[..bytes_read].to_vec()),
+            Err(e) if is_link_lost(&e) => {
+                let _ = event_tx.send(AppEvent::LinkLost(format!("Read error: {:#}", e)));
+                break; // Nothing more will arrive
+            }
+            Err(e) => {
+                let _ = event_tx.send(AppEvent::TransportError(format!("Read error: {:#}", e)));
+                continue;
+            }
+        };
+        if event_tx.send(event).is_err() {
+            break; // Main thread closed
+        }
+    }
+}
+
+/// Writer and reader threads serving one transport
+pub struct Connection {
+    commands: CommandSender,
+    /// Padding and strict checks for commands, which raw frames skip
+    codec: Codec,
+    /// Reports commands the checks refuse
+    events: mpsc::SyncSender<AppEvent>,
+    /// Stops the reader; the writer stops when `commands` is dropped
+    stop: Arc<AtomicBool>,
+}
+
+impl Connection {
+    pub fn start(transport: FramedLink, event_tx: &mpsc::SyncSender<AppEvent>) -> Result<Self> {
+        let (cmd_tx, cmd_rx) = mailbox::mailbox(COMMAND_CAPACITY);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // One thread writes commands, the other reads everything
+        let codec = transport.codec().clone();
+        let reader = transport.try_clone()?;
+        let event_tx_clone = event_tx.clone();
+        thread::spawn(move || {
+            run_writer_thread(transport, cmd_rx, event_tx_clone);
+        });
+        let event_tx_clone = event_tx.clone();
+        let stop_clone = stop.clone();
+        thread::spawn(move || {
+            run_reader_thread(reader, event_tx_clone, stop_clone);
+        });
+
+        Ok(Self {
+            commands: cmd_tx,
+            codec,
+            events: event_tx.clone(),
+            stop,
+        })
+    }
+
+    pub fn send(&self, command: Vec<u8>) {
+        match self.codec.check(&command) {
+            Ok(()) => self.send_raw(command),
+            Err(e) => {
+                // The main loop is the one sending; it reads the error once done
+                let _ = self
+                    .events
+                    .try_send(AppEvent::TransportError(format!("Write error: {}", e)));
+            }
+        }
+    }
+
+    /// Send `command` without the padding and strict checks
+    pub fn send_raw(&self, command: Vec<u8>) {
+        // Commands a full mailbox refuses are counted in its shed, which the
+        // status line reports
+        let _ = self.commands.send(command);
+    }
+
+    pub fn shed(&self) -> Shed {
+        self.commands.shed()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,583 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use crossterm::{
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyEvent, KeyEventKind},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{backend::CrosstermBackend, Terminal};
+use serialtest::alarms::{parse_threshold, ChannelAlarms, Threshold};
+use serialtest::channels::{parse_pair, ChannelLinks};
+use serialtest::diagnose::Failure;
+use serialtest::discovery::{self, DEFAULT_BROWSE_TIME};
+use serialtest::expr::{ChannelMappings, Mapping};
+use serialtest::framing::{Codec, Padding, StreamFraming};
+use serialtest::keymap::Keymap;
+use serialtest::mailbox::Shed;
+use serialtest::protocol;
+#[cfg(feature = "lua")]
+use serialtest::script::ScriptHost;
+use serialtest::session::{Session, DEFAULT_SESSION_FILE};
+use serialtest::target::Target;
+use serialtest::transport::{FramedLink, LinkOptions};
+use serialtest::units::VoltageRange;
+use serialtest::version;
+use serialtest::wake::{Backoff, SleepDetector};
+use serialtest::widgets::{ThemeName, ValueDisplay, ValueFormat};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod app;
+mod connection;
+mod ui;
+
+use app::App;
+use connection::Connection;
+use ui::ui;
+
+/// TUI diagnostic tool for DAC control
+#[derive(Parser, Debug)]
+#[command(name = "tui_diagnostic")]
+#[command(about = "Interactive TUI diagnostic tool for DAC control")]
+struct Args {
+    /// Connection target: serial device path, network address (IPv4:port, [IPv6]:port) or
+    /// serial:, pty:, tcp:, udp:, tls: or scheme:// target; without one, pick from the
+    /// detected serial ports and bridges
+    target: Option<Target>,
+
+    /// DAC value step size for up/down keys
+    #[arg(short, long, default_value = "256")]
+    step: u16,
+
+    /// Read timeout in milliseconds
+    #[arg(long, default_value = "200")]
+    read_timeout: u64,
+
+    /// Write timeout in milliseconds
+    #[arg(long, default_value = "1000")]
+    write_timeout: u64,
+
+    /// Keepalive interval in seconds
+    #[arg(long, default_value = "5")]
+    keepalive_interval: u64,
+
+    /// Send a heartbeat to the tcp_server bridge every SECS and reconnect when it has not
+    /// answered anything for three intervals (0 = off)
+    #[arg(long, value_name = "SECS", default_value = "0")]
+    heartbeat: u64,
+
+    /// Color theme (T cycles themes at runtime)
+    #[arg(long, value_enum, default_value = "default")]
+    theme: ThemeName,
+
+    /// DAC values as raw code, hex, percent or volts (F cycles displays at runtime)
+    #[arg(long, value_enum, default_value = "raw")]
+    display: ValueDisplay,
+
+    /// Output voltage at code 0, for the volts display
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    vmin: f64,
+
+    /// Output voltage at code 65535, for the volts display
+    #[arg(long, default_value = "10", allow_negative_numbers = true)]
+    vmax: f64,
+
+    /// Keep COMPLEMENT at 65535 - MASTER on every write (repeatable)
+    #[arg(long = "complement", value_name = "COMPLEMENT=MASTER", value_parser = parse_pair)]
+    complements: Vec<(u8, u8)>,
+
+    /// Highlight a DAC channel outside MIN..=MAX, e.g. 3=1000:50000 (repeatable)
+    #[arg(long = "alarm", value_name = "CHANNEL=MIN:MAX", value_parser = parse_threshold)]
+    alarms: Vec<(u8, Threshold)>,
+
+    /// Derive a channel from the others on every update, e.g. "ch3 = 0.5*ch1 + 1000" (repeatable)
+    #[arg(long = "map", value_name = "FORMULA")]
+    mappings: Vec<Mapping>,
+
+    /// Ramp large steps (Space) over this many milliseconds instead of jumping (0 = jump)
+    #[arg(long, default_value = "0")]
+    ramp: u64,
+
+    /// Table offset sweep step interval in milliseconds
+    #[arg(long, default_value = "100")]
+    sweep_interval: u64,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Trailing bytes that do not fill a 4-byte command: reject the batch, or zero-fill it
+    #[arg(long, value_enum, default_value = "reject")]
+    padding: Padding,
+
+    /// Validate channels, tables, GPIO pins and value fields before sending
+    #[arg(long)]
+    strict: bool,
+
+    /// Coalesce commands and send them at most this many milliseconds later (0 = send each at once)
+    #[arg(long, value_name = "MS", default_value = "0")]
+    coalesce: u64,
+
+    /// Session file written on exit and read by --resume
+    #[arg(long, value_name = "FILE", default_value = DEFAULT_SESSION_FILE)]
+    session: PathBuf,
+
+    /// Restore the saved session (outputs, selection, modes) and replay it to the device;
+    /// the saved target is used when none is given
+    #[arg(long)]
+    resume: bool,
+
+    /// Do not write the session file on exit
+    #[arg(long)]
+    no_save: bool,
+
+    /// Leave the link down when the port or connection fails (e.g. after the host
+    /// slept) instead of reopening it and restoring the outputs
+    #[arg(long)]
+    no_reopen: bool,
+
+    /// Directory for reports exported with E
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    report_dir: PathBuf,
+
+    /// Key bindings to apply over the defaults (`action = key ...` lines)
+    #[arg(long, value_name = "FILE")]
+    keymap: Option<PathBuf>,
+
+    /// Lua script with on_start, timers and hotkey handlers
+    #[cfg(feature = "lua")]
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+}
+
+/// Events queued for the main loop before the reader thread waits for it
+const EVENT_CAPACITY: usize = 256;
+
+fn codec(args: &Args) -> Codec {
+    Codec::new(args.crc, args.framing)
+        .with_padding(args.padding)
+        .with_strict(args.strict)
+}
+
+/// Open `target` with the codec and coalescing the arguments ask for
+fn open_link(target: &Target, args: &Args) -> Result<FramedLink> {
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(args.read_timeout),
+        write_timeout: Duration::from_millis(args.write_timeout),
+    };
+    let link = FramedLink::open(target, &options, codec(args))
+        .with_context(|| format!("Failed to open {}", target))?;
+    Ok(link.with_coalescing(Duration::from_millis(args.coalesce)))
+}
+
+#[derive(Debug, Clone)]
+enum AppEvent {
+    Input(KeyEvent),
+    TransportError(String),
+    /// The port or connection is gone and has to be reopened
+    LinkLost(String),
+    Response(Vec<u8>),
+}
+
+/// Open `target` again in place of `connection`, carrying the statistics and
+/// shed counts over
+fn reconnect(
+    target: &Target,
+    args: &Args,
+    app: &mut App,
+    connection: &mut Connection,
+    shed_before: &mut Shed,
+    event_tx: &mpsc::SyncSender<AppEvent>,
+) -> Result<()> {
+    let transport = open_link(target, args)?;
+    let stats = transport.stats();
+    let fresh = Connection::start(transport, event_tx)?;
+    let previous = app.stats.snapshot();
+    app.stats = stats;
+    app.stats.update(|stats| {
+        *stats = previous;
+        stats.record_reconnect();
+    });
+    let shed = connection.shed();
+    shed_before.coalesced += shed.coalesced;
+    shed_before.dropped += shed.dropped;
+    shed_before.refused += shed.refused;
+    *connection = fresh;
+    Ok(())
+}
+
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
+    let links = ChannelLinks::from_pairs(&args.complements)?;
+    let alarms = ChannelAlarms::from_thresholds(&args.alarms)?;
+    let session = if args.resume {
+        let session = Session::load(&args.session)
+            .with_context(|| format!("Failed to load session {}", args.session.display()))?;
+        Some(session)
+    } else {
+        None
+    };
+    let saved_target = session.as_ref().and_then(|s| s.target.clone());
+    let target = match args.target.clone().or(saved_target) {
+        Some(target) => target,
+        None if std::io::stdin().is_terminal() => discovery::pick_target(DEFAULT_BROWSE_TIME)?,
+        None => return Err(anyhow!("No target given and stdin is not a terminal").into()),
+    };
+    #[cfg(feature = "lua")]
+    let script = args.script.as_deref().map(ScriptHost::load).transpose()?;
+    let keymap = match &args.keymap {
+        Some(path) => Keymap::load(path)
+            .with_context(|| format!("Failed to load keymap {}", path.display()))?,
+        None => Keymap::default(),
+    };
+
+    // Setup terminal
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    // Create app
+    let mut app = App::new(
+        args.step,
+        Duration::from_secs(args.keepalive_interval),
+        Duration::from_millis(args.sweep_interval),
+    );
+    app.keymap = keymap;
+    app.target = target.to_string();
+    app.report_dir = args.report_dir.clone();
+    app.strict = args.strict;
+    app.state.theme = args.theme;
+    app.state.format = ValueFormat {
+        display: args.display,
+        range: VoltageRange::new(args.vmin, args.vmax).context("Bad --vmin/--vmax")?,
+    };
+    app.state.links = links;
+    app.state.alarms = alarms;
+    app.state.mappings = ChannelMappings::new(args.mappings.clone());
+    app.state.ramp_duration = Duration::from_millis(args.ramp);
+    app.state.heartbeat_interval =
+        (args.heartbeat > 0).then(|| Duration::from_secs(args.heartbeat));
+
+    // Create transport
+    let transport = open_link(&target, &args)?;
+    app.stats = transport.stats();
+    println!("Connected via {} to {}", transport.kind(), target);
+
+    // Start transport threads
+    let (event_tx, event_rx) = mpsc::sync_channel::<AppEvent>(EVENT_CAPACITY);
+    let mut connection = Connection::start(transport, &event_tx)?;
+
+    // Start event input thread
+    let event_tx_clone = event_tx.clone();
+    thread::spawn(move || loop {
+        if let Ok(Event::Key(key)) = event::read() {
+            if key.kind == KeyEventKind::Press && event_tx_clone.send(AppEvent::Input(key)).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    connection.send(app.hello());
+    match &session {
+        Some(session) => {
+            connection.send(app.restore(session));
+        }
+        None if args.session.exists() && !args.no_save => {
+            app.state.last_command = format!(
+                "Saved session in {}: restart with --resume to restore it (overwritten on exit)",
+                args.session.display()
+            );
+        }
+        None => {}
+    }
+
+    #[cfg(feature = "lua")]
+    {
+        app.script = script;
+        if let Some(command) = app.run_script(|script| script.start().map(Some)) {
+            connection.send(command);
+        }
+    }
+
+    // Main loop
+    let mut last_tick = Instant::now();
+    let tick_rate = Duration::from_millis(250);
+    // Shed by earlier connections, plus what the current one had last reported
+    let mut shed_before = Shed::default();
+    let mut shed_reported = Shed::default();
+    // Set while the link is down and being reopened
+    let mut reopen: Option<Backoff> = None;
+    let mut sleep = SleepDetector::new();
+
+    'main: loop {
+        // Timers and reconnects change the last command outside key handling
+        app.log_command();
+        terminal.draw(|f| ui(f, &app))?;
+
+        let mut timeout = tick_rate
+            .checked_sub(last_tick.elapsed())
+            .unwrap_or_else(|| Duration::from_secs(0));
+        // Wake up in time for the next keepalive
+        if let Some(due_in) = app.keepalive_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        if let Some(due_in) = app.sweep_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        if let Some(due_in) = app.macro_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        #[cfg(feature = "lua")]
+        if let Some(due_in) = app.script_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        if let Some(due_in) = app.heartbeat_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        if let Some(due_in) = app.probe_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        if let Some(due_in) = app.repeat_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        if let Some(due_in) = app.ramp_due_in() {
+            timeout = timeout.min(due_in);
+        }
+        if let Some(backoff) = &reopen {
+            timeout = timeout.min(backoff.due_in());
+        }
+
+        // Handle everything that piled up during the last redraw, not one event per frame
+        let first = event_rx.recv_timeout(timeout).ok();
+        let backlog = event_rx.try_iter().take(EVENT_CAPACITY);
+        for event in first.into_iter().chain(backlog) {
+            match event {
+                AppEvent::Input(key) => {
+                    if let Some(command) = app.handle_key(key) {
+                        connection.send(command);
+                    }
+                    if app.should_quit {
+                        break 'main;
+                    }
+                }
+                AppEvent::TransportError(err) => {
+                    // Table writes may not have arrived
+                    app.forget_tables();
+                    app.log(format!("! {}", err));
+                    app.state.status_message = format!("Error: {}", err);
+                }
+                AppEvent::LinkLost(err) => {
+                    app.forget_tables();
+                    app.log(format!("! {}", err));
+                    app.state.status_message = format!("Link lost: {}", err);
+                    if !args.no_reopen {
+                        reopen.get_or_insert_with(Backoff::default);
+                    }
+                }
+                AppEvent::Response(response_data) => {
+                    app.state.last_received = app.state.clock.now();
+                    app.table_acks(&response_data);
+                    if response_data == protocol::HEARTBEAT_RESPONSE {
+                        // Only keeps the link alive
+                    } else if app.probe_answer() {
+                        // Timed by the latency probe, shown in its popup
+                    } else if response_data.is_empty() {
+                        app.state.last_response = "No data".to_string();
+                    } else if app.hello_response(&response_data) {
+                        // Decides whether register reads are offered
+                    } else if let Some(text) = app.register_response(&response_data) {
+                        app.state.last_response = text;
+                        app.log(format!("< {}", app.state.last_response));
+                    } else {
+                        let role = match response_data[..] {
+                            [0x00, protocol::STATUS_READ_ONLY, ..] => {
+                                " (read-only: another client controls the bridge, A takes over)"
+                            }
+                            [0x00, protocol::STATUS_DISPLACED, ..] => {
+                                " (displaced: another client took control of the bridge)"
+                            }
+                            _ => "",
+                        };
+                        app.state.last_response = format!(
+                            "{} bytes: {:02x?}{}",
+                            response_data.len(),
+                            response_data,
+                            role
+                        );
+                        app.log(format!("< {}", app.state.last_response));
+                    }
+                }
+            }
+            app.log_command();
+        }
+
+        let shed = connection.shed();
+        let total = Shed {
+            coalesced: shed_before.coalesced + shed.coalesced,
+            dropped: shed_before.dropped + shed.dropped,
+            refused: shed_before.refused + shed.refused,
+        };
+        if total != shed_reported {
+            app.state.status_message = format!(
+                "Device not keeping up: {} DAC update(s) merged, {} dropped, {} command(s) refused",
+                total.coalesced, total.dropped, total.refused
+            );
+            shed_reported = total;
+        }
+
+        if app.repeat_due_in() == Some(Duration::ZERO) {
+            if let Some(command) = app.handle_repeat() {
+                connection.send(command);
+            }
+        }
+
+        if app.ramp_due_in() == Some(Duration::ZERO) {
+            if let Some(command) = app.handle_ramp() {
+                connection.send(command);
+            }
+        }
+
+        if app.keepalive_due_in() == Some(Duration::ZERO) {
+            let command = app.handle_keepalive();
+            connection.send(command);
+        }
+
+        if app.probe_due_in() == Some(Duration::ZERO) {
+            if let Some(command) = app.handle_probe() {
+                connection.send(command);
+            }
+        }
+
+        if app.macro_due_in() == Some(Duration::ZERO) {
+            if let Some(command) = app.handle_macro() {
+                connection.send(command);
+            }
+            if app.should_quit {
+                break;
+            }
+        }
+
+        // Typed or replayed, a raw frame goes out past the checks
+        if let Some(frame) = app.state.raw_frame.take() {
+            connection.send_raw(frame);
+        }
+
+        if app.sweep_due_in() == Some(Duration::ZERO) {
+            let command = app.handle_sweep();
+            connection.send(command);
+        }
+
+        #[cfg(feature = "lua")]
+        if app.script_due_in() == Some(Duration::ZERO) {
+            if let Some(command) = app.run_script(|script| script.tick().map(Some)) {
+                connection.send(command);
+            }
+        }
+
+        if app.heartbeat_due_in() == Some(Duration::ZERO) {
+            connection.send(app.handle_heartbeat());
+        }
+
+        if let Some(slept) = sleep.check() {
+            // The device may have lost power meanwhile; a link that died with it
+            // fails these writes and is reopened
+            app.log(format!("! Host slept for {:.0}s", slept.as_secs_f64()));
+            if !args.no_reopen && reopen.is_none() {
+                connection.send(app.resync(&target));
+                app.state.last_command = format!(
+                    "Host slept for {:.0}s, outputs restored",
+                    slept.as_secs_f64()
+                );
+            }
+        }
+
+        if let Some(backoff) = reopen.as_mut().filter(|backoff| backoff.due_in().is_zero()) {
+            match reconnect(
+                &target,
+                &args,
+                &mut app,
+                &mut connection,
+                &mut shed_before,
+                &event_tx,
+            ) {
+                Ok(()) => {
+                    reopen = None;
+                    app.state.last_received = app.state.clock.now();
+                    connection.send(app.resync(&target));
+                    app.state.status_message.clear();
+                    app.state.last_command = "Link reopened, outputs restored".to_string();
+                }
+                Err(e) => {
+                    let wait = backoff.failed();
+                    app.state.status_message = format!(
+                        "Link down, reopening in {:.0}s: {:#}",
+                        wait.as_secs_f64(),
+                        e
+                    );
+                }
+            }
+        }
+
+        if let Some(silent) = app.bridge_lost() {
+            // Half-open connection: replace it rather than wait for TCP to notice
+            app.state.last_received = app.state.clock.now();
+            match reconnect(
+                &target,
+                &args,
+                &mut app,
+                &mut connection,
+                &mut shed_before,
+                &event_tx,
+            ) {
+                Ok(()) => {
+                    app.state.last_command = format!(
+                        "Bridge silent for {:.0}s, reconnected",
+                        silent.as_secs_f64()
+                    );
+                }
+                Err(e) => {
+                    app.state.last_command = format!(
+                        "Bridge silent for {:.0}s, reconnect failed: {}",
+                        silent.as_secs_f64(),
+                        e
+                    );
+                }
+            }
+        }
+
+        if last_tick.elapsed() >= tick_rate {
+            last_tick = Instant::now();
+        }
+    }
+
+    // Cleanup
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    terminal.show_cursor()?;
+
+    if !args.no_save {
+        match app.session(&target).save(&args.session) {
+            Ok(()) => println!("Session saved to {}", args.session.display()),
+            Err(e) => eprintln!("Failed to save session: {}", e),
+        }
+    }
+
+    println!("=== Transport Statistics ===");
+    println!("{}", app.stats.snapshot());
+
+    Ok(())
+}
@@ -0,0 +1,554 @@
+//! Drawing the panel, the help pane and the reference screen.
+
+use crate::app::{App, GangMode, LatencyProbe, PROBE_COUNT};
+use crossterm::event::KeyCode;
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Gauge, List, ListItem, Paragraph},
+    Frame,
+};
+use serialtest::keymap::{Action, Key, Keymap};
+use serialtest::protocol::{self, DAC_CHANNELS};
+use serialtest::widgets::{self, DacMarks, Theme};
+
+/// Height needed for the full layout including the help pane
+const FULL_HEIGHT: u16 = 37;
+/// Height needed for the full layout once the help pane is hidden
+const NO_HELP_HEIGHT: u16 = 24;
+/// Narrower terminals get the compact layout whatever their height
+const MIN_FULL_WIDTH: u16 = 80;
+
+/// Layout chosen from the terminal size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LayoutMode {
+    Full,
+    NoHelp,
+    Compact,
+}
+
+impl LayoutMode {
+    fn for_size(area: Rect) -> Self {
+        if area.width < MIN_FULL_WIDTH || area.height < NO_HELP_HEIGHT {
+            LayoutMode::Compact
+        } else if area.height < FULL_HEIGHT {
+            LayoutMode::NoHelp
+        } else {
+            LayoutMode::Full
+        }
+    }
+}
+
+pub fn title_text(app: &App) -> String {
+    let mut title = "DAC Control Panel - TUI Diagnostic Tool".to_string();
+    let gang: Vec<String> = (0..DAC_CHANNELS)
+        .filter(|&ch| app.state.gang[ch])
+        .map(|ch| ch.to_string())
+        .collect();
+    if !gang.is_empty() {
+        title.push_str(&format!(
+            " [GANG {}: {}]",
+            gang.join(","),
+            match app.state.gang_mode {
+                GangMode::Absolute => "absolute",
+                GangMode::Ratio => "ratio",
+            }
+        ));
+    }
+    let mapped: Vec<String> = (0..DAC_CHANNELS as u8)
+        .filter(|&ch| app.state.mappings.is_mapped(ch))
+        .map(|ch| ch.to_string())
+        .collect();
+    if !mapped.is_empty() {
+        title.push_str(&format!(" [MAP {}]", mapped.join(",")));
+    }
+    let alarmed: Vec<String> = (0..DAC_CHANNELS as u8)
+        .filter(|&ch| {
+            app.state
+                .alarms
+                .in_alarm(ch, app.state.dac_values[ch as usize])
+        })
+        .map(|ch| ch.to_string())
+        .collect();
+    if !alarmed.is_empty() {
+        title.push_str(&format!(" [ALARM {}]", alarmed.join(",")));
+    }
+    if app.state.deferred {
+        let pending = app.state.pending.iter().filter(|&&p| p).count();
+        title.push_str(&format!(" [DEFERRED: {} pending, L to apply]", pending));
+    }
+    if let Some(recording) = &app.state.recording {
+        title.push_str(&format!(" [REC {} keys]", recording.keys.len()));
+    }
+    if let Some(progress) = app.playback_progress() {
+        title.push_str(&format!(" [PLAY {}]", progress));
+    }
+    title
+}
+
+pub fn table_text(app: &App) -> String {
+    match &app.state.offset_input {
+        Some(input) => format!("Offset: {}_ (Enter to send, ESC to cancel)", input),
+        None => format!(
+            "Table Offset: {} (0x{:02X}){}",
+            app.state.table_offset,
+            app.state.table_offset,
+            if app.state.sweeping { " SWEEP" } else { "" }
+        ),
+    }
+}
+
+pub fn keepalive_text(app: &App) -> String {
+    if app.state.keepalive_paused {
+        format!("PAUSED (sent {})", app.state.keepalive_count)
+    } else {
+        format!(
+            "Every {:.1}s (sent {})",
+            app.state.keepalive_interval.as_secs_f64(),
+            app.state.keepalive_count
+        )
+    }
+}
+
+pub fn status_text(app: &App) -> String {
+    let mut text = format!(
+        "Last: {} | Response: {}",
+        app.state.last_command, app.state.last_response
+    );
+    if !app.state.status_message.is_empty() {
+        text.push_str(&format!(" | {}", app.state.status_message));
+    }
+    text
+}
+
+/// Gang and alarm flags of every channel
+fn dac_marks(app: &App) -> [DacMarks; DAC_CHANNELS] {
+    std::array::from_fn(|ch| DacMarks {
+        ganged: app.state.gang[ch],
+        alarm: app
+            .state
+            .alarms
+            .in_alarm(ch as u8, app.state.dac_values[ch]),
+    })
+}
+
+pub fn stats_text(app: &App) -> String {
+    app.stats.snapshot().summary()
+}
+
+pub fn ui(f: &mut Frame, app: &App) {
+    let theme = app.state.theme.theme();
+    if let Some(scroll) = app.state.reference {
+        render_reference(f, f.size(), &app.keymap, scroll, &theme);
+        return;
+    }
+    match LayoutMode::for_size(f.size()) {
+        LayoutMode::Full => ui_full(f, app, &theme, true),
+        LayoutMode::NoHelp => ui_full(f, app, &theme, false),
+        LayoutMode::Compact => ui_compact(f, app, &theme),
+    }
+    if let Some(probe) = &app.state.probe {
+        render_probe(f, f.size(), probe, &theme);
+    }
+}
+
+/// Latency probe results in a popup over the panel
+fn render_probe(f: &mut Frame, area: Rect, probe: &LatencyProbe, theme: &Theme) {
+    let width = area.width.min(64);
+    let height = area.height.min(7);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let progress = if probe.running() {
+        format!("Sending keepalive {} of {}...", probe.sent, PROBE_COUNT)
+    } else {
+        format!("{} keepalives sent one at a time", probe.sent)
+    };
+    let mut lines = vec![
+        Line::from(progress),
+        Line::from(probe.summary()),
+        Line::from(""),
+    ];
+    if let Some((verdict, good)) = probe.verdict() {
+        let style = if good { theme.on } else { theme.alert };
+        lines.push(Line::from(Span::styled(verdict, style)));
+    }
+    let text = Paragraph::new(lines).style(theme.text).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Latency probe (any key closes)"),
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(text, popup);
+}
+
+fn ui_full(f: &mut Frame, app: &App, theme: &Theme, show_help: bool) {
+    let mut constraints = vec![
+        Constraint::Length(3), // Title
+        Constraint::Min(8),    // DAC sliders
+        Constraint::Length(5), // GPIO status
+        Constraint::Length(3), // Table offset
+        Constraint::Length(4), // Last command and traffic
+    ];
+    if show_help {
+        constraints.push(Constraint::Length(14)); // Help or log
+    }
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(f.size());
+
+    // Title, as a progress bar while a macro plays
+    match app.playback_progress() {
+        Some(progress) => {
+            let title = Gauge::default()
+                .block(Block::default().borders(Borders::ALL))
+                .gauge_style(theme.title)
+                .ratio(progress.ratio())
+                .label(title_text(app));
+            f.render_widget(title, chunks[0]);
+        }
+        None => {
+            let title = Paragraph::new(title_text(app))
+                .style(theme.title)
+                .alignment(Alignment::Center)
+                .block(Block::default().borders(Borders::ALL));
+            f.render_widget(title, chunks[0]);
+        }
+    }
+
+    // DAC Sliders
+    widgets::render_dac_gauges(
+        f,
+        chunks[1],
+        &app.state.dac_values,
+        Some(app.state.selected_channel),
+        &dac_marks(app),
+        &app.state.format,
+        theme,
+    );
+
+    // GPIO Status
+    widgets::render_gpio_states(f, chunks[2], &app.state.gpio_states, theme);
+
+    let control_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(chunks[3]);
+
+    // Table Offset
+    let table_info = Paragraph::new(table_text(app))
+        .style(theme.info)
+        .alignment(Alignment::Center)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Table Control"),
+        );
+    f.render_widget(table_info, control_chunks[0]);
+
+    // Keepalive
+    let keepalive_style = if app.state.keepalive_paused {
+        theme.alert
+    } else {
+        theme.info
+    };
+    let keepalive_info = Paragraph::new(keepalive_text(app))
+        .style(keepalive_style)
+        .alignment(Alignment::Center)
+        .block(Block::default().borders(Borders::ALL).title("Keepalive"));
+    f.render_widget(keepalive_info, control_chunks[1]);
+
+    // Last Command, Response and traffic counters
+    let last_cmd = Paragraph::new(format!("{}\n{}", status_text(app), stats_text(app)))
+        .style(theme.status)
+        .alignment(Alignment::Center)
+        .block(Block::default().borders(Borders::ALL).title("Status"));
+    f.render_widget(last_cmd, chunks[4]);
+
+    // Help, or the log when shown
+    if show_help {
+        if app.state.show_log {
+            render_log(f, chunks[5], app, theme);
+        } else {
+            render_help(f, chunks[5], &app.keymap, theme);
+        }
+    }
+}
+
+/// One line per element, DACs as a table: fits 80x24 and smaller SSH windows
+fn ui_compact(f: &mut Frame, app: &App, theme: &Theme) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1), // Title
+            Constraint::Min(3),    // DAC table
+            Constraint::Length(1), // GPIO status
+            Constraint::Length(1), // Table offset and keepalive
+            Constraint::Length(1), // Last command
+            Constraint::Length(1), // Traffic
+        ])
+        .split(f.size());
+
+    let title = Paragraph::new(title_text(app))
+        .style(theme.title)
+        .alignment(Alignment::Center);
+    f.render_widget(title, chunks[0]);
+
+    widgets::render_dac_table(
+        f,
+        chunks[1],
+        &app.state.dac_values,
+        Some(app.state.selected_channel),
+        &dac_marks(app),
+        &app.state.format,
+        theme,
+    );
+    widgets::render_gpio_line(f, chunks[2], &app.state.gpio_states, theme);
+
+    let keepalive_style = if app.state.keepalive_paused {
+        theme.alert
+    } else {
+        theme.info
+    };
+    let info = Paragraph::new(Line::from(vec![
+        Span::styled(table_text(app), theme.info),
+        Span::styled(" | Keepalive: ", theme.info),
+        Span::styled(keepalive_text(app), keepalive_style),
+    ]));
+    f.render_widget(info, chunks[3]);
+
+    let status = Paragraph::new(status_text(app)).style(theme.status);
+    f.render_widget(status, chunks[4]);
+
+    let traffic = Paragraph::new(stats_text(app)).style(theme.status);
+    f.render_widget(traffic, chunks[5]);
+}
+
+/// Help pane entries: the actions sharing an entry and what they do
+const HELP: [(&[Action], &str); 24] = [
+    (
+        &[Action::PrevChannel, Action::NextChannel],
+        "Select DAC channel",
+    ),
+    (&[Action::StepUp, Action::StepDown], "Adjust DAC value"),
+    (&[Action::LargeStep], "Large step (+8192)"),
+    (
+        &[
+            Action::TableOffset(0),
+            Action::TableOffset(1),
+            Action::TableOffset(2),
+            Action::TableOffset(3),
+            Action::TableOffset(4),
+            Action::TableOffset(5),
+            Action::TableOffset(6),
+            Action::TableOffset(7),
+            Action::TableOffset(8),
+            Action::TableOffset(9),
+        ],
+        "Set table offset",
+    ),
+    (&[Action::FineDown, Action::FineUp], "step by 16 (1 lsb)"),
+    (
+        &[Action::ShorterKeepalive, Action::LongerKeepalive],
+        "Keepalive interval -/+ 0.5s",
+    ),
+    (&[Action::PauseKeepalive], "Pause/resume keepalive"),
+    (
+        &[
+            Action::EnterTableOffset,
+            Action::Registers,
+            Action::RawFrame,
+            Action::FillTable,
+        ],
+        "Type offset/reg/raw/table",
+    ),
+    (&[Action::Sweep], "Sweep table offset"),
+    (
+        &[Action::PrevTableOffset, Action::NextTableOffset],
+        "Table offset -/+ 1",
+    ),
+    (&[Action::Deferred], "Deferred (load-only) mode"),
+    (&[Action::Ldac], "Apply pending changes (LDAC)"),
+    (&[Action::Gang], "Add/remove channel in gang"),
+    (&[Action::GangMode], "Gang mode absolute/ratio"),
+    (&[Action::Theme], "Cycle color theme"),
+    (&[Action::Undo, Action::Redo], "Undo/redo DAC and GPIO"),
+    (
+        &[
+            Action::Gpio(0),
+            Action::Gpio(1),
+            Action::Gpio(2),
+            Action::Gpio(3),
+            Action::Gpio(4),
+            Action::Gpio(5),
+            Action::Gpio(6),
+            Action::Gpio(7),
+        ],
+        "Toggle GPIO 0-7",
+    ),
+    (
+        &[Action::RecordMacro, Action::ReplayMacro],
+        "Record/replay macro",
+    ),
+    (&[Action::Display], "Value display raw/hex/%/V"),
+    (
+        &[Action::Takeover, Action::LatencyProbe],
+        "Take bridge control / latency",
+    ),
+    (&[Action::ToggleLog], "Show log instead of help"),
+    (
+        &[Action::SelectMore, Action::SelectLess],
+        "Select more/fewer log lines",
+    ),
+    (
+        &[Action::Copy, Action::Export],
+        "Copy state or log / report",
+    ),
+    (&[Action::HelpScreen, Action::Quit], "Reference / quit"),
+];
+
+/// Keys of a help entry: the first key of each action, `0-9` for a run of digits
+fn help_keys(keymap: &Keymap, actions: &[Action]) -> String {
+    let keys: Vec<String> = actions
+        .iter()
+        .filter_map(|&action| keymap.keys(action).first().map(Key::to_string))
+        .collect();
+    if keys.is_empty() {
+        return "-".to_string();
+    }
+    if actions.len() <= 2 {
+        return keys.join(" ");
+    }
+    let digits: Vec<u32> = keys
+        .iter()
+        .filter_map(|key| key.parse().ok().filter(|_| key.len() == 1))
+        .collect();
+    let consecutive = digits.windows(2).all(|pair| pair[1] == pair[0] + 1);
+    if digits.len() == keys.len() && consecutive {
+        format!("{}-{}", keys[0], keys[keys.len() - 1])
+    } else {
+        keys.concat()
+    }
+}
+
+fn render_help(f: &mut Frame, area: Rect, keymap: &Keymap, theme: &Theme) {
+    let entries: Vec<String> = HELP
+        .iter()
+        .map(|(actions, text)| format!("{:<5} : {}", help_keys(keymap, actions), text))
+        .collect();
+    let help_items: Vec<ListItem> = entries
+        .chunks(2)
+        .map(|pair| ListItem::new(format!("{:<33} {}", pair[0], pair.get(1).map_or("", |s| s))))
+        .collect();
+
+    let help_list = List::new(help_items)
+        .block(Block::default().borders(Borders::ALL).title("Controls"))
+        .style(theme.text);
+
+    f.render_widget(help_list, area);
+}
+
+/// Lines of the reference screen: command layouts, responses and every key binding
+fn reference_lines(keymap: &Keymap) -> Vec<String> {
+    let mut lines = vec![
+        "Commands: 4 bytes, the value big-endian".to_string(),
+        format!(
+            "  {:<16} {:<14} {:<16} Meaning",
+            "First byte", "Second byte", "3rd & 4th"
+        ),
+    ];
+    lines.extend(protocol::command_layouts().iter().map(|layout| {
+        format!(
+            "  {:<16} {:<14} {:<16} {}",
+            layout.first, layout.second, layout.value, layout.meaning
+        )
+    }));
+    lines.push(String::new());
+    lines.push("Responses".to_string());
+    lines.extend(
+        protocol::response_formats()
+            .iter()
+            .map(|(bytes, meaning)| format!("  {:<16} {}", bytes, meaning)),
+    );
+    lines.push(String::new());
+    lines.push("Keys (rebind with --keymap)".to_string());
+    let bindings: Vec<String> = Action::all()
+        .into_iter()
+        .map(|action| {
+            let keys: Vec<String> = keymap.keys(action).iter().map(Key::to_string).collect();
+            let keys = if keys.is_empty() {
+                "-".to_string()
+            } else {
+                keys.join(" ")
+            };
+            format!("{:<18} {}", action.name(), keys)
+        })
+        .collect();
+    lines.extend(
+        bindings
+            .chunks(2)
+            .map(|pair| format!("  {:<38} {}", pair[0], pair.get(1).map_or("", |s| s))),
+    );
+    lines
+}
+
+/// New scroll position of the reference screen, `None` once a key closes it
+pub fn scroll_reference(keymap: &Keymap, scroll: u16, code: KeyCode) -> Option<u16> {
+    let last = reference_lines(keymap).len().saturating_sub(1) as u16;
+    match code {
+        KeyCode::Up => Some(scroll.saturating_sub(1)),
+        KeyCode::Down => Some((scroll + 1).min(last)),
+        KeyCode::PageUp => Some(scroll.saturating_sub(10)),
+        KeyCode::PageDown => Some((scroll + 10).min(last)),
+        _ => None,
+    }
+}
+
+fn render_reference(f: &mut Frame, area: Rect, keymap: &Keymap, scroll: u16, theme: &Theme) {
+    let lines = reference_lines(keymap);
+    let rows = area.height.saturating_sub(2);
+    let scroll = scroll.min((lines.len() as u16).saturating_sub(rows));
+    let reference = Paragraph::new(lines.join("\n"))
+        .style(theme.text)
+        .scroll((scroll, 0))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Reference (↑↓ PgUp PgDn scroll, any other key closes)"),
+        );
+    f.render_widget(reference, area);
+}
+
+/// Newest log lines that fit, keeping the first selected line in view
+fn render_log(f: &mut Frame, area: Rect, app: &App, theme: &Theme) {
+    let log = &app.state.log;
+    let rows = area.height.saturating_sub(2) as usize;
+    let first_selected = log.len().saturating_sub(app.state.log_selection);
+    let start = log.len().saturating_sub(rows).min(first_selected);
+    let items: Vec<ListItem> = log
+        .iter()
+        .enumerate()
+        .skip(start)
+        .take(rows)
+        .map(|(i, line)| {
+            let style = if i >= first_selected {
+                theme.selected
+            } else {
+                theme.text
+            };
+            ListItem::new(line.as_str()).style(style)
+        })
+        .collect();
+
+    let title = format!(
+        "Log ({} of {} lines selected, Y copies)",
+        app.state.log_selection.min(log.len()),
+        log.len()
+    );
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(list, area);
+}
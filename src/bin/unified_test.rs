@@ -1,16 +1,34 @@
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use std::io::{Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use serialtest::channels::{parse_pair, ChannelLinks};
+use serialtest::desired::ExitState;
+use serialtest::diagnose::Failure;
+use serialtest::discovery::{self, DEFAULT_BROWSE_TIME};
+use serialtest::error::DacError;
+use serialtest::events::EventLog;
+use serialtest::framing::{Codec, Padding, StreamFraming};
+use serialtest::report::Value;
+use serialtest::stats::LatencyStats;
+use serialtest::target::Target;
+use serialtest::transport::{FramedLink, LinkOptions};
+use serialtest::version;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
 
 /// Unified test program that can communicate over serial or TCP
 #[derive(Parser, Debug)]
 #[command(name = "unified_test")]
 #[command(about = "Test program supporting both serial and TCP communication")]
 struct Args {
-    /// Connection target: serial device path (e.g., /dev/ttyACM0, COM5) or network address (IPv4:port, [IPv6]:port)
-    target: String,
+    /// Connection target: serial device path (e.g., /dev/ttyACM0, COM5), network address
+    /// (IPv4:port, [IPv6]:port) or serial:, pty:, tcp:, udp:, tls: or scheme:// target;
+    /// without one, pick from the detected serial ports and bridges
+    target: Option<Target>,
+
+    /// Second path to the same device (e.g. the bridge when TARGET is its serial port); every
+    /// main-loop command is sent over both paths in turn and their latency and reliability compared
+    #[arg(long, value_name = "TARGET")]
+    compare: Option<Target>,
 
     /// Test rate in Hz
     #[arg(short, long, default_value = "10")]
@@ -27,6 +45,40 @@ struct Args {
     /// Write timeout in milliseconds
     #[arg(long, default_value = "1000")]
     write_timeout: u64,
+
+    /// Append a CRC16 to every command and validate response CRCs
+    #[arg(long)]
+    crc: bool,
+
+    /// Stream framing around every command and response
+    #[arg(long, value_enum, default_value = "raw")]
+    framing: StreamFraming,
+
+    /// Trailing bytes that do not fill a 4-byte command: reject the batch, or zero-fill it
+    #[arg(long, value_enum, default_value = "reject")]
+    padding: Padding,
+
+    /// Validate channels, tables, GPIO pins and value fields before sending
+    #[arg(long)]
+    strict: bool,
+
+    /// Channel pairs where COMPLEMENT carries 65535 - MASTER (repeatable)
+    #[arg(
+        long = "complement",
+        value_name = "COMPLEMENT=MASTER",
+        value_parser = parse_pair,
+        default_values = ["4=0", "5=1", "6=2", "7=3"]
+    )]
+    complements: Vec<(u8, u8)>,
+
+    /// State to leave the device in when the test stops: zero (every DAC at 0, every GPIO
+    /// off), hold (as the test left it) or a state file as read by `csv1 apply`
+    #[arg(long, value_name = "FILE|zero|hold", default_value = "hold")]
+    on_exit: ExitState,
+
+    /// Print one JSON object per line for every event, command and result instead of text
+    #[arg(long)]
+    json: bool,
 }
 
 // Protocol documentation:
@@ -47,211 +99,290 @@ struct Args {
  * + -----------------------------------------------+
  */
 
-/// Transport abstraction trait
-trait Transport {
-    fn write_data(&mut self, data: &[u8]) -> Result<usize>;
-    fn read_data(&mut self, buffer: &mut [u8]) -> Result<usize>;
-    fn transport_type(&self) -> &'static str;
-}
-
-/// Serial port transport implementation
-struct SerialTransport {
-    port: Box<dyn serialport::SerialPort>,
+/// Open `target` with the codec and complement pairs the arguments ask for
+fn open_link(target: &Target, args: &Args, log: EventLog) -> Result<FramedLink> {
+    let links = ChannelLinks::from_pairs(&args.complements)?;
+    let codec = Codec::new(args.crc, args.framing)
+        .with_padding(args.padding)
+        .with_strict(args.strict);
+    let options = LinkOptions {
+        read_timeout: Duration::from_millis(args.read_timeout),
+        write_timeout: Duration::from_millis(args.write_timeout),
+    };
+    let link = FramedLink::open(target, &options, codec)
+        .with_context(|| format!("Failed to open {}", target))?
+        .with_links(links);
+    log.info(
+        "opening",
+        Value::object()
+            .with("target", target.to_string())
+            .with("transport", link.kind()),
+        format!(
+            "Opened {} via {} link (read_timeout={}ms, write_timeout={}ms)",
+            target,
+            link.kind(),
+            args.read_timeout,
+            args.write_timeout
+        ),
+    );
+    Ok(link)
 }
 
-impl SerialTransport {
-    fn new(device_path: &str) -> Result<Self> {
-        let port = serialport::new(device_path, 115_200)
-            .timeout(Duration::from_millis(100))
-            .open()
-            .with_context(|| format!("Failed to open serial port: {}", device_path))?;
-
-        Ok(SerialTransport { port })
-    }
+/// One way to reach the device, with its main-loop counters
+struct CommandPath {
+    target: Target,
+    transport: FramedLink,
+    commands: u64,
+    write_errors: u64,
+    /// Response times; one per command that got a response
+    latency: LatencyStats,
 }
 
-impl Transport for SerialTransport {
-    fn write_data(&mut self, data: &[u8]) -> Result<usize> {
-        // Pad data to multiple of 4 bytes as required by protocol
-        let mut padded_data = data.to_vec();
-        while padded_data.len() % 4 != 0 {
-            padded_data.push(0);
-        }
-
-        self.port
-            .write(&padded_data)
-            .with_context(|| "Serial write failed")
-    }
-
-    fn read_data(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        match self.port.read(buffer) {
-            Ok(n) => Ok(n),
-            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(0),
-            Err(e) => Err(anyhow!("Serial read failed: {}", e)),
+impl CommandPath {
+    fn new(target: Target, transport: FramedLink) -> Self {
+        Self {
+            target,
+            transport,
+            commands: 0,
+            write_errors: 0,
+            latency: LatencyStats::default(),
         }
     }
-
-    fn transport_type(&self) -> &'static str {
-        "Serial"
-    }
 }
 
-/// TCP transport implementation
-struct TcpTransport {
-    stream: TcpStream,
-}
-
-impl TcpTransport {
-    fn new(address: &str, read_timeout_ms: u64, write_timeout_ms: u64) -> Result<Self> {
-        let stream = TcpStream::connect(address)
-            .with_context(|| format!("Failed to connect to TCP address: {}", address))?;
-
-        stream.set_read_timeout(Some(Duration::from_millis(read_timeout_ms)))?;
-        stream.set_write_timeout(Some(Duration::from_millis(write_timeout_ms)))?;
-
-        Ok(TcpTransport { stream })
+/// Side-by-side main-loop numbers of two paths to the same device
+fn print_comparison(paths: &mut [CommandPath]) {
+    fn ms(latency: Option<Duration>) -> String {
+        latency.map_or("-".to_string(), |d| {
+            format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+        })
     }
-}
 
-impl Transport for TcpTransport {
-    fn write_data(&mut self, data: &[u8]) -> Result<usize> {
-        // Pad data to multiple of 4 bytes as required by protocol
-        let mut padded_data = data.to_vec();
-        while padded_data.len() % 4 != 0 {
-            padded_data.push(0);
-        }
-
-        self.stream
-            .write_all(&padded_data)
-            .with_context(|| "TCP write failed")?;
-        Ok(padded_data.len())
+    let names: Vec<String> = paths.iter().map(|p| p.target.to_string()).collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(12);
+    println!("\n=== Path Comparison (main loop) ===");
+    print!("{:<16}", "");
+    for name in &names {
+        print!(" {:>width$}", name, width = width);
     }
+    println!();
 
-    fn read_data(&mut self, buffer: &mut [u8]) -> Result<usize> {
-        match self.stream.read(buffer) {
-            Ok(n) => Ok(n),
-            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(0),
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(0),
-            Err(e) if e.raw_os_error() == Some(35) => Ok(0), // EAGAIN on macOS/BSD
-            Err(e) if e.raw_os_error() == Some(11) => Ok(0), // EAGAIN on Linux
-            Err(e) => {
-                eprintln!("TCP read error (continuing): {}", e);
-                Ok(0) // Continue operation even on read errors
-            }
+    let mut row = |label: &str, value: &mut dyn FnMut(&mut CommandPath) -> String| {
+        print!("{:<16}", label);
+        for path in paths.iter_mut() {
+            print!(" {:>width$}", value(path), width = width);
         }
-    }
-
-    fn transport_type(&self) -> &'static str {
-        "TCP"
-    }
-}
-
-/// Determine transport type based on target string format
-fn create_transport(target: &str, args: &Args) -> Result<Box<dyn Transport>> {
-    // Check if it looks like a network address (contains : and possibly [])
-    if target.contains(':') {
-        // Try to parse as socket address to validate format
-        let addr = if target.starts_with('[') {
-            // IPv6 format [::1]:1234
-            target
-                .to_socket_addrs()
-                .with_context(|| format!("Invalid IPv6 address format: {}", target))?
-                .next()
-                .ok_or_else(|| anyhow!("Could not resolve address: {}", target))?
+        println!();
+    };
+    row("Commands", &mut |p| p.commands.to_string());
+    row("Responses", &mut |p| p.latency.count().to_string());
+    row("Response rate", &mut |p| {
+        if p.commands > 0 {
+            format!(
+                "{:.1}%",
+                p.latency.count() as f64 / p.commands as f64 * 100.0
+            )
         } else {
-            // IPv4 format 127.0.0.1:1234
-            target
-                .to_socket_addrs()
-                .with_context(|| format!("Invalid IPv4 address format: {}", target))?
-                .next()
-                .ok_or_else(|| anyhow!("Could not resolve address: {}", target))?
-        };
-
-        println!(
-            "Connecting to {} via TCP (read_timeout={}ms, write_timeout={}ms)...",
-            addr, args.read_timeout, args.write_timeout
-        );
-        Ok(Box::new(TcpTransport::new(
-            target,
-            args.read_timeout,
-            args.write_timeout,
-        )?))
-    } else {
-        // Assume it's a serial device path
-        println!("Opening serial device: {}", target);
-        Ok(Box::new(SerialTransport::new(target)?))
+            "-".to_string()
+        }
+    });
+    row("No response", &mut |p| {
+        (p.commands - p.write_errors - p.latency.count() as u64).to_string()
+    });
+    row("Write errors", &mut |p| p.write_errors.to_string());
+    row("Latency min", &mut |p| ms(p.latency.min()));
+    row("Latency mean", &mut |p| ms(p.latency.mean()));
+    row("Latency p50", &mut |p| ms(p.latency.percentile(50.0)));
+    row("Latency p90", &mut |p| ms(p.latency.percentile(90.0)));
+    row("Latency p99", &mut |p| ms(p.latency.percentile(99.0)));
+    row("Latency max", &mut |p| ms(p.latency.max()));
+
+    if let [a, b] = paths {
+        if let (Some(a50), Some(b50)) = (a.latency.percentile(50.0), b.latency.percentile(50.0)) {
+            let diff = b50.as_secs_f64() - a50.as_secs_f64();
+            println!(
+                "Median latency of {} is {:.2}ms {} than {}",
+                b.target,
+                diff.abs() * 1000.0,
+                if diff >= 0.0 { "higher" } else { "lower" },
+                a.target
+            );
+        }
     }
 }
 
 /// Protocol helper functions
-fn write_command(transport: &mut Box<dyn Transport>, data: &[u8], verbose: bool) -> Result<()> {
-    let result = transport.write_data(data)?;
+fn write_command(
+    transport: &mut FramedLink,
+    data: &[u8],
+    verbose: bool,
+    log: EventLog,
+) -> Result<()> {
+    let result = transport.write_commands(data)?;
     if verbose {
-        println!("Wrote {} bytes: {:?}", result, data);
+        log.info(
+            "sent",
+            Value::object().with("bytes", data.to_vec()),
+            format!("Wrote {} bytes: {:?}", result, data),
+        );
     }
     Ok(())
 }
 
-fn read_response(transport: &mut Box<dyn Transport>, verbose: bool) -> Result<Vec<u8>> {
+fn read_response(transport: &mut FramedLink, verbose: bool, log: EventLog) -> Result<Vec<u8>> {
     let mut buffer = vec![0u8; 1000];
-    let bytes_read = transport.read_data(&mut buffer)?;
+    let bytes_read = match transport.read_responses(&mut buffer) {
+        Ok(n) => n,
+        Err(e @ DacError::Protocol(_)) => {
+            log.warn(
+                "response_rejected",
+                Value::object().with("error", e.to_string()),
+                format!("Response rejected (continuing): {}", e),
+            );
+            0
+        }
+        Err(e) => return Err(e.into()),
+    };
     buffer.truncate(bytes_read);
 
     if verbose {
         if bytes_read > 0 {
-            println!("Read {} bytes: {:?}", bytes_read, &buffer);
+            log.info(
+                "received",
+                Value::object().with("bytes", buffer.clone()),
+                format!("Read {} bytes: {:?}", bytes_read, &buffer),
+            );
         } else {
-            println!("No response data (timeout or no data available)");
+            log.info(
+                "no_response",
+                Value::Null,
+                "No response data (timeout or no data available)",
+            );
         }
     }
 
     Ok(buffer)
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// A path's main-loop numbers and transport counters for the `stats` event
+fn path_value(path: &mut CommandPath) -> Value {
+    let stats = path.transport.stats().snapshot();
+    let latency = &mut path.latency;
+    Value::object()
+        .with("target", path.target.to_string())
+        .with("commands", path.commands)
+        .with("responses", latency.count())
+        .with("write_errors", path.write_errors)
+        .with("writes", stats.writes)
+        .with("reads", stats.reads)
+        .with("bytes_out", stats.bytes_out)
+        .with("bytes_in", stats.bytes_in)
+        .with("timeouts", stats.timeouts)
+        .with("errors", stats.errors)
+        .with("latency_min_ms", latency.min())
+        .with("latency_mean_ms", latency.mean())
+        .with("latency_p50_ms", latency.percentile(50.0))
+        .with("latency_p90_ms", latency.percentile(90.0))
+        .with("latency_p99_ms", latency.percentile(99.0))
+        .with("latency_max_ms", latency.max())
+}
 
-    let mut transport = create_transport(&args.target, &args)?;
+fn main() -> Result<(), Failure> {
+    let args = version::parse_args::<Args>();
+    let log = EventLog::new(args.json);
+
+    let result = run(&args, log);
+    if let Err(e) = &result {
+        if log.is_json() {
+            log.record("error", Value::object().with("error", format!("{:#}", e)));
+            std::process::exit(1);
+        }
+    }
+    Ok(result?)
+}
 
-    println!(
-        "Connected via {} at {}Hz (read_timeout={}ms)",
-        transport.transport_type(),
-        args.rate,
-        args.read_timeout
+fn run(args: &Args, log: EventLog) -> Result<()> {
+    let exit_commands = args
+        .on_exit
+        .commands()
+        .with_context(|| format!("Failed to read the exit state {}", args.on_exit))?;
+    let target = match args.target.clone() {
+        Some(target) => target,
+        None if std::io::stdin().is_terminal() && !log.is_json() => {
+            discovery::pick_target(DEFAULT_BROWSE_TIME)?
+        }
+        None => return Err(anyhow!("No target given and stdin is not a terminal")),
+    };
+    let mut transport = open_link(&target, args, log)?;
+    let compare = match &args.compare {
+        Some(compare) => Some(CommandPath::new(
+            compare.clone(),
+            open_link(compare, args, log)?,
+        )),
+        None => None,
+    };
+
+    log.info(
+        "connected",
+        Value::object()
+            .with("target", target.to_string())
+            .with("transport", transport.kind())
+            .with("rate_hz", args.rate)
+            .with("read_timeout_ms", args.read_timeout)
+            .with("crc", args.crc)
+            .with("framing", format!("{:?}", args.framing).to_lowercase()),
+        format!(
+            "Connected via {} at {}Hz (read_timeout={}ms)",
+            transport.kind(),
+            args.rate,
+            args.read_timeout
+        ),
     );
+    if !log.is_json() {
+        if args.crc {
+            println!("CRC framing enabled");
+        }
+        if args.framing != StreamFraming::Raw {
+            println!("Stream framing: {:?}", args.framing);
+        }
+    }
+    let step = |name: &str, text: &str| log.info("step", Value::object().with("step", name), text);
 
     // GPIO setup - same as original protocol
-    println!("Setting up GPIO...");
+    step("gpio", "Setting up GPIO...");
     write_command(
         &mut transport,
         &[0xfe, 0, 0, 1, 0xfe, 1, 0, 1],
         args.verbose,
+        log,
     )?;
-    let _response = read_response(&mut transport, args.verbose)?;
+    let _response = read_response(&mut transport, args.verbose, log)?;
     std::thread::sleep(Duration::from_millis(50));
 
     // Init1 - same as original protocol
-    println!("Sending init1...");
+    step("init1", "Sending init1...");
     write_command(
         &mut transport,
         &[16, 49, 0, 0, 16, 50, 64, 0, 16, 51, 128, 0],
         args.verbose,
+        log,
     )?;
-    let _response = read_response(&mut transport, args.verbose)?;
+    let _response = read_response(&mut transport, args.verbose, log)?;
     std::thread::sleep(Duration::from_millis(50));
 
     // Init2 - same as original protocol
-    println!("Sending init2...");
+    step("init2", "Sending init2...");
     write_command(
         &mut transport,
         &[17, 49, 64, 0, 17, 50, 128, 0, 17, 51, 0, 0],
         args.verbose,
+        log,
     )?;
-    let _response = read_response(&mut transport, args.verbose)?;
+    let _response = read_response(&mut transport, args.verbose, log)?;
     std::thread::sleep(Duration::from_millis(100));
 
     // Init3 - broken into smaller 4-byte chunks to avoid buffer overflow
-    println!("Sending init3 (in chunks)...");
+    step("init3", "Sending init3 (in chunks)...");
     let init3_commands = [
         [0, 16, 0, 0],
         [1, 17, 0, 0],
@@ -264,9 +395,9 @@ fn main() -> Result<()> {
     ];
 
     for (i, cmd) in init3_commands.iter().enumerate() {
-        write_command(&mut transport, cmd, args.verbose)?;
-        let _response = read_response(&mut transport, args.verbose)?;
-        if args.verbose {
+        write_command(&mut transport, cmd, args.verbose, log)?;
+        let _response = read_response(&mut transport, args.verbose, log)?;
+        if args.verbose && !log.is_json() {
             println!("Init3 chunk {} completed", i + 1);
         }
         std::thread::sleep(Duration::from_millis(20));
@@ -275,18 +406,32 @@ fn main() -> Result<()> {
     std::thread::sleep(Duration::from_millis(100));
 
     // Keepalive test
-    println!("Sending keepalive commands...");
+    step("keepalive", "Sending keepalive commands...");
     for i in 0..3 {
-        write_command(&mut transport, &[0xfd, 0, 0, 0], args.verbose)?;
-        let _response = read_response(&mut transport, args.verbose)?;
-        if args.verbose {
+        write_command(&mut transport, &[0xfd, 0, 0, 0], args.verbose, log)?;
+        let _response = read_response(&mut transport, args.verbose, log)?;
+        if args.verbose && !log.is_json() {
             println!("Keepalive {} completed", i + 1);
         }
         std::thread::sleep(Duration::from_secs(1));
     }
 
     // Main loop - same logic as original
-    println!("Starting main data loop...");
+    let mut paths = vec![CommandPath::new(target, transport)];
+    if let Some(compare) = compare {
+        log.info(
+            "comparing",
+            Value::object()
+                .with("target", paths[0].target.to_string())
+                .with("compare", compare.target.to_string()),
+            format!(
+                "Comparing {} with {}: every command goes over both",
+                paths[0].target, compare.target
+            ),
+        );
+        paths.push(compare);
+    }
+    step("main", "Starting main data loop...");
     let mut msg: Vec<u8> = vec![255, 0, 0, 0];
     let mut v: u16 = 0;
     let mut c: u8 = 0;
@@ -296,7 +441,11 @@ fn main() -> Result<()> {
     let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
     let r = running.clone();
     ctrlc::set_handler(move || {
-        println!("\nReceived Ctrl+C, shutting down...");
+        log.info(
+            "interrupted",
+            Value::Null,
+            "\nReceived Ctrl+C, shutting down...",
+        );
         r.store(false, std::sync::atomic::Ordering::SeqCst);
     })
     .context("Error setting Ctrl+C handler")?;
@@ -316,30 +465,53 @@ fn main() -> Result<()> {
         }
 
         msg[0] = c;
-        if c < 4 {
-            msg[2] = ((v & 0xff00) >> 8) as u8;
-            msg[3] = (v & 0xff) as u8;
-        } else {
-            msg[2] = (((65535 - v) & 0xff00) >> 8) as u8;
-            msg[3] = ((65535 - v) & 0xff) as u8;
-        }
-
-        match write_command(&mut transport, &msg, args.verbose) {
-            Ok(()) => {
-                let _response = read_response(&mut transport, args.verbose)?;
-                if args.verbose || loop_count % 100 == 0 {
-                    println!(
-                        "Loop {}: DAC {} = {}",
-                        loop_count,
-                        c,
-                        ((msg[2] as u16) << 8) | (msg[3] as u16)
+        msg[2] = ((v & 0xff00) >> 8) as u8;
+        msg[3] = (v & 0xff) as u8;
+
+        let mut written = false;
+        for path in &mut paths {
+            let sent = Instant::now();
+            path.commands += 1;
+            match write_command(&mut path.transport, &msg, args.verbose, log) {
+                Ok(()) => {
+                    let response = read_response(&mut path.transport, args.verbose, log)?;
+                    let latency = (!response.is_empty()).then(|| sent.elapsed());
+                    if let Some(latency) = latency {
+                        path.latency.record(latency);
+                    }
+                    log.record(
+                        "command",
+                        Value::object()
+                            .with("target", path.target.to_string())
+                            .with("loop", loop_count)
+                            .with("command", msg.clone())
+                            .with("response", response)
+                            .with("latency_ms", latency),
                     );
+                    written = true;
+                }
+                Err(e) => {
+                    path.write_errors += 1;
+                    log.warn(
+                        "write_error",
+                        Value::object()
+                            .with("target", path.target.to_string())
+                            .with("loop", loop_count)
+                            .with("command", msg.clone())
+                            .with("error", format!("{:#}", e)),
+                        format!("Write error in main loop ({}): {}", path.target, e),
+                    );
+                    std::thread::sleep(Duration::from_millis(100));
                 }
             }
-            Err(e) => {
-                eprintln!("Write error in main loop: {}", e);
-                std::thread::sleep(Duration::from_millis(100));
-            }
+        }
+        if written && !log.is_json() && (args.verbose || loop_count % 100 == 0) {
+            println!(
+                "Loop {}: DAC {} = {}",
+                loop_count,
+                c,
+                ((msg[2] as u16) << 8) | (msg[3] as u16)
+            );
         }
 
         loop_count += 1;
@@ -349,6 +521,37 @@ fn main() -> Result<()> {
         }
     }
 
+    if !exit_commands.is_empty() {
+        step(
+            "exit",
+            &format!("Leaving the device at {}...", args.on_exit),
+        );
+        let transport = &mut paths[0].transport;
+        // The exit state sets complements as it says, not from their masters
+        for cmd in &exit_commands {
+            transport.write_unchecked(&cmd.encode())?;
+            let _response = read_response(transport, args.verbose, log)?;
+        }
+    }
+
+    if log.is_json() {
+        for path in &mut paths {
+            log.record("stats", path_value(path));
+        }
+        log.record("finished", Value::object().with("passed", true));
+        return Ok(());
+    }
+    for path in &paths {
+        if paths.len() > 1 {
+            println!("\n=== Transport Statistics ({}) ===", path.target);
+        } else {
+            println!("\n=== Transport Statistics ===");
+        }
+        println!("{}", path.transport.stats().snapshot());
+    }
+    if paths.len() > 1 {
+        print_comparison(&mut paths);
+    }
     println!("Test completed successfully.");
     Ok(())
 }
@@ -0,0 +1,39 @@
+//! Cancellation of long-running client operations.
+//!
+//! An operation such as a queued table upload
+//! ([`CommandStream::flush_queue_cancellable`](crate::stream::CommandStream::flush_queue_cancellable))
+//! takes a [`CancellationToken`]. Any clone of the token, e.g. one held by a UI
+//! thread, can cancel it; the operation stops between commands, sends its
+//! cleanup commands and fails with [`DacError::Cancelled`].
+
+use crate::error::{DacError, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared flag asking an operation to stop; clones cancel the same operation
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the operation to stop at its next check
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// [`DacError::Cancelled`] once cancelled, for use with `?`
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(DacError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
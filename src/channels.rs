@@ -0,0 +1,165 @@
+//! Complementary channel pairs.
+//!
+//! A complement channel always carries `65535 - master`. Clients resolve every
+//! write through [`ChannelLinks`] so the pair can never drift apart, whichever
+//! side of it is written: [`DacClient`](crate::client::DacClient) and
+//! [`FramedLink`](crate::transport::FramedLink) do so on their own once given
+//! the links.
+
+use crate::error::{DacError, Result};
+use crate::framing::COMMAND_LEN;
+use crate::protocol::{Command, DAC_CHANNELS};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelLinks {
+    /// Master of each complement channel
+    complement_of: [Option<u8>; DAC_CHANNELS],
+}
+
+impl ChannelLinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build links from `(complement, master)` pairs
+    pub fn from_pairs(pairs: &[(u8, u8)]) -> Result<Self> {
+        let mut links = Self::new();
+        for &(channel, master) in pairs {
+            links.set_complement(channel, master)?;
+        }
+        Ok(links)
+    }
+
+    /// Make `channel` the complement of `master`
+    ///
+    /// Chains are rejected: a master cannot itself be a complement, and a
+    /// complement cannot be the master of another channel.
+    pub fn set_complement(&mut self, channel: u8, master: u8) -> Result<()> {
+        for ch in [channel, master] {
+            if ch as usize >= DAC_CHANNELS {
+                return Err(DacError::InvalidArgument(format!(
+                    "DAC channel {} out of range 0-{}",
+                    ch,
+                    DAC_CHANNELS - 1
+                )));
+            }
+        }
+        if channel == master {
+            return Err(DacError::InvalidArgument(format!(
+                "DAC{} cannot be its own complement",
+                channel
+            )));
+        }
+        if self.complement_of[master as usize].is_some() {
+            return Err(DacError::InvalidArgument(format!(
+                "DAC{} is already a complement and cannot be a master",
+                master
+            )));
+        }
+        if self.complement_of.contains(&Some(channel)) {
+            return Err(DacError::InvalidArgument(format!(
+                "DAC{} is already a master and cannot be a complement",
+                channel
+            )));
+        }
+
+        self.complement_of[channel as usize] = Some(master);
+        Ok(())
+    }
+
+    pub fn complement_of(&self, channel: u8) -> Option<u8> {
+        self.complement_of.get(channel as usize).copied().flatten()
+    }
+
+    /// Every `(channel, value)` write needed to set `channel` to `value`
+    ///
+    /// Writing a complement sets its master to the inverse, and the master's
+    /// complements follow; the requested channel always comes first.
+    pub fn resolve(&self, channel: u8, value: u16) -> Vec<(u8, u16)> {
+        let (master, master_value) = match self.complement_of(channel) {
+            Some(master) => (master, u16::MAX - value),
+            None => (channel, value),
+        };
+
+        let mut writes = vec![(channel, value)];
+        if master != channel {
+            writes.push((master, master_value));
+        }
+        for (ch, link) in self.complement_of.iter().enumerate() {
+            if *link == Some(master) && ch as u8 != channel {
+                writes.push((ch as u8, u16::MAX - master_value));
+            }
+        }
+        writes
+    }
+
+    /// DAC write commands for [`resolve`](Self::resolve)
+    pub fn writes(&self, channel: u8, value: u16) -> Vec<Command> {
+        self.resolve(channel, value)
+            .into_iter()
+            .map(|(channel, value)| Command::DacWrite { channel, value })
+            .collect()
+    }
+
+    /// `data` with every DAC write command replaced by its [`writes`](Self::writes)
+    ///
+    /// Other commands, and a partial command at the end, are kept as they are.
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for chunk in data.chunks(COMMAND_LEN) {
+            match Command::decode(chunk) {
+                Some(Command::DacWrite { channel, value }) => out.extend(
+                    self.writes(channel, value)
+                        .iter()
+                        .flat_map(|command| command.encode()),
+                ),
+                _ => out.extend_from_slice(chunk),
+            }
+        }
+        out
+    }
+}
+
+/// Parse a `complement=master` pair, e.g. `4=0`, for command-line options
+pub fn parse_pair(s: &str) -> std::result::Result<(u8, u8), String> {
+    let (channel, master) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected COMPLEMENT=MASTER, got '{}'", s))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u8>()
+            .map_err(|e| format!("invalid channel '{}': {}", v, e))
+    };
+    Ok((parse(channel)?, parse(master)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_either_side_writes_the_pair() {
+        let links = ChannelLinks::from_pairs(&[(4, 0)]).unwrap();
+        assert_eq!(links.resolve(0, 0x1000), vec![(0, 0x1000), (4, 0xEFFF)]);
+        assert_eq!(links.resolve(4, 0x1000), vec![(4, 0x1000), (0, 0xEFFF)]);
+        assert_eq!(links.resolve(1, 0x1000), vec![(1, 0x1000)]);
+    }
+
+    #[test]
+    fn apply_expands_only_dac_writes() {
+        let links = ChannelLinks::from_pairs(&[(4, 0)]).unwrap();
+        let data = [0x00, 0x00, 0x12, 0x34, 0xfe, 0x01, 0x00, 0x01, 0x04, 0x10];
+        assert_eq!(
+            links.apply(&data),
+            [0x00, 0x00, 0x12, 0x34, 0x04, 0x00, 0xED, 0xCB, 0xfe, 0x01, 0x00, 0x01, 0x04, 0x10]
+        );
+    }
+
+    #[test]
+    fn chains_are_rejected() {
+        let mut links = ChannelLinks::from_pairs(&[(4, 0)]).unwrap();
+        assert!(links.set_complement(0, 1).is_err());
+        assert!(links.set_complement(5, 4).is_err());
+        assert!(links.set_complement(3, 3).is_err());
+    }
+}
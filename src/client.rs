@@ -0,0 +1,775 @@
+//! A device client with failover between redundant targets.
+//!
+//! [`DacClient`] sends one command at a time, waits for its response and keeps
+//! the [`DeviceState`] those commands produce. Given several targets (e.g. a
+//! primary and a backup bridge host), it switches to the next one when the
+//! current connection dies, replays the state to it and retries the command,
+//! so callers only see an error when no target is left.
+//!
+//! A DAC write to a channel with a complement (see [`ChannelLinks`]) writes
+//! both sides of the pair. DAC writes go through the client's
+//! [`ChannelAlarms`] first. Their
+//! thresholds act as soft limits: by default a write outside them fails with
+//! [`DacError::OutOfLimits`], or it can be clamped into range instead.
+//!
+//! [`negotiate`](DacClient::negotiate) offers protocol features in a hello
+//! at the start of the connection. Firmware without hello answers with an
+//! error status or not at all, so it is detected by that or by the timeout,
+//! and then nothing optional is used.
+//!
+//! [`write_table`](DacClient::write_table) sends only the entries that differ
+//! from what the client last wrote there, so editing a table again is quick.
+//! [`upload_table`](DacClient::upload_table) also reads a table back after
+//! writing it, where the firmware agreed to table reads in a hello
+//! ([`Features::READBACK`]), and rewrites only the entries that came back
+//! wrong, e.g. after bytes were dropped on the serial line.
+//!
+//! A client shared between threads as a [`SharedClient`] can also
+//! [`ramp_to`] a value in the background.
+
+use crate::alarms::{AlarmPolicy, ChannelAlarms};
+use crate::cancel::CancellationToken;
+use crate::channels::ChannelLinks;
+use crate::clock::{self, SharedClock};
+use crate::device::DeviceState;
+use crate::error::{DacError, Result};
+use crate::framing::Codec;
+use crate::protocol::{
+    self, Command, Features, Hello, DAC_CHANNELS, PROTOCOL_VERSION, TABLES, TABLE_LEN,
+};
+use crate::ramp::{Ramp, RampHandle};
+use crate::stream::CommandStream;
+use crate::tables::{Mismatch, TableShadow, Verification};
+use crate::target::Target;
+use crate::transport::{self, Link, LinkOptions};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+type FailoverFn = Box<dyn FnMut(&Target, &DacError) + Send>;
+type Stream = CommandStream<Box<dyn Link>>;
+
+/// Send `cmd` on `stream` and wait for its response, failing on a non-zero status
+fn exchange(stream: &mut Stream, cmd: Command) -> Result<Vec<Vec<u8>>> {
+    let result = stream.send(&cmd.encode()).and_then(|_| stream.drain());
+    let responses = stream.take_responses();
+    result?;
+    responses
+        .iter()
+        .try_for_each(|r| protocol::check_status(r))?;
+    Ok(responses)
+}
+
+/// Send `frame` on `stream` unchecked and wait for the responses, whatever their status
+fn exchange_raw(stream: &mut Stream, frame: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let result = stream.send_raw(frame).and_then(|_| stream.drain());
+    let responses = stream.take_responses();
+    result.map(|_| responses)
+}
+
+/// Offer `wanted` in a hello on a fresh `stream` and switch it to the features agreed
+///
+/// `codec` is what `stream` was opened with; CRC agreed on is turned on in it.
+fn say_hello(stream: &mut Stream, codec: &Codec, wanted: Features) -> Result<Hello> {
+    let hello = Command::Hello {
+        version: PROTOCOL_VERSION,
+        features: wanted,
+    };
+    let result = stream.send(&hello.encode()).and_then(|_| stream.drain());
+    let responses = stream.take_responses();
+    let answer = match result {
+        Ok(()) => match responses.last() {
+            Some(response) => protocol::hello_answer(response)?,
+            None => Hello::LEGACY,
+        },
+        // Firmware that ignores unknown commands
+        Err(DacError::Timeout) => Hello::LEGACY,
+        Err(e) => return Err(e),
+    };
+    // Never more than offered, whatever the device claims
+    let answer = Hello {
+        features: answer.features.intersection(wanted),
+        ..answer
+    };
+    if answer.features.contains(Features::CRC) && !codec.crc() {
+        stream.set_codec(codec.clone().with_crc(true));
+    }
+    Ok(answer)
+}
+
+/// Whether `error` means the link itself is gone, rather than the command failing
+fn is_connection_lost(error: &DacError) -> bool {
+    matches!(error, DacError::Transport(_) | DacError::Timeout)
+}
+
+/// No limits yet, refusing writes outside any set later
+fn soft_limits() -> ChannelAlarms {
+    ChannelAlarms::new().with_policy(AlarmPolicy::Refuse)
+}
+
+pub struct DacClient {
+    targets: Vec<Target>,
+    /// Index of the connected target
+    current: usize,
+    options: LinkOptions,
+    codec: Codec,
+    stream: Stream,
+    state: DeviceState,
+    failovers: u64,
+    on_failover: Option<FailoverFn>,
+    clock: SharedClock,
+    alarms: ChannelAlarms,
+    links: ChannelLinks,
+    /// Features offered, and what the device agreed to, once negotiated
+    hello: Option<(Features, Hello)>,
+    /// Table entries written on the current connection
+    tables: TableShadow,
+}
+
+impl DacClient {
+    /// Connect to the first of `targets` that opens
+    pub fn connect(targets: Vec<Target>, options: LinkOptions, codec: Codec) -> Result<Self> {
+        let mut last_error = None;
+        for (index, target) in targets.iter().enumerate() {
+            match transport::open_target(target, &options) {
+                Ok(link) => {
+                    let stream = CommandStream::new(link, codec.clone(), 1);
+                    return Ok(Self {
+                        targets,
+                        current: index,
+                        options,
+                        codec,
+                        stream,
+                        state: DeviceState::new(),
+                        failovers: 0,
+                        on_failover: None,
+                        clock: clock::system(),
+                        alarms: soft_limits(),
+                        links: ChannelLinks::new(),
+                        hello: None,
+                        tables: TableShadow::new(),
+                    });
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| DacError::InvalidArgument("No target given".to_string())))
+    }
+
+    /// A client on an already open `link`, without failover
+    ///
+    /// For links that are not reachable through a [`Target`], such as a
+    /// [`MockTransport`](crate::mock::MockTransport) in tests.
+    pub fn from_link(link: Box<dyn Link>, codec: Codec) -> Self {
+        Self {
+            targets: Vec::new(),
+            current: 0,
+            options: LinkOptions {
+                read_timeout: Default::default(),
+                write_timeout: Default::default(),
+            },
+            stream: CommandStream::new(link, codec.clone(), 1),
+            codec,
+            state: DeviceState::new(),
+            failovers: 0,
+            on_failover: None,
+            clock: clock::system(),
+            alarms: soft_limits(),
+            links: ChannelLinks::new(),
+            hello: None,
+            tables: TableShadow::new(),
+        }
+    }
+
+    /// Call `on_failover` with the new target and the error that ended the old connection
+    pub fn with_on_failover(
+        mut self,
+        on_failover: impl FnMut(&Target, &DacError) + Send + 'static,
+    ) -> Self {
+        self.on_failover = Some(Box::new(on_failover));
+        self
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.stream = self.stream.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    /// Check every DAC write against `alarms` and their policy, replacing any limits
+    pub fn with_alarms(mut self, alarms: ChannelAlarms) -> Self {
+        self.alarms = alarms;
+        self
+    }
+
+    pub fn alarms(&self) -> &ChannelAlarms {
+        &self.alarms
+    }
+
+    /// Keep complement channels at `65535 - master` on every DAC write
+    pub fn with_links(mut self, links: ChannelLinks) -> Self {
+        self.links = links;
+        self
+    }
+
+    pub fn links(&self) -> &ChannelLinks {
+        &self.links
+    }
+
+    /// Keep `channel` within `min..=max`, as the limit policy says
+    pub fn set_limits(&mut self, channel: u8, min: u16, max: u16) -> Result<()> {
+        self.alarms.set(channel, min, max)
+    }
+
+    pub fn clear_limits(&mut self, channel: u8) {
+        self.alarms.clear(channel);
+    }
+
+    /// Refuse (the default) or clamp writes outside the limits; `Warn` only flags them
+    pub fn set_limit_policy(&mut self, policy: AlarmPolicy) {
+        self.alarms = self.alarms.with_policy(policy);
+    }
+
+    /// The target currently connected, `None` for a client made [`from_link`](Self::from_link)
+    pub fn target(&self) -> Option<&Target> {
+        self.targets.get(self.current)
+    }
+
+    /// Transport name of the current link, e.g. "TCP"
+    pub fn kind(&self) -> &'static str {
+        self.stream.get_ref().kind()
+    }
+
+    /// State built from every command sent successfully
+    pub fn state(&self) -> &DeviceState {
+        &self.state
+    }
+
+    /// Offer `wanted` to the device in a hello and use the features it agrees to
+    ///
+    /// Call it first thing on a new connection; it is repeated after every
+    /// failover. A device without hello (an error status, or no answer within
+    /// the stall timeout) agrees to nothing, which is not an error.
+    pub fn negotiate(&mut self, wanted: Features) -> Result<Hello> {
+        let hello = say_hello(&mut self.stream, &self.codec, wanted)?;
+        self.hello = Some((wanted, hello));
+        Ok(hello)
+    }
+
+    /// What the device answered to the hello, `None` before [`negotiate`](Self::negotiate)
+    pub fn hello(&self) -> Option<Hello> {
+        self.hello.map(|(_, hello)| hello)
+    }
+
+    /// Fail unless a hello ran and the device agreed to `feature`
+    ///
+    /// For commands the stock firmware does not have, which are never sent
+    /// blind.
+    fn require(&self, feature: Features) -> Result<()> {
+        match self.hello() {
+            Some(hello) if hello.features.contains(feature) => Ok(()),
+            Some(hello) => Err(DacError::Unsupported(format!(
+                "{} (protocol version {})",
+                feature, hello.version
+            ))),
+            None => Err(DacError::Unsupported(format!(
+                "{} (not negotiated; run a hello first)",
+                feature
+            ))),
+        }
+    }
+
+    /// Number of times the client switched targets
+    pub fn failovers(&self) -> u64 {
+        self.failovers
+    }
+
+    /// Send `cmd` and wait for its response, failing on a non-zero status
+    ///
+    /// A DAC write to either side of a complement pair writes the whole
+    /// pair, the channel asked for first. Each write outside its
+    /// channel's limits is refused or clamped first, as the limit policy
+    /// says. If the connection is lost, the command is retried once on the
+    /// next target that opens and accepts the replayed state.
+    pub fn send(&mut self, cmd: Command) -> Result<()> {
+        match cmd {
+            Command::DacWrite { channel, value } => self
+                .links
+                .writes(channel, value)
+                .into_iter()
+                .try_for_each(|write| self.request(write).map(|_| ())),
+            cmd => self.request(cmd).map(|_| ()),
+        }
+    }
+
+    /// [`send`](Self::send), returning the responses
+    fn request(&mut self, cmd: Command) -> Result<Vec<Vec<u8>>> {
+        let cmd = self.alarms.check(cmd)?;
+        let responses = match exchange(&mut self.stream, cmd) {
+            Err(e) if is_connection_lost(&e) => {
+                self.fail_over(e)?;
+                exchange(&mut self.stream, cmd)?
+            }
+            result => result?,
+        };
+        self.state.apply(&cmd);
+        self.tables.record(&cmd);
+        Ok(responses)
+    }
+
+    /// Send the bytes of `frame` as they are and return the responses, e.g. to
+    /// try out firmware commands the protocol does not know
+    ///
+    /// The escape hatch from [`send`](Self::send): the frame skips the codec's
+    /// padding and strict checks (a partial command is zero-filled), the soft
+    /// limits and the status check, and it is not reflected in the
+    /// [`state`](Self::state). Fails with [`DacError::Timeout`] if the device
+    /// does not answer every command in it.
+    pub fn send_raw(&mut self, frame: &[u8]) -> Result<Vec<Vec<u8>>> {
+        if frame.is_empty() {
+            return Err(DacError::InvalidArgument("Empty frame".to_string()));
+        }
+        match exchange_raw(&mut self.stream, frame) {
+            Err(e) if is_connection_lost(&e) => {
+                self.fail_over(e)?;
+                exchange_raw(&mut self.stream, frame)
+            }
+            result => result,
+        }
+    }
+
+    pub fn write_register(&mut self, reg: u8, value: u16) -> Result<()> {
+        self.send(Command::RegisterWrite { reg, value })
+    }
+
+    /// Read a register, which the device answers with an extended response
+    ///
+    /// Only firmware with register and table reads has the command, so this
+    /// fails with [`DacError::Unsupported`] unless [`negotiate`](Self::negotiate)
+    /// ran and the device agreed to [`Features::READBACK`] and
+    /// [`Features::EXTENDED`].
+    pub fn read_register(&mut self, reg: u8) -> Result<u16> {
+        self.require(Features::READBACK | Features::EXTENDED)?;
+        let responses = self.request(Command::RegisterRead { reg })?;
+        let response = responses
+            .last()
+            .ok_or_else(|| DacError::Protocol("No answer to a register read".to_string()))?;
+        protocol::register_value(response)
+    }
+
+    /// Read entry `index` of `table`, which the device answers with an extended response
+    ///
+    /// Like [`read_register`](Self::read_register) this needs
+    /// [`Features::READBACK`] and [`Features::EXTENDED`] agreed in a hello,
+    /// and fails with [`DacError::Unsupported`] otherwise or if the device
+    /// answers the read with an error status anyway.
+    pub fn read_table_entry(&mut self, table: u8, index: u8) -> Result<u16> {
+        if table as usize >= TABLES {
+            return Err(DacError::InvalidArgument(format!(
+                "Table {} out of range 0-{}",
+                table,
+                TABLES - 1
+            )));
+        }
+        self.require(Features::READBACK | Features::EXTENDED)?;
+        let responses = match self.request(Command::TableRead { table, index }) {
+            Err(DacError::DeviceStatus(status)) => {
+                return Err(DacError::Unsupported(format!(
+                    "table reads (status 0x{:02X})",
+                    status
+                )))
+            }
+            result => result?,
+        };
+        let response = responses
+            .last()
+            .ok_or_else(|| DacError::Protocol("No answer to a table read".to_string()))?;
+        protocol::register_value(response)
+    }
+
+    /// Read `table` back at the given entries and return those differing from `expected`
+    fn compare_table(
+        &mut self,
+        table: u8,
+        expected: &[u16],
+        indices: &[u8],
+    ) -> Result<Vec<Mismatch>> {
+        let mut mismatches = Vec::new();
+        for &index in indices {
+            let expected = expected[index as usize];
+            let actual = self.read_table_entry(table, index)?;
+            if actual != expected {
+                // The shadow keeps what the device really holds
+                self.tables.record(&Command::TableWrite {
+                    table,
+                    index,
+                    value: actual,
+                });
+                mismatches.push(Mismatch {
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Write `values` to `table` from entry 0, returning how many entries were sent
+    ///
+    /// Entries this client already wrote with the same value on the current
+    /// connection are skipped. After a failover every entry is sent again,
+    /// except those the state replay wrote.
+    pub fn write_table(&mut self, table: u8, values: &[u16]) -> Result<usize> {
+        if table as usize >= TABLES {
+            return Err(DacError::InvalidArgument(format!(
+                "Table {} out of range 0-{}",
+                table,
+                TABLES - 1
+            )));
+        }
+        let changes = self.tables.changes(table, values);
+        for &cmd in &changes {
+            self.send(cmd)?;
+        }
+        Ok(changes.len())
+    }
+
+    /// [`write_table`](Self::write_table), then read every entry back
+    ///
+    /// Entries that read back wrong are written again and re-read, up to
+    /// `retries` times; the rest are not sent twice. The result lists what is
+    /// still wrong after that. Verifying costs one read per entry, and fails
+    /// with [`DacError::Unsupported`] unless the device agreed to
+    /// [`Features::READBACK`] in a hello.
+    pub fn upload_table(
+        &mut self,
+        table: u8,
+        values: &[u16],
+        retries: usize,
+    ) -> Result<Verification> {
+        let values = &values[..values.len().min(TABLE_LEN)];
+        self.write_table(table, values)?;
+        let all: Vec<u8> = (0..values.len()).map(|index| index as u8).collect();
+        let mut verification = Verification {
+            rewritten: 0,
+            mismatches: self.compare_table(table, values, &all)?,
+        };
+        for _ in 0..retries {
+            if verification.passed() {
+                break;
+            }
+            let failed: Vec<u8> = verification.mismatches.iter().map(|m| m.index).collect();
+            for &index in &failed {
+                self.send(Command::TableWrite {
+                    table,
+                    index,
+                    value: values[index as usize],
+                })?;
+            }
+            verification.rewritten += failed.len();
+            verification.mismatches = self.compare_table(table, values, &failed)?;
+        }
+        Ok(verification)
+    }
+
+    /// Switch to the next target that opens and takes the state, the current one last
+    ///
+    /// Without targets to try, `cause` is returned as is.
+    fn fail_over(&mut self, cause: DacError) -> Result<()> {
+        let mut last_error = None;
+        for step in 1..=self.targets.len() {
+            let index = (self.current + step) % self.targets.len();
+            match self.open_restored(&self.targets[index]) {
+                Ok((stream, hello)) => {
+                    self.stream = stream;
+                    self.tables.clear();
+                    for cmd in self.state.restore_commands() {
+                        self.tables.record(&cmd);
+                    }
+                    if let Some((wanted, _)) = self.hello {
+                        self.hello = hello.map(|hello| (wanted, hello));
+                    }
+                    self.current = index;
+                    self.failovers += 1;
+                    if let Some(on_failover) = &mut self.on_failover {
+                        on_failover(&self.targets[index], &cause);
+                    }
+                    return Ok(());
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or(cause))
+    }
+
+    /// Open `target`, negotiate as before and replay the state to it
+    fn open_restored(&self, target: &Target) -> Result<(Stream, Option<Hello>)> {
+        let link = transport::open_target(target, &self.options)?;
+        let mut stream =
+            CommandStream::new(link, self.codec.clone(), 1).with_clock(self.clock.clone());
+        let hello = match self.hello {
+            Some((wanted, _)) => Some(say_hello(&mut stream, &self.codec, wanted)?),
+            None => None,
+        };
+        for cmd in self.state.restore_commands() {
+            exchange(&mut stream, cmd)?;
+        }
+        Ok((stream, hello))
+    }
+}
+
+/// A client used from several threads, e.g. by [`ramp_to`] and a request handler
+pub type SharedClient = Arc<Mutex<DacClient>>;
+
+/// Move `channel` linearly from its current value to `target` over `duration`
+///
+/// The writes are sent from a background thread every
+/// [`RAMP_STEP`](crate::ramp::RAMP_STEP), timed by the client's clock. The
+/// client is locked only while a write is sent, so other commands go out in
+/// between; writes pass through the soft limits like any other. The handle
+/// reports when the ramp ends and the channel's final value.
+pub fn ramp_to(
+    client: &SharedClient,
+    channel: u8,
+    target: u16,
+    duration: Duration,
+) -> Result<RampHandle> {
+    if channel as usize >= DAC_CHANNELS {
+        return Err(DacError::InvalidArgument(format!(
+            "DAC channel {} out of range 0-{}",
+            channel,
+            DAC_CHANNELS - 1
+        )));
+    }
+    let (from, clock) = {
+        let client = client.lock().unwrap();
+        (client.state.dac[channel as usize], client.clock.clone())
+    };
+    let ramp = Ramp::new(channel, from, target, duration);
+    let client = client.clone();
+    let cancel = CancellationToken::new();
+    let token = cancel.clone();
+    let thread = thread::spawn(move || {
+        let start = clock.now();
+        let mut sent = from;
+        loop {
+            token.check()?;
+            let elapsed = clock.elapsed(start);
+            let value = ramp.value_at(elapsed);
+            let done = ramp.is_done(elapsed);
+            // Long, shallow ramps repeat values; only changes are sent
+            if value != sent || done {
+                client.lock().unwrap().send(ramp.command_at(elapsed))?;
+                sent = value;
+            }
+            if done {
+                return Ok(client.lock().unwrap().state.dac[channel as usize]);
+            }
+            clock.sleep(ramp.next_step_in(elapsed));
+        }
+    });
+    Ok(RampHandle::new(cancel, thread))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::framing;
+    use crate::mock::MockTransport;
+    use crate::stream::DEFAULT_STALL_TIMEOUT;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    fn client(mock: &MockTransport) -> DacClient {
+        DacClient::from_link(Box::new(mock.clone()), Codec::default())
+    }
+
+    #[test]
+    fn complement_pairs_are_written_together() {
+        let mock = MockTransport::new().with_auto_ack();
+        let links = ChannelLinks::from_pairs(&[(4, 0)]).unwrap();
+        let mut client = client(&mock).with_links(links);
+        client
+            .send(Command::DacWrite {
+                channel: 4,
+                value: 0x0100,
+            })
+            .unwrap();
+        assert_eq!(
+            mock.commands(),
+            vec![
+                Command::DacWrite {
+                    channel: 4,
+                    value: 0x0100
+                },
+                Command::DacWrite {
+                    channel: 0,
+                    value: 0xFEFF
+                },
+            ]
+        );
+        assert_eq!(client.state().dac[0], 0xFEFF);
+    }
+
+    #[test]
+    fn register_reads_wait_for_readback_in_a_hello() {
+        let mock = MockTransport::new();
+        let mut client = client(&mock);
+        assert!(matches!(
+            client.read_register(16),
+            Err(DacError::Unsupported(_))
+        ));
+        assert!(mock.written().is_empty());
+
+        mock.push_response(&[0x01, 0x03, 1, 0x00, 0x02]);
+        client
+            .negotiate(Features::EXTENDED | Features::READBACK)
+            .unwrap();
+        assert!(matches!(
+            client.read_table_entry(0, 0),
+            Err(DacError::Unsupported(_))
+        ));
+
+        mock.push_response(&[0x01, 0x03, 1, 0x00, 0x06]);
+        client
+            .negotiate(Features::EXTENDED | Features::READBACK)
+            .unwrap();
+        mock.push_response(&[0x01, 0x02, 0x12, 0x34]);
+        assert_eq!(client.read_register(16).unwrap(), 0x1234);
+    }
+
+    /// Commands read from `stream` until it closes, each acknowledged
+    fn ack_until_closed(mut stream: TcpStream, limit: usize) -> Vec<Command> {
+        let mut commands = Vec::new();
+        let mut cmd = [0u8; 4];
+        while commands.len() < limit && stream.read_exact(&mut cmd).is_ok() {
+            commands.extend(Command::decode(&cmd));
+            if stream.write_all(&[0x00, 0x00]).is_err() {
+                break;
+            }
+        }
+        commands
+    }
+
+    #[test]
+    fn failover_replays_the_state_and_retries() {
+        let primary = TcpListener::bind("127.0.0.1:0").unwrap();
+        let backup = TcpListener::bind("127.0.0.1:0").unwrap();
+        let targets = [&primary, &backup].map(|listener| Target::Tcp {
+            address: listener.local_addr().unwrap().to_string(),
+        });
+        // The primary answers one command, reads the next and hangs up
+        let primary = thread::spawn(move || {
+            let (mut stream, _) = primary.accept().unwrap();
+            ack_until_closed(stream.try_clone().unwrap(), 1);
+            stream.read_exact(&mut [0u8; 4]).unwrap();
+        });
+        let backup =
+            thread::spawn(move || ack_until_closed(backup.accept().unwrap().0, usize::MAX));
+
+        let options = LinkOptions {
+            read_timeout: Duration::from_secs(1),
+            write_timeout: Duration::from_secs(1),
+        };
+        let switched = Arc::new(Mutex::new(Vec::new()));
+        let on_failover = switched.clone();
+        let mut client = DacClient::connect(targets.to_vec(), options, Codec::default())
+            .unwrap()
+            .with_on_failover(move |target, _| on_failover.lock().unwrap().push(target.clone()));
+        let first = Command::DacWrite {
+            channel: 1,
+            value: 5,
+        };
+        let second = Command::DacWrite {
+            channel: 2,
+            value: 7,
+        };
+        client.send(first).unwrap();
+        client.send(second).unwrap();
+        primary.join().unwrap();
+
+        assert_eq!(client.failovers(), 1);
+        assert_eq!(client.target(), Some(&targets[1]));
+        assert_eq!(*switched.lock().unwrap(), vec![targets[1].clone()]);
+        drop(client);
+        let replayed = backup.join().unwrap();
+        assert!(replayed.contains(&first));
+        assert_eq!(replayed.last(), Some(&second));
+    }
+
+    #[test]
+    fn writes_outside_the_limits_are_refused_or_clamped() {
+        let mock = MockTransport::new().with_auto_ack();
+        let mut client = client(&mock);
+        client.set_limits(2, 100, 200).unwrap();
+        let write = Command::DacWrite {
+            channel: 2,
+            value: 300,
+        };
+        assert!(matches!(
+            client.send(write),
+            Err(DacError::OutOfLimits { value: 300, .. })
+        ));
+        assert!(mock.written().is_empty());
+
+        client.set_limit_policy(AlarmPolicy::Clamp);
+        client.send(write).unwrap();
+        assert_eq!(
+            mock.commands(),
+            vec![Command::DacWrite {
+                channel: 2,
+                value: 200
+            }]
+        );
+        assert_eq!(client.state().dac[2], 200);
+    }
+
+    #[test]
+    fn crc_agreed_in_a_hello_turns_on_the_codec() {
+        let mock = MockTransport::new();
+        let mut client = client(&mock);
+        mock.push_response(&[0x01, 0x03, 1, 0x00, 0x03]);
+        let hello = client
+            .negotiate(Features::CRC | Features::EXTENDED)
+            .unwrap();
+        assert_eq!(hello.features, Features::CRC | Features::EXTENDED);
+
+        let mut ack = vec![0x00, 0x00];
+        framing::append_crc(&mut ack);
+        mock.push_response(&ack);
+        client.send(Command::Ldac).unwrap();
+        let written = mock.written();
+        let ldac = written.last().unwrap();
+        assert_eq!(framing::check_crc(ldac).unwrap(), Command::Ldac.encode());
+    }
+
+    #[test]
+    fn firmware_without_hello_agrees_to_nothing() {
+        let mock = MockTransport::new();
+        let mut client = client(&mock);
+        mock.push_response(&[0x00, 0x01]);
+        assert_eq!(client.negotiate(Features::ALL).unwrap(), Hello::LEGACY);
+
+        mock.push_response(&[0x00, 0x00]);
+        client.send(Command::Ldac).unwrap();
+        assert_eq!(mock.written().last().unwrap(), &Command::Ldac.encode());
+    }
+
+    #[test]
+    fn silent_firmware_agrees_to_nothing_after_the_stall_timeout() {
+        let clock = ManualClock::new();
+        let shared: SharedClock = Arc::new(clock.clone());
+        let mock =
+            MockTransport::new().with_read_timeout(shared.clone(), Duration::from_millis(50));
+        let mut client = client(&mock).with_clock(shared);
+        assert_eq!(client.negotiate(Features::ALL).unwrap(), Hello::LEGACY);
+        assert_eq!(clock.total_elapsed(), DEFAULT_STALL_TIMEOUT);
+    }
+}
@@ -0,0 +1,195 @@
+//! Time source for timing-dependent library code.
+//!
+//! Keepalive scheduling and stall detection in
+//! [`CommandStream`](crate::stream::CommandStream) and the batching delay of
+//! [`CoalescingWriter`](crate::transport::CoalescingWriter) read the time
+//! through a [`Clock`]. [`SystemClock`] is real time. [`ManualClock`] only moves
+//! when told to (or when something sleeps on it), so tests of that logic run
+//! instantly and the same way every time.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wait for `duration` to pass on this clock
+    fn sleep(&self, duration: Duration);
+
+    /// Time passed since `earlier`, zero if `earlier` is in the future
+    fn elapsed(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The real clock, the default everywhere
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// Real time: [`Instant::now`] and [`thread::sleep`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Virtual time that stands still until [`advance`](Self::advance)d
+///
+/// Clones share one time, so a test keeps a handle to move the clock while the
+/// code under test holds another. [`sleep`](Clock::sleep) returns at once
+/// after advancing the clock by the time slept.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// Virtual time passed since the clock was created
+    pub fn total_elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.total_elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DacError;
+    use crate::framing::Codec;
+    use crate::mock::MockTransport;
+    use crate::protocol::Command;
+    use crate::stream::CommandStream;
+    use crate::transport::CoalescingWriter;
+    use std::io::Write;
+
+    fn table_write(index: u8) -> [u8; 4] {
+        Command::TableWrite {
+            table: 0,
+            index,
+            value: 0x1000,
+        }
+        .encode()
+    }
+
+    #[test]
+    fn manual_clock_moves_only_when_told() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let other = clock.clone();
+        assert_eq!(clock.elapsed(start), Duration::ZERO);
+        clock.advance(Duration::from_millis(5));
+        other.sleep(Duration::from_millis(10));
+        assert_eq!(clock.elapsed(start), Duration::from_millis(15));
+        assert_eq!(other.total_elapsed(), Duration::from_millis(15));
+        assert_eq!(
+            clock.elapsed(clock.now() + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn coalesced_bytes_are_due_after_the_delay() {
+        let clock = ManualClock::new();
+        let mock = MockTransport::new();
+        let mut writer = CoalescingWriter::new(mock.clone(), Duration::from_millis(10))
+            .with_clock(Arc::new(clock.clone()));
+        writer.write_all(&table_write(0)).unwrap();
+        clock.advance(Duration::from_millis(9));
+        assert_eq!(writer.due_in(), Some(Duration::from_millis(1)));
+        assert!(!writer.flush_if_due().unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert!(writer.flush_if_due().unwrap());
+        assert_eq!(mock.written(), vec![table_write(0).to_vec()]);
+        assert_eq!(writer.due_in(), None);
+    }
+
+    #[test]
+    fn keepalive_goes_out_once_per_interval_while_flushing() {
+        let clock = ManualClock::new();
+        let mock = MockTransport::new().with_auto_ack();
+        let mut stream = CommandStream::new(mock.clone(), Codec::default(), 1)
+            .with_clock(Arc::new(clock.clone()))
+            .with_keepalive(Duration::from_secs(1));
+        stream.enqueue(&[table_write(0), table_write(1)].concat());
+
+        assert_eq!(stream.pump().unwrap(), 1);
+        stream.poll().unwrap();
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(stream.pump().unwrap(), 1);
+        stream.poll().unwrap();
+        // Nothing queued, so nothing to keep alive
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(stream.pump().unwrap(), 0);
+
+        stream.enqueue(&table_write(2));
+        assert_eq!(stream.pump().unwrap(), 1);
+        stream.poll().unwrap();
+        assert_eq!(stream.pump().unwrap(), 1);
+        stream.poll().unwrap();
+        let commands = mock.commands();
+        assert_eq!(commands.len(), 4);
+        assert_eq!(commands[2], Command::KeepAlive);
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|&&c| c == Command::KeepAlive)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn silent_link_stalls_after_the_timeout() {
+        let clock = ManualClock::new();
+        let shared: SharedClock = Arc::new(clock.clone());
+        let mock =
+            MockTransport::new().with_read_timeout(shared.clone(), Duration::from_millis(100));
+        let mut stream = CommandStream::new(mock, Codec::default(), 4)
+            .with_clock(shared)
+            .with_stall_timeout(Duration::from_millis(500));
+        stream.send(&table_write(0)).unwrap();
+        assert!(matches!(stream.drain(), Err(DacError::Timeout)));
+        assert_eq!(clock.total_elapsed(), Duration::from_millis(500));
+        assert_eq!(stream.in_flight(), 0);
+    }
+}
@@ -0,0 +1,173 @@
+//! Closed-loop control of a DAC channel.
+//!
+//! A [`ClosedLoop`] reads a [`FeedbackSource`] every period, runs the error
+//! against the setpoint through a [`Pid`] and writes the result to one DAC
+//! channel, e.g. to hold a voltage measured by a multimeter. The output is
+//! the channel's value when the loop started plus the PID correction, so a
+//! loop taking over a channel that is already close starts without a jump.
+//! Positive gains suit outputs that raise the measurement; use negative gains
+//! for a reversing stage.
+
+use crate::cancel::CancellationToken;
+use crate::client::DacClient;
+use crate::error::{DacError, Result};
+use crate::feedback::FeedbackSource;
+use crate::protocol::{Command, DAC_CHANNELS};
+use std::time::Duration;
+
+/// PID controller, its output clamped to a range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pid {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+    min: f64,
+    max: f64,
+    integral: f64,
+    last_error: Option<f64>,
+}
+
+impl Pid {
+    pub fn new(kp: f64, ki: f64, kd: f64) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            min: f64::NEG_INFINITY,
+            max: f64::INFINITY,
+            integral: 0.0,
+            last_error: None,
+        }
+    }
+
+    /// Clamp the output to `min..=max`
+    ///
+    /// While clamped the integral stops growing, so it does not wind up and
+    /// overshoot once the output can follow again.
+    pub fn with_output_range(mut self, min: f64, max: f64) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Forget the integral and the last error
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_error = None;
+    }
+
+    /// Output for `error`, `dt` after the previous update
+    ///
+    /// The derivative term is zero on the first update.
+    pub fn update(&mut self, error: f64, dt: Duration) -> f64 {
+        let dt = dt.as_secs_f64();
+        let integral = self.integral + error * dt;
+        let derivative = match self.last_error {
+            Some(last) if dt > 0.0 => (error - last) / dt,
+            _ => 0.0,
+        };
+        self.last_error = Some(error);
+        let output = self.kp * error + self.ki * integral + self.kd * derivative;
+        let clamped = output.clamp(self.min, self.max);
+        if clamped == output {
+            self.integral = integral;
+        }
+        clamped
+    }
+}
+
+/// One iteration of a [`ClosedLoop`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Step {
+    /// Time since the loop started
+    pub elapsed: Duration,
+    pub measured: f64,
+    /// Setpoint minus the measurement
+    pub error: f64,
+    /// Value written to the channel
+    pub value: u16,
+}
+
+pub struct ClosedLoop {
+    channel: u8,
+    setpoint: f64,
+    pid: Pid,
+    period: Duration,
+    source: Box<dyn FeedbackSource>,
+}
+
+impl ClosedLoop {
+    /// Hold `source` at `setpoint` by adjusting `channel`, once every 100 ms
+    pub fn new(
+        channel: u8,
+        setpoint: f64,
+        pid: Pid,
+        source: Box<dyn FeedbackSource>,
+    ) -> Result<Self> {
+        if channel as usize >= DAC_CHANNELS {
+            return Err(DacError::InvalidArgument(format!(
+                "DAC channel {} out of range 0-{}",
+                channel,
+                DAC_CHANNELS - 1
+            )));
+        }
+        Ok(Self {
+            channel,
+            setpoint,
+            pid,
+            period: Duration::from_millis(100),
+            source,
+        })
+    }
+
+    /// Read and correct once every `period`
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// Change the setpoint of a running or stopped loop
+    pub fn set_setpoint(&mut self, setpoint: f64) {
+        self.setpoint = setpoint;
+    }
+
+    /// Control the channel until `cancel` is cancelled, calling `on_step` after
+    /// every write
+    ///
+    /// Timed by the client's clock. Writes pass through the client's soft
+    /// limits like any other. Fails with [`DacError::Cancelled`] once
+    /// cancelled, or with the first failed reading or write.
+    pub fn run(
+        &mut self,
+        client: &mut DacClient,
+        cancel: &CancellationToken,
+        mut on_step: impl FnMut(&Step),
+    ) -> Result<()> {
+        let clock = client.clock().clone();
+        let bias = client.state().dac[self.channel as usize] as f64;
+        let mut pid = self.pid.with_output_range(-bias, u16::MAX as f64 - bias);
+        pid.reset();
+        let start = clock.now();
+        let mut last = start;
+        loop {
+            cancel.check()?;
+            let measured = self.source.read()?;
+            let now = clock.now();
+            let error = self.setpoint - measured;
+            let output = pid.update(error, now - last);
+            last = now;
+            let value = (bias + output).round() as u16;
+            client.send(Command::DacWrite {
+                channel: self.channel,
+                value,
+            })?;
+            on_step(&Step {
+                elapsed: now - start,
+                measured,
+                error,
+                value,
+            });
+            clock.sleep(self.period.saturating_sub(clock.elapsed(now)));
+        }
+    }
+}
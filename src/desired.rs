@@ -0,0 +1,395 @@
+//! Declarative device states for `csv1 apply`.
+//!
+//! A state file names only the outputs it cares about, in TOML:
+//!
+//! ```text
+//! # warm-up state
+//! table_offset = 5
+//!
+//! [dac]
+//! 0 = 32768
+//! 3 = "45%"
+//!
+//! [gpio]
+//! 2 = true
+//!
+//! [attach]
+//! 1 = 0
+//! ```
+//!
+//! or, in a `.yaml` or `.yml` file, the same in YAML:
+//!
+//! ```text
+//! table_offset: 5
+//! dac:
+//!   0: 32768
+//!   3: 45%
+//! gpio:
+//!   2: true
+//! attach:
+//!   1: 0
+//! ```
+//!
+//! DAC values are codes (decimal or `0x` hex) or a percent of full scale;
+//! GPIO states are `true`/`false`, `"on"`/`"off"` or `1`/`0`. Anything the
+//! file leaves out stays as it is on the device.
+//!
+//! [`ExitState`] is the `--on-exit` choice of the test programs: a state
+//! file, everything zeroed, or the outputs left as the test had them.
+
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS, TABLES};
+use crate::snapshot::Snapshot;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The outputs a state file asks for; `None` for those it leaves alone
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DesiredState {
+    pub dac: [Option<u16>; DAC_CHANNELS],
+    pub gpio: [Option<bool>; GPIO_PINS],
+    /// Table to attach to each channel
+    pub attached: [Option<u8>; DAC_CHANNELS],
+    pub table_offset: Option<u8>,
+}
+
+/// A state file as TOML and YAML both read it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+    table_offset: Option<Scalar>,
+    #[serde(default)]
+    dac: BTreeMap<Scalar, Scalar>,
+    #[serde(default)]
+    gpio: BTreeMap<Scalar, Scalar>,
+    #[serde(default)]
+    attach: BTreeMap<Scalar, Scalar>,
+}
+
+/// A key or value, which TOML and YAML type differently (`0` is a string key
+/// in TOML and a number in YAML, `45%` a string in both)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+    Bool(bool),
+    Int(i64),
+    Text(String),
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Bool(b) => write!(f, "{}", b),
+            Scalar::Int(n) => write!(f, "{}", n),
+            Scalar::Text(s) => write!(f, "{}", s.trim()),
+        }
+    }
+}
+
+fn invalid(reason: impl fmt::Display) -> DacError {
+    DacError::InvalidArgument(format!("State file: {}", reason))
+}
+
+/// Decimal or `0x` hex number, `None` if it does not fit `T`
+fn number<T: TryFrom<u32>>(s: &str) -> Option<T> {
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => s.parse().ok()?,
+    };
+    T::try_from(value).ok()
+}
+
+/// DAC code, or the code of a percent of full scale
+fn dac_value(s: &str) -> Option<u16> {
+    match s.strip_suffix('%') {
+        Some(percent) => {
+            let percent: f64 = percent.trim().parse().ok()?;
+            (0.0..=100.0)
+                .contains(&percent)
+                .then(|| (percent / 100.0 * 65535.0).round() as u16)
+        }
+        None => number(s),
+    }
+}
+
+fn on_off(s: &str) -> Option<bool> {
+    match s {
+        "true" | "on" | "1" => Some(true),
+        "false" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Each entry of `section` by index below `count`, its value read by `parse`
+fn entries<T, const N: usize>(
+    section: &str,
+    entries: &BTreeMap<Scalar, Scalar>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<[Option<T>; N]> {
+    let mut array = [const { None }; N];
+    for (key, value) in entries {
+        let key = key.to_string();
+        let index = key
+            .parse::<usize>()
+            .ok()
+            .filter(|&i| i < N)
+            .ok_or_else(|| invalid(format!("[{}] key '{}' is not in 0-{}", section, key, N - 1)))?;
+        let value = value.to_string();
+        array[index] =
+            Some(parse(&value).ok_or_else(|| {
+                invalid(format!("bad value '{}' for {}.{}", value, section, index))
+            })?);
+    }
+    Ok(array)
+}
+
+impl TryFrom<StateFile> for DesiredState {
+    type Error = DacError;
+
+    fn try_from(file: StateFile) -> Result<Self> {
+        let table_offset = file
+            .table_offset
+            .map(|offset| {
+                let offset = offset.to_string();
+                number(&offset)
+                    .ok_or_else(|| invalid(format!("bad value '{}' for table_offset", offset)))
+            })
+            .transpose()?;
+        Ok(DesiredState {
+            dac: entries("dac", &file.dac, dac_value)?,
+            gpio: entries("gpio", &file.gpio, on_off)?,
+            attached: entries("attach", &file.attach, |value| {
+                number(value).filter(|&t: &u8| (t as usize) < TABLES)
+            })?,
+            table_offset,
+        })
+    }
+}
+
+/// Every output of a snapshot, as a state file naming them all would
+impl From<&Snapshot> for DesiredState {
+    fn from(snapshot: &Snapshot) -> Self {
+        Self {
+            dac: snapshot.dac.map(Some),
+            gpio: snapshot.gpio.map(Some),
+            attached: snapshot.attached,
+            table_offset: Some(snapshot.table_offset),
+        }
+    }
+}
+
+impl DesiredState {
+    /// Read a state file: YAML for `.yaml` and `.yml` files, TOML otherwise
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&text),
+            _ => text.parse(),
+        }
+    }
+
+    pub fn from_yaml(s: &str) -> Result<Self> {
+        serde_yaml::from_str::<StateFile>(s)
+            .map_err(invalid)?
+            .try_into()
+    }
+
+    /// Commands that bring a device from `current` to this state
+    ///
+    /// Attachments and the table offset go first, then the DAC values (with
+    /// one LDAC after them) and the GPIOs. Outputs `current` already has are
+    /// skipped; without a known current state every named output is sent. A
+    /// new attachment or offset reloads the attached channels, so their DAC
+    /// values are sent in any case.
+    pub fn commands(&self, current: Option<&Snapshot>) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for (channel, &table) in self.attached.iter().enumerate() {
+            if let Some(table) = table {
+                if current.is_none_or(|c| c.attached[channel] != Some(table)) {
+                    commands.push(Command::AttachTable {
+                        channel: channel as u8,
+                        table,
+                    });
+                }
+            }
+        }
+        if let Some(offset) = self.table_offset {
+            if current.is_none_or(|c| c.table_offset != offset) {
+                commands.push(Command::UseTable { offset });
+            }
+        }
+        let reloaded = !commands.is_empty();
+        let mut wrote_dac = false;
+        for (channel, &value) in self.dac.iter().enumerate() {
+            let Some(value) = value else {
+                continue;
+            };
+            let attached = self.attached[channel]
+                .or_else(|| current.and_then(|c| c.attached[channel]))
+                .is_some();
+            if current.is_none_or(|c| c.dac[channel] != value) || (reloaded && attached) {
+                commands.push(Command::DacWrite {
+                    channel: channel as u8,
+                    value,
+                });
+                wrote_dac = true;
+            }
+        }
+        if wrote_dac {
+            commands.push(Command::Ldac);
+        }
+        for (pin, &on) in self.gpio.iter().enumerate() {
+            if let Some(on) = on {
+                if current.is_none_or(|c| c.gpio[pin] != on) {
+                    commands.push(Command::Gpio { pin: pin as u8, on });
+                }
+            }
+        }
+        commands
+    }
+}
+
+/// A TOML state file
+impl FromStr for DesiredState {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        toml::from_str::<StateFile>(s).map_err(invalid)?.try_into()
+    }
+}
+
+/// What a test program leaves the device in when it stops (`--on-exit`)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ExitState {
+    /// Leave the outputs as the test had them
+    #[default]
+    Hold,
+    /// Every DAC at 0 and every GPIO off
+    Zero,
+    /// The state in a state file
+    File(PathBuf),
+}
+
+/// `hold`, `zero` or anything else as a state file path
+impl FromStr for ExitState {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "hold" => ExitState::Hold,
+            "zero" => ExitState::Zero,
+            "" => return Err(DacError::InvalidArgument("empty exit state".to_string())),
+            path => ExitState::File(PathBuf::from(path)),
+        })
+    }
+}
+
+impl fmt::Display for ExitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitState::Hold => write!(f, "hold"),
+            ExitState::Zero => write!(f, "zero"),
+            ExitState::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl ExitState {
+    /// Commands that bring the device to this state; none for [`ExitState::Hold`]
+    ///
+    /// A state file is read here, so call this before the test starts.
+    pub fn commands(&self) -> Result<Vec<Command>> {
+        Ok(match self {
+            ExitState::Hold => Vec::new(),
+            ExitState::Zero => DesiredState {
+                dac: [Some(0); DAC_CHANNELS],
+                gpio: [Some(false); GPIO_PINS],
+                ..DesiredState::default()
+            }
+            .commands(None),
+            ExitState::File(path) => DesiredState::load(path)?.commands(None),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+# warm-up state
+table_offset = 5
+
+[dac]
+0 = 32768
+3 = "50%"
+7 = 0xFFFF
+
+[gpio]
+2 = true
+4 = "off"
+
+[attach]
+1 = 0
+"#;
+
+    const YAML: &str = "
+table_offset: 5
+dac:
+  0: 32768
+  3: 50%
+  7: 0xFFFF
+gpio:
+  2: true
+  4: off
+attach:
+  1: 0
+";
+
+    #[test]
+    fn toml_and_yaml_read_the_same_state() {
+        let state: DesiredState = TOML.parse().unwrap();
+        assert_eq!(state.table_offset, Some(5));
+        assert_eq!(state.dac[0], Some(32768));
+        assert_eq!(state.dac[3], Some(32768));
+        assert_eq!(state.dac[7], Some(0xFFFF));
+        assert_eq!(state.dac[1], None);
+        assert_eq!(state.gpio[2], Some(true));
+        assert_eq!(state.gpio[4], Some(false));
+        assert_eq!(state.attached[1], Some(0));
+        assert_eq!(DesiredState::from_yaml(YAML).unwrap(), state);
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        assert!("[dac]\n8 = 1".parse::<DesiredState>().is_err());
+        assert!("[dac]\n0 = \"101%\"".parse::<DesiredState>().is_err());
+        assert!("[attach]\n0 = 4".parse::<DesiredState>().is_err());
+        assert!("[leds]\n0 = 1".parse::<DesiredState>().is_err());
+        assert!(DesiredState::from_yaml("gpio:\n  0: maybe").is_err());
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        let state: DesiredState = "[dac]\n0 = 5\n1 = 6\n[gpio]\n0 = true".parse().unwrap();
+        let mut current = Snapshot::default();
+        current.dac[0] = 5;
+        assert_eq!(
+            state.commands(Some(&current)),
+            vec![
+                Command::DacWrite {
+                    channel: 1,
+                    value: 6
+                },
+                Command::Ldac,
+                Command::Gpio { pin: 0, on: true },
+            ]
+        );
+        current.dac[1] = 6;
+        current.gpio[0] = true;
+        assert!(state.commands(Some(&current)).is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+//! Device state reconstructed from the command stream.
+//!
+//! Used by the simulator to track what the hardware would be doing. Direct DAC
+//! writes and `UseTable` (which loads entry `offset` of each attached table into
+//! its channel) fill the DAC input registers. The outputs follow immediately,
+//! unless `hold_until_ldac` is set, in which case they only change on LDAC.
+
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS, TABLES, TABLE_LEN};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+pub struct DeviceState {
+    /// Current DAC output codes
+    pub dac: [u16; DAC_CHANNELS],
+    /// DAC input registers, transferred to `dac` on LDAC
+    pub loaded: [u16; DAC_CHANNELS],
+    /// Model the LDAC pin: outputs only change on an LDAC command
+    pub hold_until_ldac: bool,
+    /// Table attached to each channel
+    pub attached: [Option<u8>; DAC_CHANNELS],
+    pub tables: [[u16; TABLE_LEN]; TABLES],
+    /// Last offset selected with `UseTable`
+    pub table_offset: u8,
+    pub gpio: [bool; GPIO_PINS],
+    pub registers: BTreeMap<u8, u16>,
+    pub keepalives: u64,
+    pub ldac_count: u64,
+    /// Number of commands applied
+    pub commands: u64,
+}
+
+impl Default for DeviceState {
+    fn default() -> Self {
+        Self {
+            dac: [0; DAC_CHANNELS],
+            loaded: [0; DAC_CHANNELS],
+            hold_until_ldac: false,
+            attached: [None; DAC_CHANNELS],
+            tables: [[0; TABLE_LEN]; TABLES],
+            table_offset: 0,
+            gpio: [false; GPIO_PINS],
+            registers: BTreeMap::new(),
+            keepalives: 0,
+            ldac_count: 0,
+            commands: 0,
+        }
+    }
+}
+
+impl DeviceState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the state as the device would on receiving `cmd`
+    pub fn apply(&mut self, cmd: &Command) {
+        self.commands += 1;
+
+        match *cmd {
+            Command::DacWrite { channel, value } => {
+                self.loaded[channel as usize] = value;
+            }
+            Command::AttachTable { channel, table } => {
+                self.attached[channel as usize] = Some(table);
+            }
+            Command::TableWrite {
+                table,
+                index,
+                value,
+            } => {
+                self.tables[table as usize][index as usize] = value;
+            }
+            Command::UseTable { offset } => {
+                self.table_offset = offset;
+                for (value, table) in self.loaded.iter_mut().zip(self.attached) {
+                    if let Some(table) = table {
+                        *value = self.tables[table as usize][offset as usize];
+                    }
+                }
+            }
+            Command::Gpio { pin, on } => {
+                if let Some(state) = self.gpio.get_mut(pin as usize) {
+                    *state = on;
+                }
+            }
+            Command::KeepAlive => self.keepalives += 1,
+            Command::Ldac => {
+                self.ldac_count += 1;
+                self.dac = self.loaded;
+            }
+            Command::RegisterWrite { reg, value } => {
+                self.registers.insert(reg, value);
+            }
+            // Only meaningful to a bridge
+            Command::Takeover | Command::Heartbeat => {}
+            // Change nothing; the answer is in the response
+            Command::RegisterRead { .. } | Command::Hello { .. } | Command::TableRead { .. } => {}
+        }
+
+        if !self.hold_until_ldac {
+            self.dac = self.loaded;
+        }
+    }
+
+    /// Commands that bring a freshly connected device to this state
+    ///
+    /// Non-zero table entries, attachments and the table offset come first,
+    /// then the outputs (latched with LDAC), values loaded but not latched yet,
+    /// the GPIOs and the registers.
+    pub fn restore_commands(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for (table, entries) in self.tables.iter().enumerate() {
+            for (index, &value) in entries.iter().enumerate() {
+                if value != 0 {
+                    commands.push(Command::TableWrite {
+                        table: table as u8,
+                        index: index as u8,
+                        value,
+                    });
+                }
+            }
+        }
+        for (channel, table) in self.attached.iter().enumerate() {
+            if let Some(table) = *table {
+                commands.push(Command::AttachTable {
+                    channel: channel as u8,
+                    table,
+                });
+            }
+        }
+        commands.push(Command::UseTable {
+            offset: self.table_offset,
+        });
+        for (channel, &value) in self.dac.iter().enumerate() {
+            commands.push(Command::DacWrite {
+                channel: channel as u8,
+                value,
+            });
+        }
+        commands.push(Command::Ldac);
+        for (channel, (&loaded, &value)) in self.loaded.iter().zip(&self.dac).enumerate() {
+            if loaded != value {
+                commands.push(Command::DacWrite {
+                    channel: channel as u8,
+                    value: loaded,
+                });
+            }
+        }
+        for (pin, &on) in self.gpio.iter().enumerate() {
+            commands.push(Command::Gpio { pin: pin as u8, on });
+        }
+        for (&reg, &value) in &self.registers {
+            commands.push(Command::RegisterWrite { reg, value });
+        }
+        commands
+    }
+}
@@ -0,0 +1,261 @@
+//! Likely causes of failed connections, and what to do about them.
+//!
+//! [`advise`] looks through an error and its sources for the I/O, serial port or
+//! [`DacError`] behind it and names the usual cause: a missing or busy
+//! serial port, a missing `dialout` group, no bridge listening, a firewall,
+//! a wrong baud rate. Every binary returns its errors as a [`Failure`], which
+//! prints the advice after the error:
+//!
+//! ```text
+//! Error: Failed to connect to /dev/ttyACM0
+//!
+//! Caused by:
+//!     Transport error: Permission denied
+//!
+//! Hint: no permission to open the serial port
+//!   - Add yourself to the group owning it: sudo usermod -aG dialout $USER
+//!   - Log out and back in (or run `newgrp dialout`) for the group to apply
+//! ```
+
+use crate::discovery;
+use crate::error::DacError;
+use crate::target::DEFAULT_BAUD;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a link failed, as far as the error tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// The serial device node does not exist
+    NoDevice,
+    /// The serial port may not be opened by this user
+    PermissionDenied,
+    /// Another program has the serial port open
+    PortBusy,
+    /// Nothing listens on the address, or a firewall rejects the connection
+    Refused,
+    /// No route to the host
+    Unreachable,
+    /// The host name does not resolve
+    UnknownHost,
+    /// The listening address is taken by another program
+    AddressInUse,
+    /// The peer closed or reset the connection
+    Disconnected,
+    /// The link is open but nothing answers
+    NoResponse,
+    /// Answers arrive but do not decode
+    Garbled,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cause::NoDevice => "the serial device does not exist",
+            Cause::PermissionDenied => "no permission to open the serial port",
+            Cause::PortBusy => "the serial port is in use by another program",
+            Cause::Refused => "the connection was refused",
+            Cause::Unreachable => "the host cannot be reached",
+            Cause::UnknownHost => "the host name does not resolve",
+            Cause::AddressInUse => "the address is already in use",
+            Cause::Disconnected => "the other side closed the connection",
+            Cause::NoResponse => "the device does not answer",
+            Cause::Garbled => "the device's answers do not decode",
+        })
+    }
+}
+
+/// A likely cause and the steps that usually fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advice {
+    pub cause: Cause,
+    pub hints: Vec<String>,
+}
+
+impl fmt::Display for Advice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hint: {}", self.cause)?;
+        for hint in &self.hints {
+            write!(f, "\n  - {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// `EBUSY` as the serialport crate reports it: by its description only
+const BUSY: [&str; 2] = ["Device or resource busy", "Resource busy"];
+
+/// Name resolution failures, which the standard library reports as uncategorized
+const LOOKUP_FAILED: [&str; 3] = [
+    "failed to lookup address",
+    "Name or service not known",
+    "No such host is known",
+];
+
+fn classify_io(error: &io::Error) -> Option<Cause> {
+    let text = error.to_string();
+    if LOOKUP_FAILED.iter().any(|s| text.contains(s)) {
+        return Some(Cause::UnknownHost);
+    }
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::EBUSY) {
+        return Some(Cause::PortBusy);
+    }
+    if BUSY.contains(&text.as_str()) {
+        return Some(Cause::PortBusy);
+    }
+    Some(match error.kind() {
+        io::ErrorKind::NotFound => Cause::NoDevice,
+        // Windows denies access to a port another program has open
+        io::ErrorKind::PermissionDenied if cfg!(windows) => Cause::PortBusy,
+        io::ErrorKind::PermissionDenied => Cause::PermissionDenied,
+        io::ErrorKind::ConnectionRefused => Cause::Refused,
+        io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => Cause::Unreachable,
+        io::ErrorKind::AddrInUse => Cause::AddressInUse,
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => Cause::Disconnected,
+        io::ErrorKind::TimedOut => Cause::NoResponse,
+        _ => return None,
+    })
+}
+
+fn classify(error: &(dyn Error + 'static)) -> Option<Cause> {
+    if let Some(error) = error.downcast_ref::<DacError>() {
+        return match error {
+            DacError::Timeout => Some(Cause::NoResponse),
+            DacError::Protocol(_) => Some(Cause::Garbled),
+            DacError::InvalidArgument(message) if message.starts_with("Could not resolve") => {
+                Some(Cause::UnknownHost)
+            }
+            DacError::Transport(e) => classify_io(e),
+            _ => None,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<serialport::Error>() {
+        return classify_io(&io::Error::from(error.clone()));
+    }
+    error.downcast_ref::<io::Error>().and_then(classify_io)
+}
+
+/// Serial ports present now, for a target that names a missing one
+fn present_ports() -> String {
+    let ports: Vec<String> = discovery::serial_ports()
+        .into_iter()
+        .map(|port| format!("{} ({})", port.target, port.description))
+        .collect();
+    if ports.is_empty() {
+        "No serial ports are present".to_string()
+    } else {
+        format!("Serial ports present: {}", ports.join(", "))
+    }
+}
+
+fn hints(cause: Cause) -> Vec<String> {
+    let baud = format!(
+        "On a serial port, check the baud rate: the device runs at {} (serial:PATH?baud=N)",
+        DEFAULT_BAUD
+    );
+    match cause {
+        Cause::NoDevice => vec![
+            "Check that the device is plugged in and powered, and the cable carries data"
+                .to_string(),
+            present_ports(),
+        ],
+        Cause::PermissionDenied if cfg!(target_os = "linux") => vec![
+            "Add yourself to the group owning it: sudo usermod -aG dialout $USER \
+             (uucp on Arch)"
+                .to_string(),
+            "Log out and back in (or run `newgrp dialout`) for the group to apply".to_string(),
+        ],
+        Cause::PermissionDenied => {
+            vec!["Check the port's owner and mode with `ls -l`, or run as its owner".to_string()]
+        }
+        Cause::PortBusy => vec![
+            "Close the other program: another tool of this kit, a terminal (screen, minicom) \
+             or ModemManager"
+                .to_string(),
+            if cfg!(windows) {
+                "Several clients can share the device through tcp_server".to_string()
+            } else {
+                "Find it with `fuser -v PORT` or `lsof PORT`; several clients can share the \
+                 device through tcp_server"
+                    .to_string()
+            },
+        ],
+        Cause::Refused => vec![
+            "Check that tcp_server (or the simulator) runs on that host and port".to_string(),
+            "A firewall on the host may reject the port; open it (e.g. ufw allow 2012/tcp)"
+                .to_string(),
+        ],
+        Cause::Unreachable => vec![
+            "Check the host's address and that it is up and on your network or VPN".to_string(),
+        ],
+        Cause::UnknownHost => vec![
+            "Check the spelling, or use the IP address; .local names need mDNS on this host"
+                .to_string(),
+        ],
+        Cause::AddressInUse => vec![
+            "Another bridge or server may already listen there; stop it or pick another port"
+                .to_string(),
+        ],
+        Cause::Disconnected => vec![
+            "The bridge may have stopped, lost its device or dropped a silent client \
+             (--client-timeout; send heartbeats)"
+                .to_string(),
+            "A USB adapter that was unplugged or slept needs reopening".to_string(),
+        ],
+        Cause::NoResponse => vec![
+            "Check that the device is powered and runs its firmware".to_string(),
+            baud,
+            "Through a bridge, check that the bridge reaches its device (its log)".to_string(),
+        ],
+        Cause::Garbled => vec![
+            baud,
+            "Both sides need the same --crc and --framing settings".to_string(),
+        ],
+    }
+}
+
+/// The likely cause of `error` with what to try, if anything in its chain is known
+pub fn advise(error: &(dyn Error + 'static)) -> Option<Advice> {
+    let cause = std::iter::successors(Some(error), |&e| e.source()).find_map(classify)?;
+    Some(Advice {
+        cause,
+        hints: hints(cause),
+    })
+}
+
+/// The error a binary's `main` returns: printed with its sources, like
+/// `anyhow`, and followed by [`advise`]'s hints
+pub struct Failure(Box<dyn Error + Send + Sync>);
+
+impl<E: Into<Box<dyn Error + Send + Sync>>> From<E> for Failure {
+    fn from(error: E) -> Self {
+        Failure(error.into())
+    }
+}
+
+impl fmt::Debug for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let causes: Vec<&dyn Error> =
+            std::iter::successors(self.0.source(), |&e| e.source()).collect();
+        if !causes.is_empty() {
+            write!(f, "\n\nCaused by:")?;
+            for (i, cause) in causes.iter().enumerate() {
+                if causes.len() == 1 {
+                    write!(f, "\n    {}", cause)?;
+                } else {
+                    write!(f, "\n    {}: {}", i, cause)?;
+                }
+            }
+        }
+        if let Some(advice) = advise(&*self.0) {
+            write!(f, "\n\n{}", advice)?;
+        }
+        Ok(())
+    }
+}
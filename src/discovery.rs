@@ -0,0 +1,310 @@
+//! Finding devices to connect to.
+//!
+//! [`discover`] lists the serial ports the OS reports and, with the `mdns`
+//! feature, the bridges advertising [`SERVICE_TYPE`] on the local network
+//! (`tcp_server --mdns`). [`pick_target`] shows that list on the terminal and
+//! lets the user choose one, by number or part of its description, or type any
+//! other target.
+//!
+//! On Windows a serial target may also name its port by part of the friendly
+//! name or USB description, e.g. `serial:FT232R`; [`resolve_serial`] finds
+//! the `COMn` port it means.
+
+use crate::error::{DacError, Result};
+use crate::target::{Target, DEFAULT_BAUD};
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+/// mDNS service type advertised by `tcp_server --mdns`
+pub const SERVICE_TYPE: &str = "_csv1-ol8._tcp.local.";
+
+/// How long [`discover`] listens for mDNS answers by default
+pub const DEFAULT_BROWSE_TIME: Duration = Duration::from_millis(1500);
+
+/// A target found by [`discover`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub target: Target,
+    pub description: String,
+}
+
+/// Key sorting port names with their numbers in numeric order, COM2 before COM10
+fn port_order(name: &str) -> (String, u64) {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (stem, number) = name.split_at(name.len() - digits);
+    (stem.to_ascii_lowercase(), number.parse().unwrap_or(0))
+}
+
+/// Serial ports reported by the OS, with USB product names (the friendly
+/// names on Windows) and serial numbers where known
+pub fn serial_ports() -> Vec<Candidate> {
+    let Ok(mut ports) = serialport::available_ports() else {
+        return Vec::new();
+    };
+    ports.sort_by_key(|port| port_order(&port.port_name));
+
+    ports
+        .into_iter()
+        .map(|port| {
+            let description = match port.port_type {
+                serialport::SerialPortType::UsbPort(usb) => {
+                    // Windows friendly names end in the port, "USB Serial Device (COM5)"
+                    let product = usb.product.map(|product| {
+                        product
+                            .strip_suffix(&format!(" ({})", port.port_name))
+                            .unwrap_or(&product)
+                            .to_string()
+                    });
+                    let serial_number = usb.serial_number.map(|sn| format!("SN {}", sn));
+                    let name = [usb.manufacturer, product, serial_number]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    format!("USB {:04x}:{:04x} {}", usb.vid, usb.pid, name)
+                        .trim_end()
+                        .to_string()
+                }
+                serialport::SerialPortType::BluetoothPort => "Bluetooth serial".to_string(),
+                serialport::SerialPortType::PciPort => "PCI serial".to_string(),
+                serialport::SerialPortType::Unknown => "serial".to_string(),
+            };
+            Candidate {
+                target: Target::Serial {
+                    path: port.port_name,
+                    baud: DEFAULT_BAUD,
+                },
+                description,
+            }
+        })
+        .collect()
+}
+
+/// Candidates whose target or description contains `query`, ignoring case
+pub fn matching<'a>(query: &str, candidates: &'a [Candidate]) -> Vec<&'a Candidate> {
+    let query = query.to_lowercase();
+    candidates
+        .iter()
+        .filter(|c| {
+            c.target.to_string().to_lowercase().contains(&query)
+                || c.description.to_lowercase().contains(&query)
+        })
+        .collect()
+}
+
+/// The port a serial target's path names
+///
+/// On Windows a path that is not a `COMn` port name is looked up in the
+/// [`serial_ports`] and must match exactly one of them, so `FT232R` or
+/// `0403:6001` can stand for `COM7`. Elsewhere paths are used as given.
+pub fn resolve_serial(path: &str) -> Result<String> {
+    #[cfg(windows)]
+    if !is_port_name(path) {
+        return find_port(path);
+    }
+    Ok(path.to_string())
+}
+
+/// Whether `path` is a Windows port name, `COM5`, or a device path like `\\.\COM12`
+#[cfg(windows)]
+fn is_port_name(path: &str) -> bool {
+    if path.starts_with(r"\\.\") {
+        return true;
+    }
+    match (path.get(..3), path.get(3..)) {
+        (Some(prefix), Some(number)) => {
+            prefix.eq_ignore_ascii_case("com")
+                && !number.is_empty()
+                && number.chars().all(|c| c.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+/// The one port whose name or description contains `query`
+#[cfg(windows)]
+fn find_port(query: &str) -> Result<String> {
+    let ports = serial_ports();
+    let list = |candidates: &[&Candidate]| {
+        candidates
+            .iter()
+            .map(|c| format!("{} ({})", c.target, c.description))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match matching(query, &ports)[..] {
+        [Candidate {
+            target: Target::Serial { path, .. },
+            ..
+        }] => Ok(path.clone()),
+        [] => Err(DacError::InvalidArgument(format!(
+            "No serial port matches '{}' (found: {})",
+            query,
+            list(&ports.iter().collect::<Vec<_>>())
+        ))),
+        ref several => Err(DacError::InvalidArgument(format!(
+            "'{}' matches several serial ports: {}",
+            query,
+            list(several)
+        ))),
+    }
+}
+
+/// Bridges answering an mDNS query for [`SERVICE_TYPE`] within `wait`
+#[cfg(feature = "mdns")]
+pub fn bridges(wait: Duration) -> Result<Vec<Candidate>> {
+    use mdns_sd::{ServiceDaemon, ServiceEvent};
+    use std::time::Instant;
+
+    let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+    let events = daemon.browse(SERVICE_TYPE).map_err(mdns_error)?;
+
+    let deadline = Instant::now() + wait;
+    let mut found: Vec<(String, Candidate)> = Vec::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        // Prefer IPv4: link-local IPv6 addresses need a scope to be usable
+        let Some(ip) = info
+            .get_addresses()
+            .iter()
+            .min_by_key(|ip| ip.is_ipv6())
+            .copied()
+        else {
+            continue;
+        };
+        let name = info
+            .get_fullname()
+            .strip_suffix(SERVICE_TYPE)
+            .unwrap_or(info.get_fullname())
+            .trim_end_matches('.');
+        let device = info.get_property_val_str("device").unwrap_or("?");
+        let candidate = Candidate {
+            target: Target::Tcp {
+                address: std::net::SocketAddr::new(ip, info.get_port()).to_string(),
+            },
+            description: format!("bridge '{}' for {}", name, device),
+        };
+        // Later resolutions of the same instance may add addresses
+        match found.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = candidate,
+            None => found.push((name.to_string(), candidate)),
+        }
+    }
+
+    let _ = daemon.shutdown();
+    Ok(found.into_iter().map(|(_, candidate)| candidate).collect())
+}
+
+/// Keeps a bridge advertised over mDNS until dropped
+#[cfg(feature = "mdns")]
+pub struct Advertisement {
+    daemon: mdns_sd::ServiceDaemon,
+}
+
+#[cfg(feature = "mdns")]
+impl Advertisement {
+    /// Advertise a bridge for `device` listening on `port` as `name`
+    pub fn new(name: &str, port: u16, device: &str) -> Result<Self> {
+        use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+        let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+        let host = format!("{}.local.", name);
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            name,
+            &host,
+            "",
+            port,
+            &[("device", device)][..],
+        )
+        .map_err(mdns_error)?
+        .enable_addr_auto();
+        daemon.register(info).map_err(mdns_error)?;
+        Ok(Self { daemon })
+    }
+}
+
+#[cfg(feature = "mdns")]
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[cfg(feature = "mdns")]
+fn mdns_error(e: mdns_sd::Error) -> DacError {
+    DacError::Transport(io::Error::other(format!("mDNS: {}", e)))
+}
+
+/// Serial ports plus, with the `mdns` feature, bridges found within `wait`
+pub fn discover(wait: Duration) -> Vec<Candidate> {
+    let mut found = serial_ports();
+    found.extend(discovered_bridges(wait));
+    found
+}
+
+#[cfg(feature = "mdns")]
+fn discovered_bridges(wait: Duration) -> Vec<Candidate> {
+    bridges(wait).unwrap_or_else(|e| {
+        eprintln!("Bridge discovery failed: {}", e);
+        Vec::new()
+    })
+}
+
+#[cfg(not(feature = "mdns"))]
+fn discovered_bridges(_wait: Duration) -> Vec<Candidate> {
+    Vec::new()
+}
+
+/// Let the user choose a discovered target or type one on the terminal
+pub fn pick_target(wait: Duration) -> Result<Target> {
+    eprintln!("No target given, looking for devices...");
+    let candidates = discover(wait);
+
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        if candidates.is_empty() {
+            eprintln!("No serial ports or bridges found.");
+        } else {
+            eprintln!();
+            for (i, c) in candidates.iter().enumerate() {
+                eprintln!(
+                    "  {:>2}) {:<32} {}",
+                    i + 1,
+                    c.target.to_string(),
+                    c.description
+                );
+            }
+            eprintln!();
+        }
+        eprint!("Choose a number or name, or enter a target (empty to quit): ");
+        io::stderr().flush()?;
+
+        line.clear();
+        let choice = match stdin.lock().read_line(&mut line)? {
+            0 => "",
+            _ => line.trim(),
+        };
+        if choice.is_empty() {
+            return Err(DacError::InvalidArgument("no target chosen".to_string()));
+        }
+        if let Ok(n) = choice.parse::<usize>() {
+            match candidates.get(n.wrapping_sub(1)) {
+                Some(c) => return Ok(c.target.clone()),
+                None => eprintln!("No entry {}", n),
+            }
+            continue;
+        }
+        // Part of a single entry, e.g. its USB product name
+        if let [c] = matching(choice, &candidates)[..] {
+            return Ok(c.target.clone());
+        }
+        match choice.parse() {
+            Ok(target) => return Ok(target),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Pre-flight checks of a target, for `csv1 doctor`.
+//!
+//! [`examine`] opens a raw link to the target and tries what real use will
+//! need, one check after the other:
+//!
+//! | Check     | What it tries                                                   |
+//! |-----------|-----------------------------------------------------------------|
+//! | connect   | Open the link, timed                                            |
+//! | keepalive | A keepalive is acknowledged with `00 00`                        |
+//! | latency   | Round trips of a series of keepalives                           |
+//! | heartbeat | A bridge answers heartbeats itself; a device acknowledges them  |
+//! | padding   | A command sent in two parts is held until complete, not padded  |
+//! | hello     | Protocol version and features the device agrees to              |
+//! | extended  | A register read comes back as an extended response (readback)   |
+//!
+//! The checks stop at the first failure to connect or to get any answer; a
+//! failed check carries [`diagnose::advise`]'s hints.
+//!
+//! The padding check sends a register read of register 253 in two parts,
+//! `F8` and `FD 00 00`. Held until complete it is one read; zero-filled it
+//! becomes a read of register 0 and a keepalive, so neither way writes
+//! anything.
+
+use crate::client::DacClient;
+use crate::diagnose::{self, Advice};
+use crate::error::{DacError, Result};
+use crate::framing::{self, Codec};
+use crate::protocol::{self, Command, Features, CMD_KEEPALIVE, CMD_REGISTER_READ};
+use crate::report::Value;
+use crate::stats::LatencyStats;
+use crate::target::Target;
+use crate::transport::{self, Link, LinkOptions};
+use crate::version;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Keepalives the latency check sends by default
+pub const DEFAULT_PROBES: usize = 20;
+
+/// Round trip above which the latency check warns
+const SLOW: Duration = Duration::from_millis(50);
+
+/// Register the padding check reads; its number doubles as a keepalive
+const PADDING_REGISTER: u8 = CMD_KEEPALIVE;
+
+/// Outcome of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// Works, but not the way real use may expect
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Verdict::Pass => "ok",
+            Verdict::Warn => "warn",
+            Verdict::Fail => "fail",
+        })
+    }
+}
+
+/// One line of the report
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub verdict: Verdict,
+    pub detail: String,
+    pub advice: Option<Advice>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            verdict: Verdict::Pass,
+            detail: detail.into(),
+            advice: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            verdict: Verdict::Warn,
+            ..Self::pass(name, detail)
+        }
+    }
+
+    fn fail(name: &'static str, error: &DacError) -> Self {
+        Self {
+            verdict: Verdict::Fail,
+            advice: diagnose::advise(error),
+            ..Self::pass(name, error.to_string())
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        Value::object()
+            .with("check", self.name)
+            .with("verdict", self.verdict.to_string())
+            .with("detail", self.detail.as_str())
+            .with(
+                "hints",
+                self.advice
+                    .as_ref()
+                    .map(|advice| advice.hints.clone())
+                    .unwrap_or_default(),
+            )
+    }
+}
+
+/// The checks run against a target, in order
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub target: String,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn count(&self, verdict: Verdict) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.verdict == verdict)
+            .count()
+    }
+
+    /// Whether no check failed; warnings pass
+    pub fn passed(&self) -> bool {
+        self.count(Verdict::Fail) == 0
+    }
+
+    pub fn to_value(&self) -> Value {
+        Value::object()
+            .with("target", self.target.as_str())
+            .with("passed", self.passed())
+            .with(
+                "checks",
+                self.checks.iter().map(Check::to_value).collect::<Vec<_>>(),
+            )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Checking {}", self.target)?;
+        for check in &self.checks {
+            writeln!(
+                f,
+                "  [{:^4}] {:<10} {}",
+                check.verdict, check.name, check.detail
+            )?;
+            if let Some(advice) = &check.advice {
+                for line in advice.to_string().lines() {
+                    writeln!(f, "         {}", line)?;
+                }
+            }
+        }
+        let (passed, warnings, failed) = (
+            self.count(Verdict::Pass),
+            self.count(Verdict::Warn),
+            self.count(Verdict::Fail),
+        );
+        if failed == 0 {
+            write!(
+                f,
+                "Ready: {} checks passed, {} warning(s)",
+                passed, warnings
+            )
+        } else {
+            write!(
+                f,
+                "Not ready: {} check(s) failed, {} passed, {} warning(s)",
+                failed, passed, warnings
+            )
+        }
+    }
+}
+
+/// Whether `error` is a read running into the link's timeout
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// One raw response, or `None` if nothing arrives within the read timeout
+fn read_response(link: &mut dyn Link) -> Result<Option<Vec<u8>>> {
+    let mut response = vec![0u8; 2];
+    match link.read_exact(&mut response) {
+        Ok(()) => {}
+        Err(e) if is_timeout(&e) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    response.resize(framing::response_len(response[0], response[1]), 0);
+    link.read_exact(&mut response[2..])?;
+    Ok(Some(response))
+}
+
+/// Send `frame` and wait for one response, timing the round trip
+fn exchange(link: &mut dyn Link, frame: &[u8]) -> Result<(Option<Vec<u8>>, Duration)> {
+    let start = Instant::now();
+    link.write_all(frame)?;
+    link.flush()?;
+    let response = read_response(link)?;
+    Ok((response, start.elapsed()))
+}
+
+/// Read and drop whatever still arrives, so the next check starts clean
+fn drain(link: &mut dyn Link) -> Result<usize> {
+    let mut count = 0;
+    while read_response(link)?.is_some() {
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn keepalive(link: &mut dyn Link) -> Result<Check> {
+    let name = "keepalive";
+    Ok(match exchange(link, &Command::KeepAlive.encode())? {
+        (Some(response), _) if response == [0x00, 0x00] => {
+            Check::pass(name, "acknowledged with 00 00")
+        }
+        (Some(response), _) => Check::fail(
+            name,
+            &DacError::Protocol(format!("Unexpected answer {}", hex(&response))),
+        ),
+        (None, _) => Check::fail(name, &DacError::Timeout),
+    })
+}
+
+fn latency(link: &mut dyn Link, probes: usize) -> Result<Check> {
+    let name = "latency";
+    let mut stats = LatencyStats::default();
+    let mut lost = 0;
+    for _ in 0..probes {
+        match exchange(link, &Command::KeepAlive.encode())? {
+            (Some(_), elapsed) => stats.record(elapsed),
+            (None, _) => lost += 1,
+        }
+    }
+    let (Some(min), Some(mean), Some(max)) = (stats.min(), stats.mean(), stats.max()) else {
+        return Ok(Check::fail(name, &DacError::Timeout));
+    };
+    let detail = format!(
+        "{} keepalives: min {:.2} ms, mean {:.2} ms, max {:.2} ms",
+        stats.count(),
+        min.as_secs_f64() * 1000.0,
+        mean.as_secs_f64() * 1000.0,
+        max.as_secs_f64() * 1000.0
+    );
+    let check = if lost > 0 {
+        Check::warn(name, format!("{}; {} unanswered", detail, lost))
+    } else if max > SLOW {
+        Check::warn(name, format!("{}; slow for closed loops", detail))
+    } else {
+        Check::pass(name, detail)
+    };
+    // Late answers to lost probes must not pass for the next check's
+    drain(link)?;
+    Ok(check)
+}
+
+fn heartbeat(link: &mut dyn Link) -> Result<Check> {
+    let name = "heartbeat";
+    let check = match exchange(link, &Command::Heartbeat.encode())? {
+        (Some(response), _) if response == protocol::HEARTBEAT_RESPONSE => {
+            Check::pass(name, "answered by a bridge (tcp_server)")
+        }
+        (Some(response), _) if response == [0x00, 0x00] => {
+            Check::pass(name, "acknowledged by the device; no bridge in between")
+        }
+        (Some(response), _) => Check::warn(
+            name,
+            format!(
+                "answered {}; heartbeats may drop the client",
+                hex(&response)
+            ),
+        ),
+        (None, _) => Check::warn(name, "not answered; the device ignores heartbeats"),
+    };
+    drain(link)?;
+    Ok(check)
+}
+
+fn padding(link: &mut dyn Link) -> Result<Check> {
+    let name = "padding";
+    let (early, _) = exchange(link, &[CMD_REGISTER_READ])?;
+    let (late, _) = exchange(link, &[PADDING_REGISTER, 0x00, 0x00])?;
+    let extra = drain(link)?;
+    Ok(match (early, late) {
+        (None, Some(_)) if extra == 0 => {
+            Check::pass(name, "partial commands are held until complete")
+        }
+        (Some(_), _) => Check::warn(
+            name,
+            "partial commands are zero-filled at once (a bridge with --padding zero); \
+             send whole commands",
+        ),
+        (None, None) => Check::warn(
+            name,
+            "a command sent in two parts was dropped; send each command in one write",
+        ),
+        (None, Some(_)) => Check::warn(
+            name,
+            format!("a command sent in two parts got {} answers", extra + 1),
+        ),
+    })
+}
+
+/// Hello and extended responses, through a client on the same link
+fn hello(client: &mut DacClient, checks: &mut Vec<Check>) {
+    let hello = match client.negotiate(Features::ALL) {
+        Ok(hello) => hello,
+        Err(e) => {
+            checks.push(Check::fail("hello", &e));
+            return;
+        }
+    };
+    checks.push(if hello.version == 0 {
+        Check::warn("hello", version::device_text(&hello))
+    } else {
+        Check::pass("hello", version::device_text(&hello))
+    });
+
+    let name = "extended";
+    if !hello.features.contains(Features::EXTENDED) {
+        checks.push(Check::warn(
+            name,
+            "not supported; register reads are unavailable",
+        ));
+        return;
+    }
+    if !hello.features.contains(Features::READBACK) {
+        checks.push(Check::pass(
+            name,
+            "agreed; the firmware has no register reads to try them with",
+        ));
+        return;
+    }
+    checks.push(match client.read_register(0) {
+        Ok(value) => Check::pass(
+            name,
+            format!("register reads work (REG0 = 0x{:04X})", value),
+        ),
+        Err(e) => Check::fail(name, &e),
+    });
+}
+
+/// Run the checks against `target`, sending `probes` keepalives for the latency
+pub fn examine(target: &Target, options: &LinkOptions, probes: usize) -> Report {
+    let mut checks = Vec::new();
+    let start = Instant::now();
+    match transport::open_target(target, options) {
+        Ok(mut link) => {
+            checks.push(Check::pass(
+                "connect",
+                format!(
+                    "{} link open in {:.1} ms",
+                    link.kind(),
+                    start.elapsed().as_secs_f64() * 1000.0
+                ),
+            ));
+            if let Err(e) = raw_checks(&mut *link, probes, &mut checks) {
+                checks.push(Check::fail("link", &e));
+            } else if checks.iter().all(|check| check.verdict != Verdict::Fail) {
+                hello(
+                    &mut DacClient::from_link(link, Codec::default()),
+                    &mut checks,
+                );
+            }
+        }
+        Err(e) => checks.push(Check::fail("connect", &e)),
+    }
+    Report {
+        target: target.to_string(),
+        checks,
+    }
+}
+
+/// The checks on the raw link, stopping once nothing answers
+fn raw_checks(link: &mut dyn Link, probes: usize, checks: &mut Vec<Check>) -> Result<()> {
+    checks.push(keepalive(link)?);
+    if checks
+        .last()
+        .is_some_and(|check| check.verdict == Verdict::Fail)
+    {
+        return Ok(());
+    }
+    if probes > 0 {
+        checks.push(latency(link, probes)?);
+    }
+    checks.push(heartbeat(link)?);
+    checks.push(padding(link)?);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! Error type returned by the library.
+//!
+//! Binaries wrap these in `anyhow` at the edges; programmatic users can match
+//! on the variant to tell a dead link from a device that refused a command.
+
+use std::io;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, DacError>;
+
+#[derive(Debug, Error)]
+pub enum DacError {
+    /// The serial port, socket or file failed
+    #[error("Transport error: {0}")]
+    Transport(#[source] io::Error),
+
+    /// The device did not answer in time
+    #[error("Timed out waiting for the device")]
+    Timeout,
+
+    /// Bytes arrived but could not be decoded (bad CRC, framing or length)
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+
+    /// The device answered with a non-zero status code
+    #[error("Device returned status 0x{0:02X}")]
+    DeviceStatus(u8),
+
+    /// A caller-supplied value is out of range or unsupported
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// The device did not agree to the protocol feature needed, in its hello
+    #[error("Not supported by the device: {0}")]
+    Unsupported(String),
+
+    /// A DAC write was refused for leaving the channel's soft limits
+    #[error("DAC{channel} value {value} is outside its limits {min}-{max}")]
+    OutOfLimits {
+        channel: u8,
+        value: u16,
+        min: u16,
+        max: u16,
+    },
+
+    /// One device of a [`DeviceGroup`](crate::group::DeviceGroup) failed
+    #[error("Device {device}: {source}")]
+    Group {
+        device: usize,
+        #[source]
+        source: Box<DacError>,
+    },
+
+    /// A user script failed to load or raised an error
+    #[error("Script error: {0}")]
+    Script(String),
+
+    /// The operation was stopped through its cancellation token
+    #[error("Operation cancelled")]
+    Cancelled,
+}
+
+impl From<io::Error> for DacError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => DacError::Timeout,
+            _ => DacError::Transport(e),
+        }
+    }
+}
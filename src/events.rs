@@ -0,0 +1,68 @@
+//! JSON-lines output of the command-line tools.
+//!
+//! With `--json`, `unified_test` and `tcp_robust_test` print one JSON object
+//! per line on stdout instead of their text output, warnings included, so
+//! wrappers can follow a run without scraping it:
+//!
+//! ```text
+//! {"time":"2026-10-17T09:12:44Z","event":"connected","target":"tcp:127.0.0.1:2012","transport":"TCP"}
+//! {"time":"2026-10-17T09:12:45Z","event":"command","command":[3,0,5,254],"response":[0,0],"latency_ms":1.21}
+//! {"time":"2026-10-17T09:12:51Z","event":"finished","passed":true}
+//! ```
+//!
+//! Every line has `time` and `event`; the other fields depend on the event.
+//! Byte strings are arrays of numbers and durations are in milliseconds, as in
+//! reports.
+
+use crate::report::{self, Value};
+use std::fmt;
+use std::time::SystemTime;
+
+/// Where a tool's progress goes: text for people, or JSON lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventLog {
+    json: bool,
+}
+
+impl EventLog {
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+
+    pub fn is_json(self) -> bool {
+        self.json
+    }
+
+    /// Print `text` on stdout, or `event` with `fields` as a JSON line
+    pub fn info(self, event: &str, fields: Value, text: impl fmt::Display) {
+        if self.json {
+            self.record(event, fields);
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    /// Print `text` on stderr, or `event` with `fields` as a JSON line on stdout
+    pub fn warn(self, event: &str, fields: Value, text: impl fmt::Display) {
+        if self.json {
+            self.record(event, fields);
+        } else {
+            eprintln!("{}", text);
+        }
+    }
+
+    /// Print `event` with `fields` as a JSON line; nothing in text mode
+    pub fn record(self, event: &str, fields: Value) {
+        if self.json {
+            println!("{}", line(event, fields));
+        }
+    }
+}
+
+/// The JSON line for `event`: its time and name, then the fields of `fields`
+pub fn line(event: &str, fields: Value) -> Value {
+    Value::object()
+        .with("time", report::utc_timestamp(SystemTime::now()))
+        .with("event", event)
+        .merge(fields)
+}
@@ -0,0 +1,295 @@
+//! Per-channel output formulas.
+//!
+//! A mapping such as `ch3 = 0.5*ch1 + 1000` derives one DAC channel from the
+//! others. Expressions support numbers, `ch0`..`ch7`, `+ - * /`, parentheses
+//! and `min(a, b)` / `max(a, b)`; results are rounded and clamped to 0..65535.
+
+use crate::error::{DacError, Result};
+use crate::protocol::DAC_CHANNELS;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Channel(u8),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+    Min(Box<Expr>, Box<Expr>),
+    Max(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, values: &[u16]) -> f64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Channel(ch) => values.get(*ch as usize).copied().unwrap_or(0) as f64,
+            Expr::Neg(e) => -e.eval(values),
+            Expr::Binary(a, op, b) => {
+                let (a, b) = (a.eval(values), b.eval(values));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    _ => a / b,
+                }
+            }
+            Expr::Min(a, b) => a.eval(values).min(b.eval(values)),
+            Expr::Max(a, b) => a.eval(values).max(b.eval(values)),
+        }
+    }
+
+    fn references(&self, channel: u8) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Channel(ch) => *ch == channel,
+            Expr::Neg(e) => e.references(channel),
+            Expr::Binary(a, _, b) | Expr::Min(a, b) | Expr::Max(a, b) => {
+                a.references(channel) || b.references(channel)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(char),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+            let n = text
+                .parse()
+                .map_err(|_| DacError::InvalidArgument(format!("Invalid number '{}'", text)))?;
+            tokens.push(Token::Number(n));
+        } else if c.is_ascii_alphabetic() {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                if !c.is_ascii_alphanumeric() {
+                    break;
+                }
+                text.push(c.to_ascii_lowercase());
+                chars.next();
+            }
+            tokens.push(Token::Ident(text));
+        } else if "+-*/(),=".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return Err(DacError::InvalidArgument(format!(
+                "Unexpected character '{}' in '{}'",
+                c, input
+            )));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_channel(name: &str) -> Option<u8> {
+    let ch: u8 = name.strip_prefix("ch")?.parse().ok()?;
+    (usize::from(ch) < DAC_CHANNELS).then_some(ch)
+}
+
+/// Recursive descent over `expr := term (('+'|'-') term)*`,
+/// `term := factor (('*'|'/') factor)*`, `factor := '-' factor | atom`
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<()> {
+        match self.next() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            other => Err(DacError::InvalidArgument(format!(
+                "Expected '{}', found {:?}",
+                symbol, other
+            ))),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Symbol(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Symbol(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Symbol('-')) => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Some(Token::Symbol('(')) => {
+                let e = self.expr()?;
+                self.expect(')')?;
+                Ok(e)
+            }
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) if name == "min" || name == "max" => {
+                self.expect('(')?;
+                let a = self.expr()?;
+                self.expect(',')?;
+                let b = self.expr()?;
+                self.expect(')')?;
+                Ok(if name == "min" {
+                    Expr::Min(Box::new(a), Box::new(b))
+                } else {
+                    Expr::Max(Box::new(a), Box::new(b))
+                })
+            }
+            Some(Token::Ident(name)) => parse_channel(&name).map(Expr::Channel).ok_or_else(|| {
+                DacError::InvalidArgument(format!("Unknown name '{}' (use ch0-ch7)", name))
+            }),
+            other => Err(DacError::InvalidArgument(format!(
+                "Unexpected {:?} in expression",
+                other
+            ))),
+        }
+    }
+}
+
+/// One `chN = <expr>` formula
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub target: u8,
+    expr: Expr,
+    source: String,
+}
+
+impl Mapping {
+    /// Evaluate against the current channel values, rounded and clamped
+    pub fn eval(&self, values: &[u16]) -> u16 {
+        let v = self.expr.eval(values);
+        if v.is_nan() {
+            0
+        } else {
+            v.round().clamp(0.0, 65535.0) as u16
+        }
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens = tokenize(s)?;
+        let target = match tokens.as_slice() {
+            [Token::Ident(name), Token::Symbol('='), ..] => parse_channel(name),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            DacError::InvalidArgument(format!("Expected 'chN = <expression>', got '{}'", s))
+        })?;
+
+        let mut parser = Parser { tokens, pos: 2 };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(DacError::InvalidArgument(format!(
+                "Unexpected {:?} after expression in '{}'",
+                token, s
+            )));
+        }
+        if expr.references(target) {
+            return Err(DacError::InvalidArgument(format!(
+                "ch{} cannot depend on itself",
+                target
+            )));
+        }
+
+        Ok(Mapping {
+            target,
+            expr,
+            source: s.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Formulas evaluated in order after every update
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelMappings {
+    mappings: Vec<Mapping>,
+}
+
+impl ChannelMappings {
+    pub fn new(mappings: Vec<Mapping>) -> Self {
+        Self { mappings }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.iter()
+    }
+
+    /// Whether `channel` is driven by a formula
+    pub fn is_mapped(&self, channel: u8) -> bool {
+        self.mappings.iter().any(|m| m.target == channel)
+    }
+
+    /// Evaluate every formula and return the channels whose value changes
+    ///
+    /// Later formulas see the results of earlier ones, so mappings can chain.
+    pub fn evaluate(&self, values: &[u16]) -> Vec<(u8, u16)> {
+        let mut current = values.to_vec();
+        let mut changes: Vec<(u8, u16)> = Vec::new();
+
+        for mapping in &self.mappings {
+            let value = mapping.eval(&current);
+            let Some(slot) = current.get_mut(mapping.target as usize) else {
+                continue;
+            };
+            if *slot != value {
+                *slot = value;
+                changes.retain(|&(ch, _)| ch != mapping.target);
+                changes.push((mapping.target, value));
+            }
+        }
+
+        changes
+    }
+}
@@ -0,0 +1,242 @@
+//! Measurements fed back into a control loop.
+//!
+//! A [`FeedbackSource`] reads one value each time it is asked, e.g. for the
+//! PID loop of [`control`](crate::control). Sources are opened from
+//! `scheme://rest` specs through a [`FeedbackRegistry`], which knows:
+//!
+//! | Spec                          | Value                                                |
+//! |-------------------------------|------------------------------------------------------|
+//! | `file://PATH`                 | First number in the file, re-read every time (sysfs) |
+//! | `tcp://HOST:PORT`             | Newest line of a stream of one value per line        |
+//! | `scpi://HOST:PORT[/QUERY]`    | Answer of an instrument to `QUERY` (default `READ?`) |
+//!
+//! Other sources are added in code with [`FeedbackRegistry::register`].
+
+use crate::error::{DacError, Result};
+use crate::transport::{split_scheme, LinkOptions};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+
+/// Query sent to an SCPI instrument when the spec names none
+pub const DEFAULT_SCPI_QUERY: &str = "READ?";
+
+/// Something that measures the quantity under control
+pub trait FeedbackSource: Send {
+    /// Take one reading
+    fn read(&mut self) -> Result<f64>;
+}
+
+/// Opens a source from the part of a spec after `scheme://`
+pub type FeedbackFactory = fn(&str, &LinkOptions) -> Result<Box<dyn FeedbackSource>>;
+
+/// Parse the first number of `text`; fields may be separated by whitespace or
+/// commas and carry a unit, as instruments answer `+1.2345E+00,V`
+pub fn parse_value(text: &str) -> Result<f64> {
+    let field = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .find(|field| !field.is_empty())
+        .ok_or_else(|| DacError::Protocol("Empty feedback reading".to_string()))?;
+    field
+        .parse()
+        .map_err(|_| DacError::Protocol(format!("Feedback reading '{}' is not a number", field)))
+}
+
+/// Spec schemes and the factories that open them
+#[derive(Clone, Default)]
+pub struct FeedbackRegistry {
+    schemes: Vec<(String, FeedbackFactory)>,
+}
+
+impl FeedbackRegistry {
+    /// A registry without any schemes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with `file://`, `tcp://` and `scpi://`
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("file", open_file);
+        registry.register("tcp", open_tcp);
+        registry.register("scpi", open_scpi);
+        registry
+    }
+
+    /// Add or replace the factory for `scheme` (matched case-insensitively)
+    pub fn register(&mut self, scheme: &str, factory: FeedbackFactory) {
+        let scheme = scheme.to_ascii_lowercase();
+        self.schemes.retain(|(s, _)| *s != scheme);
+        self.schemes.push((scheme, factory));
+    }
+
+    /// Registered scheme names, in registration order
+    pub fn schemes(&self) -> impl Iterator<Item = &str> {
+        self.schemes.iter().map(|(s, _)| s.as_str())
+    }
+
+    /// Open a `scheme://rest` spec
+    pub fn open(&self, spec: &str, options: &LinkOptions) -> Result<Box<dyn FeedbackSource>> {
+        let (scheme, rest) = split_scheme(spec).ok_or_else(|| {
+            DacError::InvalidArgument(format!("Feedback source '{}' needs a scheme://", spec))
+        })?;
+        let factory = self
+            .schemes
+            .iter()
+            .find(|(s, _)| s.eq_ignore_ascii_case(scheme))
+            .map(|(_, factory)| *factory)
+            .ok_or_else(|| {
+                DacError::InvalidArgument(format!(
+                    "Unknown feedback scheme '{}' (known: {})",
+                    scheme,
+                    self.schemes().collect::<Vec<_>>().join(", ")
+                ))
+            })?;
+        factory(rest, options)
+    }
+}
+
+/// A file holding the current value, such as an IIO `in_voltage0_raw`
+struct FileSource {
+    path: PathBuf,
+}
+
+impl FeedbackSource for FileSource {
+    fn read(&mut self) -> Result<f64> {
+        parse_value(&std::fs::read_to_string(&self.path)?)
+    }
+}
+
+fn open_file(path: &str, _options: &LinkOptions) -> Result<Box<dyn FeedbackSource>> {
+    let path = PathBuf::from(path);
+    // Fail at startup, not at the first reading
+    std::fs::metadata(&path)?;
+    Ok(Box::new(FileSource { path }))
+}
+
+fn connect(address: &str, options: &LinkOptions) -> Result<TcpStream> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| DacError::InvalidArgument(format!("Could not resolve {}", address)))?;
+    let stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(options.read_timeout))?;
+    stream.set_write_timeout(Some(options.write_timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Bytes of a line-based TCP stream, split into lines as they complete
+struct LineReader {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl LineReader {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Wait for the next bytes from the peer
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 1024];
+        match self.stream.read(&mut chunk)? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "feedback connection closed",
+            )),
+            n => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+        }
+    }
+
+    /// Take in whatever the peer has sent already, without waiting
+    fn drain(&mut self) -> Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let result = loop {
+            match self.read_chunk() {
+                Ok(()) => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        Ok(result?)
+    }
+
+    /// Take the newest complete line, dropping older ones
+    fn take_newest(&mut self) -> Option<String> {
+        let end = self.buffer.iter().rposition(|&b| b == b'\n')?;
+        let start = self.buffer[..end]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let line = String::from_utf8_lossy(&self.buffer[start..end]).into_owned();
+        self.buffer.drain(..=end);
+        Some(line)
+    }
+
+    /// The newest complete line, waiting for one if none is there yet
+    fn newest_line(&mut self) -> Result<String> {
+        loop {
+            if let Some(line) = self.take_newest() {
+                return Ok(line);
+            }
+            self.read_chunk()?;
+        }
+    }
+}
+
+/// A peer streaming one value per line
+struct TcpSource {
+    reader: LineReader,
+}
+
+impl FeedbackSource for TcpSource {
+    fn read(&mut self) -> Result<f64> {
+        // Values sent faster than they are read would pile up; use the latest
+        self.reader.drain()?;
+        parse_value(&self.reader.newest_line()?)
+    }
+}
+
+fn open_tcp(address: &str, options: &LinkOptions) -> Result<Box<dyn FeedbackSource>> {
+    Ok(Box::new(TcpSource {
+        reader: LineReader::new(connect(address, options)?),
+    }))
+}
+
+/// An instrument on a raw SCPI socket (port 5025 on most), asked for every reading
+struct ScpiSource {
+    reader: LineReader,
+    query: String,
+}
+
+impl FeedbackSource for ScpiSource {
+    fn read(&mut self) -> Result<f64> {
+        // An answer that timed out earlier must not be taken for this one
+        self.reader.drain()?;
+        self.reader.buffer.clear();
+        self.reader
+            .stream
+            .write_all(format!("{}\n", self.query).as_bytes())?;
+        parse_value(&self.reader.newest_line()?)
+    }
+}
+
+fn open_scpi(rest: &str, options: &LinkOptions) -> Result<Box<dyn FeedbackSource>> {
+    let (address, query) = match rest.split_once('/') {
+        Some((address, query)) if !query.is_empty() => (address, query),
+        Some((address, _)) => (address, DEFAULT_SCPI_QUERY),
+        None => (rest, DEFAULT_SCPI_QUERY),
+    };
+    Ok(Box::new(ScpiSource {
+        reader: LineReader::new(connect(address, options)?),
+        query: query.to_string(),
+    }))
+}
@@ -0,0 +1,192 @@
+//! Response filters of the serial bridge.
+//!
+//! Every device response the bridge passes on to a client first runs through
+//! a [`FilterChain`], which may rewrite or drop it. Filters see the response
+//! without CRC, next to the 4-byte command it answers. The built-in ones
+//! ([`BuiltinFilter`]) are picked with `tcp_server --filter`:
+//!
+//! | Filter             | Effect                                                        |
+//! |--------------------|---------------------------------------------------------------|
+//! | `dedupe-keepalive` | Drop keepalive acks repeating the last one a client was sent  |
+//! | `timestamp`        | Append the receive time (ms since the Unix epoch, 8 bytes) to extended responses |
+//! | `strip-padding`    | Cut zero bytes beyond the length a response's header declares |
+//!
+//! Other filters implement [`ResponseFilter`] and are pushed onto the chain
+//! the bridge builds in `main`.
+
+use crate::protocol::CMD_KEEPALIVE;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One step of a [`FilterChain`]
+pub trait ResponseFilter: Send {
+    /// Name shown in the bridge's log
+    fn name(&self) -> &str;
+
+    /// What to pass on for `response`, the device's answer to `command` from
+    /// `client`; `None` drops it
+    fn filter(&mut self, client: &str, command: &[u8], response: Vec<u8>) -> Option<Vec<u8>>;
+
+    /// Forget what was kept about `client`, which has disconnected
+    fn client_left(&mut self, _client: &str) {}
+}
+
+/// Filters run on every response, in the order they were pushed
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn ResponseFilter>>,
+}
+
+impl FilterChain {
+    /// A chain that passes every response unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, filter: Box<dyn ResponseFilter>) {
+        self.filters.push(filter);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Filter names, in chain order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.filters.iter().map(|filter| filter.name())
+    }
+
+    /// Run `response` through every filter, stopping at the first that drops it
+    pub fn apply(&mut self, client: &str, command: &[u8], response: Vec<u8>) -> Option<Vec<u8>> {
+        self.filters
+            .iter_mut()
+            .try_fold(response, |response, filter| {
+                filter.filter(client, command, response)
+            })
+    }
+
+    pub fn client_left(&mut self, client: &str) {
+        for filter in &mut self.filters {
+            filter.client_left(client);
+        }
+    }
+}
+
+impl fmt::Debug for FilterChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+/// Filters that come with the bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BuiltinFilter {
+    /// Drop keepalive acks repeating the last one the client was sent; only for
+    /// clients that do not wait for every ack
+    DedupeKeepalive,
+    /// Append the time the bridge received an extended response to its payload
+    Timestamp,
+    /// Cut zero padding beyond the length a response's header declares
+    StripPadding,
+}
+
+impl BuiltinFilter {
+    pub fn build(self) -> Box<dyn ResponseFilter> {
+        match self {
+            BuiltinFilter::DedupeKeepalive => Box::new(DedupeKeepalive::default()),
+            BuiltinFilter::Timestamp => Box::new(Timestamp),
+            BuiltinFilter::StripPadding => Box::new(StripPadding),
+        }
+    }
+}
+
+/// Length of a response as declared by its header, if it has a known one
+fn declared_len(response: &[u8]) -> Option<usize> {
+    match *response {
+        [0x00, _, ..] => Some(2),
+        [0x01, len, ..] => Some(2 + len as usize),
+        _ => None,
+    }
+}
+
+/// Passes a keepalive ack only if it differs from the previous one the same
+/// client was sent, e.g. an error status; any other response starts over
+#[derive(Debug, Default)]
+pub struct DedupeKeepalive {
+    last_ack: HashMap<String, Vec<u8>>,
+}
+
+impl ResponseFilter for DedupeKeepalive {
+    fn name(&self) -> &str {
+        "dedupe-keepalive"
+    }
+
+    fn filter(&mut self, client: &str, command: &[u8], response: Vec<u8>) -> Option<Vec<u8>> {
+        if command.first() != Some(&CMD_KEEPALIVE) {
+            self.last_ack.remove(client);
+            return Some(response);
+        }
+        if self.last_ack.get(client) == Some(&response) {
+            return None;
+        }
+        self.last_ack.insert(client.to_string(), response.clone());
+        Some(response)
+    }
+
+    fn client_left(&mut self, client: &str) {
+        self.last_ack.remove(client);
+    }
+}
+
+/// Appends milliseconds since the Unix epoch, MSB first, to extended responses
+///
+/// Standard responses keep their fixed two bytes, and extended ones too long
+/// to take eight more bytes pass unchanged.
+#[derive(Debug, Default)]
+pub struct Timestamp;
+
+impl ResponseFilter for Timestamp {
+    fn name(&self) -> &str {
+        "timestamp"
+    }
+
+    fn filter(&mut self, _client: &str, _command: &[u8], mut response: Vec<u8>) -> Option<Vec<u8>> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let stamp = millis.to_be_bytes();
+        match *response {
+            [0x01, len, ..] if declared_len(&response) == Some(response.len()) => {
+                if let Some(len) = len.checked_add(stamp.len() as u8) {
+                    response[1] = len;
+                    response.extend_from_slice(&stamp);
+                }
+            }
+            _ => {}
+        }
+        Some(response)
+    }
+}
+
+/// Cuts trailing zero bytes beyond the declared length, left by devices that
+/// pad their frames to a fixed size
+#[derive(Debug, Default)]
+pub struct StripPadding;
+
+impl ResponseFilter for StripPadding {
+    fn name(&self) -> &str {
+        "strip-padding"
+    }
+
+    fn filter(&mut self, _client: &str, _command: &[u8], mut response: Vec<u8>) -> Option<Vec<u8>> {
+        if let Some(len) = declared_len(&response) {
+            if response.len() > len && response[len..].iter().all(|&byte| byte == 0) {
+                response.truncate(len);
+            }
+        }
+        Some(response)
+    }
+}
@@ -0,0 +1,631 @@
+//! Optional framing layers on top of the 4-byte command protocol.
+//!
+//! Two independent layers are available:
+//!
+//! - **CRC** for electrically noisy links (see below).
+//! - **Stream framing** ([`StreamFraming`]): COBS or SLIP delimiters around
+//!   every command and response, so a receiver that lost byte alignment (e.g.
+//!   after a reset mid-frame) resynchronizes at the next delimiter.
+//!
+//! With CRC framing enabled every 4-byte command is followed by a
+//! CRC-16/CCITT-FALSE of the command bytes, and every response (standard
+//! `[0x00, status]` or extended `[0x01, len, ...payload]`) is followed by the
+//! CRC of its header and payload. The CRC is sent MSB first, like the 16-bit
+//! values inside commands.
+//!
+//! ```text
+//! + ------------------------------------------+
+//! | bytes 0..3        | bytes 4..5            |
+//! + ------------------------------------------+
+//! | command           | CRC16(command)        |
+//! + ------------------------------------------+
+//! ```
+//!
+//! Command batches must be a whole number of 4-byte commands unless a
+//! [`Padding`] policy of [`Padding::Zero`] is chosen: zero-filling a
+//! truncated `[x]` would send `[x, 0, 0, 0]`, i.e. "write DAC x = 0".
+
+use crate::error::{DacError, Result};
+use crate::protocol::validate_commands;
+use clap::ValueEnum;
+
+/// Length of a plain protocol command
+pub const COMMAND_LEN: usize = 4;
+
+/// Length of the CRC trailer appended to commands and responses
+pub const CRC_LEN: usize = 2;
+
+/// Status code answered by CRC-aware devices for a frame with a bad CRC
+pub const STATUS_CRC_ERROR: u8 = 0xFE;
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF, no reflection, no final xor)
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Append the CRC of `frame` to its end
+pub fn append_crc(frame: &mut Vec<u8>) {
+    let crc = crc16(frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Check the trailing CRC of `frame`, returning the bytes it covers
+pub fn check_crc(frame: &[u8]) -> Result<&[u8]> {
+    if frame.len() < CRC_LEN {
+        return Err(DacError::Protocol(format!(
+            "Frame too short for CRC: {} bytes",
+            frame.len()
+        )));
+    }
+    let (body, trailer) = frame.split_at(frame.len() - CRC_LEN);
+    let received = ((trailer[0] as u16) << 8) | (trailer[1] as u16);
+    let expected = crc16(body);
+    if received != expected {
+        return Err(DacError::Protocol(format!(
+            "CRC mismatch: received 0x{:04X}, expected 0x{:04X} over {:02X?}",
+            received, expected, body
+        )));
+    }
+    Ok(body)
+}
+
+/// What to do with trailing bytes that do not fill a whole command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Padding {
+    /// Refuse to send the batch
+    #[default]
+    Reject,
+    /// Zero-fill the last command to 4 bytes (the legacy behavior)
+    Zero,
+}
+
+impl Padding {
+    /// Check that `data` may be sent under this policy
+    pub fn check(&self, data: &[u8]) -> Result<()> {
+        if *self == Padding::Reject && !data.len().is_multiple_of(COMMAND_LEN) {
+            return Err(DacError::InvalidArgument(format!(
+                "Misaligned command batch: {} bytes is not a whole number of {}-byte commands: {:02X?}",
+                data.len(),
+                COMMAND_LEN,
+                data
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Length of one command on the wire
+pub fn command_frame_len(crc: bool) -> usize {
+    if crc {
+        COMMAND_LEN + CRC_LEN
+    } else {
+        COMMAND_LEN
+    }
+}
+
+/// Pad `data` to whole 4-byte commands and, when `crc` is set, append a CRC to each command
+pub fn encode_commands(data: &[u8], crc: bool) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len().div_ceil(COMMAND_LEN) * command_frame_len(crc));
+    for chunk in data.chunks(COMMAND_LEN) {
+        let start = encoded.len();
+        encoded.extend_from_slice(chunk);
+        encoded.resize(start + COMMAND_LEN, 0);
+        if crc {
+            let crc = crc16(&encoded[start..]);
+            encoded.extend_from_slice(&crc.to_be_bytes());
+        }
+    }
+    encoded
+}
+
+/// Total length of a response (without CRC) given its first two bytes
+///
+/// Unknown headers are treated as 2-byte legacy responses, matching the bridge.
+pub fn response_len(first_byte: u8, second_byte: u8) -> usize {
+    match first_byte {
+        0x01 => 2 + second_byte as usize,
+        _ => 2,
+    }
+}
+
+/// Verify the CRC of every response in `data` and return the responses without their trailers
+pub fn strip_response_crcs(data: &[u8]) -> Result<Vec<u8>> {
+    let mut stripped = Vec::with_capacity(data.len());
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        if rest.len() < 2 {
+            return Err(DacError::Protocol(format!(
+                "Truncated response header: {:02X?}",
+                rest
+            )));
+        }
+        let frame_len = response_len(rest[0], rest[1]) + CRC_LEN;
+        if rest.len() < frame_len {
+            return Err(DacError::Protocol(format!(
+                "Truncated response: expected {} bytes, got {}: {:02X?}",
+                frame_len,
+                rest.len(),
+                rest
+            )));
+        }
+        let (frame, tail) = rest.split_at(frame_len);
+        stripped.extend_from_slice(check_crc(frame)?);
+        rest = tail;
+    }
+
+    Ok(stripped)
+}
+
+/// Verify and strip the complete responses at the start of `pending`
+///
+/// A response whose CRC has not fully arrived stays in `pending` for the next
+/// read. Responses with a bad CRC are dropped; the first such error is returned
+/// next to the good responses.
+fn take_crc_responses(pending: &mut Vec<u8>) -> (Vec<u8>, Option<DacError>) {
+    let mut stripped = Vec::with_capacity(pending.len());
+    let mut first_error = None;
+    let mut start = 0;
+
+    while pending.len() - start >= 2 {
+        let frame_len = response_len(pending[start], pending[start + 1]) + CRC_LEN;
+        if pending.len() - start < frame_len {
+            break;
+        }
+        match check_crc(&pending[start..start + frame_len]) {
+            Ok(body) => stripped.extend_from_slice(body),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+        start += frame_len;
+    }
+    pending.drain(..start);
+
+    (stripped, first_error)
+}
+
+/// Delimiter-based framing of the byte stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum StreamFraming {
+    /// Bare frames back to back (the legacy wire format)
+    #[default]
+    Raw,
+    /// Consistent Overhead Byte Stuffing, frames terminated by 0x00
+    Cobs,
+    /// RFC 1055 SLIP, frames surrounded by 0xC0
+    Slip,
+}
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+impl StreamFraming {
+    /// Wrap one frame for the wire
+    pub fn encode(&self, frame: &[u8]) -> Vec<u8> {
+        match self {
+            StreamFraming::Raw => frame.to_vec(),
+            StreamFraming::Cobs => {
+                let mut encoded = cobs_encode(frame);
+                encoded.push(0x00);
+                encoded
+            }
+            StreamFraming::Slip => {
+                // Leading END flushes any line noise received before the frame
+                let mut encoded = Vec::with_capacity(frame.len() + 2);
+                encoded.push(SLIP_END);
+                for &byte in frame {
+                    match byte {
+                        SLIP_END => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+                        SLIP_ESC => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                        _ => encoded.push(byte),
+                    }
+                }
+                encoded.push(SLIP_END);
+                encoded
+            }
+        }
+    }
+
+    fn delimiter(&self) -> Option<u8> {
+        match self {
+            StreamFraming::Raw => None,
+            StreamFraming::Cobs => Some(0x00),
+            StreamFraming::Slip => Some(SLIP_END),
+        }
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            StreamFraming::Raw => Ok(frame.to_vec()),
+            StreamFraming::Cobs => cobs_decode(frame),
+            StreamFraming::Slip => slip_decode(frame),
+        }
+    }
+}
+
+fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_index = 0;
+    let mut code: u8 = 1;
+    encoded.push(0);
+
+    for &byte in data {
+        if byte != 0 {
+            encoded.push(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            encoded[code_index] = code;
+            code_index = encoded.len();
+            encoded.push(0);
+            code = 1;
+        }
+    }
+    encoded[code_index] = code;
+    encoded
+}
+
+fn cobs_decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return Err(DacError::Protocol(format!(
+                "Invalid COBS frame: {:02X?}",
+                data
+            )));
+        }
+        decoded.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+        if code < 0xFF && i < data.len() {
+            decoded.push(0);
+        }
+    }
+
+    Ok(decoded)
+}
+
+fn slip_decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+
+    while let Some(&byte) = bytes.next() {
+        if byte != SLIP_ESC {
+            decoded.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(&SLIP_ESC_END) => decoded.push(SLIP_END),
+            Some(&SLIP_ESC_ESC) => decoded.push(SLIP_ESC),
+            _ => {
+                return Err(DacError::Protocol(format!(
+                    "Invalid SLIP escape in frame: {:02X?}",
+                    data
+                )))
+            }
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Reassembles delimited frames from a byte stream
+#[derive(Debug, Clone, Default)]
+pub struct FrameDecoder {
+    framing: StreamFraming,
+    pending: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(framing: StreamFraming) -> Self {
+        Self {
+            framing,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Feed received bytes, returning every frame completed by them
+    ///
+    /// In raw mode the bytes are returned as a single frame. Frames that fail to
+    /// decode are returned as errors; decoding resumes at the next delimiter.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Result<Vec<u8>>> {
+        let Some(delimiter) = self.framing.delimiter() else {
+            return vec![Ok(data.to_vec())];
+        };
+
+        let mut frames = Vec::new();
+        for &byte in data {
+            if byte != delimiter {
+                self.pending.push(byte);
+                continue;
+            }
+            // Back-to-back delimiters (e.g. SLIP's leading END) carry no frame
+            if !self.pending.is_empty() {
+                frames.push(self.framing.decode(&self.pending));
+                self.pending.clear();
+            }
+        }
+        frames
+    }
+
+    /// Number of bytes received towards a frame that is not complete yet
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Client-side codec combining the CRC and stream framing layers
+#[derive(Debug, Clone, Default)]
+pub struct Codec {
+    crc: bool,
+    framing: StreamFraming,
+    padding: Padding,
+    strict: bool,
+    decoder: FrameDecoder,
+    /// Raw-framed bytes of a CRC-checked response that is not complete yet
+    pending: Vec<u8>,
+}
+
+impl Codec {
+    pub fn new(crc: bool, framing: StreamFraming) -> Self {
+        Self {
+            crc,
+            framing,
+            padding: Padding::default(),
+            strict: false,
+            decoder: FrameDecoder::new(framing),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Turn the CRC layer on or off, keeping the other settings
+    pub fn with_crc(mut self, crc: bool) -> Self {
+        self.crc = crc;
+        self
+    }
+
+    pub fn crc(&self) -> bool {
+        self.crc
+    }
+
+    /// Replace the default [`Padding::Reject`] policy
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Check every command with
+    /// [`Command::validate`](crate::protocol::Command::validate) before encoding it
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Split `data` into commands and apply CRC and stream framing to each
+    ///
+    /// Fails if `data` ends in a partial command and the padding policy is
+    /// [`Padding::Reject`], or in strict mode if any command is invalid.
+    pub fn encode_commands(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.check(data)?;
+        Ok(self.encode_unchecked(data))
+    }
+
+    /// The padding and strict checks of [`encode_commands`](Self::encode_commands) alone
+    pub fn check(&self, data: &[u8]) -> Result<()> {
+        self.padding.check(data)?;
+        if self.strict {
+            validate_commands(&encode_commands(data, false))?;
+        }
+        Ok(())
+    }
+
+    /// Apply CRC and stream framing without any check, zero-filling a partial
+    /// last command, e.g. for raw frames the user insists on
+    pub fn encode_unchecked(&self, data: &[u8]) -> Vec<u8> {
+        let frames = encode_commands(data, self.crc);
+        if self.framing == StreamFraming::Raw {
+            return frames;
+        }
+        frames
+            .chunks(command_frame_len(self.crc))
+            .flat_map(|frame| self.framing.encode(frame))
+            .collect()
+    }
+
+    /// Decode received bytes back into legacy responses
+    ///
+    /// Stream-framed responses, and with CRC also raw ones, split across reads
+    /// are held until complete, so an empty result does not mean nothing arrived.
+    pub fn decode_responses(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        if self.crc && self.framing == StreamFraming::Raw {
+            self.pending.extend_from_slice(data);
+            let (stripped, error) = take_crc_responses(&mut self.pending);
+            return match error {
+                Some(e) if stripped.is_empty() => Err(e),
+                _ => Ok(stripped),
+            };
+        }
+
+        let mut responses = Vec::with_capacity(data.len());
+        let mut first_error = None;
+
+        for frame in self.decoder.push(data) {
+            let decoded = frame.and_then(|frame| {
+                if self.crc {
+                    strip_response_crcs(&frame)
+                } else {
+                    Ok(frame)
+                }
+            });
+            match decoded {
+                Ok(frame) => responses.extend_from_slice(&frame),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) if responses.is_empty() => Err(e),
+            _ => Ok(responses),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `response` followed by its CRC, as a CRC-aware device sends it
+    fn with_crc(response: &[u8]) -> Vec<u8> {
+        let mut frame = response.to_vec();
+        append_crc(&mut frame);
+        frame
+    }
+
+    #[test]
+    fn crc_response_split_into_single_bytes() {
+        let mut codec = Codec::new(true, StreamFraming::Raw);
+        let mut wire = with_crc(&[0x00, 0x00]);
+        wire.extend(with_crc(&[0x01, 0x02, 0x12, 0x34]));
+
+        let mut decoded = Vec::new();
+        for byte in &wire {
+            decoded.extend(codec.decode_responses(&[*byte]).unwrap());
+        }
+        assert_eq!(decoded, [0x00, 0x00, 0x01, 0x02, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn crc_response_tail_kept_for_next_read() {
+        let mut codec = Codec::new(true, StreamFraming::Raw);
+        let mut wire = with_crc(&[0x00, 0x00]);
+        wire.extend(with_crc(&[0x00, 0x01]));
+
+        assert_eq!(codec.decode_responses(&wire[..5]).unwrap(), [0x00, 0x00]);
+        assert_eq!(codec.decode_responses(&wire[5..]).unwrap(), [0x00, 0x01]);
+    }
+
+    #[test]
+    fn bad_crc_drops_only_its_response() {
+        let mut codec = Codec::new(true, StreamFraming::Raw);
+        let mut wire = with_crc(&[0x00, 0x00]);
+        let mut bad = with_crc(&[0x00, 0x01]);
+        bad[3] ^= 0xFF;
+        wire.extend(bad);
+        wire.extend(with_crc(&[0x00, 0x02]));
+
+        assert_eq!(
+            codec.decode_responses(&wire).unwrap(),
+            [0x00, 0x00, 0x00, 0x02]
+        );
+        assert!(codec
+            .decode_responses(&with_crc(&[0x00, 0x03])[..1])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn only_bad_crc_is_an_error() {
+        let mut codec = Codec::new(true, StreamFraming::Raw);
+        let mut bad = with_crc(&[0x00, 0x00]);
+        bad[2] ^= 0xFF;
+        assert!(codec.decode_responses(&bad).is_err());
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert!(check_crc(&with_crc(&[0xFB, 0x10, 0xBE, 0xEF])).is_ok());
+    }
+
+    #[test]
+    fn cobs_and_slip_round_trip() {
+        let long: Vec<u8> = (0..300).map(|i| (i % 255 + 1) as u8).collect();
+        let frames: [&[u8]; 4] = [
+            &[0x00, 0x00, 0x00, 0x00],
+            &[0xC0, 0xDB, 0x00, 0xDC],
+            &[0x01, 0x02, 0x12, 0x34],
+            &long,
+        ];
+        for framing in [StreamFraming::Cobs, StreamFraming::Slip] {
+            let wire: Vec<u8> = frames.iter().flat_map(|f| framing.encode(f)).collect();
+            let decoded: Vec<Vec<u8>> = FrameDecoder::new(framing)
+                .push(&wire)
+                .into_iter()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert_eq!(decoded, frames, "{:?}", framing);
+        }
+    }
+
+    #[test]
+    fn framed_responses_split_across_reads() {
+        for framing in [StreamFraming::Cobs, StreamFraming::Slip] {
+            let mut codec = Codec::new(true, framing);
+            let mut wire = framing.encode(&with_crc(&[0x00, 0x00]));
+            wire.extend(framing.encode(&with_crc(&[0x01, 0x02, 0xC0, 0x00])));
+
+            let mut decoded = Vec::new();
+            for chunk in wire.chunks(3) {
+                decoded.extend(codec.decode_responses(chunk).unwrap());
+            }
+            assert_eq!(
+                decoded,
+                [0x00, 0x00, 0x01, 0x02, 0xC0, 0x00],
+                "{:?}",
+                framing
+            );
+        }
+    }
+
+    #[test]
+    fn bad_frame_resyncs_at_the_next_delimiter() {
+        let mut decoder = FrameDecoder::new(StreamFraming::Cobs);
+        let mut wire = vec![0x05, 0x01, 0x00];
+        wire.extend(StreamFraming::Cobs.encode(&[0x00, 0x00]));
+        let frames = decoder.push(&wire);
+        assert!(frames[0].is_err());
+        assert_eq!(frames[1].as_ref().unwrap(), &[0x00, 0x00]);
+        assert_eq!(decoder.pending_len(), 0);
+    }
+
+    #[test]
+    fn each_command_gets_its_own_frame_and_crc() {
+        let codec = Codec::new(true, StreamFraming::Slip);
+        let commands = [0x00, 0x12, 0x34, 0x56, 0xFD, 0x00, 0x00, 0x00];
+        let wire = codec.encode_commands(&commands).unwrap();
+        let frames = FrameDecoder::new(StreamFraming::Slip).push(&wire);
+        assert_eq!(frames.len(), 2);
+        for (frame, command) in frames.iter().zip(commands.chunks(COMMAND_LEN)) {
+            assert_eq!(check_crc(frame.as_ref().unwrap()).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn partial_commands_are_rejected_unless_zero_filled() {
+        let partial = [0x00, 0x12, 0x34, 0x56, 0x03];
+        assert!(Codec::default().encode_commands(&partial).is_err());
+        let codec = Codec::default().with_padding(Padding::Zero);
+        assert_eq!(
+            codec.encode_commands(&partial).unwrap(),
+            [0x00, 0x12, 0x34, 0x56, 0x03, 0x00, 0x00, 0x00]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Synchronized start across several devices.
+//!
+//! A [`DeviceGroup`] holds one [`DacClient`] per board. An experiment needing
+//! cross-board timing first [`arm`](DeviceGroup::arm)s every device: each is
+//! sent its setup (table uploads and attachments, DAC values loaded for LDAC)
+//! and every response is awaited, so all that is left is the trigger.
+//! [`trigger`](DeviceGroup::trigger) then releases the devices together:
+//!
+//! - [`Trigger::Software`]: one thread per device waits on a barrier and sends
+//!   the trigger command (usually LDAC or `UseTable`) the moment all are
+//!   released. The spread between the first and last send is reported.
+//! - [`Trigger::Gpio`]: one device pulses a GPIO pin wired to the hardware
+//!   trigger inputs (e.g. the LDAC pins) of the boards, so the alignment is
+//!   electrical and only one command is sent.
+
+use crate::client::DacClient;
+use crate::error::{DacError, Result};
+use crate::framing::Codec;
+use crate::protocol::{Command, GPIO_PINS};
+use crate::target::Target;
+use crate::transport::LinkOptions;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How the armed devices are started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Send this command to every device at once
+    Software(Command),
+    /// Switch `pin` of device `device` on for `pulse`, then off again
+    Gpio {
+        device: usize,
+        pin: u8,
+        pulse: Duration,
+    },
+}
+
+/// When the trigger went out to each device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriggerReport {
+    /// Time from the first trigger write to each device's, in group order;
+    /// only the pulsing device for a GPIO trigger
+    pub sent: Vec<Duration>,
+}
+
+impl TriggerReport {
+    /// Time between the first and the last trigger write
+    pub fn spread(&self) -> Duration {
+        self.sent.iter().max().copied().unwrap_or_default()
+    }
+
+    /// Whether every trigger write went out within `window` of the first
+    pub fn within(&self, window: Duration) -> bool {
+        self.spread() <= window
+    }
+}
+
+/// Attach the index of the device that failed
+fn on_device(device: usize) -> impl FnOnce(DacError) -> DacError {
+    move |e| DacError::Group {
+        device,
+        source: Box::new(e),
+    }
+}
+
+/// One client per device, armed and triggered together
+pub struct DeviceGroup {
+    clients: Vec<DacClient>,
+}
+
+impl DeviceGroup {
+    pub fn new(clients: Vec<DacClient>) -> Self {
+        Self { clients }
+    }
+
+    /// Connect to every target, one device each
+    pub fn connect(targets: &[Target], options: LinkOptions, codec: Codec) -> Result<Self> {
+        let clients = targets
+            .iter()
+            .enumerate()
+            .map(|(device, target)| {
+                DacClient::connect(vec![target.clone()], options, codec.clone())
+                    .map_err(on_device(device))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::new(clients))
+    }
+
+    pub fn clients(&self) -> &[DacClient] {
+        &self.clients
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Send each device its own setup commands, all devices in parallel
+    ///
+    /// Returns once every device has answered every command, failing with
+    /// [`DacError::Group`] for the first device (in group order) that failed.
+    pub fn arm(&mut self, sequences: &[Vec<Command>]) -> Result<()> {
+        if sequences.len() != self.clients.len() {
+            return Err(DacError::InvalidArgument(format!(
+                "{} sequences for {} devices",
+                sequences.len(),
+                self.clients.len()
+            )));
+        }
+        let results: Vec<Result<()>> = thread::scope(|scope| {
+            let arming: Vec<_> = self
+                .clients
+                .iter_mut()
+                .zip(sequences)
+                .map(|(client, sequence)| {
+                    scope.spawn(move || sequence.iter().try_for_each(|&cmd| client.send(cmd)))
+                })
+                .collect();
+            arming.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        results
+            .into_iter()
+            .enumerate()
+            .try_for_each(|(device, result)| result.map_err(on_device(device)))
+    }
+
+    /// Start every armed device
+    ///
+    /// A hardware-triggered change is not seen by the other devices' clients,
+    /// so their [`state`](DacClient::state) does not include it.
+    pub fn trigger(&mut self, trigger: Trigger) -> Result<TriggerReport> {
+        match trigger {
+            Trigger::Software(cmd) => self.trigger_software(cmd),
+            Trigger::Gpio { device, pin, pulse } => {
+                if pin as usize >= GPIO_PINS {
+                    return Err(DacError::InvalidArgument(format!(
+                        "GPIO pin {} out of range 0-{}",
+                        pin,
+                        GPIO_PINS - 1
+                    )));
+                }
+                let client = self.clients.get_mut(device).ok_or_else(|| {
+                    DacError::InvalidArgument(format!("No device {} in the group", device))
+                })?;
+                client
+                    .send(Command::Gpio { pin, on: true })
+                    .map_err(on_device(device))?;
+                thread::sleep(pulse);
+                client
+                    .send(Command::Gpio { pin, on: false })
+                    .map_err(on_device(device))?;
+                Ok(TriggerReport {
+                    sent: vec![Duration::ZERO],
+                })
+            }
+        }
+    }
+
+    fn trigger_software(&mut self, cmd: Command) -> Result<TriggerReport> {
+        // Threads are started and parked first, so none is held up by spawning
+        let barrier = Barrier::new(self.clients.len());
+        let results: Vec<(Instant, Result<()>)> = thread::scope(|scope| {
+            let barrier = &barrier;
+            let senders: Vec<_> = self
+                .clients
+                .iter_mut()
+                .map(|client| {
+                    scope.spawn(move || {
+                        barrier.wait();
+                        (Instant::now(), client.send(cmd))
+                    })
+                })
+                .collect();
+            senders.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        let first = results.iter().map(|(at, _)| *at).min();
+        let mut sent = Vec::with_capacity(results.len());
+        for (device, (at, result)) in results.into_iter().enumerate() {
+            result.map_err(on_device(device))?;
+            sent.push(first.map_or(Duration::ZERO, |first| at - first));
+        }
+        Ok(TriggerReport { sent })
+    }
+}
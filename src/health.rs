@@ -0,0 +1,173 @@
+//! Health endpoint of the bridge, for container orchestrators.
+//!
+//! A [`Health`] holds what the bridge is doing; [`serve_http`] answers `GET`
+//! requests with it as JSON, so Docker `HEALTHCHECK`s and Kubernetes probes
+//! need no protocol client:
+//!
+//! | Path      | Status                                                      |
+//! |-----------|-------------------------------------------------------------|
+//! | `/health` | 200 unless draining (liveness)                              |
+//! | `/ready`  | 200 while serving; 503 waiting, reopening or draining       |
+//!
+//! ```text
+//! {"status":"serving","ready":true,"clients":2,"device_open":true,"uptime_s":3605}
+//! ```
+
+use crate::error::Result;
+use crate::report::Value;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What the bridge is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Status {
+    /// Waiting for the serial device to appear (`--device-wait`)
+    #[default]
+    Waiting,
+    /// Accepting clients
+    Serving,
+    /// The device went away and is being reopened
+    Reopening,
+    /// Shutting down: no new clients, outstanding responses are being sent
+    Draining,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Waiting => "waiting",
+            Status::Serving => "serving",
+            Status::Reopening => "reopening",
+            Status::Draining => "draining",
+        })
+    }
+}
+
+/// The bridge's state as reported by the endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HealthState {
+    pub status: Status,
+    pub clients: usize,
+    pub device_open: bool,
+}
+
+/// Bridge state shared between the bridge and the HTTP thread
+#[derive(Debug)]
+pub struct Health {
+    state: Mutex<HealthState>,
+    started: Instant,
+}
+
+impl Health {
+    pub fn new(status: Status) -> Self {
+        Self {
+            state: Mutex::new(HealthState {
+                status,
+                ..HealthState::default()
+            }),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn state(&self) -> HealthState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn set_status(&self, status: Status) {
+        self.state.lock().unwrap().status = status;
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut HealthState)) {
+        f(&mut self.state.lock().unwrap());
+    }
+
+    /// Whether the bridge is alive: anything but draining
+    pub fn is_live(&self) -> bool {
+        self.state().status != Status::Draining
+    }
+
+    /// Whether the bridge takes clients and has not lost its device
+    pub fn is_ready(&self) -> bool {
+        self.state().status == Status::Serving
+    }
+
+    pub fn to_value(&self) -> Value {
+        let state = self.state();
+        Value::object()
+            .with("status", state.status.to_string())
+            .with("ready", state.status == Status::Serving)
+            .with("clients", state.clients)
+            .with("device_open", state.device_open)
+            .with("uptime_s", self.started.elapsed().as_secs())
+    }
+}
+
+/// Answer health requests on `listener` until the process exits
+///
+/// Unlike the twin server this keeps answering after a shutdown begins, so
+/// probes see the bridge draining.
+pub fn serve_http(listener: TcpListener, health: &Health) -> Result<()> {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = answer(stream, health) {
+                    eprintln!("Health request failed: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Health accept failed: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Read one request and write its response
+fn answer(stream: TcpStream, health: &Health) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip the headers; requests have no body
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("");
+
+    let status = |ok: bool| {
+        if ok {
+            "200 OK"
+        } else {
+            "503 Service Unavailable"
+        }
+    };
+    let (status, body) = match (method, path.trim_end_matches('/')) {
+        ("GET" | "HEAD", "/health") => (status(health.is_live()), health.to_value()),
+        ("GET" | "HEAD", "/ready") => (status(health.is_ready()), health.to_value()),
+        ("GET" | "HEAD", _) => ("404 Not Found", error("no such path")),
+        _ => ("405 Method Not Allowed", error("only GET is supported")),
+    };
+    let body = format!("{}\n", body);
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        if method == "HEAD" { "" } else { &body }
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn error(message: &str) -> Value {
+    Value::object().with("error", message)
+}
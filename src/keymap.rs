@@ -0,0 +1,490 @@
+//! Key bindings of `tui_diagnostic`.
+//!
+//! Every key the panel reacts to is bound to an [`Action`] in a [`Keymap`].
+//! The defaults are the QWERTY layout in the help pane; a keymap file rebinds
+//! actions with `action = key key ...` lines, e.g. for AZERTY:
+//!
+//! ```text
+//! # GPIO toggles on the bottom row of an AZERTY keyboard
+//! gpio0 = w
+//! gpio1 = x
+//! gpio6 = ,
+//! gpio7 = ;
+//! quit = esc ctrl+q
+//! ```
+//!
+//! A line replaces all keys of its action, and a key taken by another action
+//! is released there. An empty list unbinds the action. Keys are single
+//! characters (letters ignore case), `space`, `esc`, `enter`, `tab`,
+//! `backspace`, `left`, `right`, `up`, `down`, `pageup`, `pagedown`, `home`,
+//! `end` or `f1`-`f12`, optionally prefixed with `ctrl+` and/or `alt+`.
+
+use crate::error::{DacError, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Something a key press does in the panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    PrevChannel,
+    NextChannel,
+    /// Step the selected DAC up, accelerating while held
+    StepUp,
+    StepDown,
+    /// Step the selected DAC by 16 (1 LSB of the 12-bit converter)
+    FineUp,
+    FineDown,
+    /// +8192, wrapping from 65535 to 0
+    LargeStep,
+    /// Select table offset 0-9
+    TableOffset(u8),
+    NextTableOffset,
+    PrevTableOffset,
+    /// Type a table offset 0-255
+    EnterTableOffset,
+    Sweep,
+    Deferred,
+    Ldac,
+    Gang,
+    GangMode,
+    Theme,
+    Display,
+    /// Toggle GPIO pin 0-7
+    Gpio(u8),
+    ShorterKeepalive,
+    LongerKeepalive,
+    PauseKeepalive,
+    Takeover,
+    Undo,
+    Redo,
+    RecordMacro,
+    ReplayMacro,
+    /// Show the activity log in place of the help pane
+    ToggleLog,
+    /// Select one more (older) log line
+    SelectMore,
+    SelectLess,
+    /// Copy the selected log lines, or the state summary without the log
+    Copy,
+    /// Write the state and recent log to a report file
+    Export,
+    /// Show the full key and protocol reference screen
+    HelpScreen,
+    /// Type a register to read or a register and value to write
+    Registers,
+    /// Type hex bytes to send unchecked
+    RawFrame,
+    /// Time a burst of keepalives and show the round trips
+    LatencyProbe,
+    /// Type a table and a generator to fill it from
+    FillTable,
+}
+
+impl Action {
+    /// Every action, in help pane order
+    pub fn all() -> Vec<Action> {
+        let mut actions = vec![
+            Action::Quit,
+            Action::PrevChannel,
+            Action::NextChannel,
+            Action::StepUp,
+            Action::StepDown,
+            Action::FineUp,
+            Action::FineDown,
+            Action::LargeStep,
+        ];
+        actions.extend((0..10).map(Action::TableOffset));
+        actions.extend([
+            Action::NextTableOffset,
+            Action::PrevTableOffset,
+            Action::EnterTableOffset,
+            Action::Sweep,
+            Action::Deferred,
+            Action::Ldac,
+            Action::Gang,
+            Action::GangMode,
+            Action::Theme,
+            Action::Display,
+        ]);
+        actions.extend((0..8).map(Action::Gpio));
+        actions.extend([
+            Action::ShorterKeepalive,
+            Action::LongerKeepalive,
+            Action::PauseKeepalive,
+            Action::Takeover,
+            Action::Undo,
+            Action::Redo,
+            Action::RecordMacro,
+            Action::ReplayMacro,
+            Action::ToggleLog,
+            Action::SelectMore,
+            Action::SelectLess,
+            Action::Copy,
+            Action::Export,
+            Action::HelpScreen,
+            Action::Registers,
+            Action::RawFrame,
+            Action::LatencyProbe,
+            Action::FillTable,
+        ]);
+        actions
+    }
+
+    /// Name used in keymap files
+    pub fn name(self) -> String {
+        match self {
+            Action::Quit => "quit".into(),
+            Action::PrevChannel => "prev-channel".into(),
+            Action::NextChannel => "next-channel".into(),
+            Action::StepUp => "step-up".into(),
+            Action::StepDown => "step-down".into(),
+            Action::FineUp => "fine-up".into(),
+            Action::FineDown => "fine-down".into(),
+            Action::LargeStep => "large-step".into(),
+            Action::TableOffset(offset) => format!("table{}", offset),
+            Action::NextTableOffset => "next-table".into(),
+            Action::PrevTableOffset => "prev-table".into(),
+            Action::EnterTableOffset => "enter-table".into(),
+            Action::Sweep => "sweep".into(),
+            Action::Deferred => "deferred".into(),
+            Action::Ldac => "ldac".into(),
+            Action::Gang => "gang".into(),
+            Action::GangMode => "gang-mode".into(),
+            Action::Theme => "theme".into(),
+            Action::Display => "display".into(),
+            Action::Gpio(pin) => format!("gpio{}", pin),
+            Action::ShorterKeepalive => "shorter-keepalive".into(),
+            Action::LongerKeepalive => "longer-keepalive".into(),
+            Action::PauseKeepalive => "pause-keepalive".into(),
+            Action::Takeover => "takeover".into(),
+            Action::Undo => "undo".into(),
+            Action::Redo => "redo".into(),
+            Action::RecordMacro => "record".into(),
+            Action::ReplayMacro => "replay".into(),
+            Action::ToggleLog => "log".into(),
+            Action::SelectMore => "select-more".into(),
+            Action::SelectLess => "select-less".into(),
+            Action::Copy => "copy".into(),
+            Action::Export => "export".into(),
+            Action::HelpScreen => "help".into(),
+            Action::Registers => "registers".into(),
+            Action::RawFrame => "raw".into(),
+            Action::LatencyProbe => "latency-probe".into(),
+            Action::FillTable => "fill-table".into(),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Action::all()
+            .into_iter()
+            .find(|action| action.name() == s)
+            .ok_or_else(|| DacError::InvalidArgument(format!("Unknown action '{}'", s)))
+    }
+}
+
+/// A key with the modifiers that matter for bindings
+///
+/// Shift is ignored: it is already part of the character, and letters match
+/// in either case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl Key {
+    pub const fn new(code: KeyCode) -> Self {
+        Self {
+            code,
+            ctrl: false,
+            alt: false,
+        }
+    }
+
+    pub const fn char(c: char) -> Self {
+        Self::new(KeyCode::Char(c))
+    }
+
+    pub const fn with_ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    pub const fn with_alt(mut self) -> Self {
+        self.alt = true;
+        self
+    }
+
+    /// Letters stored in lower case so bindings ignore Shift and Caps Lock
+    fn normalized(mut self) -> Self {
+        if let KeyCode::Char(c) = self.code {
+            self.code = KeyCode::Char(c.to_ascii_lowercase());
+        }
+        self
+    }
+}
+
+impl From<&KeyEvent> for Key {
+    fn from(event: &KeyEvent) -> Self {
+        Key {
+            code: event.code,
+            ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
+            alt: event.modifiers.contains(KeyModifiers::ALT),
+        }
+        .normalized()
+    }
+}
+
+impl FromStr for Key {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || DacError::InvalidArgument(format!("Unknown key '{}'", s));
+        let mut key = Key::new(KeyCode::Null);
+        let mut rest = s;
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            if lower.starts_with("ctrl+") && rest.len() > 5 {
+                key.ctrl = true;
+                rest = &rest[5..];
+            } else if lower.starts_with("alt+") && rest.len() > 4 {
+                key.alt = true;
+                rest = &rest[4..];
+            } else {
+                break;
+            }
+        }
+        let mut chars = rest.chars();
+        key.code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "esc" => KeyCode::Esc,
+                "enter" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                name => {
+                    let n = name.strip_prefix('f').ok_or_else(invalid)?;
+                    match n.parse() {
+                        Ok(n @ 1..=12) => KeyCode::F(n),
+                        _ => return Err(invalid()),
+                    }
+                }
+            },
+        };
+        Ok(key.normalized())
+    }
+}
+
+/// Label for the help pane: `Ctrl+R`, `SPACE`, `←`
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.alt {
+            write!(f, "Alt+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "SPACE"),
+            KeyCode::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            KeyCode::Esc => write!(f, "ESC"),
+            KeyCode::Enter => write!(f, "ENTER"),
+            KeyCode::Tab => write!(f, "TAB"),
+            KeyCode::Backspace => write!(f, "BKSP"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            KeyCode::Home => write!(f, "Home"),
+            KeyCode::End => write!(f, "End"),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Keys bound to actions; one action per key, any number of keys per action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut bindings = vec![
+            (Key::new(KeyCode::Esc), Action::Quit),
+            (Key::char('q').with_ctrl(), Action::Quit),
+            (Key::new(KeyCode::Left), Action::PrevChannel),
+            (Key::new(KeyCode::Right), Action::NextChannel),
+            (Key::new(KeyCode::Up), Action::StepUp),
+            (Key::new(KeyCode::Down), Action::StepDown),
+            (Key::char('='), Action::FineUp),
+            (Key::char('-'), Action::FineDown),
+            (Key::char(' '), Action::LargeStep),
+        ];
+        for offset in 0..10 {
+            bindings.push((
+                Key::char((b'0' + offset) as char),
+                Action::TableOffset(offset),
+            ));
+        }
+        bindings.extend([
+            (Key::char('=').with_alt(), Action::NextTableOffset),
+            (Key::char('+').with_alt(), Action::NextTableOffset),
+            (Key::char('-').with_alt(), Action::PrevTableOffset),
+            (Key::char('_').with_alt(), Action::PrevTableOffset),
+            (Key::char('o'), Action::EnterTableOffset),
+            (Key::char('s'), Action::Sweep),
+            (Key::char('d'), Action::Deferred),
+            (Key::char('l'), Action::Ldac),
+            (Key::char('g'), Action::Gang),
+            (Key::char('r'), Action::GangMode),
+            (Key::char('t'), Action::Theme),
+            (Key::char('f'), Action::Display),
+        ]);
+        for (pin, c) in "zxcvbnm,".chars().enumerate() {
+            bindings.push((Key::char(c), Action::Gpio(pin as u8)));
+        }
+        bindings.extend([
+            (Key::char('['), Action::ShorterKeepalive),
+            (Key::char(']'), Action::LongerKeepalive),
+            (Key::char('p'), Action::PauseKeepalive),
+            (Key::char('a'), Action::Takeover),
+            (Key::char('u'), Action::Undo),
+            (Key::char('r').with_ctrl(), Action::Redo),
+            (Key::char('q'), Action::RecordMacro),
+            (Key::char('@'), Action::ReplayMacro),
+            (Key::char('h'), Action::ToggleLog),
+            (Key::new(KeyCode::PageUp), Action::SelectMore),
+            (Key::new(KeyCode::PageDown), Action::SelectLess),
+            (Key::char('y'), Action::Copy),
+            (Key::char('e'), Action::Export),
+            (Key::new(KeyCode::F(1)), Action::HelpScreen),
+            (Key::char('i'), Action::Registers),
+            (Key::char('w'), Action::RawFrame),
+            (Key::char('j'), Action::LatencyProbe),
+            (Key::char('k'), Action::FillTable),
+        ]);
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /// The default keymap with the bindings in `path` applied
+    pub fn load(path: &Path) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// The action bound to `event`, if any
+    pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+        let key = Key::from(event);
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|&(_, action)| action)
+    }
+
+    /// Keys bound to `action`, in binding order
+    pub fn keys(&self, action: Action) -> Vec<Key> {
+        self.bindings
+            .iter()
+            .filter(|&&(_, bound)| bound == action)
+            .map(|&(key, _)| key)
+            .collect()
+    }
+
+    /// Bind `action` to exactly `keys`, releasing them from other actions
+    pub fn bind(&mut self, action: Action, keys: &[Key]) {
+        self.bindings
+            .retain(|(key, bound)| *bound != action && !keys.contains(key));
+        self.bindings.extend(keys.iter().map(|&key| (key, action)));
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |line: usize, reason: String| {
+            DacError::InvalidArgument(format!("Keymap line {}: {}", line, reason))
+        };
+        // Prefix a bad action or key with its line
+        let at_line = |line: usize| {
+            move |e| match e {
+                DacError::InvalidArgument(reason) => invalid(line, reason),
+                e => e,
+            }
+        };
+        let mut keymap = Keymap::default();
+        for (i, line) in s.lines().enumerate() {
+            let n = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (action, keys) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(n, "expected action = keys".into()))?;
+            let action: Action = action.trim().parse().map_err(at_line(n))?;
+            let keys = keys
+                .split_whitespace()
+                .map(Key::from_str)
+                .collect::<Result<Vec<_>>>()
+                .map_err(at_line(n))?;
+            keymap.bind(action, &keys);
+        }
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn q_records_and_esc_or_ctrl_q_quit_by_default() {
+        let keymap = Keymap::default();
+        let q = press(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert_eq!(keymap.action(&q), Some(Action::RecordMacro));
+        let ctrl_q = press(KeyCode::Char('q'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.action(&ctrl_q), Some(Action::Quit));
+        let esc = press(KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(keymap.action(&esc), Some(Action::Quit));
+    }
+
+    #[test]
+    fn q_can_be_bound_to_quit_again() {
+        let keymap: Keymap = "quit = esc ctrl+q q\nrecord = f2".parse().unwrap();
+        let q = press(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert_eq!(keymap.action(&q), Some(Action::Quit));
+        let f2 = press(KeyCode::F(2), KeyModifiers::NONE);
+        assert_eq!(keymap.action(&f2), Some(Action::RecordMacro));
+        assert_eq!(
+            keymap.keys(Action::RecordMacro),
+            vec![Key::new(KeyCode::F(2))]
+        );
+    }
+}
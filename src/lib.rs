@@ -0,0 +1,59 @@
+//! Shared protocol helpers for the csv1-ol8 test programs.
+//!
+//! The binaries in `src/bin` open their links through [`transport`]; this
+//! library holds the pieces that must behave identically on every side of a
+//! link (clients, the serial bridge and the simulator).
+
+pub mod alarms;
+pub mod audit;
+pub mod cancel;
+pub mod channels;
+pub mod client;
+pub mod clock;
+pub mod control;
+pub mod desired;
+pub mod device;
+pub mod diagnose;
+pub mod discovery;
+pub mod doctor;
+pub mod error;
+pub mod events;
+pub mod expr;
+pub mod feedback;
+pub mod filter;
+pub mod framing;
+pub mod group;
+pub mod health;
+pub mod keymap;
+pub mod logfile;
+pub mod mailbox;
+pub mod mock;
+pub mod modbus;
+pub mod mqtt;
+pub mod progress;
+pub mod protocol;
+pub mod ramp;
+pub mod repeats;
+pub mod report;
+pub mod schedule;
+pub mod scpi;
+#[cfg(feature = "lua")]
+pub mod script;
+pub mod sequencer;
+pub mod session;
+pub mod shell;
+pub mod sim;
+pub mod snapshot;
+pub mod stats;
+pub mod stream;
+pub mod tables;
+pub mod target;
+pub mod transport;
+#[cfg(unix)]
+pub mod tuning;
+pub mod twin;
+pub mod units;
+pub mod version;
+pub mod wake;
+pub mod waveform;
+pub mod widgets;
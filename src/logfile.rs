@@ -0,0 +1,246 @@
+//! Rotation for files that keep growing while a tool runs.
+//!
+//! A [`RotatingFile`] appends to a file until it reaches the size or age
+//! limit of its [`Rotation`]. The file is then renamed to `FILE.1` (older files
+//! move to `FILE.2`, ...), gzipped to `FILE.1.gz` if asked, files beyond the
+//! retention count are removed and a new file is started.
+//!
+//! Policies are written as comma-separated settings, e.g. on the command line:
+//!
+//! ```text
+//! size=10M,age=1d,keep=7,compress
+//! ```
+//!
+//! | Setting    | Meaning                                                     |
+//! |------------|-------------------------------------------------------------|
+//! | `size=N`   | Rotate before the file grows past N bytes (`K`, `M`, `G`)   |
+//! | `age=N`    | Rotate once the file is N old (`s`, `m`, `h`, `d`; seconds) |
+//! | `keep=N`   | Rotated files to keep (default 5); 0 empties the file       |
+//! | `compress` | Gzip rotated files                                          |
+
+use crate::error::{DacError, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Rotated files kept when a policy does not say
+pub const DEFAULT_KEEP: usize = 5;
+
+/// When to rotate a file and what to keep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rotation {
+    /// Rotate before the file grows past this many bytes
+    pub max_size: Option<u64>,
+    /// Rotate once the file is this old
+    pub max_age: Option<Duration>,
+    /// Rotated files to keep; 0 discards the contents on rotation
+    pub keep: usize,
+    /// Gzip rotated files
+    pub compress: bool,
+}
+
+impl Default for Rotation {
+    /// Never rotate
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_age: None,
+            keep: DEFAULT_KEEP,
+            compress: false,
+        }
+    }
+}
+
+/// `value` with an optional unit suffix from `units`, e.g. `10M`
+pub(crate) fn parse_scaled(value: &str, units: &[(char, u64)]) -> Option<u64> {
+    let (number, scale) = match value.chars().last() {
+        Some(c) if c.is_ascii_alphabetic() => {
+            let scale = units
+                .iter()
+                .find(|(unit, _)| unit.eq_ignore_ascii_case(&c))?
+                .1;
+            (&value[..value.len() - 1], scale)
+        }
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+impl FromStr for Rotation {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: String| {
+            DacError::InvalidArgument(format!("Invalid rotation '{}': {}", s, reason))
+        };
+        let mut rotation = Rotation::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = setting.split_once('=').unwrap_or((setting, ""));
+            match key {
+                "size" => {
+                    let size =
+                        parse_scaled(value, &[('k', 1 << 10), ('m', 1 << 20), ('g', 1 << 30)])
+                            .filter(|&size| size > 0)
+                            .ok_or_else(|| invalid(format!("bad size '{}'", value)))?;
+                    rotation.max_size = Some(size);
+                }
+                "age" => {
+                    let secs =
+                        parse_scaled(value, &[('s', 1), ('m', 60), ('h', 3600), ('d', 86_400)])
+                            .filter(|&secs| secs > 0)
+                            .ok_or_else(|| invalid(format!("bad age '{}'", value)))?;
+                    rotation.max_age = Some(Duration::from_secs(secs));
+                }
+                "keep" => {
+                    rotation.keep = value
+                        .parse()
+                        .map_err(|_| invalid(format!("bad keep '{}'", value)))?
+                }
+                "compress" if value.is_empty() => rotation.compress = true,
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown setting '{}' (size=, age=, keep=, compress)",
+                        setting
+                    )))
+                }
+            }
+        }
+        Ok(rotation)
+    }
+}
+
+/// A file opened for appending that rotates itself by a [`Rotation`] policy
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    started: SystemTime,
+    header: Option<String>,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: &Path, rotation: Rotation) -> Result<Self> {
+        let file = open_append(path)?;
+        let metadata = file.metadata()?;
+        let started = if metadata.len() > 0 {
+            metadata
+                .created()
+                .or_else(|_| metadata.modified())
+                .unwrap_or_else(|_| SystemTime::now())
+        } else {
+            SystemTime::now()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            file,
+            size: metadata.len(),
+            started,
+            header: None,
+        })
+    }
+
+    /// Start every new file with `header` (e.g. CSV column names)
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        self.header = Some(header.into());
+        self
+    }
+
+    /// Append `data`, rotating first if it would break the policy
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.due(data.len() as u64) {
+            self.rotate()?;
+        }
+        if self.size == 0 {
+            if let Some(header) = &self.header {
+                let header = format!("{}\n", header);
+                self.file.write_all(header.as_bytes())?;
+                self.size += header.len() as u64;
+            }
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Whether appending `len` bytes needs a rotation first
+    ///
+    /// An empty file is never rotated, so a single oversized write still lands.
+    fn due(&self, len: u64) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .rotation
+            .max_size
+            .is_some_and(|max| self.size + len > max);
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|max| self.started.elapsed().unwrap_or_default() >= max);
+        too_big || too_old
+    }
+
+    /// Move the current file out of the way and start a new one
+    pub fn rotate(&mut self) -> Result<()> {
+        let keep = self.rotation.keep;
+        if keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for suffix in ["", ".gz"] {
+                remove_if_exists(&self.rotated(keep, suffix))?;
+            }
+            for n in (1..keep).rev() {
+                for suffix in ["", ".gz"] {
+                    let from = self.rotated(n, suffix);
+                    if from.exists() {
+                        std::fs::rename(&from, self.rotated(n + 1, suffix))?;
+                    }
+                }
+            }
+            let rotated = self.rotated(1, "");
+            std::fs::rename(&self.path, &rotated)?;
+            self.file = open_append(&self.path)?;
+            if self.rotation.compress {
+                compress(&rotated, &self.rotated(1, ".gz"))?;
+            }
+        }
+        self.size = 0;
+        self.started = SystemTime::now();
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize, suffix: &str) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".{}{}", n, suffix));
+        PathBuf::from(path)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Gzip `from` into `to` and remove `from`
+fn compress(from: &Path, to: &Path) -> Result<()> {
+    let mut input = File::open(from)?;
+    let mut output = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut input, &mut output)?;
+    output.finish()?;
+    std::fs::remove_file(from)?;
+    Ok(())
+}
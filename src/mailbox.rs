@@ -0,0 +1,348 @@
+//! Bounded, coalescing hand-off of commands to a writer thread.
+//!
+//! A UI produces DAC writes as fast as keys repeat or a sweep ticks; if the
+//! device stalls, an unbounded channel to the writer grows without limit and
+//! the device ends up minutes behind the screen. A [`mailbox`] keeps the
+//! commands in order but only the latest value matters per DAC channel: a new
+//! direct write replaces a queued write to the same channel when nothing but
+//! other direct writes follows it. The mailbox never holds more than
+//! `capacity` commands: once it is full, the oldest direct write that a later
+//! queued write supersedes makes room, and a command that still does not fit
+//! is refused and handed back to the sender. Other commands (GPIO, keepalives,
+//! tables, LDAC) are never dropped once queued.
+
+use crate::framing::COMMAND_LEN;
+use crate::protocol::{Command, DAC_CHANNELS};
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Commands a mailbox merged or dropped instead of queueing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Shed {
+    /// DAC writes merged into a queued write to the same channel
+    pub coalesced: u64,
+    /// Superseded DAC writes dropped because the mailbox was full
+    pub dropped: u64,
+    /// Commands refused because the mailbox was full of commands it keeps
+    pub refused: u64,
+}
+
+impl Shed {
+    pub fn total(&self) -> u64 {
+        self.coalesced + self.dropped + self.refused
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    /// One 4-byte command per entry (a trailing partial command stays whole)
+    queue: VecDeque<Vec<u8>>,
+    /// Direct writes queued per DAC channel
+    dac_queued: [usize; DAC_CHANNELS],
+    senders: usize,
+    receiver: bool,
+    shed: Shed,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl State {
+    fn push(&mut self, unit: &[u8]) {
+        if let Some(channel) = dac_channel(unit) {
+            self.dac_queued[channel as usize] += 1;
+        }
+        self.queue.push_back(unit.to_vec());
+    }
+
+    fn remove(&mut self, i: usize) {
+        if let Some(channel) = self.queue.remove(i).as_deref().and_then(dac_channel) {
+            self.dac_queued[channel as usize] -= 1;
+        }
+    }
+
+    /// Index of the queued write to `channel` a new one may replace
+    ///
+    /// Only direct writes to other channels may follow it: anything else (an
+    /// LDAC, a table attachment) could observe the value being replaced. Such
+    /// a run holds at most one write per channel, so this looks at no more
+    /// than [`DAC_CHANNELS`] commands.
+    fn coalescable(&self, channel: u8) -> Option<usize> {
+        for (i, unit) in self.queue.iter().enumerate().rev() {
+            match dac_channel(unit) {
+                Some(queued) if queued == channel => return Some(i),
+                Some(_) => {}
+                None => return None,
+            }
+        }
+        None
+    }
+
+    /// Index of the oldest direct write a later queued write to its channel supersedes
+    fn superseded(&self) -> Option<usize> {
+        if self.dac_queued.iter().all(|&queued| queued < 2) {
+            return None;
+        }
+        self.queue.iter().position(|unit| {
+            dac_channel(unit).is_some_and(|channel| self.dac_queued[channel as usize] > 1)
+        })
+    }
+
+    fn clear(&mut self) {
+        self.queue.clear();
+        self.dac_queued = [0; DAC_CHANNELS];
+    }
+}
+
+/// Create a mailbox holding at most `capacity` commands
+pub fn mailbox(capacity: usize) -> (CommandSender, CommandReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            dac_queued: [0; DAC_CHANNELS],
+            senders: 1,
+            receiver: true,
+            shed: Shed::default(),
+        }),
+        ready: Condvar::new(),
+        capacity: capacity.max(1),
+    });
+    (
+        CommandSender {
+            shared: shared.clone(),
+        },
+        CommandReceiver { shared },
+    )
+}
+
+/// Channel of `unit` if it is a direct DAC write
+fn dac_channel(unit: &[u8]) -> Option<u8> {
+    match Command::decode(unit) {
+        Some(Command::DacWrite { channel, .. }) if unit.len() == COMMAND_LEN => Some(channel),
+        _ => None,
+    }
+}
+
+/// Sending half of a [`mailbox`]; clones feed the same receiver
+#[derive(Debug)]
+pub struct CommandSender {
+    shared: Arc<Shared>,
+}
+
+impl CommandSender {
+    /// Queue the commands in `data`, merging and shedding DAC writes as needed
+    ///
+    /// Commands are queued in order until one does not fit; that one and the
+    /// rest come back as [`TrySendError::Full`]. All of `data` comes back as
+    /// [`TrySendError::Disconnected`] once the receiver is gone.
+    pub fn send(&self, data: Vec<u8>) -> Result<(), TrySendError<Vec<u8>>> {
+        let mut state = self.shared.lock();
+        if !state.receiver {
+            return Err(TrySendError::Disconnected(data));
+        }
+        let mut result = Ok(());
+        for (n, unit) in data.chunks(COMMAND_LEN).enumerate() {
+            if let Some(i) = dac_channel(unit).and_then(|ch| state.coalescable(ch)) {
+                state.queue[i] = unit.to_vec();
+                state.shed.coalesced += 1;
+                continue;
+            }
+            state.push(unit);
+            if state.queue.len() > self.shared.capacity {
+                match state.superseded() {
+                    Some(i) => {
+                        state.remove(i);
+                        state.shed.dropped += 1;
+                    }
+                    None => {
+                        let last = state.queue.len() - 1;
+                        state.remove(last);
+                        let rest = data[n * COMMAND_LEN..].to_vec();
+                        state.shed.refused += commands_in(&rest);
+                        result = Err(TrySendError::Full(rest));
+                        break;
+                    }
+                }
+            }
+        }
+        self.shared.ready.notify_one();
+        result
+    }
+
+    /// Commands merged or dropped so far
+    pub fn shed(&self) -> Shed {
+        self.shared.lock().shed
+    }
+
+    /// Commands waiting for the receiver
+    pub fn pending(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+}
+
+impl Clone for CommandSender {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for CommandSender {
+    fn drop(&mut self) {
+        self.shared.lock().senders -= 1;
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Receiving half of a [`mailbox`]
+#[derive(Debug)]
+pub struct CommandReceiver {
+    shared: Arc<Shared>,
+}
+
+impl CommandReceiver {
+    /// Wait for commands and take everything queued, in order
+    ///
+    /// Fails once the queue is empty and every sender is gone.
+    pub fn recv(&self) -> Result<Vec<u8>, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(commands) = take_all(&mut state) {
+                return Ok(commands);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self.shared.ready.wait(state).unwrap();
+        }
+    }
+
+    /// Like [`recv`](Self::recv), waiting at most `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Vec<u8>, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(commands) = take_all(&mut state) {
+                return Ok(commands);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.shared.ready.wait_timeout(state, left).unwrap().0;
+        }
+    }
+}
+
+impl Drop for CommandReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver = false;
+        state.clear();
+    }
+}
+
+fn take_all(state: &mut State) -> Option<Vec<u8>> {
+    if state.queue.is_empty() {
+        return None;
+    }
+    let commands = state.queue.drain(..).flatten().collect();
+    state.clear();
+    Some(commands)
+}
+
+fn commands_in(data: &[u8]) -> u64 {
+    data.len().div_ceil(COMMAND_LEN) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dac(channel: u8, value: u16) -> Vec<u8> {
+        Command::DacWrite { channel, value }.encode().to_vec()
+    }
+
+    fn gpio(pin: u8) -> Vec<u8> {
+        Command::Gpio { pin, on: true }.encode().to_vec()
+    }
+
+    #[test]
+    fn dac_writes_merge_until_another_command_follows() {
+        let (tx, rx) = mailbox(16);
+        tx.send([dac(0, 1), dac(1, 1), dac(0, 2)].concat()).unwrap();
+        assert_eq!(tx.pending(), 2);
+        tx.send(Command::Ldac.encode().to_vec()).unwrap();
+        tx.send(dac(0, 3)).unwrap();
+        assert_eq!(tx.shed().coalesced, 1);
+        assert_eq!(
+            rx.recv().unwrap(),
+            [
+                dac(0, 2),
+                dac(1, 1),
+                Command::Ldac.encode().to_vec(),
+                dac(0, 3)
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn other_commands_keep_their_order() {
+        let (tx, rx) = mailbox(16);
+        tx.send(gpio(1)).unwrap();
+        tx.send(dac(2, 5)).unwrap();
+        tx.send([gpio(0), Command::KeepAlive.encode().to_vec()].concat())
+            .unwrap();
+        assert_eq!(
+            rx.recv().unwrap(),
+            [
+                gpio(1),
+                dac(2, 5),
+                gpio(0),
+                Command::KeepAlive.encode().to_vec()
+            ]
+            .concat()
+        );
+        assert_eq!(tx.shed(), Shed::default());
+    }
+
+    #[test]
+    fn full_mailbox_drops_superseded_writes_then_refuses() {
+        let (tx, rx) = mailbox(3);
+        tx.send([dac(0, 1), gpio(0), dac(0, 2)].concat()).unwrap();
+        // Room is made by dropping the first write to channel 0
+        tx.send(gpio(1)).unwrap();
+        assert_eq!(tx.shed().dropped, 1);
+        assert_eq!(tx.pending(), 3);
+
+        let rest = [gpio(2), dac(1, 7)].concat();
+        assert_eq!(tx.send(rest.clone()), Err(TrySendError::Full(rest)));
+        assert_eq!(tx.shed().refused, 2);
+        assert_eq!(rx.recv().unwrap(), [gpio(0), dac(0, 2), gpio(1)].concat());
+
+        tx.send(dac(1, 7)).unwrap();
+        drop(rx);
+        assert_eq!(
+            tx.send(dac(1, 8)),
+            Err(TrySendError::Disconnected(dac(1, 8)))
+        );
+    }
+}
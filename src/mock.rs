@@ -0,0 +1,240 @@
+//! In-memory link for testing code that talks to a device.
+//!
+//! [`MockTransport`] records everything written to it and answers with
+//! scripted responses, so logic built on [`DacClient`] or [`CommandStream`]
+//! runs without sockets, PTYs or hardware. Handles are cheap clones sharing
+//! one state: give one to the client and keep one to script and inspect.
+//!
+//! [`DacClient`]: crate::client::DacClient
+//! [`CommandStream`]: crate::stream::CommandStream
+
+use crate::clock::SharedClock;
+use crate::framing::COMMAND_LEN;
+use crate::protocol::Command;
+use crate::transport::Link;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Standard "OK" response used by [`MockTransport::with_auto_ack`]
+const ACK: [u8; 2] = [0x00, 0x00];
+
+#[derive(Debug, Default)]
+struct MockState {
+    /// Every write, in order
+    written: Vec<Vec<u8>>,
+    /// Responses waiting for a write to release them
+    scripted: VecDeque<Vec<u8>>,
+    /// Bytes released and not read yet
+    readable: VecDeque<u8>,
+    auto_ack: bool,
+    disconnected: bool,
+    /// Clock a read with nothing to return waits on, and for how long
+    read_timeout: Option<(SharedClock, Duration)>,
+}
+
+/// A scripted in-memory [`Link`]
+///
+/// Each write releases the next scripted response for reading. With
+/// [`with_auto_ack`](Self::with_auto_ack), a write with no scripted response
+/// left is answered with `[0x00, 0x00]` per command. Reads with nothing
+/// released time out like a quiet device, at once unless a read timeout is
+/// set with [`with_read_timeout`](Self::with_read_timeout).
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer every command without a scripted response with status 0
+    ///
+    /// The acknowledgements are raw `[0x00, 0x00]` frames, for clients
+    /// without CRC or stream framing.
+    pub fn with_auto_ack(self) -> Self {
+        self.state.lock().unwrap().auto_ack = true;
+        self
+    }
+
+    /// Wait `timeout` on `clock` before a read with nothing to return times out
+    ///
+    /// With a [`ManualClock`](crate::clock::ManualClock) shared with the code
+    /// under test, every empty read moves virtual time on like a real link's
+    /// read timeout would, so stall timeouts expire without waiting.
+    pub fn with_read_timeout(self, clock: SharedClock, timeout: Duration) -> Self {
+        self.state.lock().unwrap().read_timeout = Some((clock, timeout));
+        self
+    }
+
+    /// Queue `response` (bytes exactly as the device sends them) for the next write
+    pub fn push_response(&self, response: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripted
+            .push_back(response.to_vec());
+    }
+
+    /// Bytes of every write so far, one entry per write
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.state.lock().unwrap().written.clone()
+    }
+
+    /// Every write so far decoded as raw 4-byte commands
+    ///
+    /// Bytes that do not decode are skipped, so this only makes sense
+    /// without CRC or stream framing; use [`written`](Self::written) otherwise.
+    pub fn commands(&self) -> Vec<Command> {
+        self.state
+            .lock()
+            .unwrap()
+            .written
+            .iter()
+            .flat_map(|data| data.chunks_exact(COMMAND_LEN).filter_map(Command::decode))
+            .collect()
+    }
+
+    /// Forget what was written so far
+    pub fn clear_written(&self) {
+        self.state.lock().unwrap().written.clear();
+    }
+
+    /// Act like a dropped connection: reads return end of file, writes fail
+    pub fn disconnect(&self) {
+        self.state.lock().unwrap().disconnected = true;
+    }
+}
+
+impl Read for MockTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.readable.is_empty() {
+            if state.disconnected {
+                return Ok(0);
+            }
+            if let Some((clock, timeout)) = state.read_timeout.clone() {
+                // Other handles may write while this one waits
+                drop(state);
+                clock.sleep(timeout);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no scripted response",
+            ));
+        }
+        let n = buffer.len().min(state.readable.len());
+        for (slot, byte) in buffer.iter_mut().zip(state.readable.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockTransport {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let state = &mut *self.state.lock().unwrap();
+        if state.disconnected {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "mock transport disconnected",
+            ));
+        }
+        state.written.push(data.to_vec());
+        match state.scripted.pop_front() {
+            Some(response) => state.readable.extend(response),
+            None if state.auto_ack => {
+                for _ in data.chunks(COMMAND_LEN) {
+                    state.readable.extend(ACK);
+                }
+            }
+            None => {}
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Link for MockTransport {
+    fn kind(&self) -> &'static str {
+        "Mock"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::DacClient;
+    use crate::error::DacError;
+    use crate::framing::Codec;
+    use crate::stream::CommandStream;
+
+    fn client(mock: &MockTransport) -> DacClient {
+        DacClient::from_link(Box::new(mock.clone()), Codec::default())
+    }
+
+    #[test]
+    fn auto_ack_answers_every_command() {
+        let mock = MockTransport::new().with_auto_ack();
+        let mut client = client(&mock);
+        let write = Command::DacWrite {
+            channel: 3,
+            value: 0x1234,
+        };
+        client.send(write).unwrap();
+        client.send(Command::Ldac).unwrap();
+        assert_eq!(mock.commands(), vec![write, Command::Ldac]);
+        assert_eq!(client.state().dac[3], 0x1234);
+
+        mock.clear_written();
+        assert!(mock.written().is_empty());
+    }
+
+    #[test]
+    fn scripted_responses_go_before_auto_acks() {
+        let mock = MockTransport::new().with_auto_ack();
+        let mut client = client(&mock);
+        mock.push_response(&[0x00, 0x02]);
+        assert!(matches!(
+            client.send(Command::Ldac),
+            Err(DacError::DeviceStatus(0x02))
+        ));
+        client.send(Command::Ldac).unwrap();
+    }
+
+    #[test]
+    fn responses_released_in_pieces_are_reassembled() {
+        let mock = MockTransport::new();
+        let mut stream = CommandStream::new(mock.clone(), Codec::default(), 2);
+        mock.push_response(&[0x00]);
+        mock.push_response(&[0x00, 0x00, 0x00]);
+        stream.send(&Command::Ldac.encode()).unwrap();
+        stream.send(&Command::KeepAlive.encode()).unwrap();
+        stream.drain().unwrap();
+        assert_eq!(stream.take_responses(), vec![vec![0x00, 0x00]; 2]);
+        assert_eq!(mock.written().len(), 2);
+    }
+
+    #[test]
+    fn disconnected_link_is_a_transport_error() {
+        let mock = MockTransport::new().with_auto_ack();
+        let mut client = client(&mock);
+        mock.disconnect();
+        assert!(matches!(
+            client.send(Command::Ldac),
+            Err(DacError::Transport(_))
+        ));
+        assert_eq!(client.failovers(), 0);
+        assert!(mock.written().is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+//! Modbus TCP front-end: the device as a Modbus server.
+//!
+//! PLCs and SCADA systems see the DAC through this register map:
+//!
+//! | Table             | Address | Meaning                                     |
+//! |-------------------|---------|---------------------------------------------|
+//! | Holding registers | 0-7     | DAC channel outputs (write: `DacWrite`)      |
+//! | Holding registers | 8       | Table offset (write 0-255: `UseTable`)       |
+//! | Coils             | 0-7     | GPIO pins (write: `Gpio`)                    |
+//!
+//! Function codes 01 (read coils), 03 (read holding registers), 05/15 (write
+//! single/multiple coils) and 06/16 (write single/multiple registers) are
+//! supported. Reads are answered from the [`DeviceState`] built from the
+//! commands sent, since the device itself cannot be read back.
+
+use crate::device::DeviceState;
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS};
+
+/// Length of the MBAP header in front of every PDU
+pub const MBAP_LEN: usize = 7;
+
+/// Holding register selecting the table offset
+pub const REG_TABLE_OFFSET: u16 = DAC_CHANNELS as u16;
+
+/// Number of holding registers: the DAC channels and the table offset
+pub const HOLDING_REGISTERS: usize = DAC_CHANNELS + 1;
+
+pub const FC_READ_COILS: u8 = 0x01;
+pub const FC_READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const FC_WRITE_SINGLE_COIL: u8 = 0x05;
+pub const FC_WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const FC_WRITE_MULTIPLE_COILS: u8 = 0x0F;
+pub const FC_WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Largest PDU allowed by the specification
+const MAX_PDU: usize = 253;
+
+/// One Modbus TCP application data unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub transaction: u16,
+    pub unit: u8,
+    pub pdu: Vec<u8>,
+}
+
+impl Frame {
+    /// Split the first complete frame off `buf`, or `None` until one has arrived
+    pub fn take(buf: &mut Vec<u8>) -> Result<Option<Frame>> {
+        if buf.len() < MBAP_LEN {
+            return Ok(None);
+        }
+        let protocol = u16::from_be_bytes([buf[2], buf[3]]);
+        if protocol != 0 {
+            return Err(DacError::Protocol(format!(
+                "Not a Modbus frame (protocol id {})",
+                protocol
+            )));
+        }
+        // The length counts the unit id and the PDU
+        let length = u16::from_be_bytes([buf[4], buf[5]]) as usize;
+        if !(2..=MAX_PDU + 1).contains(&length) {
+            return Err(DacError::Protocol(format!(
+                "Bad Modbus frame length {}",
+                length
+            )));
+        }
+        let total = MBAP_LEN - 1 + length;
+        if buf.len() < total {
+            return Ok(None);
+        }
+        let frame = Frame {
+            transaction: u16::from_be_bytes([buf[0], buf[1]]),
+            unit: buf[6],
+            pdu: buf[MBAP_LEN..total].to_vec(),
+        };
+        buf.drain(..total);
+        Ok(Some(frame))
+    }
+
+    /// A frame answering this one with `pdu`
+    pub fn reply(&self, pdu: Vec<u8>) -> Frame {
+        Frame {
+            transaction: self.transaction,
+            unit: self.unit,
+            pdu,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MBAP_LEN + self.pdu.len());
+        out.extend_from_slice(&self.transaction.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+        out.extend_from_slice(&(self.pdu.len() as u16 + 1).to_be_bytes());
+        out.push(self.unit);
+        out.extend_from_slice(&self.pdu);
+        out
+    }
+}
+
+/// Modbus exception codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    IllegalFunction = 0x01,
+    IllegalDataAddress = 0x02,
+    IllegalDataValue = 0x03,
+    ServerDeviceFailure = 0x04,
+}
+
+impl Exception {
+    /// The exception response PDU to function code `function`
+    pub fn response(self, function: u8) -> Vec<u8> {
+        vec![function | 0x80, self as u8]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    ReadCoils { start: u16, count: u16 },
+    ReadHoldingRegisters { start: u16, count: u16 },
+    WriteSingleCoil { address: u16, on: bool },
+    WriteSingleRegister { address: u16, value: u16 },
+    WriteMultipleCoils { start: u16, values: Vec<bool> },
+    WriteMultipleRegisters { start: u16, values: Vec<u16> },
+}
+
+fn word(pdu: &[u8], at: usize) -> std::result::Result<u16, Exception> {
+    pdu.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(Exception::IllegalDataValue)
+}
+
+/// `start..start + count` if it lies within `size` entries
+fn check_range(start: u16, count: usize, size: usize) -> std::result::Result<(), Exception> {
+    if start as usize + count <= size {
+        Ok(())
+    } else {
+        Err(Exception::IllegalDataAddress)
+    }
+}
+
+impl Request {
+    /// Parse a request PDU
+    pub fn decode(pdu: &[u8]) -> std::result::Result<Request, Exception> {
+        let function = *pdu.first().ok_or(Exception::IllegalFunction)?;
+        if !matches!(
+            function,
+            FC_READ_COILS
+                | FC_READ_HOLDING_REGISTERS
+                | FC_WRITE_SINGLE_COIL
+                | FC_WRITE_SINGLE_REGISTER
+                | FC_WRITE_MULTIPLE_COILS
+                | FC_WRITE_MULTIPLE_REGISTERS
+        ) {
+            return Err(Exception::IllegalFunction);
+        }
+        let start = word(pdu, 1)?;
+        let request = match function {
+            FC_READ_COILS | FC_READ_HOLDING_REGISTERS => {
+                let count = word(pdu, 3)?;
+                let max = if function == FC_READ_COILS { 2000 } else { 125 };
+                if count == 0 || count > max {
+                    return Err(Exception::IllegalDataValue);
+                }
+                if function == FC_READ_COILS {
+                    Request::ReadCoils { start, count }
+                } else {
+                    Request::ReadHoldingRegisters { start, count }
+                }
+            }
+            FC_WRITE_SINGLE_COIL => Request::WriteSingleCoil {
+                address: start,
+                on: match word(pdu, 3)? {
+                    0xFF00 => true,
+                    0x0000 => false,
+                    _ => return Err(Exception::IllegalDataValue),
+                },
+            },
+            FC_WRITE_SINGLE_REGISTER => Request::WriteSingleRegister {
+                address: start,
+                value: word(pdu, 3)?,
+            },
+            FC_WRITE_MULTIPLE_COILS | FC_WRITE_MULTIPLE_REGISTERS => {
+                let count = word(pdu, 3)? as usize;
+                let bytes = *pdu.get(5).ok_or(Exception::IllegalDataValue)? as usize;
+                let data = pdu.get(6..6 + bytes).ok_or(Exception::IllegalDataValue)?;
+                if function == FC_WRITE_MULTIPLE_COILS {
+                    if count == 0 || count > 1968 || bytes != count.div_ceil(8) {
+                        return Err(Exception::IllegalDataValue);
+                    }
+                    let values = (0..count)
+                        .map(|i| data[i / 8] & (1 << (i % 8)) != 0)
+                        .collect();
+                    Request::WriteMultipleCoils { start, values }
+                } else {
+                    if count == 0 || count > 123 || bytes != count * 2 {
+                        return Err(Exception::IllegalDataValue);
+                    }
+                    let values = data
+                        .chunks_exact(2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                        .collect();
+                    Request::WriteMultipleRegisters { start, values }
+                }
+            }
+            _ => unreachable!(),
+        };
+        Ok(request)
+    }
+
+    pub fn function(&self) -> u8 {
+        match self {
+            Request::ReadCoils { .. } => FC_READ_COILS,
+            Request::ReadHoldingRegisters { .. } => FC_READ_HOLDING_REGISTERS,
+            Request::WriteSingleCoil { .. } => FC_WRITE_SINGLE_COIL,
+            Request::WriteSingleRegister { .. } => FC_WRITE_SINGLE_REGISTER,
+            Request::WriteMultipleCoils { .. } => FC_WRITE_MULTIPLE_COILS,
+            Request::WriteMultipleRegisters { .. } => FC_WRITE_MULTIPLE_REGISTERS,
+        }
+    }
+
+    /// The device commands carrying out this request, in address order
+    pub fn commands(&self) -> std::result::Result<Vec<Command>, Exception> {
+        let coil = |pin: usize, on: bool| Command::Gpio { pin: pin as u8, on };
+        let register = |address: usize, value: u16| {
+            if address < DAC_CHANNELS {
+                Ok(Command::DacWrite {
+                    channel: address as u8,
+                    value,
+                })
+            } else {
+                u8::try_from(value)
+                    .map(|offset| Command::UseTable { offset })
+                    .map_err(|_| Exception::IllegalDataValue)
+            }
+        };
+
+        match self {
+            Request::ReadCoils { start, count } => {
+                check_range(*start, *count as usize, GPIO_PINS).map(|_| Vec::new())
+            }
+            Request::ReadHoldingRegisters { start, count } => {
+                check_range(*start, *count as usize, HOLDING_REGISTERS).map(|_| Vec::new())
+            }
+            Request::WriteSingleCoil { address, on } => {
+                check_range(*address, 1, GPIO_PINS)?;
+                Ok(vec![coil(*address as usize, *on)])
+            }
+            Request::WriteSingleRegister { address, value } => {
+                check_range(*address, 1, HOLDING_REGISTERS)?;
+                Ok(vec![register(*address as usize, *value)?])
+            }
+            Request::WriteMultipleCoils { start, values } => {
+                check_range(*start, values.len(), GPIO_PINS)?;
+                Ok(values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &on)| coil(*start as usize + i, on))
+                    .collect())
+            }
+            Request::WriteMultipleRegisters { start, values } => {
+                check_range(*start, values.len(), HOLDING_REGISTERS)?;
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &value)| register(*start as usize + i, value))
+                    .collect()
+            }
+        }
+    }
+
+    /// The response PDU, once the request's commands have been applied to `state`
+    pub fn response(&self, state: &DeviceState) -> Vec<u8> {
+        let mut out = vec![self.function()];
+        match self {
+            Request::ReadCoils { start, count } => {
+                let coils = &state.gpio[*start as usize..][..*count as usize];
+                out.push(coils.len().div_ceil(8) as u8);
+                for byte in coils.chunks(8) {
+                    out.push(
+                        byte.iter()
+                            .enumerate()
+                            .fold(0, |acc, (i, &on)| acc | (u8::from(on) << i)),
+                    );
+                }
+            }
+            Request::ReadHoldingRegisters { start, count } => {
+                out.push((*count * 2) as u8);
+                for address in *start as usize..(*start + *count) as usize {
+                    let value = match state.dac.get(address) {
+                        Some(&value) => value,
+                        None => state.table_offset as u16,
+                    };
+                    out.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Request::WriteSingleCoil { address, on } => {
+                out.extend_from_slice(&address.to_be_bytes());
+                out.extend_from_slice(&if *on { 0xFF00u16 } else { 0 }.to_be_bytes());
+            }
+            Request::WriteSingleRegister { address, value } => {
+                out.extend_from_slice(&address.to_be_bytes());
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Request::WriteMultipleCoils { start, values } => {
+                out.extend_from_slice(&start.to_be_bytes());
+                out.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            }
+            Request::WriteMultipleRegisters { start, values } => {
+                out.extend_from_slice(&start.to_be_bytes());
+                out.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,190 @@
+//! MQTT topics and Home Assistant discovery for the device.
+//!
+//! Every DAC channel and GPIO pin gets a retained state topic and a command
+//! topic under `<prefix>/<device id>`:
+//!
+//! | Topic                   | Payload                                   |
+//! |-------------------------|-------------------------------------------|
+//! | `.../dac/<n>`           | Output code, 0-65535                      |
+//! | `.../dac/<n>/set`       | Code to write                             |
+//! | `.../gpio/<n>`          | `ON` or `OFF`                             |
+//! | `.../gpio/<n>/set`      | `ON` or `OFF`                             |
+//! | `.../availability`      | `online`, or `offline` (last will)        |
+//!
+//! [`Topics::discovery`] builds the retained Home Assistant discovery configs
+//! that make each channel a `number` entity and each pin a `switch`, grouped
+//! under one device.
+
+use crate::device::DeviceState;
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS};
+use crate::report::{self, Value};
+
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Topics {
+    /// `<prefix>/<device id>`
+    base: String,
+    device_id: String,
+    /// Home Assistant discovery prefix, usually `homeassistant`
+    discovery_prefix: String,
+}
+
+impl Topics {
+    pub fn new(prefix: &str, device_id: &str, discovery_prefix: &str) -> Self {
+        Self {
+            base: format!("{}/{}", prefix.trim_end_matches('/'), device_id),
+            device_id: device_id.to_string(),
+            discovery_prefix: discovery_prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn availability(&self) -> String {
+        format!("{}/availability", self.base)
+    }
+
+    pub fn dac(&self, channel: usize) -> String {
+        format!("{}/dac/{}", self.base, channel)
+    }
+
+    pub fn gpio(&self, pin: usize) -> String {
+        format!("{}/gpio/{}", self.base, pin)
+    }
+
+    /// Filter matching every command topic
+    pub fn commands(&self) -> String {
+        format!("{}/+/+/set", self.base)
+    }
+
+    /// Home Assistant's birth topic; discovery is republished when it comes online
+    pub fn ha_status(&self) -> String {
+        format!("{}/status", self.discovery_prefix)
+    }
+
+    /// The command a message on a command topic asks for
+    ///
+    /// `None` if `topic` is not a command topic of this device.
+    pub fn parse_command(&self, topic: &str, payload: &[u8]) -> Option<Result<Command>> {
+        let rest = topic.strip_prefix(&self.base)?.strip_prefix('/')?;
+        let (kind, index) = rest.strip_suffix("/set")?.split_once('/')?;
+        let index: u8 = index.parse().ok()?;
+        let payload = String::from_utf8_lossy(payload);
+        let payload = payload.trim();
+        let invalid =
+            || DacError::InvalidArgument(format!("Bad payload {:?} on {}", payload, topic));
+        let cmd = match kind {
+            "dac" if (index as usize) < DAC_CHANNELS => payload
+                .parse::<f64>()
+                .ok()
+                .filter(|v| (0.0..=65535.0).contains(v))
+                .map(|v| Command::DacWrite {
+                    channel: index,
+                    value: v.round() as u16,
+                })
+                .ok_or_else(invalid),
+            "gpio" if (index as usize) < GPIO_PINS => match payload {
+                "ON" => Ok(Command::Gpio {
+                    pin: index,
+                    on: true,
+                }),
+                "OFF" => Ok(Command::Gpio {
+                    pin: index,
+                    on: false,
+                }),
+                _ => Err(invalid()),
+            },
+            _ => return None,
+        };
+        Some(cmd)
+    }
+
+    /// Retained `(topic, payload)` of every channel and pin
+    pub fn states(&self, state: &DeviceState) -> Vec<(String, String)> {
+        let dac = state
+            .dac
+            .iter()
+            .enumerate()
+            .map(|(ch, value)| (self.dac(ch), value.to_string()));
+        let gpio = state
+            .gpio
+            .iter()
+            .enumerate()
+            .map(|(pin, &on)| (self.gpio(pin), on_off(on).to_string()));
+        dac.chain(gpio).collect()
+    }
+
+    /// Retained `(topic, payload)` of the channel or pin `cmd` writes
+    pub fn state_of(&self, cmd: &Command, state: &DeviceState) -> Option<(String, String)> {
+        match *cmd {
+            Command::DacWrite { channel, .. } => {
+                let ch = channel as usize;
+                Some((self.dac(ch), state.dac[ch].to_string()))
+            }
+            Command::Gpio { pin, .. } => {
+                let pin = pin as usize;
+                Some((self.gpio(pin), on_off(state.gpio[pin]).to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Retained Home Assistant discovery `(topic, config)` of every entity
+    pub fn discovery(&self) -> Vec<(String, String)> {
+        let id = format!("csv1_{}", self.device_id);
+        let device = Value::object()
+            .with("identifiers", vec![id.clone()])
+            .with("name", format!("csv1 {}", self.device_id))
+            .with("manufacturer", "csv1")
+            .with("model", "OL8")
+            .with("sw_version", report::VERSION);
+        let entity = |name: String, unique_id: String, state: String| {
+            Value::object()
+                .with("name", name)
+                .with("unique_id", unique_id)
+                .with("state_topic", state.clone())
+                .with("command_topic", format!("{}/set", state))
+                .with("availability_topic", self.availability())
+                .with("device", device.clone())
+        };
+
+        let numbers = (0..DAC_CHANNELS).map(|ch| {
+            let config = entity(
+                format!("DAC {}", ch),
+                format!("{}_dac{}", id, ch),
+                self.dac(ch),
+            )
+            .with("min", 0)
+            .with("max", 65535)
+            .with("step", 1)
+            .with("mode", "box");
+            (
+                format!("{}/number/{}/dac{}/config", self.discovery_prefix, id, ch),
+                config.to_string(),
+            )
+        });
+        let switches = (0..GPIO_PINS).map(|pin| {
+            let config = entity(
+                format!("GPIO {}", pin),
+                format!("{}_gpio{}", id, pin),
+                self.gpio(pin),
+            )
+            .with("payload_on", "ON")
+            .with("payload_off", "OFF");
+            (
+                format!("{}/switch/{}/gpio{}/config", self.discovery_prefix, id, pin),
+                config.to_string(),
+            )
+        });
+        numbers.chain(switches).collect()
+    }
+}
@@ -0,0 +1,67 @@
+//! Progress of bulk operations.
+//!
+//! Long operations such as queued table uploads
+//! ([`CommandStream::with_progress`](crate::stream::CommandStream::with_progress))
+//! report a [`Progress`] after every step. The callback gets it directly; to
+//! follow an operation from another thread, send it through a channel from the
+//! callback. Its `Display` is the one-line text the command-line tools print.
+
+use crate::protocol::Command;
+use std::fmt;
+use std::time::Duration;
+
+/// Callback receiving the progress of an operation
+pub type ProgressFn = Box<dyn FnMut(&Progress) + Send>;
+
+/// How far an operation has got
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// Items finished
+    pub done: usize,
+    /// Items in the whole operation, including those finished
+    pub total: usize,
+    /// Time since the operation started
+    pub elapsed: Duration,
+    /// The item just finished, e.g. the last table entry sent
+    pub current: Option<Command>,
+}
+
+impl Progress {
+    /// Share finished, from 0.0 to 1.0 (1.0 for an empty operation)
+    pub fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            (self.done as f64 / self.total as f64).min(1.0)
+        }
+    }
+
+    /// Time left at the rate so far, `None` before the first item
+    pub fn eta(&self) -> Option<Duration> {
+        if self.done == 0 {
+            return None;
+        }
+        let left = self.total.saturating_sub(self.done) as f64;
+        Some(self.elapsed.mul_f64(left / self.done as f64))
+    }
+}
+
+/// `180/256 (70%), 0.3s left: Table write: ...`
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} ({:.0}%)",
+            self.done,
+            self.total,
+            self.ratio() * 100.0
+        )?;
+        if let Some(eta) = self.eta() {
+            write!(f, ", {:.1}s left", eta.as_secs_f64())?;
+        }
+        if let Some(current) = &self.current {
+            write!(f, ": {}", current)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,674 @@
+//! The csv1-ol8 4-byte command protocol.
+//!
+//! ```text
+//! + -----------------------------------------------+
+//! | First byte  | Second byte  | third & 4th bytes |
+//! + -----------------------------------------------+
+//! | n = 0..7    | 0x00         | vv                | DirectWrite DAC(n)=vv
+//! | n = 0..7    | i+16 (16..19)| vv                | AttachTable DAC(n)=Table(i)
+//! | i+16(16..19)| n (0..255)   | vv                | Table(i)[n]=vv
+//! | 0xff        | n (0..255)   | 0x0000            | UseTable
+//! | 0xfe        | n (0..7)     | 0x0000..0x0001    | control GPIOn
+//! | 0xfd        | 0x00         | 0x0000            | KeepAlive (to avoid disabling GPIO0)
+//! | 0xfc        | 0x00         | 0x0000            | LDAC - update DACs with loaded values
+//! | 0xfb        | n (0..255)   | vv                | Register write
+//! | 0xf8        | n (0..255)   | 0x0000            | Register read, answered `01 02 hh ll`
+//! | 0xf7        | v (version)  | feature bits      | Hello, answered `01 03 vv ff ff`
+//! | 0xf5        | n (0..255)   | i (0..3)          | Table(i)[n] read, answered `01 02 hh ll`
+//! | 0xfa        | 0x00         | 0x0000            | Take control of a `--roles` bridge (not forwarded)
+//! | 0xf9        | 0x00         | 0x0000            | Heartbeat, answered by the bridge (not forwarded)
+//! + -----------------------------------------------+
+//! ```
+//!
+//! The two reads, `0xf8` and `0xf5`, are not part of the stock firmware. Only
+//! a device that agrees to [`Features::READBACK`] in its hello has them, and
+//! [`DacClient`](crate::client::DacClient) refuses to send them to any other.
+
+use crate::error::{DacError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of DAC channels
+pub const DAC_CHANNELS: usize = 8;
+
+/// Number of GPIO pins
+pub const GPIO_PINS: usize = 8;
+
+/// Number of waveform tables
+pub const TABLES: usize = 4;
+
+/// Entries per waveform table
+pub const TABLE_LEN: usize = 256;
+
+/// First byte value addressing table 0
+pub const TABLE_BASE: u8 = 16;
+
+pub const CMD_USE_TABLE: u8 = 0xFF;
+pub const CMD_GPIO: u8 = 0xFE;
+pub const CMD_KEEPALIVE: u8 = 0xFD;
+pub const CMD_LDAC: u8 = 0xFC;
+pub const CMD_REGISTER: u8 = 0xFB;
+pub const CMD_TAKEOVER: u8 = 0xFA;
+pub const CMD_HEARTBEAT: u8 = 0xF9;
+pub const CMD_REGISTER_READ: u8 = 0xF8;
+pub const CMD_HELLO: u8 = 0xF7;
+pub const CMD_TABLE_READ: u8 = 0xF5;
+
+/// Protocol version these tools speak in a hello; 0 stands for a device without hello
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// A bridge's answer to a heartbeat: an extended response carrying the command byte,
+/// so clients can tell it from device responses
+pub const HEARTBEAT_RESPONSE: [u8; 3] = [0x01, 0x01, CMD_HEARTBEAT];
+
+/// Status answered by a `--roles` bridge to a write from a read-only observer
+pub const STATUS_READ_ONLY: u8 = 0xFD;
+
+/// Status answered once to a controller displaced by another client's takeover
+pub const STATUS_DISPLACED: u8 = 0xFC;
+
+/// One row of the command reference: the four bytes and what the command does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandLayout {
+    pub first: String,
+    pub second: String,
+    /// Third and fourth bytes, a big-endian value
+    pub value: String,
+    pub meaning: &'static str,
+}
+
+/// Every command layout, in the order of the module table
+pub fn command_layouts() -> Vec<CommandLayout> {
+    let channels = format!("n = 0..{}", DAC_CHANNELS - 1);
+    let tables = format!(
+        "i+{} ({}..{})",
+        TABLE_BASE,
+        TABLE_BASE,
+        TABLE_BASE + TABLES as u8 - 1
+    );
+    let row = |first: String, second: String, value: &str, meaning| CommandLayout {
+        first,
+        second,
+        value: value.to_string(),
+        meaning,
+    };
+    let fixed = |cmd: u8, second: &str, value: &str, meaning| {
+        row(format!("0x{:02x}", cmd), second.to_string(), value, meaning)
+    };
+    vec![
+        row(
+            channels.clone(),
+            "0x00".into(),
+            "vv",
+            "DirectWrite DAC(n)=vv",
+        ),
+        row(
+            channels,
+            tables.clone(),
+            "0x0000",
+            "AttachTable DAC(n)=Table(i)",
+        ),
+        row(tables, "n (0..255)".into(), "vv", "Table(i)[n]=vv"),
+        fixed(CMD_USE_TABLE, "n (0..255)", "0x0000", "UseTable offset n"),
+        fixed(
+            CMD_GPIO,
+            &format!("n (0..{})", GPIO_PINS - 1),
+            "0x0000..0x0001",
+            "Control GPIOn",
+        ),
+        fixed(
+            CMD_KEEPALIVE,
+            "0x00",
+            "0x0000",
+            "KeepAlive (to avoid disabling GPIO0)",
+        ),
+        fixed(
+            CMD_LDAC,
+            "0x00",
+            "0x0000",
+            "LDAC - update DACs with loaded values",
+        ),
+        fixed(CMD_REGISTER, "n (0..255)", "vv", "Register write"),
+        fixed(
+            CMD_REGISTER_READ,
+            "n (0..255)",
+            "0x0000",
+            "Register read (extended response, readback firmware)",
+        ),
+        fixed(
+            CMD_HELLO,
+            "v (version)",
+            "feature bits",
+            "Hello: offer protocol features",
+        ),
+        fixed(
+            CMD_TABLE_READ,
+            "n (0..255)",
+            &format!("i (0..{})", TABLES - 1),
+            "Table(i)[n] read (extended response, readback firmware)",
+        ),
+        fixed(
+            CMD_TAKEOVER,
+            "0x00",
+            "0x0000",
+            "Take control of a `--roles` bridge",
+        ),
+        fixed(
+            CMD_HEARTBEAT,
+            "0x00",
+            "0x0000",
+            "Heartbeat, answered by the bridge",
+        ),
+    ]
+}
+
+/// Response byte patterns and what they mean
+pub fn response_formats() -> Vec<(String, &'static str)> {
+    let status = |status: u8| format!("00 {:02X}", status);
+    vec![
+        (status(0x00), "OK"),
+        ("00 ss".to_string(), "Device error status ss"),
+        (
+            status(STATUS_READ_ONLY),
+            "Write refused: read-only observer of a `--roles` bridge",
+        ),
+        (
+            status(STATUS_DISPLACED),
+            "Control taken over by another client (sent once)",
+        ),
+        (
+            status(crate::framing::STATUS_CRC_ERROR),
+            "Command failed its CRC check (`--crc`)",
+        ),
+        (
+            "01 ll ..".to_string(),
+            "Extended response: ll payload bytes",
+        ),
+        (
+            "01 02 hh ll".to_string(),
+            "Register or table read: the value hhll",
+        ),
+        (
+            "01 03 vv ff ff".to_string(),
+            "Hello: device version vv, features agreed ffff",
+        ),
+        (
+            HEARTBEAT_RESPONSE
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "Heartbeat answered by the bridge",
+        ),
+    ]
+}
+
+/// Check the status byte of a standard `[0x00, status]` response
+///
+/// Extended `[0x01, len, payload]` responses carry no status and always pass.
+pub fn check_status(response: &[u8]) -> Result<()> {
+    match *response {
+        [0x00, 0x00, ..] | [0x01, ..] => Ok(()),
+        [0x00, status, ..] => Err(DacError::DeviceStatus(status)),
+        _ => Err(DacError::Protocol(format!(
+            "Unexpected response: {:02X?}",
+            response
+        ))),
+    }
+}
+
+/// Value of a register or table read response, `[0x01, 0x02, hi, lo]`
+///
+/// Payload bytes after the value, such as a bridge's timestamp, are ignored.
+pub fn register_value(response: &[u8]) -> Result<u16> {
+    match *response {
+        [0x01, 0x02..=0xFF, hi, lo, ..] => Ok(u16::from_be_bytes([hi, lo])),
+        [0x00, status, ..] if status != 0 => Err(DacError::DeviceStatus(status)),
+        _ => Err(DacError::Protocol(format!(
+            "Expected a register value, got {:02X?}",
+            response
+        ))),
+    }
+}
+
+/// Optional protocol features, agreed on through a hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Features(u16);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    /// CRC16 on every command and response after the hello
+    pub const CRC: Features = Features(1 << 0);
+    /// Extended `01 ll ..` responses, such as register values
+    pub const EXTENDED: Features = Features(1 << 1);
+    /// Register (`0xF8`) and table (`0xF5`) reads, which only firmware
+    /// listing this bit in its hello implements; they answer with extended
+    /// responses, so they come with [`EXTENDED`](Self::EXTENDED)
+    pub const READBACK: Features = Features(1 << 2);
+    /// Every feature these tools know
+    pub const ALL: Features = Features(Self::CRC.0 | Self::EXTENDED.0 | Self::READBACK.0);
+
+    const NAMES: [(Features, &'static str); 3] = [
+        (Self::CRC, "crc"),
+        (Self::EXTENDED, "extended"),
+        (Self::READBACK, "readback"),
+    ];
+
+    /// Features from their wire bits, unknown bits included
+    pub const fn from_bits(bits: u16) -> Self {
+        Features(bits)
+    }
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersection(self, other: Features) -> Self {
+        Features(self.0 & other.0)
+    }
+
+    pub const fn without(self, other: Features) -> Self {
+        Features(self.0 & !other.0)
+    }
+}
+
+impl std::ops::BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<String> = Self::NAMES
+            .iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let unknown = self.without(Self::ALL);
+        if unknown != Self::NONE {
+            names.push(format!("0x{:04X}", unknown.0));
+        }
+        if names.is_empty() {
+            return write!(f, "none");
+        }
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+impl FromStr for Features {
+    type Err = DacError;
+
+    /// Comma-separated names, e.g. `crc,extended`, or `none`
+    fn from_str(s: &str) -> Result<Self> {
+        let mut features = Features::NONE;
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if name.eq_ignore_ascii_case("none") {
+                continue;
+            }
+            let (feature, _) = Self::NAMES
+                .iter()
+                .find(|(_, known)| known.eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    DacError::InvalidArgument(format!(
+                        "Unknown feature '{}' (known: crc, extended, readback)",
+                        name
+                    ))
+                })?;
+            features = features | *feature;
+        }
+        Ok(features)
+    }
+}
+
+/// What a device said about itself in answer to a hello
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hello {
+    /// Its protocol version, 0 for firmware without hello
+    pub version: u8,
+    /// The features offered that it agreed to use
+    pub features: Features,
+}
+
+impl Hello {
+    /// A device that does not know hello: version 0, no features
+    pub const LEGACY: Hello = Hello {
+        version: 0,
+        features: Features::NONE,
+    };
+}
+
+/// The answer to a hello, `[0x01, 0x03, version, hi, lo]`
+///
+/// Any two-byte response, such as an error status, comes from firmware that
+/// does not know the command and is taken as [`Hello::LEGACY`]. Payload bytes
+/// after the features are ignored.
+pub fn hello_answer(response: &[u8]) -> Result<Hello> {
+    match *response {
+        [0x01, 0x03..=0xFF, version, hi, lo, ..] => Ok(Hello {
+            version,
+            features: Features::from_bits(u16::from_be_bytes([hi, lo])),
+        }),
+        [_, _] => Ok(Hello::LEGACY),
+        _ => Err(DacError::Protocol(format!(
+            "Expected a hello answer, got {:02X?}",
+            response
+        ))),
+    }
+}
+
+/// Parse hex bytes such as `fb 10 be ef`, `0xfb,0x10` or `fb10beef` into a raw frame
+pub fn parse_frame(text: &str) -> Result<Vec<u8>> {
+    let invalid = |token: &str| DacError::InvalidArgument(format!("Invalid hex bytes '{}'", token));
+    let mut frame = Vec::new();
+    for token in text
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .filter(|token| !token.is_empty())
+    {
+        let digits = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .unwrap_or(token);
+        if digits.is_empty() || !digits.is_ascii() || digits.len() % 2 != 0 {
+            return Err(invalid(token));
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid(token))?;
+            frame.push(u8::from_str_radix(pair, 16).map_err(|_| invalid(token))?);
+        }
+    }
+    if frame.is_empty() {
+        return Err(DacError::InvalidArgument("Empty frame".to_string()));
+    }
+    Ok(frame)
+}
+
+/// Commands writing `values` to `table` from entry 0 (at most [`TABLE_LEN`] are used)
+pub fn table_upload(table: u8, values: &[u16]) -> Vec<u8> {
+    values
+        .iter()
+        .take(TABLE_LEN)
+        .enumerate()
+        .flat_map(|(index, &value)| {
+            Command::TableWrite {
+                table,
+                index: index as u8,
+                value,
+            }
+            .encode()
+        })
+        .collect()
+}
+
+/// Validate every 4-byte command in `data` (see [`Command::validate`])
+pub fn validate_commands(data: &[u8]) -> Result<()> {
+    data.chunks(4)
+        .try_for_each(|cmd| Command::validate(cmd).map(|_| ()))
+}
+
+/// A decoded protocol command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    DacWrite {
+        channel: u8,
+        value: u16,
+    },
+    AttachTable {
+        channel: u8,
+        table: u8,
+    },
+    TableWrite {
+        table: u8,
+        index: u8,
+        value: u16,
+    },
+    UseTable {
+        offset: u8,
+    },
+    Gpio {
+        pin: u8,
+        on: bool,
+    },
+    KeepAlive,
+    Ldac,
+    RegisterWrite {
+        reg: u8,
+        value: u16,
+    },
+    /// Handled by a `--roles` bridge, never sent on to the device
+    Takeover,
+    /// Answered by the bridge itself to show the connection is alive
+    Heartbeat,
+    /// Answered with the register's value in an extended response, by
+    /// firmware with [`Features::READBACK`]
+    RegisterRead {
+        reg: u8,
+    },
+    /// Offers `features` at protocol `version`, answered with a [`Hello`]
+    Hello {
+        version: u8,
+        features: Features,
+    },
+    /// Answered with the table entry in an extended response, by firmware
+    /// with [`Features::READBACK`]
+    TableRead {
+        table: u8,
+        index: u8,
+    },
+}
+
+impl Command {
+    /// Decode a 4-byte command, returning `None` for unknown commands
+    pub fn decode(cmd: &[u8]) -> Option<Command> {
+        let [cmd_type, param, hi, lo] = *cmd else {
+            return None;
+        };
+        let value = ((hi as u16) << 8) | (lo as u16);
+
+        match cmd_type {
+            0..=7 if param == 0x00 => Some(Command::DacWrite {
+                channel: cmd_type,
+                value,
+            }),
+            0..=7 if (TABLE_BASE..TABLE_BASE + TABLES as u8).contains(&param) => {
+                Some(Command::AttachTable {
+                    channel: cmd_type,
+                    table: param - TABLE_BASE,
+                })
+            }
+            16..=19 => Some(Command::TableWrite {
+                table: cmd_type - TABLE_BASE,
+                index: param,
+                value,
+            }),
+            CMD_USE_TABLE => Some(Command::UseTable { offset: param }),
+            CMD_GPIO => Some(Command::Gpio {
+                pin: param,
+                on: value != 0,
+            }),
+            CMD_KEEPALIVE => Some(Command::KeepAlive),
+            CMD_LDAC => Some(Command::Ldac),
+            CMD_REGISTER => Some(Command::RegisterWrite { reg: param, value }),
+            CMD_TAKEOVER => Some(Command::Takeover),
+            CMD_HEARTBEAT => Some(Command::Heartbeat),
+            CMD_REGISTER_READ => Some(Command::RegisterRead { reg: param }),
+            CMD_HELLO => Some(Command::Hello {
+                version: param,
+                features: Features::from_bits(value),
+            }),
+            CMD_TABLE_READ if value < TABLES as u16 => Some(Command::TableRead {
+                table: lo,
+                index: param,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Decode a 4-byte command, explaining what is wrong with invalid ones
+    ///
+    /// Stricter than [`decode`](Self::decode): fields the device ignores must
+    /// be zero and GPIO values must be 0 or 1, so a corrupted or mis-built
+    /// frame is caught before the device misinterprets it.
+    pub fn validate(cmd: &[u8]) -> Result<Command> {
+        let invalid = |reason: String| {
+            DacError::InvalidArgument(format!("Invalid command {:02X?}: {}", cmd, reason))
+        };
+        let [cmd_type, param, hi, lo] = *cmd else {
+            return Err(invalid(format!("expected 4 bytes, got {}", cmd.len())));
+        };
+        let value = u16::from_be_bytes([hi, lo]);
+        let unused_zero = |what: &str| {
+            if value == 0 {
+                Ok(())
+            } else {
+                Err(invalid(format!(
+                    "{} takes no value, got 0x{:04X}",
+                    what, value
+                )))
+            }
+        };
+
+        match cmd_type {
+            0..=7 if param == 0x00 => {}
+            0..=7 if (TABLE_BASE..TABLE_BASE + TABLES as u8).contains(&param) => {
+                unused_zero("attach table")?
+            }
+            0..=7 => {
+                return Err(invalid(format!(
+                    "second byte 0x{:02X} is neither 0x00 (direct write) nor a table {}-{}",
+                    param,
+                    TABLE_BASE,
+                    TABLE_BASE + TABLES as u8 - 1
+                )))
+            }
+            8..=15 => {
+                return Err(invalid(format!(
+                    "DAC channel {} out of range 0-{}",
+                    cmd_type,
+                    DAC_CHANNELS - 1
+                )))
+            }
+            16..=19 | CMD_REGISTER | CMD_HELLO => {}
+            CMD_USE_TABLE => unused_zero("use table")?,
+            CMD_GPIO if param as usize >= GPIO_PINS => {
+                return Err(invalid(format!(
+                    "GPIO pin {} out of range 0-{}",
+                    param,
+                    GPIO_PINS - 1
+                )))
+            }
+            CMD_GPIO if value > 1 => {
+                return Err(invalid(format!(
+                    "GPIO value must be 0 or 1, got 0x{:04X}",
+                    value
+                )))
+            }
+            CMD_GPIO => {}
+            CMD_KEEPALIVE | CMD_LDAC | CMD_TAKEOVER | CMD_HEARTBEAT if param != 0 => {
+                return Err(invalid(format!(
+                    "second byte must be 0x00, got 0x{:02X}",
+                    param
+                )))
+            }
+            CMD_KEEPALIVE => unused_zero("keepalive")?,
+            CMD_LDAC => unused_zero("LDAC")?,
+            CMD_TAKEOVER => unused_zero("takeover")?,
+            CMD_HEARTBEAT => unused_zero("heartbeat")?,
+            CMD_REGISTER_READ => unused_zero("register read")?,
+            CMD_TABLE_READ if value as usize >= TABLES => {
+                return Err(invalid(format!(
+                    "table {} out of range 0-{}",
+                    value,
+                    TABLES - 1
+                )))
+            }
+            CMD_TABLE_READ => {}
+            _ => {
+                return Err(invalid(format!(
+                    "unknown command byte 0x{:02X} (tables are {}-{})",
+                    cmd_type,
+                    TABLE_BASE,
+                    TABLE_BASE + TABLES as u8 - 1
+                )))
+            }
+        }
+
+        Command::decode(cmd).ok_or_else(|| invalid("undecodable".to_string()))
+    }
+
+    /// Encode the command into its 4 wire bytes
+    pub fn encode(&self) -> [u8; 4] {
+        let (b0, b1, value) = match *self {
+            Command::DacWrite { channel, value } => (channel, 0x00, value),
+            Command::AttachTable { channel, table } => (channel, TABLE_BASE + table, 0),
+            Command::TableWrite {
+                table,
+                index,
+                value,
+            } => (TABLE_BASE + table, index, value),
+            Command::UseTable { offset } => (CMD_USE_TABLE, offset, 0),
+            Command::Gpio { pin, on } => (CMD_GPIO, pin, on as u16),
+            Command::KeepAlive => (CMD_KEEPALIVE, 0, 0),
+            Command::Ldac => (CMD_LDAC, 0, 0),
+            Command::RegisterWrite { reg, value } => (CMD_REGISTER, reg, value),
+            Command::Takeover => (CMD_TAKEOVER, 0, 0),
+            Command::Heartbeat => (CMD_HEARTBEAT, 0, 0),
+            Command::RegisterRead { reg } => (CMD_REGISTER_READ, reg, 0),
+            Command::Hello { version, features } => (CMD_HELLO, version, features.bits()),
+            Command::TableRead { table, index } => (CMD_TABLE_READ, index, table as u16),
+        };
+        let [hi, lo] = value.to_be_bytes();
+        [b0, b1, hi, lo]
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Command::DacWrite { channel, value } => write!(
+                f,
+                "Direct DAC write: channel={}, value=0x{:04X}",
+                channel, value
+            ),
+            Command::AttachTable { channel, table } => {
+                write!(f, "Attach table: channel={}, table={}", channel, table)
+            }
+            Command::TableWrite {
+                table,
+                index,
+                value,
+            } => write!(
+                f,
+                "Table write: table={}, offset={}, value=0x{:04X}",
+                table, index, value
+            ),
+            Command::UseTable { offset } => write!(f, "Use table: offset={}", offset),
+            Command::Gpio { pin, on } => write!(
+                f,
+                "GPIO control: pin={}, state={}",
+                pin,
+                if on { "ON" } else { "OFF" }
+            ),
+            Command::KeepAlive => write!(f, "Keep alive"),
+            Command::Ldac => write!(f, "LDAC update"),
+            Command::RegisterWrite { reg, value } => {
+                write!(f, "Register write: reg={}, value=0x{:04X}", reg, value)
+            }
+            Command::Takeover => write!(f, "Take bridge control"),
+            Command::Heartbeat => write!(f, "Heartbeat"),
+            Command::RegisterRead { reg } => write!(f, "Register read: reg={}", reg),
+            Command::Hello { version, features } => {
+                write!(f, "Hello: version={}, features={}", version, features)
+            }
+            Command::TableRead { table, index } => {
+                write!(f, "Table read: table={}, offset={}", table, index)
+            }
+        }
+    }
+}
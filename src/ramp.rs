@@ -0,0 +1,94 @@
+//! Linear ramps of a DAC channel to a target value.
+//!
+//! A [`Ramp`] only computes the value a channel should carry some time into
+//! the transition. [`client::ramp_to`](crate::client::ramp_to) sends those
+//! values from a background thread and returns a [`RampHandle`]; the TUI and
+//! Lua scripts step ramps from their own event loops.
+
+use crate::cancel::CancellationToken;
+use crate::error::Result;
+use crate::protocol::Command;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Time between the writes of a ramp
+pub const RAMP_STEP: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ramp {
+    pub channel: u8,
+    pub from: u16,
+    pub to: u16,
+    pub duration: Duration,
+}
+
+impl Ramp {
+    pub fn new(channel: u8, from: u16, to: u16, duration: Duration) -> Self {
+        Self {
+            channel,
+            from,
+            to,
+            duration,
+        }
+    }
+
+    /// Value `elapsed` into the ramp, the target once it is over
+    pub fn value_at(&self, elapsed: Duration) -> u16 {
+        if self.is_done(elapsed) {
+            return self.to;
+        }
+        let ratio = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+        let value = self.from as f64 + (self.to as f64 - self.from as f64) * ratio;
+        value.round() as u16
+    }
+
+    pub fn is_done(&self, elapsed: Duration) -> bool {
+        elapsed >= self.duration
+    }
+
+    /// The write for `elapsed` into the ramp
+    pub fn command_at(&self, elapsed: Duration) -> Command {
+        Command::DacWrite {
+            channel: self.channel,
+            value: self.value_at(elapsed),
+        }
+    }
+
+    /// Time until the next write is due, zero once the ramp is over
+    pub fn next_step_in(&self, elapsed: Duration) -> Duration {
+        RAMP_STEP.min(self.duration.saturating_sub(elapsed))
+    }
+}
+
+/// A ramp running on a background thread
+///
+/// Dropping the handle lets the ramp run to its end unobserved.
+#[derive(Debug)]
+pub struct RampHandle {
+    cancel: CancellationToken,
+    thread: JoinHandle<Result<u16>>,
+}
+
+impl RampHandle {
+    pub(crate) fn new(cancel: CancellationToken, thread: JoinHandle<Result<u16>>) -> Self {
+        Self { cancel, thread }
+    }
+
+    /// Stop the ramp before its next write; [`wait`](Self::wait) then fails
+    /// with [`DacError::Cancelled`](crate::error::DacError::Cancelled)
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Whether the ramp has ended, by reaching its target, failing or being cancelled
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Block until the ramp ends, returning the channel's final value
+    pub fn wait(self) -> Result<u16> {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
@@ -0,0 +1,156 @@
+//! Rate-limited logging of repeated errors.
+//!
+//! A device unplugged for minutes makes a bridge fail every write and every
+//! keepalive, and one line per failure buries everything else in the log.
+//! [`Repeats`] prints the first error of a kind and counts the repeats within
+//! a window instead; the next line of that kind, or [`Repeats::due`] once the
+//! window has passed, sums them up:
+//!
+//! ```text
+//! Serial write error: Broken pipe
+//! Serial write error: Broken pipe (×327 in the last 10s)
+//! ```
+//!
+//! Errors are of one kind if their text up to the first `": "` is the same,
+//! e.g. `Serial write error` or `TCP write error to 10.0.0.7:51544`, so the
+//! details may differ. [`eprint`] does this for stderr.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window in which repeats of an error are counted rather than printed
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct Entry {
+    since: Instant,
+    /// Repeats not printed so far
+    count: u64,
+    last: String,
+}
+
+impl Entry {
+    fn new(message: &str) -> Self {
+        Self {
+            since: Instant::now(),
+            count: 0,
+            last: message.to_string(),
+        }
+    }
+
+    fn summary(&self, message: &str, count: u64) -> String {
+        format!(
+            "{} (×{} in the last {:.0}s)",
+            message,
+            count,
+            self.since.elapsed().as_secs_f64()
+        )
+    }
+}
+
+/// Errors seen in the current window, by kind; shared between threads
+#[derive(Debug)]
+pub struct Repeats {
+    window: Duration,
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+/// The part of `message` naming its kind
+fn kind(message: &str) -> &str {
+    message.split(": ").next().unwrap_or(message)
+}
+
+impl Repeats {
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The line to print for `message` now, or `None` if it only counts as a repeat
+    pub fn check(&self, message: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(kind(message)) else {
+            entries.insert(kind(message).to_string(), Entry::new(message));
+            return Some(message.to_string());
+        };
+        if entry.since.elapsed() < self.window {
+            entry.count += 1;
+            entry.last = message.to_string();
+            return None;
+        }
+        let line = match entry.count {
+            0 => message.to_string(),
+            count => entry.summary(message, count + 1),
+        };
+        *entry = Entry::new(message);
+        Some(line)
+    }
+
+    /// Summaries of the kinds whose window has passed with repeats not printed yet
+    pub fn due(&self) -> Vec<String> {
+        self.take(|entry| entry.since.elapsed() >= self.window)
+    }
+
+    /// Summaries of every kind with repeats not printed yet, e.g. before exiting
+    pub fn flush(&self) -> Vec<String> {
+        self.take(|_| true)
+    }
+
+    /// When the next summary is due, if any repeats are waiting for one
+    pub fn next_due(&self) -> Option<Instant> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .values()
+            .filter(|entry| entry.count > 0)
+            .map(|entry| entry.since + self.window)
+            .min()
+    }
+
+    fn take(&self, ended: impl Fn(&Entry) -> bool) -> Vec<String> {
+        let mut summaries = Vec::new();
+        self.entries.lock().unwrap().retain(|_, entry| {
+            if !ended(entry) {
+                return true;
+            }
+            if entry.count > 0 {
+                summaries.push(entry.summary(&entry.last, entry.count));
+            }
+            false
+        });
+        summaries
+    }
+}
+
+impl Default for Repeats {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+static STDERR: Repeats = Repeats::new(DEFAULT_WINDOW);
+
+/// Print `message` on stderr unless it repeats an error of the current window
+pub fn eprint(message: impl fmt::Display) {
+    eprint_due();
+    if let Some(line) = STDERR.check(&message.to_string()) {
+        eprintln!("{}", line);
+    }
+}
+
+/// Print the summaries of [`eprint`] repeats whose window has passed
+pub fn eprint_due() {
+    for summary in STDERR.due() {
+        eprintln!("{}", summary);
+    }
+}
+
+/// Print the summaries of every [`eprint`] repeat not printed yet
+pub fn eprint_flush() {
+    for summary in STDERR.flush() {
+        eprintln!("{}", summary);
+    }
+}
@@ -0,0 +1,347 @@
+//! Machine-readable test reports.
+//!
+//! A report is a tree of [`Value`]s written as JSON, or flattened into CSV
+//! columns named by their path (`phases.main.writes`). CSV reports append one
+//! row per run to an existing file with the same columns, so results from
+//! several runs (e.g. across firmware versions in CI) collect in one table.
+
+use crate::error::{DacError, Result};
+use crate::logfile::{RotatingFile, Rotation};
+use clap::ValueEnum;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Crate version of the tools
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// `git describe` of the tree the tools were built from, or "unknown"
+pub const GIT_VERSION: &str = env!("SERIALTEST_GIT_VERSION");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl ReportFormat {
+    /// CSV for `.csv` files, JSON otherwise
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => ReportFormat::Csv,
+            _ => ReportFormat::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Value>),
+    /// Fields in insertion order
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object() -> Self {
+        Value::Object(Vec::new())
+    }
+
+    /// Add a field to an object
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        if let Value::Object(fields) = &mut self {
+            fields.push((key.to_string(), value.into()));
+        }
+        self
+    }
+
+    /// Add the fields of object `other` to an object; anything else but null
+    /// becomes a field named `value`
+    pub fn merge(self, other: Value) -> Self {
+        match other {
+            Value::Object(fields) => fields
+                .into_iter()
+                .fold(self, |merged, (key, value)| merged.with(&key, value)),
+            Value::Null => self,
+            other => self.with("value", other),
+        }
+    }
+
+    /// Field `key` of an object, or element `key` of an array
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        }
+    }
+
+    /// `(path, value)` for every leaf, paths joined with '.' (array elements by index)
+    pub fn flatten(&self) -> Vec<(String, String)> {
+        let mut out = Vec::new();
+        self.flatten_into("", &mut out);
+        out
+    }
+
+    fn flatten_into(&self, prefix: &str, out: &mut Vec<(String, String)>) {
+        let path = |key: &str| {
+            if prefix.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", prefix, key)
+            }
+        };
+        match self {
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    value.flatten_into(&path(key), out);
+                }
+            }
+            Value::Array(items) => {
+                for (i, value) in items.iter().enumerate() {
+                    value.flatten_into(&path(&i.to_string()), out);
+                }
+            }
+            Value::Null => out.push((prefix.to_string(), String::new())),
+            Value::Str(s) => out.push((prefix.to_string(), s.clone())),
+            other => out.push((prefix.to_string(), other.to_string())),
+        }
+    }
+
+    /// Write the report to `path`; CSV rows are appended when the columns match
+    pub fn write_to(&self, path: &Path, format: ReportFormat) -> Result<()> {
+        self.write_rotated(path, format, &Rotation::default())
+    }
+
+    /// Like [`write_to`](Self::write_to), rotating CSV files by `rotation`
+    ///
+    /// JSON reports are replaced on every run, so only CSV files rotate.
+    pub fn write_rotated(
+        &self,
+        path: &Path,
+        format: ReportFormat,
+        rotation: &Rotation,
+    ) -> Result<()> {
+        match format {
+            ReportFormat::Json => std::fs::write(path, format!("{:#}\n", self))?,
+            ReportFormat::Csv => {
+                let (header, row): (Vec<_>, Vec<_>) = self
+                    .flatten()
+                    .into_iter()
+                    .map(|(key, value)| (csv_field(&key), csv_field(&value)))
+                    .unzip();
+                let header = header.join(",");
+                let row = row.join(",");
+
+                let existing = match std::fs::read_to_string(path) {
+                    Ok(existing) => Some(existing),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(e.into()),
+                };
+                match existing {
+                    Some(existing)
+                        if !existing.is_empty()
+                            && existing.lines().next() != Some(header.as_str()) =>
+                    {
+                        return Err(DacError::InvalidArgument(format!(
+                            "{} has different columns; use a new report file",
+                            path.display()
+                        )))
+                    }
+                    _ => RotatingFile::open(path, rotation.clone())?
+                        .with_header(header)
+                        .write(format!("{}\n", row).as_bytes())?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+macro_rules! from_int {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Value {
+            fn from(v: $t) -> Self {
+                Value::Int(v as i64)
+            }
+        }
+    )*};
+}
+from_int!(u8, u16, u32, u64, usize, i32, i64);
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+/// Durations are reported in milliseconds
+impl From<Duration> for Value {
+    fn from(v: Duration) -> Self {
+        Value::Float(v.as_micros() as f64 / 1000.0)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Str(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Str(v)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Self {
+        Value::Array(v.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl Value {
+    fn write_json(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) if v.is_finite() => write!(f, "{}", v),
+            Value::Float(_) => f.write_str("null"),
+            Value::Str(s) => f.write_str(&json_string(s)),
+            Value::Array(items) => {
+                f.write_str("[")?;
+                for (i, value) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(if f.alternate() { ", " } else { "," })?;
+                    }
+                    value.write_json(f, indent)?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(fields) if fields.is_empty() => f.write_str("{}"),
+            Value::Object(fields) => {
+                let pretty = f.alternate();
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    if pretty {
+                        write!(f, "\n{:width$}", "", width = (indent + 1) * 2)?;
+                    }
+                    f.write_str(&json_string(key))?;
+                    f.write_str(if pretty { ": " } else { ":" })?;
+                    value.write_json(f, indent + 1)?;
+                }
+                if pretty {
+                    write!(f, "\n{:width$}", "", width = indent * 2)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// JSON; `{:#}` pretty-prints
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_json(f, 0)
+    }
+}
+
+/// Calendar fields of a UTC time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcTime {
+    pub year: i64,
+    /// 1-12
+    pub month: u32,
+    /// 1-31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    /// 0 is Sunday
+    pub weekday: u32,
+}
+
+impl UtcTime {
+    pub fn of(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+        let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+        // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: (rem / 3600) as u32,
+            minute: (rem % 3600 / 60) as u32,
+            second: (rem % 60) as u32,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+}
+
+/// `time` as an RFC 3339 UTC timestamp, e.g. `2024-05-01T12:30:00Z`
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let t = UtcTime::of(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
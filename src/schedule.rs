@@ -0,0 +1,247 @@
+//! Job schedules for unattended runs (`csv1 scheduler`).
+//!
+//! A job file has one job per line: a name, a schedule and the command to run.
+//! Blank lines and `#` comments are skipped:
+//!
+//! ```text
+//! # name     schedule          command
+//! overnight  0 2 * * 1-5       tcp_robust_test tcp:lab-pi:2012 --count 100000
+//! soak       @every 30m        unified_test /dev/ttyACM0
+//! selftest   @hourly           selftest
+//! ```
+//!
+//! Schedules are cron expressions (minute, hour, day of month, month, day of
+//! week; `*`, lists, ranges and `/step`) evaluated in UTC, `@hourly`,
+//! `@daily`, `@weekly`, `@monthly`, or `@every N` with an `s`, `m`, `h` or `d`
+//! suffix. As in cron, a job restricted by both day of month and day of week
+//! runs when either matches.
+
+use crate::error::{DacError, Result};
+use crate::logfile::parse_scaled;
+use crate::report::UtcTime;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Minutes searched for the next match of a cron expression (a leap cycle)
+const CRON_HORIZON_MINUTES: u64 = 4 * 366 * 24 * 60;
+
+/// Allowed values of one cron field as a bit mask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field {
+    bits: u64,
+    /// `*`: any value
+    any: bool,
+}
+
+impl Field {
+    fn parse(s: &str, min: u32, max: u32) -> std::result::Result<Self, String> {
+        let mut bits = 0u64;
+        for part in s.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<u32>()
+                        .ok()
+                        .filter(|&s| s > 0)
+                        .ok_or_else(|| format!("bad step '{}'", step))?,
+                ),
+                None => (part, 1),
+            };
+            let value = |v: &str| {
+                v.parse::<u32>()
+                    .ok()
+                    .filter(|v| (min..=max).contains(v))
+                    .ok_or_else(|| format!("'{}' is not in {}-{}", v, min, max))
+            };
+            let (low, high) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((low, high)) => (value(low)?, value(high)?),
+                    // `5/15` runs from 5 to the end of the range
+                    None if part.contains('/') => (value(range)?, max),
+                    None => (value(range)?, value(range)?),
+                },
+            };
+            if low > high {
+                return Err(format!("empty range '{}'", range));
+            }
+            for v in (low..=high).step_by(step as usize) {
+                bits |= 1 << v;
+            }
+        }
+        Ok(Self {
+            bits,
+            any: s == "*",
+        })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+}
+
+/// A five-field cron expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cron {
+    minute: Field,
+    hour: Field,
+    day: Field,
+    month: Field,
+    weekday: Field,
+}
+
+impl Cron {
+    /// Whether the expression matches the minute containing `t`
+    pub fn matches(&self, t: &UtcTime) -> bool {
+        let day = match (self.day.any, self.weekday.any) {
+            (false, false) => self.day.contains(t.day) || self.weekday.contains(t.weekday),
+            _ => self.day.contains(t.day) && self.weekday.contains(t.weekday),
+        };
+        day && self.minute.contains(t.minute)
+            && self.hour.contains(t.hour)
+            && self.month.contains(t.month)
+    }
+}
+
+impl FromStr for Cron {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let invalid = |reason: String| {
+            DacError::InvalidArgument(format!("Invalid schedule '{}': {}", s, reason))
+        };
+        if fields.len() != 5 {
+            return Err(invalid(format!("expected 5 fields, got {}", fields.len())));
+        }
+        let mut weekday = Field::parse(fields[4], 0, 7).map_err(invalid)?;
+        // Both 0 and 7 are Sunday
+        if weekday.contains(7) {
+            weekday.bits |= 1;
+        }
+        Ok(Self {
+            minute: Field::parse(fields[0], 0, 59).map_err(invalid)?,
+            hour: Field::parse(fields[1], 0, 23).map_err(invalid)?,
+            day: Field::parse(fields[2], 1, 31).map_err(invalid)?,
+            month: Field::parse(fields[3], 1, 12).map_err(invalid)?,
+            weekday,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Cron(Cron),
+    /// A fixed interval from the previous run (or from startup)
+    Every(Duration),
+}
+
+impl Schedule {
+    /// When the job is next due after `last`, the previous run or startup
+    pub fn next_after(&self, last: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Every(interval) => Some(last + *interval),
+            Schedule::Cron(cron) => {
+                let secs = last.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                let first_minute = secs / 60 + 1;
+                (first_minute..first_minute + CRON_HORIZON_MINUTES)
+                    .map(|minute| UNIX_EPOCH + Duration::from_secs(minute * 60))
+                    .find(|&t| cron.matches(&UtcTime::of(t)))
+            }
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let cron = |expr: &str| expr.parse().map(Schedule::Cron);
+        match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["@hourly"] => cron("0 * * * *"),
+            ["@daily"] | ["@midnight"] => cron("0 0 * * *"),
+            ["@weekly"] => cron("0 0 * * 0"),
+            ["@monthly"] => cron("0 0 1 * *"),
+            ["@every", interval] => {
+                parse_scaled(interval, &[('s', 1), ('m', 60), ('h', 3600), ('d', 86_400)])
+                    .filter(|&secs| secs > 0)
+                    .map(|secs| Schedule::Every(Duration::from_secs(secs)))
+                    .ok_or_else(|| {
+                        DacError::InvalidArgument(format!("Invalid interval '{}'", interval))
+                    })
+            }
+            _ if s.starts_with('@') => Err(DacError::InvalidArgument(format!(
+                "Unknown schedule '{}' (@hourly, @daily, @weekly, @monthly, @every N)",
+                s
+            ))),
+            _ => cron(s),
+        }
+    }
+}
+
+/// A named command run on a schedule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub name: String,
+    /// The schedule as written in the job file
+    pub spec: String,
+    pub schedule: Schedule,
+    /// Shell command line
+    pub command: String,
+}
+
+/// Split the first whitespace-separated word off `s`
+fn take_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    (&s[..end], s[end..].trim_start())
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.name, self.spec, self.command)
+    }
+}
+
+/// Parse a job file
+pub fn parse_jobs(text: &str) -> Result<Vec<Job>> {
+    let mut jobs: Vec<Job> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid =
+            |reason: String| DacError::InvalidArgument(format!("Job line {}: {}", i + 1, reason));
+
+        let (name, mut rest) = take_word(line);
+        let words = match rest.split_whitespace().next() {
+            Some("@every") => 2,
+            Some(word) if word.starts_with('@') => 1,
+            _ => 5,
+        };
+        let spec_start = rest;
+        for _ in 0..words {
+            rest = take_word(rest).1;
+        }
+        let spec = spec_start[..spec_start.len() - rest.len()].trim_end();
+        if rest.is_empty() {
+            return Err(invalid(format!("job '{}' has no command", name)));
+        }
+        if jobs.iter().any(|job| job.name == name) {
+            return Err(invalid(format!("duplicate job name '{}'", name)));
+        }
+        jobs.push(Job {
+            name: name.to_string(),
+            spec: spec.to_string(),
+            schedule: spec.parse().map_err(|e| match e {
+                DacError::InvalidArgument(reason) => invalid(reason),
+                e => e,
+            })?,
+            command: rest.to_string(),
+        });
+    }
+    Ok(jobs)
+}
@@ -0,0 +1,320 @@
+//! SCPI-style text commands for instrument-control frameworks.
+//!
+//! Each line holds one or more commands separated by `;`; every command is a
+//! full header (the SCPI "relative to the previous header" rule is not used).
+//! Keywords match in short (`SOUR`) or long (`SOURce`) form, case-insensitive,
+//! and optional keywords are shown in brackets:
+//!
+//! | Command                                  | Meaning                                |
+//! |------------------------------------------|----------------------------------------|
+//! | `*IDN?`                                  | Identification                         |
+//! | `*RST`                                   | All DAC channels to 0, GPIOs 1-7 off   |
+//! | `*CLS`                                   | Clear the error queue                  |
+//! | `*OPC?`                                  | `1` once previous commands completed   |
+//! | `*TRG`                                   | LDAC                                   |
+//! | `SOURce<n>:VOLTage[:LEVel] <volts>`      | DAC channel n-1 in volts (`MIN`/`MAX`) |
+//! | `SOURce<n>:CODE <0-65535>`               | DAC channel n-1 as a raw code          |
+//! | `OUTPut<n>[:STATe] ON\|OFF\|1\|0`        | GPIO pin n-1                           |
+//! | `SYSTem:ERRor[:NEXT]?`                   | Oldest queued error, `0,"No error"`    |
+//!
+//! As in SCPI, suffixes count from 1 and default to 1: `SOUR:VOLT` is
+//! `SOUR1:VOLT`, DAC channel 0, and `OUTP8` is GPIO 7. `*RST` leaves GPIO 0
+//! alone: the device's keepalive watchdog owns that pin, and resetting the
+//! source should not act like a watchdog trip.
+//!
+//! `VOLTage`, `CODE` and `OUTPut` are also queries (`SOUR3:VOLT?`), answered
+//! from the [`DeviceState`] built from the commands sent. Volts map linearly
+//! onto codes through a [`VoltageRange`].
+
+use crate::device::DeviceState;
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS};
+use crate::units::VoltageRange;
+use std::fmt;
+
+/// An entry of the SCPI error queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScpiError {
+    pub code: i16,
+    pub message: &'static str,
+}
+
+impl ScpiError {
+    pub const NONE: ScpiError = ScpiError::new(0, "No error");
+    pub const DATA_TYPE: ScpiError = ScpiError::new(-104, "Data type error");
+    pub const PARAMETER_NOT_ALLOWED: ScpiError = ScpiError::new(-108, "Parameter not allowed");
+    pub const MISSING_PARAMETER: ScpiError = ScpiError::new(-109, "Missing parameter");
+    pub const UNDEFINED_HEADER: ScpiError = ScpiError::new(-113, "Undefined header");
+    pub const SUFFIX_OUT_OF_RANGE: ScpiError = ScpiError::new(-114, "Header suffix out of range");
+    pub const ILLEGAL_PARAMETER: ScpiError = ScpiError::new(-224, "Illegal parameter value");
+    pub const DATA_OUT_OF_RANGE: ScpiError = ScpiError::new(-222, "Data out of range");
+    pub const HARDWARE: ScpiError = ScpiError::new(-240, "Hardware error");
+    pub const QUEUE_OVERFLOW: ScpiError = ScpiError::new(-350, "Queue overflow");
+
+    const fn new(code: i16, message: &'static str) -> Self {
+        Self { code, message }
+    }
+}
+
+/// The `SYSTem:ERRor?` answer: `-113,"Undefined header"`
+impl fmt::Display for ScpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},\"{}\"", self.code, self.message)
+    }
+}
+
+/// A parsed command or query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scpi {
+    Identify,
+    Reset,
+    ClearErrors,
+    OperationComplete,
+    Trigger,
+    SetVoltage { channel: u8, volts: Level },
+    Voltage { channel: u8 },
+    SetCode { channel: u8, code: u16 },
+    Code { channel: u8 },
+    SetOutput { pin: u8, on: bool },
+    Output { pin: u8 },
+    NextError,
+}
+
+/// A voltage parameter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Volts(f64),
+    Min,
+    Max,
+}
+
+/// Whether `word` is the short or long form of `keyword`
+///
+/// The short form is the keyword's uppercase prefix: `SOUR` for `SOURce`.
+fn matches(keyword: &str, word: &str) -> bool {
+    let short: String = keyword
+        .chars()
+        .take_while(|c| c.is_ascii_uppercase())
+        .collect();
+    word.eq_ignore_ascii_case(&short) || word.eq_ignore_ascii_case(keyword)
+}
+
+/// Split a numeric suffix off a header word: `SOUR3` is `("SOUR", Some(3))`
+fn split_suffix(word: &str) -> (&str, Option<&str>) {
+    match word.find(|c: char| c.is_ascii_digit()) {
+        Some(i) => (&word[..i], Some(&word[i..])),
+        None => (word, None),
+    }
+}
+
+/// The 1-based suffix (1 when left out) as an index below `count`
+fn index(suffix: Option<&str>, count: usize) -> Result<u8, ScpiError> {
+    suffix
+        .map_or(Some(1), |s| s.parse::<u8>().ok())
+        .filter(|&n| (1..=count).contains(&(n as usize)))
+        .map(|n| n - 1)
+        .ok_or(ScpiError::SUFFIX_OUT_OF_RANGE)
+}
+
+/// Whether `words` (after the root) is `path`, with optional keywords in brackets
+fn path_is(words: &[&str], path: &[&str]) -> bool {
+    let mut words = words.iter().peekable();
+    for keyword in path {
+        let (keyword, optional) = match keyword.strip_prefix('[') {
+            Some(k) => (k.trim_end_matches(']'), true),
+            None => (*keyword, false),
+        };
+        match words.peek() {
+            Some(word) if matches(keyword, word) => {
+                words.next();
+            }
+            _ if optional => {}
+            _ => return false,
+        }
+    }
+    words.next().is_none()
+}
+
+fn parse_level(param: &str) -> Result<Level, ScpiError> {
+    if matches("MINimum", param) {
+        Ok(Level::Min)
+    } else if matches("MAXimum", param) {
+        Ok(Level::Max)
+    } else {
+        param
+            .parse::<f64>()
+            .map(Level::Volts)
+            .map_err(|_| ScpiError::DATA_TYPE)
+    }
+}
+
+fn parse_bool(param: &str) -> Result<bool, ScpiError> {
+    if param == "1" || param.eq_ignore_ascii_case("ON") {
+        Ok(true)
+    } else if param == "0" || param.eq_ignore_ascii_case("OFF") {
+        Ok(false)
+    } else {
+        Err(ScpiError::ILLEGAL_PARAMETER)
+    }
+}
+
+impl Scpi {
+    /// Parse one command (one `;`-separated part of a line)
+    pub fn parse(command: &str) -> Result<Scpi, ScpiError> {
+        let command = command.trim();
+        let (header, param) = match command.split_once(char::is_whitespace) {
+            Some((header, param)) => (header, Some(param.trim())),
+            None => (command, None),
+        };
+        let (header, query) = match header.strip_suffix('?') {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+        // A query takes no parameter; a setting needs one
+        let setting = |parse: &dyn Fn(&str) -> Result<Scpi, ScpiError>| match param {
+            Some(param) => parse(param),
+            None => Err(ScpiError::MISSING_PARAMETER),
+        };
+        if query && param.is_some() {
+            return Err(ScpiError::PARAMETER_NOT_ALLOWED);
+        }
+
+        if let Some(common) = header.strip_prefix('*') {
+            let cmd = match (common.to_ascii_uppercase().as_str(), query) {
+                ("IDN", true) => Scpi::Identify,
+                ("RST", false) => Scpi::Reset,
+                ("CLS", false) => Scpi::ClearErrors,
+                ("OPC", true) => Scpi::OperationComplete,
+                ("TRG", false) => Scpi::Trigger,
+                _ => return Err(ScpiError::UNDEFINED_HEADER),
+            };
+            if param.is_some() {
+                return Err(ScpiError::PARAMETER_NOT_ALLOWED);
+            }
+            return Ok(cmd);
+        }
+
+        let words: Vec<&str> = header.trim_start_matches(':').split(':').collect();
+        let (root, suffix) = split_suffix(words[0]);
+        let rest = &words[1..];
+
+        if matches("SOURce", root) {
+            let channel = index(suffix, DAC_CHANNELS)?;
+            if path_is(rest, &["VOLTage", "[LEVel]"]) {
+                if query {
+                    return Ok(Scpi::Voltage { channel });
+                }
+                return setting(&|p| {
+                    Ok(Scpi::SetVoltage {
+                        channel,
+                        volts: parse_level(p)?,
+                    })
+                });
+            }
+            if path_is(rest, &["CODE"]) {
+                if query {
+                    return Ok(Scpi::Code { channel });
+                }
+                return setting(&|p| {
+                    let code = p.parse::<i64>().map_err(|_| ScpiError::DATA_TYPE)?;
+                    let code = u16::try_from(code).map_err(|_| ScpiError::DATA_OUT_OF_RANGE)?;
+                    Ok(Scpi::SetCode { channel, code })
+                });
+            }
+        } else if matches("OUTPut", root) && path_is(rest, &["[STATe]"]) {
+            let pin = index(suffix, GPIO_PINS)?;
+            if query {
+                return Ok(Scpi::Output { pin });
+            }
+            return setting(&|p| {
+                Ok(Scpi::SetOutput {
+                    pin,
+                    on: parse_bool(p)?,
+                })
+            });
+        } else if matches("SYSTem", root)
+            && suffix.is_none()
+            && query
+            && path_is(rest, &["ERRor", "[NEXT]"])
+        {
+            return Ok(Scpi::NextError);
+        }
+        Err(ScpiError::UNDEFINED_HEADER)
+    }
+
+    /// The device commands carrying out this command
+    pub fn commands(&self, range: &VoltageRange) -> Result<Vec<Command>, ScpiError> {
+        Ok(match *self {
+            Scpi::Reset => (0..DAC_CHANNELS as u8)
+                .map(|channel| Command::DacWrite { channel, value: 0 })
+                .chain((1..GPIO_PINS as u8).map(|pin| Command::Gpio { pin, on: false }))
+                .collect(),
+            Scpi::Trigger => vec![Command::Ldac],
+            Scpi::SetVoltage { channel, volts } => {
+                let value = match volts {
+                    Level::Min => range.to_code(range.min().min(range.max())),
+                    Level::Max => range.to_code(range.min().max(range.max())),
+                    Level::Volts(v) => range.to_code(v),
+                }
+                .ok_or(ScpiError::DATA_OUT_OF_RANGE)?;
+                vec![Command::DacWrite { channel, value }]
+            }
+            Scpi::SetCode { channel, code } => vec![Command::DacWrite {
+                channel,
+                value: code,
+            }],
+            Scpi::SetOutput { pin, on } => vec![Command::Gpio { pin, on }],
+            _ => Vec::new(),
+        })
+    }
+
+    /// The answer to a query, once earlier commands have been applied to `state`
+    ///
+    /// `None` for settings and for `SYSTem:ERRor?`, whose answer comes from the
+    /// caller's error queue.
+    pub fn response(&self, state: &DeviceState, range: &VoltageRange) -> Option<String> {
+        match *self {
+            Scpi::Identify => Some(format!("csv1,OL8,0,{}", crate::report::VERSION)),
+            Scpi::OperationComplete => Some("1".to_string()),
+            Scpi::Voltage { channel } => Some(format!(
+                "{:.6}",
+                range.to_volts(state.dac[channel as usize])
+            )),
+            Scpi::Code { channel } => Some(state.dac[channel as usize].to_string()),
+            Scpi::Output { pin } => Some(u8::from(state.gpio[pin as usize]).to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffixes_count_from_one() {
+        assert_eq!(Scpi::parse("SOUR:VOLT?"), Ok(Scpi::Voltage { channel: 0 }));
+        assert_eq!(
+            Scpi::parse("SOURce1:VOLTage?"),
+            Ok(Scpi::Voltage { channel: 0 })
+        );
+        assert_eq!(Scpi::parse("SOUR8:CODE?"), Ok(Scpi::Code { channel: 7 }));
+        assert_eq!(Scpi::parse("OUTP8?"), Ok(Scpi::Output { pin: 7 }));
+        for header in ["SOUR0:CODE?", "SOUR9:CODE?", "OUTP0?", "OUTP9?"] {
+            assert_eq!(Scpi::parse(header), Err(ScpiError::SUFFIX_OUT_OF_RANGE));
+        }
+    }
+
+    #[test]
+    fn reset_leaves_the_watchdog_pin_alone() {
+        let range = VoltageRange::default();
+        let commands = Scpi::parse("*RST").unwrap().commands(&range).unwrap();
+        assert!(commands.contains(&Command::DacWrite {
+            channel: 7,
+            value: 0
+        }));
+        assert!(commands.contains(&Command::Gpio { pin: 1, on: false }));
+        assert!(!commands
+            .iter()
+            .any(|c| matches!(c, Command::Gpio { pin: 0, .. })));
+    }
+}
@@ -0,0 +1,387 @@
+//! Lua automation hooks (feature `lua`).
+//!
+//! A script gets a global `dac` table and may define `on_start()`:
+//!
+//! ```lua
+//! function on_start()
+//!   dac.write(0, 0x8000)
+//!   dac.every(500, function() dac.gpio(0, not dac.get_gpio(0)) end)
+//!   dac.on_key("x", function() dac.write(1, dac.get(1) + 256) end)
+//!   dac.ramp(2, 0xFFFF, 2000, function() dac.log("ramp done") end)
+//! end
+//! ```
+//!
+//! `dac.fill(table, spec)` writes a whole table generated from a
+//! [`TableSpec`], e.g. `dac.fill(0, "sine 2 | scale 0.5")`.
+//!
+//! `dac.ramp(channel, value, ms[, on_done])` moves a channel there over `ms`
+//! milliseconds and calls `on_done` at the end; writing the channel, or ramping
+//! it again, stops the ramp without calling it.
+//!
+//! Calls into `dac` only queue [`Command`]s; the host program sends them, so
+//! scripts go through the same framing, ganging and deferred-write logic as
+//! keyboard input.
+
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS, TABLES};
+use crate::ramp::Ramp;
+use crate::tables::TableSpec;
+use mlua::{Function, Lua, RegistryKey, Table};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+struct Timer {
+    interval: Duration,
+    next: Instant,
+    repeat: bool,
+    callback: RegistryKey,
+}
+
+struct ScriptRamp {
+    ramp: Ramp,
+    start: Instant,
+    /// When the next write is due
+    next: Instant,
+    on_done: Option<RegistryKey>,
+}
+
+#[derive(Default)]
+struct Shared {
+    dac: [u16; DAC_CHANNELS],
+    gpio: [bool; GPIO_PINS],
+    commands: Vec<Command>,
+    log: Vec<String>,
+    timers: Vec<Timer>,
+    keys: Vec<(char, RegistryKey)>,
+    ramps: Vec<ScriptRamp>,
+}
+
+/// A loaded script and the timers and hotkeys it registered
+pub struct ScriptHost {
+    lua: Lua,
+    shared: Rc<RefCell<Shared>>,
+}
+
+fn script_error(e: mlua::Error) -> DacError {
+    DacError::Script(e.to_string())
+}
+
+fn check_range(what: &str, n: u8, count: usize) -> mlua::Result<()> {
+    if (n as usize) < count {
+        Ok(())
+    } else {
+        Err(mlua::Error::RuntimeError(format!(
+            "{} {} out of range 0-{}",
+            what,
+            n,
+            count - 1
+        )))
+    }
+}
+
+impl ScriptHost {
+    /// Load and run a script file; `on_start` is called by [`start`](Self::start)
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        let host = Self::new()?;
+        host.lua
+            .load(&source)
+            .set_name(path.display().to_string())
+            .exec()
+            .map_err(script_error)?;
+        Ok(host)
+    }
+
+    fn new() -> Result<Self> {
+        let lua = Lua::new();
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let dac = lua.create_table().map_err(script_error)?;
+        Self::register(&lua, &dac, &shared).map_err(script_error)?;
+        lua.globals().set("dac", dac).map_err(script_error)?;
+        Ok(Self { lua, shared })
+    }
+
+    fn register(lua: &Lua, dac: &Table, shared: &Rc<RefCell<Shared>>) -> mlua::Result<()> {
+        let s = shared.clone();
+        dac.set(
+            "write",
+            lua.create_function(move |_, (channel, value): (u8, f64)| {
+                check_range("DAC channel", channel, DAC_CHANNELS)?;
+                let value = value.round().clamp(0.0, 65535.0) as u16;
+                let mut s = s.borrow_mut();
+                s.dac[channel as usize] = value;
+                s.ramps.retain(|r| r.ramp.channel != channel);
+                s.commands.push(Command::DacWrite { channel, value });
+                Ok(())
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "ramp",
+            lua.create_function(
+                move |lua, (channel, value, ms, on_done): (u8, f64, u64, Option<Function>)| {
+                    check_range("DAC channel", channel, DAC_CHANNELS)?;
+                    let value = value.round().clamp(0.0, 65535.0) as u16;
+                    let on_done = on_done.map(|f| lua.create_registry_value(f)).transpose()?;
+                    let mut s = s.borrow_mut();
+                    let from = s.dac[channel as usize];
+                    s.ramps.retain(|r| r.ramp.channel != channel);
+                    let now = Instant::now();
+                    s.ramps.push(ScriptRamp {
+                        ramp: Ramp::new(channel, from, value, Duration::from_millis(ms)),
+                        start: now,
+                        next: now,
+                        on_done,
+                    });
+                    Ok(())
+                },
+            )?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "get",
+            lua.create_function(move |_, channel: u8| {
+                check_range("DAC channel", channel, DAC_CHANNELS)?;
+                Ok(s.borrow().dac[channel as usize])
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "gpio",
+            lua.create_function(move |_, (pin, on): (u8, bool)| {
+                check_range("GPIO pin", pin, GPIO_PINS)?;
+                let mut s = s.borrow_mut();
+                s.gpio[pin as usize] = on;
+                s.commands.push(Command::Gpio { pin, on });
+                Ok(())
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "get_gpio",
+            lua.create_function(move |_, pin: u8| {
+                check_range("GPIO pin", pin, GPIO_PINS)?;
+                Ok(s.borrow().gpio[pin as usize])
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "offset",
+            lua.create_function(move |_, offset: u8| {
+                s.borrow_mut().commands.push(Command::UseTable { offset });
+                Ok(())
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "fill",
+            lua.create_function(move |_, (table, spec): (u8, String)| {
+                check_range("Table", table, TABLES)?;
+                let spec: TableSpec = spec
+                    .parse()
+                    .map_err(|e: DacError| mlua::Error::RuntimeError(e.to_string()))?;
+                s.borrow_mut().commands.extend(spec.commands(table));
+                Ok(())
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "ldac",
+            lua.create_function(move |_, ()| {
+                s.borrow_mut().commands.push(Command::Ldac);
+                Ok(())
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "keepalive",
+            lua.create_function(move |_, ()| {
+                s.borrow_mut().commands.push(Command::KeepAlive);
+                Ok(())
+            })?,
+        )?;
+
+        let s = shared.clone();
+        dac.set(
+            "log",
+            lua.create_function(move |_, message: String| {
+                s.borrow_mut().log.push(message);
+                Ok(())
+            })?,
+        )?;
+
+        for (name, repeat) in [("every", true), ("after", false)] {
+            let s = shared.clone();
+            dac.set(
+                name,
+                lua.create_function(move |lua, (ms, callback): (u64, Function)| {
+                    let interval = Duration::from_millis(ms.max(1));
+                    let callback = lua.create_registry_value(callback)?;
+                    s.borrow_mut().timers.push(Timer {
+                        interval,
+                        next: Instant::now() + interval,
+                        repeat,
+                        callback,
+                    });
+                    Ok(())
+                })?,
+            )?;
+        }
+
+        let s = shared.clone();
+        dac.set(
+            "on_key",
+            lua.create_function(move |lua, (key, callback): (String, Function)| {
+                let mut chars = key.chars();
+                let (Some(c), None) = (chars.next(), chars.next()) else {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "on_key expects a single character, got '{}'",
+                        key
+                    )));
+                };
+                let callback = lua.create_registry_value(callback)?;
+                let mut s = s.borrow_mut();
+                s.keys.retain(|(k, _)| *k != c);
+                s.keys.push((c, callback));
+                Ok(())
+            })?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Tell the script the current outputs before running any callback
+    pub fn sync(&self, dac: &[u16; DAC_CHANNELS], gpio: &[bool; GPIO_PINS]) {
+        let mut s = self.shared.borrow_mut();
+        s.dac = *dac;
+        s.gpio = *gpio;
+    }
+
+    /// Call the global `on_start()` if the script defines one
+    pub fn start(&self) -> Result<Vec<Command>> {
+        let on_start: Option<Function> =
+            self.lua.globals().get("on_start").map_err(script_error)?;
+        if let Some(on_start) = on_start {
+            self.call(on_start)?;
+        }
+        Ok(self.take_commands())
+    }
+
+    /// Time until the next timer or ramp step is due, or `None` without either
+    pub fn due_in(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let s = self.shared.borrow();
+        s.timers
+            .iter()
+            .map(|t| t.next)
+            .chain(s.ramps.iter().map(|r| r.next))
+            .map(|next| next.saturating_duration_since(now))
+            .min()
+    }
+
+    /// Step every ramp and run every due timer, returning the commands they queued
+    pub fn tick(&self) -> Result<Vec<Command>> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        {
+            let mut s = self.shared.borrow_mut();
+            let mut i = 0;
+            while i < s.ramps.len() {
+                if s.ramps[i].next > now {
+                    i += 1;
+                    continue;
+                }
+                let ramp = s.ramps[i].ramp;
+                let elapsed = now.saturating_duration_since(s.ramps[i].start);
+                let value = ramp.value_at(elapsed);
+                if value != s.dac[ramp.channel as usize] {
+                    s.dac[ramp.channel as usize] = value;
+                    s.commands.push(ramp.command_at(elapsed));
+                }
+                if !ramp.is_done(elapsed) {
+                    s.ramps[i].next = now + ramp.next_step_in(elapsed);
+                    i += 1;
+                    continue;
+                }
+                if let Some(on_done) = s.ramps.remove(i).on_done {
+                    due.push(self.lua.registry_value(&on_done).map_err(script_error)?);
+                }
+            }
+
+            let mut i = 0;
+            while i < s.timers.len() {
+                if s.timers[i].next > now {
+                    i += 1;
+                    continue;
+                }
+                let callback: Function = self
+                    .lua
+                    .registry_value(&s.timers[i].callback)
+                    .map_err(script_error)?;
+                due.push(callback);
+                if s.timers[i].repeat {
+                    let timer = &mut s.timers[i];
+                    timer.next = (timer.next + timer.interval).max(now);
+                    i += 1;
+                } else {
+                    s.timers.remove(i);
+                }
+            }
+        }
+
+        // Callbacks may register more timers or ramps, so the borrow is released first
+        for callback in due {
+            self.call(callback)?;
+        }
+        Ok(self.take_commands())
+    }
+
+    /// Run the handler bound to `key`, or return `None` if there is none
+    pub fn handle_key(&self, key: char) -> Result<Option<Vec<Command>>> {
+        let callback: Option<Function> = {
+            let s = self.shared.borrow();
+            match s.keys.iter().find(|(k, _)| *k == key) {
+                Some((_, callback)) => {
+                    Some(self.lua.registry_value(callback).map_err(script_error)?)
+                }
+                None => None,
+            }
+        };
+
+        match callback {
+            Some(callback) => {
+                self.call(callback)?;
+                Ok(Some(self.take_commands()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Messages passed to `dac.log` since the last call
+    pub fn take_log(&self) -> Vec<String> {
+        std::mem::take(&mut self.shared.borrow_mut().log)
+    }
+
+    fn call(&self, callback: Function) -> Result<()> {
+        let result = callback.call::<_, ()>(());
+        if result.is_err() {
+            // Whatever the callback queued before failing is dropped
+            self.shared.borrow_mut().commands.clear();
+        }
+        result.map_err(script_error)
+    }
+
+    fn take_commands(&self) -> Vec<Command> {
+        std::mem::take(&mut self.shared.borrow_mut().commands)
+    }
+}
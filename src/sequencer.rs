@@ -0,0 +1,203 @@
+//! Frame-by-frame playback of command sequences.
+//!
+//! A [`Sequencer`] sends one frame of commands every frame period, e.g. one
+//! table offset per frame to play the tables back as a waveform
+//! ([`table_sweep`](Sequencer::table_sweep), or back and forth with
+//! [`table_ping_pong`](Sequencer::table_ping_pong)). Frames are timed by the
+//! client's clock from the start of playback.
+//!
+//! With [`with_wall_clock`](Sequencer::with_wall_clock) playback starts on a
+//! wall-clock boundary instead, and each frame is due a whole number of
+//! periods after it by the system clock. Instances on different hosts whose
+//! clocks are disciplined by NTP (or a PPS source) then play the same frame at
+//! about the same time: within the offset between the host clocks plus the
+//! link latency, so the stimulus is loosely, not sample-accurately, in sync.
+
+use crate::cancel::CancellationToken;
+use crate::client::DacClient;
+use crate::clock::SharedClock;
+use crate::error::{DacError, Result};
+use crate::protocol::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Longest wait between cancellation checks, e.g. before an aligned start
+const CANCEL_CHECK: Duration = Duration::from_millis(50);
+
+/// What a playback did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Playback {
+    /// Frames sent
+    pub played: usize,
+    /// Frames whose time had passed before they could be sent
+    pub skipped: usize,
+}
+
+/// First multiple of `align` since the Unix epoch at or after `now`
+pub fn next_boundary(now: SystemTime, align: Duration) -> SystemTime {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let align = align.as_nanos().max(1);
+    let boundary = since_epoch.as_nanos().div_ceil(align) * align;
+    UNIX_EPOCH + Duration::from_nanos(boundary as u64)
+}
+
+/// When playback started, on the clock frames are timed by
+enum Start {
+    Clock(SharedClock, Instant),
+    Wall(SystemTime),
+}
+
+impl Start {
+    /// Time since the start, zero before it
+    fn elapsed(&self) -> Duration {
+        match self {
+            Start::Clock(clock, start) => clock.elapsed(*start),
+            Start::Wall(start) => SystemTime::now().duration_since(*start).unwrap_or_default(),
+        }
+    }
+
+    /// Time left until `at` after the start, zero once it has passed
+    fn until(&self, at: Duration) -> Duration {
+        match self {
+            Start::Clock(clock, start) => (*start + at).saturating_duration_since(clock.now()),
+            Start::Wall(start) => (*start + at)
+                .duration_since(SystemTime::now())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) {
+        match self {
+            Start::Clock(clock, _) => clock.sleep(duration),
+            Start::Wall(_) => thread::sleep(duration),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequencer {
+    frames: Vec<Vec<Command>>,
+    period: Duration,
+    repeat: usize,
+    align: Option<Duration>,
+}
+
+impl Sequencer {
+    /// Play `frames` once, one every `period`
+    pub fn new(frames: Vec<Vec<Command>>, period: Duration) -> Result<Self> {
+        if frames.is_empty() {
+            return Err(DacError::InvalidArgument(
+                "A sequence needs at least one frame".to_string(),
+            ));
+        }
+        if period.is_zero() {
+            return Err(DacError::InvalidArgument(
+                "Frame period must be above zero".to_string(),
+            ));
+        }
+        Ok(Self {
+            frames,
+            period,
+            repeat: 1,
+            align: None,
+        })
+    }
+
+    /// Step the table offset from `first` to `last`, one offset per frame
+    pub fn table_sweep(first: u8, last: u8, period: Duration) -> Result<Self> {
+        if first > last {
+            return Err(DacError::InvalidArgument(format!(
+                "First table offset {} is above the last {}",
+                first, last
+            )));
+        }
+        let frames = (first..=last)
+            .map(|offset| vec![Command::UseTable { offset }])
+            .collect();
+        Self::new(frames, period)
+    }
+
+    /// Step the table offset from `first` up to `last` and back down, one
+    /// offset per frame
+    ///
+    /// The ends are played once per pass, so repeats scan back and forth
+    /// without dwelling on `first` or `last`.
+    pub fn table_ping_pong(first: u8, last: u8, period: Duration) -> Result<Self> {
+        let mut sweep = Self::table_sweep(first, last, period)?;
+        let down: Vec<Vec<Command>> = sweep
+            .frames
+            .iter()
+            .rev()
+            .skip(1)
+            .take(sweep.frames.len().saturating_sub(2))
+            .cloned()
+            .collect();
+        sweep.frames.extend(down);
+        Ok(sweep)
+    }
+
+    /// Play the frames `times` times; 0 plays them until cancelled
+    pub fn with_repeat(mut self, times: usize) -> Self {
+        self.repeat = times;
+        self
+    }
+
+    /// Start on the next multiple of `align` since the Unix epoch and time the
+    /// frames by the system clock instead of the client's
+    ///
+    /// Use a multiple of the frame period (or the whole sequence) so every
+    /// instance lands on the same frame.
+    pub fn with_wall_clock(mut self, align: Duration) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    pub fn frames(&self) -> &[Vec<Command>] {
+        &self.frames
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Time of frame `n` after the start
+    fn frame_time(&self, n: usize) -> Duration {
+        Duration::from_nanos((self.period.as_nanos() * n as u128) as u64)
+    }
+
+    /// Send the frames on time until done or cancelled
+    ///
+    /// A frame whose time has passed when the sequencer gets to it (a stalled
+    /// host, a slow link, a clock step) is skipped, so playback stays in step
+    /// instead of falling behind. Fails with [`DacError::Cancelled`] once
+    /// `cancel` is cancelled, and with the error of a failed frame.
+    pub fn run(&self, client: &mut DacClient, cancel: &CancellationToken) -> Result<Playback> {
+        let start = match self.align {
+            Some(align) => Start::Wall(next_boundary(SystemTime::now(), align)),
+            None => Start::Clock(client.clock().clone(), client.clock().now()),
+        };
+        let total = (self.repeat > 0).then(|| self.frames.len() * self.repeat);
+        let mut playback = Playback::default();
+        let mut next = 0;
+        while total.is_none_or(|total| next < total) {
+            cancel.check()?;
+            let wait = start.until(self.frame_time(next));
+            if !wait.is_zero() {
+                start.sleep(wait.min(CANCEL_CHECK));
+                continue;
+            }
+            let mut due = (start.elapsed().as_nanos() / self.period.as_nanos()) as usize;
+            if let Some(total) = total {
+                due = due.min(total - 1);
+            }
+            let frame = due.max(next);
+            playback.skipped += frame - next;
+            for &cmd in &self.frames[frame % self.frames.len()] {
+                client.send(cmd)?;
+            }
+            playback.played += 1;
+            next = frame + 1;
+        }
+        Ok(playback)
+    }
+}
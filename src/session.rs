@@ -0,0 +1,255 @@
+//! Saved `tui_diagnostic` sessions.
+//!
+//! The TUI writes its state to a session file on exit and restores it with
+//! `--resume`. The file is plain `key = value` lines so it can be read and
+//! edited by hand:
+//!
+//! ```text
+//! target = tcp:192.168.56.102:2012
+//! channel = 2
+//! dac = 0 256 32768 0 0 0 0 65535
+//! gpio = 1 0 0 0 0 0 0 0
+//! ```
+//!
+//! Keys that are missing keep their defaults and unknown keys are rejected.
+
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS};
+use crate::target::Target;
+use crate::widgets::{ThemeName, ValueDisplay};
+use clap::ValueEnum;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Session file used when none is given
+pub const DEFAULT_SESSION_FILE: &str = "tui_diagnostic.session";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    /// Target the session was connected to
+    pub target: Option<Target>,
+    pub dac: [u16; DAC_CHANNELS],
+    pub gpio: [bool; GPIO_PINS],
+    pub selected_channel: usize,
+    pub step: u16,
+    pub table_offset: u8,
+    /// Deferred (LDAC) mode and the channels still waiting for LDAC
+    pub deferred: bool,
+    pub pending: [bool; DAC_CHANNELS],
+    pub gang: [bool; DAC_CHANNELS],
+    /// Ganged channels keep their ratios instead of moving by the same amount
+    pub gang_ratio: bool,
+    pub theme: ThemeName,
+    pub display: ValueDisplay,
+    pub keepalive_interval: Duration,
+    pub keepalive_paused: bool,
+    pub last_command: String,
+    pub last_response: String,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            target: None,
+            dac: [0; DAC_CHANNELS],
+            gpio: [false; GPIO_PINS],
+            selected_channel: 0,
+            step: 256,
+            table_offset: 0,
+            deferred: false,
+            pending: [false; DAC_CHANNELS],
+            gang: [false; DAC_CHANNELS],
+            gang_ratio: false,
+            theme: ThemeName::Default,
+            display: ValueDisplay::Raw,
+            keepalive_interval: Duration::from_secs(5),
+            keepalive_paused: false,
+            last_command: String::new(),
+            last_response: String::new(),
+        }
+    }
+}
+
+fn invalid(line: usize, reason: impl std::fmt::Display) -> DacError {
+    DacError::InvalidArgument(format!("Session line {}: {}", line, reason))
+}
+
+fn parse_value<T: FromStr>(line: usize, key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(line, format!("bad {} '{}'", key, value)))
+}
+
+fn parse_bool(line: usize, key: &str, value: &str) -> Result<bool> {
+    match value {
+        "1" | "true" | "on" => Ok(true),
+        "0" | "false" | "off" => Ok(false),
+        _ => Err(invalid(line, format!("bad {} '{}'", key, value))),
+    }
+}
+
+fn parse_array<T: Copy + Default, const N: usize>(
+    line: usize,
+    key: &str,
+    value: &str,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<[T; N]> {
+    let items: Vec<&str> = value.split_whitespace().collect();
+    if items.len() != N {
+        return Err(invalid(
+            line,
+            format!("{} needs {} values, got {}", key, N, items.len()),
+        ));
+    }
+    let mut array = [T::default(); N];
+    for (slot, item) in array.iter_mut().zip(items) {
+        *slot = parse(item)?;
+    }
+    Ok(array)
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn join_bools(values: &[bool]) -> String {
+    values
+        .iter()
+        .map(|&on| if on { "1" } else { "0" })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Write the session, replacing the file only once it is complete
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.to_string())?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Commands that bring a device to this session's outputs
+    ///
+    /// Channels still pending in deferred mode were never loaded, so they are
+    /// left for the next LDAC.
+    pub fn replay(&self) -> Vec<Command> {
+        let mut commands = vec![Command::UseTable {
+            offset: self.table_offset,
+        }];
+        for (channel, &value) in self.dac.iter().enumerate() {
+            if !self.pending[channel] {
+                commands.push(Command::DacWrite {
+                    channel: channel as u8,
+                    value,
+                });
+            }
+        }
+        for (pin, &on) in self.gpio.iter().enumerate() {
+            commands.push(Command::Gpio { pin: pin as u8, on });
+        }
+        commands
+    }
+}
+
+impl FromStr for Session {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut session = Session::default();
+        for (i, line) in s.lines().enumerate() {
+            let n = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(n, "expected key = value"))?;
+            let (key, value) = (key.trim(), value.trim());
+            let bool_of = |item: &str| parse_bool(n, key, item);
+            match key {
+                "target" => session.target = Some(parse_value(n, key, value)?),
+                "dac" => session.dac = parse_array(n, key, value, |v| parse_value(n, key, v))?,
+                "gpio" => session.gpio = parse_array(n, key, value, bool_of)?,
+                "channel" => {
+                    session.selected_channel = parse_value(n, key, value)?;
+                    if session.selected_channel >= DAC_CHANNELS {
+                        return Err(invalid(n, format!("channel {} out of range", value)));
+                    }
+                }
+                "step" => session.step = parse_value(n, key, value)?,
+                "table_offset" => session.table_offset = parse_value(n, key, value)?,
+                "deferred" => session.deferred = parse_bool(n, key, value)?,
+                "pending" => session.pending = parse_array(n, key, value, bool_of)?,
+                "gang" => session.gang = parse_array(n, key, value, bool_of)?,
+                "gang_mode" => {
+                    session.gang_ratio = match value {
+                        "absolute" => false,
+                        "ratio" => true,
+                        _ => return Err(invalid(n, format!("bad gang_mode '{}'", value))),
+                    }
+                }
+                "theme" => {
+                    session.theme = ThemeName::from_str(value, true)
+                        .map_err(|_| invalid(n, format!("bad theme '{}'", value)))?
+                }
+                "display" => {
+                    session.display = ValueDisplay::from_str(value, true)
+                        .map_err(|_| invalid(n, format!("bad display '{}'", value)))?
+                }
+                "keepalive_interval_ms" => {
+                    session.keepalive_interval = Duration::from_millis(parse_value(n, key, value)?)
+                }
+                "keepalive_paused" => session.keepalive_paused = parse_bool(n, key, value)?,
+                "last_command" => session.last_command = value.to_string(),
+                "last_response" => session.last_response = value.to_string(),
+                _ => return Err(invalid(n, format!("unknown key '{}'", key))),
+            }
+        }
+        Ok(session)
+    }
+}
+
+impl std::fmt::Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# tui_diagnostic session")?;
+        if let Some(target) = &self.target {
+            writeln!(f, "target = {}", target)?;
+        }
+        writeln!(f, "dac = {}", join(&self.dac))?;
+        writeln!(f, "gpio = {}", join_bools(&self.gpio))?;
+        writeln!(f, "channel = {}", self.selected_channel)?;
+        writeln!(f, "step = {}", self.step)?;
+        writeln!(f, "table_offset = {}", self.table_offset)?;
+        writeln!(f, "deferred = {}", self.deferred)?;
+        writeln!(f, "pending = {}", join_bools(&self.pending))?;
+        writeln!(f, "gang = {}", join_bools(&self.gang))?;
+        let gang_mode = if self.gang_ratio { "ratio" } else { "absolute" };
+        writeln!(f, "gang_mode = {}", gang_mode)?;
+        if let Some(theme) = self.theme.to_possible_value() {
+            writeln!(f, "theme = {}", theme.get_name())?;
+        }
+        if let Some(display) = self.display.to_possible_value() {
+            writeln!(f, "display = {}", display.get_name())?;
+        }
+        writeln!(
+            f,
+            "keepalive_interval_ms = {}",
+            self.keepalive_interval.as_millis()
+        )?;
+        writeln!(f, "keepalive_paused = {}", self.keepalive_paused)?;
+        writeln!(f, "last_command = {}", self.last_command)?;
+        writeln!(f, "last_response = {}", self.last_response)
+    }
+}
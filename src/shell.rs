@@ -0,0 +1,264 @@
+//! Line commands of `csv1 shell`.
+//!
+//! Each line is one command; numbers are decimal or `0x` hex:
+//!
+//! | Line                      | Effect                                               |
+//! |---------------------------|------------------------------------------------------|
+//! | `dac CH VALUE`            | Write DAC channel CH                                 |
+//! | `get CH`                  | DAC channel CH as last written through this client   |
+//! | `gpio PIN on\|off`        | Switch a GPIO pin (`1`/`0` work too)                 |
+//! | `attach CH TABLE`         | Drive channel CH from a table                        |
+//! | `table TABLE INDEX VALUE` | Write one table entry                                |
+//! | `fill TABLE SPEC`         | Write the changed entries of a generated table       |
+//! | `use OFFSET`              | Select the table offset                              |
+//! | `ldac`, `keepalive`       | Send the command                                     |
+//! | `reg REG [VALUE]`         | Read (after a hello, if none ran), or write, a register |
+//! | `raw HEX...`              | Send a frame as is and return the responses          |
+//! | `hello [FEATURES]`        | Negotiate `crc`, `extended`, `readback` (default all) or `none` |
+//! | `state`                   | DAC outputs and GPIO pins as last written            |
+//! | `help`, `quit`            | Print this list, end the shell                       |
+//!
+//! [`execute`] runs a parsed [`Line`] on a [`DacClient`] and describes the
+//! outcome twice: as text for a terminal and as fields for a JSON result.
+
+use crate::client::DacClient;
+use crate::error::{DacError, Result};
+use crate::protocol::{self, Command, Features, DAC_CHANNELS, GPIO_PINS, TABLES};
+use crate::report::Value;
+use crate::tables::TableSpec;
+use std::str::FromStr;
+
+/// Summary of the commands, printed by `help`
+pub const HELP: &str = "\
+dac CH VALUE             write DAC channel CH
+get CH                   DAC channel CH as last written
+gpio PIN on|off          switch a GPIO pin
+attach CH TABLE          drive channel CH from a table
+table TABLE INDEX VALUE  write one table entry
+fill TABLE SPEC          write a generated table, e.g. fill 0 sine 2 | scale 0.5
+use OFFSET               select the table offset
+ldac | keepalive         send the command
+reg REG [VALUE]          read or write a register
+raw HEX...               send a frame as is
+hello [FEATURES]         negotiate crc, extended, readback or none
+state                    DAC outputs and GPIO pins
+help | quit";
+
+/// One parsed shell line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    /// A protocol command, sent and checked for a zero status
+    Send(Command),
+    /// Every entry of a table, written in order
+    Fill {
+        table: u8,
+        entries: Vec<u16>,
+    },
+    Get(u8),
+    ReadRegister(u8),
+    Raw(Vec<u8>),
+    Hello(Features),
+    State,
+    Help,
+    Quit,
+}
+
+/// What a line did: text for a terminal, fields for a JSON result
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    pub fields: Value,
+}
+
+impl Reply {
+    fn new(text: impl Into<String>, fields: Value) -> Self {
+        Self {
+            text: text.into(),
+            fields,
+        }
+    }
+}
+
+/// Decimal or `0x` hex number of type `T`
+fn number<T: TryFrom<u32>>(s: &str) -> Result<T> {
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| DacError::InvalidArgument(format!("invalid number '{}': {}", s, e)))?;
+    T::try_from(value).map_err(|_| DacError::InvalidArgument(format!("{} is out of range", s)))
+}
+
+/// Number below `count`, named `what` in the error
+fn index(s: &str, count: usize, what: &str) -> Result<u8> {
+    let value: u8 = number(s)?;
+    if value as usize >= count {
+        return Err(DacError::InvalidArgument(format!(
+            "{} {} is not in 0-{}",
+            what,
+            value,
+            count - 1
+        )));
+    }
+    Ok(value)
+}
+
+/// `s` after its first `n` words, for arguments that contain spaces
+fn after_words(s: &str, n: usize) -> &str {
+    (0..n).fold(s, |rest, _| {
+        let rest = rest.trim_start();
+        rest.find(char::is_whitespace)
+            .map_or("", |end| &rest[end..])
+    })
+}
+
+impl FromStr for Line {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let channel = |s| index(s, DAC_CHANNELS, "channel");
+        let table = |s| index(s, TABLES, "table");
+        let line = match words.as_slice() {
+            ["dac", ch, value] => Line::Send(Command::DacWrite {
+                channel: channel(ch)?,
+                value: number(value)?,
+            }),
+            ["get", ch] => Line::Get(channel(ch)?),
+            ["gpio", pin, state] => Line::Send(Command::Gpio {
+                pin: index(pin, GPIO_PINS, "pin")?,
+                on: match *state {
+                    "on" | "1" => true,
+                    "off" | "0" => false,
+                    other => {
+                        return Err(DacError::InvalidArgument(format!(
+                            "expected on or off, got '{}'",
+                            other
+                        )))
+                    }
+                },
+            }),
+            ["attach", ch, t] => Line::Send(Command::AttachTable {
+                channel: channel(ch)?,
+                table: table(t)?,
+            }),
+            ["table", t, i, value] => Line::Send(Command::TableWrite {
+                table: table(t)?,
+                index: number(i)?,
+                value: number(value)?,
+            }),
+            ["fill", t, _, ..] => Line::Fill {
+                table: table(t)?,
+                entries: after_words(s, 2).parse::<TableSpec>()?.generate(),
+            },
+            ["use", offset] => Line::Send(Command::UseTable {
+                offset: number(offset)?,
+            }),
+            ["ldac"] => Line::Send(Command::Ldac),
+            ["keepalive"] => Line::Send(Command::KeepAlive),
+            ["reg", reg] => Line::ReadRegister(number(reg)?),
+            ["reg", reg, value] => Line::Send(Command::RegisterWrite {
+                reg: number(reg)?,
+                value: number(value)?,
+            }),
+            ["raw", hex @ ..] if !hex.is_empty() => {
+                Line::Raw(protocol::parse_frame(&hex.join(" "))?)
+            }
+            ["hello"] => Line::Hello(Features::ALL),
+            ["hello", features] => Line::Hello(features.parse()?),
+            ["state"] => Line::State,
+            ["help"] => Line::Help,
+            ["quit"] | ["exit"] => Line::Quit,
+            [word, ..] => {
+                return Err(DacError::InvalidArgument(format!(
+                    "unknown command or wrong arguments: '{}' (try help)",
+                    word
+                )))
+            }
+            [] => return Err(DacError::InvalidArgument("empty line".to_string())),
+        };
+        Ok(line)
+    }
+}
+
+/// Run `line` on `client`; [`Line::Quit`] does nothing here
+pub fn execute(client: &mut DacClient, line: &Line) -> Result<Reply> {
+    Ok(match line {
+        Line::Send(cmd) => {
+            client.send(*cmd)?;
+            Reply::new(
+                format!("OK: {}", cmd),
+                Value::object().with("sent", cmd.encode().to_vec()),
+            )
+        }
+        Line::Fill { table, entries } => {
+            let sent = client.write_table(*table, entries)?;
+            Reply::new(
+                format!(
+                    "OK: table {} filled ({} of {} entries sent)",
+                    table,
+                    sent,
+                    entries.len()
+                ),
+                Value::object()
+                    .with("table", *table)
+                    .with("entries", entries.len())
+                    .with("sent", sent),
+            )
+        }
+        Line::Get(channel) => {
+            let value = client.state().dac[*channel as usize];
+            Reply::new(
+                format!("DAC{} = 0x{:04X} ({})", channel, value, value),
+                Value::object()
+                    .with("channel", *channel)
+                    .with("value", value),
+            )
+        }
+        Line::ReadRegister(reg) => {
+            if client.hello().is_none() {
+                // Only firmware that says so in a hello has register reads
+                client.negotiate(Features::EXTENDED | Features::READBACK)?;
+            }
+            let value = client.read_register(*reg)?;
+            Reply::new(
+                format!("REG{} = 0x{:04X} ({})", reg, value, value),
+                Value::object().with("register", *reg).with("value", value),
+            )
+        }
+        Line::Raw(frame) => {
+            let responses = client.send_raw(frame)?;
+            Reply::new(
+                format!("{:02X?} -> {:02X?}", frame, responses),
+                Value::object()
+                    .with("sent", frame.clone())
+                    .with("responses", responses),
+            )
+        }
+        Line::Hello(features) => {
+            let hello = client.negotiate(*features)?;
+            Reply::new(
+                crate::version::device_text(&hello),
+                Value::object()
+                    .with("version", hello.version)
+                    .with("features", hello.features.to_string()),
+            )
+        }
+        Line::State => {
+            let state = client.state();
+            let gpio: Vec<&str> = state
+                .gpio
+                .iter()
+                .map(|&on| if on { "on" } else { "off" })
+                .collect();
+            Reply::new(
+                format!("DAC {:04X?}\nGPIO {}", state.dac, gpio.join(" ")),
+                Value::object()
+                    .with("dac", state.dac.to_vec())
+                    .with("gpio", state.gpio.to_vec()),
+            )
+        }
+        Line::Help => Reply::new(HELP, Value::object().with("help", HELP)),
+        Line::Quit => Reply::new("", Value::Null),
+    })
+}
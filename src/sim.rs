@@ -0,0 +1,455 @@
+//! The DAC simulator behind `tcp_server_example` and `selftest`.
+//!
+//! [`serve`] accepts TCP clients and answers their commands like the
+//! hardware: every command updates the shared [`DeviceState`] in [`SimState`]
+//! and gets a status response, unless a [`BehaviorRule`] from a behavior script
+//! overrides the response or delays it.
+//!
+//! A hello is answered with every feature offered ([`SIM_FEATURES`]), and a
+//! client agreeing to CRC gets CRC on its connection from then on.
+
+use crate::device::DeviceState;
+use crate::error::{DacError, Result};
+use crate::framing::{self, FrameDecoder, StreamFraming};
+use crate::protocol::{self, Command, Features, PROTOCOL_VERSION};
+use crate::waveform::WaveformRecorder;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const STATUS_OK: u16 = 0x0000;
+const STATUS_ERROR: u16 = 0xFFFF;
+
+/// Features the simulator agrees to in a hello
+pub const SIM_FEATURES: Features = Features::ALL;
+
+/// Number of log lines kept for the dashboard
+const LOG_CAPACITY: usize = 500;
+
+/// Simulated device shared by all client handlers and the dashboard
+#[derive(Debug, Default)]
+pub struct SimState {
+    pub device: DeviceState,
+    /// Connected clients
+    pub clients: usize,
+    /// Recent messages, shown by a dashboard
+    pub log: VecDeque<String>,
+    /// Output history, recorded when set
+    pub recorder: Option<WaveformRecorder>,
+}
+
+/// Simulator settings shared by every client handler
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub verbose: bool,
+    pub crc: bool,
+    pub framing: StreamFraming,
+    pub behaviors: Arc<Vec<BehaviorRule>>,
+    pub state: Arc<Mutex<SimState>>,
+    /// A dashboard owns the terminal, so messages go to its log pane
+    pub dashboard: bool,
+}
+
+impl SimConfig {
+    /// Print a message, or append it to the dashboard log
+    pub fn log(&self, message: String) {
+        if !self.dashboard {
+            println!("{}", message);
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.log.len() == LOG_CAPACITY {
+            state.log.pop_front();
+        }
+        state.log.push_back(message);
+    }
+
+    /// Like `log`, but to stderr outside the dashboard
+    pub fn log_error(&self, message: String) {
+        if self.dashboard {
+            self.log(message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Log command details; the dashboard always shows them
+    pub fn log_detail(&self, message: String) {
+        if self.verbose || self.dashboard {
+            self.log(message);
+        }
+    }
+}
+
+/// One byte of a response template
+#[derive(Debug, Clone, Copy)]
+enum ResponseByte {
+    Literal(u8),
+    /// Copy byte n of the received command (`$0`..`$3`)
+    Command(usize),
+}
+
+/// A scripted reaction to commands matching `pattern`
+///
+/// Script lines look like `<b0> <b1> <b2> <b3> => <response> [after <ms>ms]`:
+///
+/// ```text
+/// # pattern       response                 latency
+/// fb 10 ?? ??  => ext 12 34                after 50ms   # [0x01, 2, 0x12, 0x34]
+/// fd 00 00 00  => 00 00                    after 200ms
+/// fe ?? ?? ??  => none                                  # drop the response
+/// 05 00 ?? ??  => ext $2 $3                             # echo the written value
+/// ```
+///
+/// Pattern bytes are hex (`fb` or `0xfb`) or `??` for any value. The response
+/// is `none`, raw hex bytes, or `ext` followed by a payload that is wrapped in
+/// the extended `[0x01, len, payload]` format. The first matching rule wins;
+/// commands without a match get the built-in behavior.
+#[derive(Debug, Clone)]
+pub struct BehaviorRule {
+    pattern: [Option<u8>; 4],
+    response: Option<Vec<ResponseByte>>,
+    latency: Duration,
+}
+
+impl BehaviorRule {
+    fn matches(&self, cmd: &[u8]) -> bool {
+        self.pattern
+            .iter()
+            .zip(cmd)
+            .all(|(expected, actual)| expected.is_none_or(|b| b == *actual))
+    }
+
+    fn render(&self, cmd: &[u8]) -> Option<Vec<u8>> {
+        self.response.as_ref().map(|template| {
+            template
+                .iter()
+                .map(|byte| match *byte {
+                    ResponseByte::Literal(b) => b,
+                    ResponseByte::Command(i) => cmd[i],
+                })
+                .collect()
+        })
+    }
+}
+
+/// Behavior script parse errors, without the line they occurred on
+type ParseResult<T> = std::result::Result<T, String>;
+
+fn parse_hex_byte(token: &str) -> ParseResult<u8> {
+    let digits = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+        .unwrap_or(token);
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex byte: {}", token))
+}
+
+fn parse_response_byte(token: &str) -> ParseResult<ResponseByte> {
+    match token.strip_prefix('$') {
+        Some(index) => match index.parse::<usize>() {
+            Ok(i) if i < 4 => Ok(ResponseByte::Command(i)),
+            _ => Err(format!("Invalid command byte reference: {}", token)),
+        },
+        None => parse_hex_byte(token).map(ResponseByte::Literal),
+    }
+}
+
+fn parse_behavior_line(line: &str) -> ParseResult<BehaviorRule> {
+    let (pattern_part, action_part) = line
+        .split_once("=>")
+        .ok_or("Expected '<pattern> => <response>'")?;
+
+    let pattern_tokens: Vec<&str> = pattern_part.split_whitespace().collect();
+    if pattern_tokens.len() != 4 {
+        return Err(format!(
+            "Pattern needs 4 bytes, got {}",
+            pattern_tokens.len()
+        ));
+    }
+    let mut pattern = [None; 4];
+    for (slot, token) in pattern.iter_mut().zip(&pattern_tokens) {
+        if *token != "??" {
+            *slot = Some(parse_hex_byte(token)?);
+        }
+    }
+
+    let mut tokens: Vec<&str> = action_part.split_whitespace().collect();
+    let mut latency = Duration::ZERO;
+    if let Some(pos) = tokens.iter().position(|t| *t == "after") {
+        let value = tokens.get(pos + 1).ok_or("Missing latency after 'after'")?;
+        let ms = value.strip_suffix("ms").unwrap_or(value);
+        latency = Duration::from_millis(
+            ms.parse()
+                .map_err(|_| format!("Invalid latency: {}", value))?,
+        );
+        tokens.truncate(pos);
+    }
+
+    let response = match tokens.as_slice() {
+        [] => return Err("Missing response".to_string()),
+        ["none"] => None,
+        ["ext", payload @ ..] => {
+            if payload.len() > u8::MAX as usize {
+                return Err(format!(
+                    "Extended payload too long: {} bytes",
+                    payload.len()
+                ));
+            }
+            let mut bytes = vec![
+                ResponseByte::Literal(0x01),
+                ResponseByte::Literal(payload.len() as u8),
+            ];
+            for token in payload {
+                bytes.push(parse_response_byte(token)?);
+            }
+            Some(bytes)
+        }
+        raw => Some(
+            raw.iter()
+                .map(|token| parse_response_byte(token))
+                .collect::<ParseResult<Vec<_>>>()?,
+        ),
+    };
+
+    Ok(BehaviorRule {
+        pattern,
+        response,
+        latency,
+    })
+}
+
+/// Read a behavior script (see [`BehaviorRule`])
+pub fn load_behaviors(path: &str) -> Result<Vec<BehaviorRule>> {
+    let script = std::fs::read_to_string(path)?;
+
+    let mut rules = Vec::new();
+    for (number, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let rule = parse_behavior_line(line).map_err(|e| {
+            DacError::InvalidArgument(format!(
+                "{}:{}: invalid behavior rule: {}",
+                path,
+                number + 1,
+                e
+            ))
+        })?;
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+/// Answer one client's commands until it disconnects
+pub fn handle_client(mut stream: TcpStream, config: SimConfig) -> Result<()> {
+    let mut crc = config.crc;
+    let stream_framing = config.framing;
+    let peer_addr = stream.peer_addr()?;
+    config.log(format!("Client connected: {}", peer_addr));
+
+    let mut buffer = [0u8; 1024];
+    let mut decoder = FrameDecoder::new(stream_framing);
+    let mut frame_len = framing::command_frame_len(crc);
+
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => {
+                // Client disconnected
+                config.log(format!("Client {} disconnected", peer_addr));
+                break;
+            }
+            Ok(bytes_read) => {
+                if config.verbose {
+                    config.log(format!(
+                        "Received {} bytes from {}: {:?}",
+                        bytes_read,
+                        peer_addr,
+                        &buffer[..bytes_read]
+                    ));
+                }
+
+                // Split into commands: 4-byte chunks (6 bytes with CRC), or one
+                // command per COBS/SLIP frame
+                let mut commands = Vec::new();
+                for frame in decoder.push(&buffer[..bytes_read]) {
+                    match frame {
+                        Ok(frame) if stream_framing == StreamFraming::Raw => {
+                            commands.extend(frame.chunks(frame_len).map(|c| c.to_vec()));
+                        }
+                        Ok(frame) => commands.push(frame),
+                        Err(e) => config.log_detail(format!("  -> Discarded frame: {}", e)),
+                    }
+                }
+
+                let mut responses = Vec::new();
+                for command in commands {
+                    if command.len() != frame_len {
+                        config.log_detail(format!(
+                            "  -> Ignored {}-byte command {:?}",
+                            command.len(),
+                            command
+                        ));
+                        continue;
+                    }
+
+                    let cmd = if crc {
+                        match framing::check_crc(&command) {
+                            Ok(cmd) => cmd,
+                            Err(e) => {
+                                config.log_detail(format!("  -> Rejected frame: {}", e));
+                                let mut response = vec![0x00, framing::STATUS_CRC_ERROR];
+                                framing::append_crc(&mut response);
+                                responses.extend_from_slice(&stream_framing.encode(&response));
+                                continue;
+                            }
+                        }
+                    } else {
+                        &command[..]
+                    };
+
+                    let mut response = match config.behaviors.iter().find(|rule| rule.matches(cmd))
+                    {
+                        Some(rule) => {
+                            config.log_detail(format!(
+                                "  -> Scripted behavior for {:02X?}: {:?}",
+                                cmd, rule
+                            ));
+                            if !rule.latency.is_zero() {
+                                // Earlier responses go out on time; only this one is late
+                                if !responses.is_empty() {
+                                    stream.write_all(&responses)?;
+                                    responses.clear();
+                                }
+                                thread::sleep(rule.latency);
+                            }
+                            match rule.render(cmd) {
+                                Some(response) => response,
+                                None => continue,
+                            }
+                        }
+                        None => process_command(cmd, &config),
+                    };
+
+                    // Unless a behavior script answered it like old firmware
+                    let agreed_crc = matches!(Command::decode(cmd), Some(Command::Hello { .. }))
+                        && protocol::hello_answer(&response)
+                            .is_ok_and(|hello| hello.features.contains(Features::CRC));
+
+                    if crc {
+                        framing::append_crc(&mut response);
+                    }
+                    responses.extend_from_slice(&stream_framing.encode(&response));
+
+                    // The answer goes out as the hello came; CRC starts after it
+                    if agreed_crc && !crc {
+                        config.log_detail("  -> CRC framing from now on".to_string());
+                        crc = true;
+                        frame_len = framing::command_frame_len(crc);
+                    }
+                }
+
+                // Send responses back
+                if !responses.is_empty() {
+                    stream.write_all(&responses)?;
+                    if config.verbose {
+                        config.log(format!(
+                            "Sent {} response bytes to {}: {:?}",
+                            responses.len(),
+                            peer_addr,
+                            responses
+                        ));
+                    }
+                }
+            }
+            Err(e) => {
+                config.log_error(format!("Error reading from {}: {}", peer_addr, e));
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Carry out a command, returning its response
+fn process_command(cmd: &[u8], config: &SimConfig) -> Vec<u8> {
+    let status = |status: u16| status.to_be_bytes().to_vec();
+    if cmd.len() != 4 {
+        return status(STATUS_ERROR);
+    }
+
+    match Command::decode(cmd) {
+        Some(command) => {
+            config.log_detail(format!("  -> {}", command));
+            let mut guard = config.state.lock().unwrap();
+            let state = &mut *guard;
+            state.device.apply(&command);
+            if let Some(recorder) = state.recorder.as_mut() {
+                recorder.record(&state.device.dac);
+            }
+            match command {
+                Command::RegisterRead { reg } => {
+                    let value = state.device.registers.get(&reg).copied().unwrap_or(0);
+                    let [hi, lo] = value.to_be_bytes();
+                    vec![0x01, 0x02, hi, lo]
+                }
+                Command::TableRead { table, index } => {
+                    let value = state.device.tables[table as usize][index as usize];
+                    let [hi, lo] = value.to_be_bytes();
+                    vec![0x01, 0x02, hi, lo]
+                }
+                Command::Hello { features, .. } => {
+                    let [hi, lo] = features.intersection(SIM_FEATURES).bits().to_be_bytes();
+                    vec![0x01, 0x03, PROTOCOL_VERSION, hi, lo]
+                }
+                _ => status(STATUS_OK),
+            }
+        }
+        None if cmd[0] <= 7 => {
+            config.log_detail(format!(
+                "  -> Unknown DAC command: channel={}, param={}",
+                cmd[0], cmd[1]
+            ));
+            status(STATUS_ERROR)
+        }
+        None => {
+            config.log_detail(format!(
+                "  -> Unknown command: 0x{:02X} 0x{:02X} 0x{:02X}{:02X}",
+                cmd[0], cmd[1], cmd[2], cmd[3]
+            ));
+            status(STATUS_ERROR)
+        }
+    }
+}
+
+/// Accept clients until `running` is cleared
+///
+/// The flag is only checked when a client connects.
+pub fn serve(listener: TcpListener, config: SimConfig, running: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match stream {
+            Ok(stream) => {
+                let config = config.clone();
+                thread::spawn(move || {
+                    config.state.lock().unwrap().clients += 1;
+                    if let Err(e) = handle_client(stream, config.clone()) {
+                        config.log_error(format!("Client handler error: {}", e));
+                    }
+                    config.state.lock().unwrap().clients -= 1;
+                });
+            }
+            Err(e) => {
+                config.log_error(format!("Error accepting connection: {}", e));
+            }
+        }
+    }
+}
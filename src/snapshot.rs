@@ -0,0 +1,258 @@
+//! Device state snapshots for before/after comparisons.
+//!
+//! A snapshot holds the outputs a client believes the device has, in the same
+//! `key = value` format as `tui_diagnostic` session files:
+//!
+//! ```text
+//! # serialtest snapshot
+//! source = twin:lab-pi:8080
+//! taken = 2024-05-01T12:30:00Z
+//! dac = 0 256 32768 0 0 0 0 65535
+//! gpio = 1 0 0 0 0 0 0 0
+//! table_offset = 0
+//! attached = - - 1 - - - - -
+//! ```
+//!
+//! Unknown keys are skipped, so a TUI session file reads as a snapshot of the
+//! TUI's outputs.
+
+use crate::desired::DesiredState;
+use crate::device::DeviceState;
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, DAC_CHANNELS, GPIO_PINS, TABLES};
+use crate::report;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Snapshot {
+    /// Where the state came from
+    pub source: Option<String>,
+    /// RFC 3339 time the snapshot was taken
+    pub taken: Option<String>,
+    pub dac: [u16; DAC_CHANNELS],
+    pub gpio: [bool; GPIO_PINS],
+    pub table_offset: u8,
+    /// Table attached to each channel
+    pub attached: [Option<u8>; DAC_CHANNELS],
+}
+
+/// One field that differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// `DAC3: 4660 -> 0`
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.before, self.after)
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn table_name(table: Option<u8>) -> String {
+    table.map_or_else(|| "none".to_string(), |t| t.to_string())
+}
+
+impl Snapshot {
+    /// Snapshot of `state`, taken now
+    pub fn from_state(state: &DeviceState) -> Self {
+        Self {
+            source: None,
+            taken: Some(report::utc_timestamp(SystemTime::now())),
+            dac: state.dac,
+            gpio: state.gpio,
+            table_offset: state.table_offset,
+            attached: state.attached,
+        }
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Commands that bring a device to this snapshot's outputs
+    ///
+    /// The same as a state file naming every output sends: attachments and
+    /// the table offset first, then the DAC values (latched with LDAC) and
+    /// the GPIOs. The protocol has no way to detach a table, so channels
+    /// without one keep whatever the device has.
+    pub fn commands(&self) -> Vec<Command> {
+        DesiredState::from(self).commands(None)
+    }
+
+    /// Every channel, pin and setting that differs in `after`, in channel order
+    pub fn diff(&self, after: &Snapshot) -> Vec<Difference> {
+        let mut out = Vec::new();
+        let mut push = |field: String, before: String, after: String| {
+            if before != after {
+                out.push(Difference {
+                    field,
+                    before,
+                    after,
+                });
+            }
+        };
+        for ch in 0..DAC_CHANNELS {
+            push(
+                format!("DAC{}", ch),
+                self.dac[ch].to_string(),
+                after.dac[ch].to_string(),
+            );
+        }
+        for ch in 0..DAC_CHANNELS {
+            push(
+                format!("DAC{} table", ch),
+                table_name(self.attached[ch]),
+                table_name(after.attached[ch]),
+            );
+        }
+        for pin in 0..GPIO_PINS {
+            push(
+                format!("GPIO{}", pin),
+                on_off(self.gpio[pin]).to_string(),
+                on_off(after.gpio[pin]).to_string(),
+            );
+        }
+        push(
+            "Table offset".to_string(),
+            self.table_offset.to_string(),
+            after.table_offset.to_string(),
+        );
+        out
+    }
+}
+
+fn invalid(line: usize, reason: impl fmt::Display) -> DacError {
+    DacError::InvalidArgument(format!("Snapshot line {}: {}", line, reason))
+}
+
+/// Exactly `N` whitespace-separated items of `value`, each parsed by `parse`
+fn parse_array<T: Copy + Default, const N: usize>(
+    line: usize,
+    key: &str,
+    value: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<[T; N]> {
+    let items: Vec<&str> = value.split_whitespace().collect();
+    if items.len() != N {
+        return Err(invalid(
+            line,
+            format!("{} needs {} values, got {}", key, N, items.len()),
+        ));
+    }
+    let mut array = [T::default(); N];
+    for (slot, item) in array.iter_mut().zip(items) {
+        *slot = parse(item).ok_or_else(|| invalid(line, format!("bad {} '{}'", key, item)))?;
+    }
+    Ok(array)
+}
+
+impl FromStr for Snapshot {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut snapshot = Snapshot::default();
+        for (i, line) in s.lines().enumerate() {
+            let n = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(n, "expected key = value"))?;
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "source" => snapshot.source = Some(value.to_string()),
+                "taken" => snapshot.taken = Some(value.to_string()),
+                "dac" => snapshot.dac = parse_array(n, key, value, |v| v.parse().ok())?,
+                "gpio" => {
+                    snapshot.gpio = parse_array(n, key, value, |v| match v {
+                        "1" | "true" | "on" => Some(true),
+                        "0" | "false" | "off" => Some(false),
+                        _ => None,
+                    })?
+                }
+                "table_offset" => {
+                    snapshot.table_offset = value
+                        .parse()
+                        .map_err(|_| invalid(n, format!("bad table_offset '{}'", value)))?
+                }
+                "attached" => {
+                    snapshot.attached = parse_array(n, key, value, |v| match v {
+                        "-" => Some(None),
+                        v => v
+                            .parse()
+                            .ok()
+                            .filter(|&t: &u8| (t as usize) < TABLES)
+                            .map(Some),
+                    })?
+                }
+                _ => {}
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |items: Vec<String>| items.join(" ");
+        writeln!(f, "# serialtest snapshot")?;
+        if let Some(source) = &self.source {
+            writeln!(f, "source = {}", source)?;
+        }
+        if let Some(taken) = &self.taken {
+            writeln!(f, "taken = {}", taken)?;
+        }
+        writeln!(
+            f,
+            "dac = {}",
+            join(self.dac.iter().map(|v| v.to_string()).collect())
+        )?;
+        writeln!(
+            f,
+            "gpio = {}",
+            join(
+                self.gpio
+                    .iter()
+                    .map(|&on| u8::from(on).to_string())
+                    .collect()
+            )
+        )?;
+        writeln!(f, "table_offset = {}", self.table_offset)?;
+        writeln!(
+            f,
+            "attached = {}",
+            join(
+                self.attached
+                    .iter()
+                    .map(|t| t.map_or_else(|| "-".to_string(), |t| t.to_string()))
+                    .collect()
+            )
+        )
+    }
+}
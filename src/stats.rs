@@ -0,0 +1,161 @@
+//! Transport traffic counters.
+//!
+//! Every client transport counts what it sends and receives in a
+//! [`TransportStats`]; handles cloned for reader and writer threads share one
+//! set of counters through [`SharedStats`]. [`LatencyStats`] collects response
+//! times for percentile reports.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransportStats {
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+    pub writes: u64,
+    /// Reads that returned data
+    pub reads: u64,
+    /// Reads that ended without data
+    pub timeouts: u64,
+    pub errors: u64,
+    pub reconnects: u64,
+    pub last_write: Option<Instant>,
+    pub last_read: Option<Instant>,
+}
+
+impl TransportStats {
+    pub fn record_write(&mut self, bytes: usize) {
+        self.writes += 1;
+        self.bytes_out += bytes as u64;
+        self.last_write = Some(Instant::now());
+    }
+
+    pub fn record_read(&mut self, bytes: usize) {
+        self.reads += 1;
+        self.bytes_in += bytes as u64;
+        self.last_read = Some(Instant::now());
+    }
+
+    pub fn record_timeout(&mut self) {
+        self.timeouts += 1;
+    }
+
+    pub fn record_error(&mut self) {
+        self.errors += 1;
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+    }
+
+    /// Most recent read or write
+    pub fn last_activity(&self) -> Option<Instant> {
+        self.last_write.max(self.last_read)
+    }
+
+    /// One-line summary for status bars
+    pub fn summary(&self) -> String {
+        let idle = match self.last_activity() {
+            Some(t) => format!("{:.1}s ago", t.elapsed().as_secs_f64()),
+            None => "never".to_string(),
+        };
+        format!(
+            "TX {} B/{} writes | RX {} B/{} reads | {} errors | {} reconnects | active {}",
+            self.bytes_out,
+            self.writes,
+            self.bytes_in,
+            self.reads,
+            self.errors,
+            self.reconnects,
+            idle
+        )
+    }
+}
+
+/// Multi-line report printed by the command-line tools at exit
+impl fmt::Display for TransportStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Writes:            {}", self.writes)?;
+        writeln!(f, "Reads:             {}", self.reads)?;
+        writeln!(f, "Bytes sent:        {}", self.bytes_out)?;
+        writeln!(f, "Bytes received:    {}", self.bytes_in)?;
+        writeln!(f, "Timeouts:          {}", self.timeouts)?;
+        writeln!(f, "Errors:            {}", self.errors)?;
+        write!(f, "Reconnects:        {}", self.reconnects)?;
+        if let Some(t) = self.last_activity() {
+            write!(
+                f,
+                "\nLast activity:     {:.1}s ago",
+                t.elapsed().as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counters shared by every handle of one link
+#[derive(Debug, Clone, Default)]
+pub struct SharedStats(Arc<Mutex<TransportStats>>);
+
+impl SharedStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut TransportStats)) {
+        f(&mut self.0.lock().unwrap());
+    }
+
+    pub fn snapshot(&self) -> TransportStats {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Response times, kept in full for exact percentiles
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    /// Microseconds, sorted lazily by [`percentile`](Self::percentile)
+    samples: Vec<u32>,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, latency: Duration) {
+        self.samples
+            .push(latency.as_micros().min(u32::MAX as u128) as u32);
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().map(|&us| micros(us))
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().map(|&us| micros(us))
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let total: u64 = self.samples.iter().map(|&us| us as u64).sum();
+        Some(Duration::from_micros(total / self.samples.len() as u64))
+    }
+
+    /// The sample `p` percent of samples are at or below (nearest rank), `p` in 0-100
+    pub fn percentile(&mut self, p: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        self.samples.sort_unstable();
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * self.samples.len() as f64).ceil() as usize;
+        Some(micros(self.samples[rank.saturating_sub(1)]))
+    }
+}
+
+fn micros(us: u32) -> Duration {
+    Duration::from_micros(us as u64)
+}
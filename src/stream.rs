@@ -0,0 +1,594 @@
+//! Flow-controlled command streaming.
+//!
+//! The device answers every 4-byte command with exactly one response. An
+//! [`AckWindow`] counts the commands still waiting for theirs; a
+//! [`CommandStream`] uses it to stop sending once `window` commands are
+//! outstanding and waits for responses instead, so a device or bridge that
+//! falls behind slows the sender down rather than overflowing its buffers.
+//! This replaces a fixed sleep after every command.
+//!
+//! Commands can also be queued and sent by [`CommandStream::pump`]. A
+//! [`SendQueue`] lets urgent commands (see [`Priority`]) jump ahead, so a
+//! keepalive or GPIO change queued during a 256-entry table upload goes out
+//! next instead of after the whole table, and the device watchdog does not
+//! expire mid-transfer. All other commands keep their order.
+//! [`CommandStream::flush_queue_cancellable`] sends the queue until a
+//! [`CancellationToken`] is cancelled and then sends cleanup commands instead,
+//! reporting its [`Progress`] to the callback given to
+//! [`CommandStream::with_progress`].
+
+use crate::cancel::CancellationToken;
+use crate::clock::{self, SharedClock};
+use crate::error::{DacError, Result};
+use crate::framing::{response_len, Codec, COMMAND_LEN};
+use crate::progress::{Progress, ProgressFn};
+use crate::protocol::{Command, CMD_GPIO, CMD_HEARTBEAT, CMD_KEEPALIVE};
+use crate::transport;
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Read, Write};
+use std::time::{Duration, Instant};
+
+/// Default time [`CommandStream`] waits without any response before giving up
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Commands sent but not yet answered, and the responses that answered them
+#[derive(Debug, Clone, Default)]
+pub struct AckWindow {
+    window: usize,
+    in_flight: usize,
+    partial: Vec<u8>,
+}
+
+impl AckWindow {
+    /// Allow up to `window` unanswered commands (at least one)
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            ..Self::default()
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Commands still waiting for a response
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Whether `data` can be sent without exceeding the window
+    ///
+    /// A batch larger than the whole window is allowed once nothing is in
+    /// flight, so it can never block forever.
+    pub fn has_room(&self, data: &[u8]) -> bool {
+        self.has_room_after(0, data)
+    }
+
+    /// Like [`has_room`](Self::has_room) once `queued` more commands are sent
+    fn has_room_after(&self, queued: usize, data: &[u8]) -> bool {
+        let in_flight = self.in_flight + queued;
+        in_flight == 0 || in_flight + commands_in(data) <= self.window
+    }
+
+    /// Account for `data` having been written
+    pub fn sent(&mut self, data: &[u8]) {
+        self.in_flight += commands_in(data);
+    }
+
+    /// Feed decoded response bytes and return every response they complete
+    pub fn received(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.partial.extend_from_slice(data);
+
+        let mut responses = Vec::new();
+        while self.partial.len() >= 2 {
+            let len = response_len(self.partial[0], self.partial[1]);
+            if self.partial.len() < len {
+                break;
+            }
+            responses.push(self.partial.drain(..len).collect());
+        }
+        self.in_flight = self.in_flight.saturating_sub(responses.len());
+        responses
+    }
+
+    /// Forget every outstanding command, e.g. after responses were lost
+    pub fn reset(&mut self) {
+        self.in_flight = 0;
+        self.partial.clear();
+    }
+}
+
+fn commands_in(data: &[u8]) -> usize {
+    data.len().div_ceil(COMMAND_LEN)
+}
+
+/// Send order of queued commands: normal commands wait while urgent ones are queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Keepalives, heartbeats and GPIO changes, which guard the watchdog and outputs
+    Urgent,
+    /// Everything else, table entry writes included
+    Normal,
+}
+
+impl Priority {
+    /// Lane of one 4-byte command
+    pub fn of(command: &[u8]) -> Self {
+        match command.first() {
+            Some(&(CMD_KEEPALIVE | CMD_HEARTBEAT | CMD_GPIO)) => Priority::Urgent,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// Commands waiting to be sent, one FIFO lane per [`Priority`]
+///
+/// Only urgent commands jump the queue. Everything else keeps the order it
+/// was queued in, so a table attachment queued after a table upload takes
+/// effect once the whole table is written.
+#[derive(Debug, Clone, Default)]
+pub struct SendQueue {
+    lanes: [VecDeque<Vec<u8>>; 2],
+}
+
+impl SendQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue every 4-byte command in `data` in its own lane
+    pub fn push(&mut self, data: &[u8]) {
+        for command in data.chunks(COMMAND_LEN) {
+            self.push_to(Priority::of(command), command);
+        }
+    }
+
+    /// Queue `data` as one unit in `priority`'s lane
+    pub fn push_to(&mut self, priority: Priority, data: &[u8]) {
+        self.lanes[priority as usize].push_back(data.to_vec());
+    }
+
+    /// Next unit to send, from the highest non-empty lane
+    pub fn peek(&self) -> Option<&[u8]> {
+        self.lanes
+            .iter()
+            .find_map(|lane| lane.front())
+            .map(Vec::as_slice)
+    }
+
+    /// Remove and return the unit [`peek`](Self::peek) shows
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.lanes.iter_mut().find_map(VecDeque::pop_front)
+    }
+
+    /// Units waiting in `priority`'s lane
+    pub fn pending(&self, priority: Priority) -> usize {
+        self.lanes[priority as usize].len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(VecDeque::is_empty)
+    }
+
+    /// Drop every queued unit
+    pub fn clear(&mut self) {
+        self.lanes.iter_mut().for_each(VecDeque::clear);
+    }
+}
+
+/// Sends commands over a link, blocking while the device is behind
+///
+/// The link's read timeout bounds each wait for a response; after
+/// `stall_timeout` without any response the send fails with
+/// [`DacError::Timeout`] and the window is reset, so one lost response does
+/// not stall the stream for good.
+pub struct CommandStream<L: Read + Write> {
+    link: L,
+    codec: Codec,
+    acks: AckWindow,
+    stall_timeout: Duration,
+    responses: Vec<Vec<u8>>,
+    queue: SendQueue,
+    keepalive: Option<Duration>,
+    last_keepalive: Instant,
+    progress: Option<ProgressFn>,
+    clock: SharedClock,
+}
+
+impl<L: Read + Write> CommandStream<L> {
+    pub fn new(link: L, codec: Codec, window: usize) -> Self {
+        Self {
+            link,
+            codec,
+            acks: AckWindow::new(window),
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+            responses: Vec::new(),
+            queue: SendQueue::new(),
+            keepalive: None,
+            last_keepalive: Instant::now(),
+            progress: None,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.stall_timeout = stall_timeout;
+        self
+    }
+
+    /// Report the progress of queue flushes to `progress` after every unit sent
+    pub fn with_progress(mut self, progress: impl FnMut(&Progress) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.last_keepalive = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// While queued commands are sent, queue an urgent keepalive every `interval`
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+
+    /// Encode and decode with `codec` from now on, e.g. once a hello turned on CRC
+    ///
+    /// Bytes of a partly received frame are dropped.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// Commands still waiting for a response
+    pub fn in_flight(&self) -> usize {
+        self.acks.in_flight()
+    }
+
+    /// Queue `data` for [`pump`](Self::pump), each command in its [`Priority`] lane
+    pub fn enqueue(&mut self, data: &[u8]) {
+        self.queue.push(data);
+    }
+
+    /// Commands queued but not sent yet
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Send queued commands, most urgent first, while the window has room
+    ///
+    /// Returns how many were sent. They go out in one vectored write. Like
+    /// [`try_send`](Self::try_send) this never waits; [`poll`](Self::poll) to
+    /// make room.
+    pub fn pump(&mut self) -> Result<usize> {
+        self.queue_keepalive();
+        let batch = self.take_batch();
+        self.write_batch(&batch)?;
+        Ok(batch.len())
+    }
+
+    /// Send everything queued, waiting for responses as needed
+    pub fn flush_queue(&mut self) -> Result<()> {
+        self.flush_queue_cancellable(&CancellationToken::new(), &[])
+    }
+
+    /// Send `data`, first waiting for responses while the window is full
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.wait_until(|acks| acks.has_room(data))?;
+        self.write(data)
+    }
+
+    /// [`send`](Self::send) without the codec's padding and strict checks
+    pub fn send_raw(&mut self, data: &[u8]) -> Result<()> {
+        self.wait_until(|acks| acks.has_room(data))?;
+        let frame = self.codec.encode_unchecked(data);
+        self.write_frames(&[data], &[frame])
+    }
+
+    /// Send `data` if the window has room, otherwise return `false` at once
+    ///
+    /// For callers that cannot block: [`poll`](Self::poll) on their own
+    /// schedule and retry, e.g. on the next event-loop tick.
+    pub fn try_send(&mut self, data: &[u8]) -> Result<bool> {
+        if !self.acks.has_room(data) {
+            return Ok(false);
+        }
+        self.write(data)?;
+        Ok(true)
+    }
+
+    /// Read once and account for the responses received; returns how many arrived
+    pub fn poll(&mut self) -> Result<usize> {
+        let mut buffer = [0u8; 256];
+        let n = match self.link.read(&mut buffer) {
+            Ok(0) => {
+                return Err(DacError::Transport(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed by peer",
+                )))
+            }
+            Ok(n) => n,
+            Err(e)
+                if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock =>
+            {
+                return Ok(0)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let decoded = self.codec.decode_responses(&buffer[..n])?;
+        let responses = self.acks.received(&decoded);
+        let count = responses.len();
+        self.responses.extend(responses);
+        Ok(count)
+    }
+
+    /// Like [`flush_queue`](Self::flush_queue), stopping early once `token` is cancelled
+    ///
+    /// On cancellation the rest of the queue is dropped, the commands already
+    /// sent are waited for and `cleanup` (e.g. restoring the table attachments
+    /// a partial upload would leave wrong) is sent before returning
+    /// [`DacError::Cancelled`].
+    pub fn flush_queue_cancellable(
+        &mut self,
+        token: &CancellationToken,
+        cleanup: &[u8],
+    ) -> Result<()> {
+        let started = self.clock.now();
+        let mut done = 0;
+        loop {
+            self.queue_keepalive();
+            let Some(next) = self.queue.peek().map(<[u8]>::to_vec) else {
+                return Ok(());
+            };
+            if token.is_cancelled() {
+                self.queue.clear();
+                self.drain()?;
+                if !cleanup.is_empty() {
+                    self.send(cleanup)?;
+                    self.drain()?;
+                }
+                return Err(DacError::Cancelled);
+            }
+            self.wait_until(|acks| acks.has_room(&next))?;
+            let batch = self.take_batch();
+            self.write_batch(&batch)?;
+
+            if let Some(progress) = &mut self.progress {
+                for (i, data) in batch.iter().enumerate() {
+                    done += 1;
+                    progress(&Progress {
+                        done,
+                        total: done + batch.len() - i - 1 + self.queue.len(),
+                        elapsed: self.clock.elapsed(started),
+                        current: data.get(..COMMAND_LEN).and_then(Command::decode),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Wait until every command sent so far has been answered
+    pub fn drain(&mut self) -> Result<()> {
+        self.wait_until(|acks| acks.in_flight() == 0)
+    }
+
+    /// Responses received since the last call, oldest first
+    pub fn take_responses(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.responses)
+    }
+
+    pub fn get_ref(&self) -> &L {
+        &self.link
+    }
+
+    pub fn get_mut(&mut self) -> &mut L {
+        &mut self.link
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.write_batch(&[data])
+    }
+
+    /// Pop the queued commands that fit in the window together, most urgent first
+    fn take_batch(&mut self) -> Vec<Vec<u8>> {
+        let mut batch = Vec::new();
+        let mut queued = 0;
+        while let Some(data) = self.queue.peek() {
+            if !self.acks.has_room_after(queued, data) {
+                break;
+            }
+            queued += commands_in(data);
+            batch.extend(self.queue.pop());
+        }
+        batch
+    }
+
+    /// Encode every entry of `batch` and send them all in one vectored write
+    fn write_batch<D: AsRef<[u8]>>(&mut self, batch: &[D]) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let frames = batch
+            .iter()
+            .map(|data| self.codec.encode_commands(data.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        self.write_frames(batch, &frames)
+    }
+
+    /// Send the encoded `frames` of `batch` in one vectored write
+    fn write_frames<D: AsRef<[u8]>>(&mut self, batch: &[D], frames: &[Vec<u8>]) -> Result<()> {
+        let mut slices: Vec<IoSlice> = frames.iter().map(|frame| IoSlice::new(frame)).collect();
+        transport::write_all_vectored(&mut self.link, &mut slices)?;
+        self.link.flush()?;
+
+        for data in batch.iter().map(AsRef::as_ref) {
+            self.acks.sent(data);
+            if data
+                .chunks(COMMAND_LEN)
+                .any(|cmd| cmd.first() == Some(&CMD_KEEPALIVE))
+            {
+                self.last_keepalive = self.clock.now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue a keepalive if one is due and none is waiting
+    fn queue_keepalive(&mut self) {
+        let Some(interval) = self.keepalive else {
+            return;
+        };
+        if !self.queue.is_empty()
+            && self.clock.elapsed(self.last_keepalive) >= interval
+            && self.queue.pending(Priority::Urgent) == 0
+        {
+            self.queue
+                .push_to(Priority::Urgent, &Command::KeepAlive.encode());
+        }
+    }
+
+    fn wait_until(&mut self, ready: impl Fn(&AckWindow) -> bool) -> Result<()> {
+        let mut last_progress = self.clock.now();
+        while !ready(&self.acks) {
+            if self.poll()? > 0 {
+                last_progress = self.clock.now();
+            } else if self.clock.elapsed(last_progress) >= self.stall_timeout {
+                self.acks.reset();
+                return Err(DacError::Timeout);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+
+    fn table_write(index: u8) -> Vec<u8> {
+        Command::TableWrite {
+            table: 0,
+            index,
+            value: 0x1000,
+        }
+        .encode()
+        .to_vec()
+    }
+
+    #[test]
+    fn window_reassembles_responses_split_across_reads() {
+        let mut acks = AckWindow::new(4);
+        acks.sent(&[0; 3 * COMMAND_LEN]);
+        assert!(acks.received(&[0x00]).is_empty());
+        assert_eq!(acks.received(&[0x00, 0x01, 0x02]), vec![vec![0x00, 0x00]]);
+        assert_eq!(
+            acks.received(&[0x12, 0x34, 0x00, 0x00]),
+            vec![vec![0x01, 0x02, 0x12, 0x34], vec![0x00, 0x00]]
+        );
+        assert_eq!(acks.in_flight(), 0);
+    }
+
+    #[test]
+    fn window_room_and_oversized_batches() {
+        let mut acks = AckWindow::new(2);
+        assert!(acks.has_room(&[0; 8 * COMMAND_LEN]));
+        acks.sent(&[0; COMMAND_LEN]);
+        assert!(acks.has_room(&[0; COMMAND_LEN]));
+        assert!(!acks.has_room(&[0; 2 * COMMAND_LEN]));
+        acks.reset();
+        assert_eq!(acks.in_flight(), 0);
+        assert_eq!(AckWindow::new(0).window(), 1);
+    }
+
+    #[test]
+    fn attachment_queued_after_an_upload_waits_for_it() {
+        let attach = Command::AttachTable {
+            channel: 0,
+            table: 0,
+        }
+        .encode();
+        let mut queue = SendQueue::new();
+        queue.push(&[table_write(0), table_write(1)].concat());
+        queue.push(&attach);
+        let order: Vec<Vec<u8>> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(order, vec![table_write(0), table_write(1), attach.to_vec()]);
+    }
+
+    #[test]
+    fn only_urgent_commands_jump_the_queue() {
+        let gpio = Command::Gpio { pin: 0, on: true }.encode();
+        let dac = Command::DacWrite {
+            channel: 0,
+            value: 5,
+        }
+        .encode();
+        let keepalive = Command::KeepAlive.encode();
+        assert_eq!(Priority::of(&table_write(0)), Priority::Normal);
+        assert_eq!(Priority::of(&dac), Priority::Normal);
+        assert_eq!(Priority::of(&gpio), Priority::Urgent);
+
+        let mut queue = SendQueue::new();
+        queue.push(&table_write(0));
+        queue.push(&[dac, gpio].concat());
+        queue.push(&table_write(1));
+        queue.push(&keepalive);
+        assert_eq!(queue.pending(Priority::Urgent), 2);
+        let order: Vec<Vec<u8>> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(
+            order,
+            vec![
+                gpio.to_vec(),
+                keepalive.to_vec(),
+                table_write(0),
+                dac.to_vec(),
+                table_write(1)
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn pump_fills_the_window_urgent_first() {
+        let mock = MockTransport::new();
+        let mut stream = CommandStream::new(mock.clone(), Codec::default(), 2);
+        stream.enqueue(&[table_write(0), table_write(1)].concat());
+        stream.enqueue(&Command::KeepAlive.encode());
+        assert_eq!(stream.pump().unwrap(), 2);
+        assert_eq!(
+            mock.commands(),
+            vec![
+                Command::KeepAlive,
+                Command::TableWrite {
+                    table: 0,
+                    index: 0,
+                    value: 0x1000
+                }
+            ]
+        );
+        assert_eq!(stream.queued(), 1);
+        assert_eq!(stream.pump().unwrap(), 0);
+        assert!(!stream.try_send(&table_write(2)).unwrap());
+    }
+
+    #[test]
+    fn send_waits_for_room_in_the_window() {
+        let mock = MockTransport::new().with_auto_ack();
+        let mut stream = CommandStream::new(mock.clone(), Codec::default(), 2);
+        for index in 0..3 {
+            stream.send(&table_write(index)).unwrap();
+        }
+        assert_eq!(stream.in_flight(), 1);
+        stream.drain().unwrap();
+        assert_eq!(stream.take_responses().len(), 3);
+        assert_eq!(mock.written().len(), 3);
+    }
+}
@@ -0,0 +1,431 @@
+//! Waveform table contents: generators and transforms.
+//!
+//! A [`TableSpec`] describes a table as a generator followed by any number of
+//! transforms, separated by `|`:
+//!
+//! ```text
+//! sine 2 | scale 0.5 | offset 16384
+//! steps 0 0x4000 0xC000 | smooth 9
+//! ```
+//!
+//! | Generator               | Entries                                            |
+//! |-------------------------|----------------------------------------------------|
+//! | `ramp [FROM TO]`        | Straight line, 0 to 65535 by default               |
+//! | `triangle`              | Up to full scale at the middle entry and back down |
+//! | `sine [CYCLES [PHASE]]` | Full-scale sine, PHASE in degrees                  |
+//! | `gamma G`               | `65535 * x^G` for x from 0 to 1                    |
+//! | `scurve [STEEPNESS]`    | Logistic S-curve from 0 to 65535 (default 10)      |
+//! | `steps V...`            | The values in equally long steps                   |
+//!
+//! | Transform       | Effect                                                 |
+//! |-----------------|--------------------------------------------------------|
+//! | `scale F`       | Multiply every entry by F                              |
+//! | `offset N`      | Add N, which may be negative                           |
+//! | `invert`        | `65535 - v`                                            |
+//! | `reverse`       | Last entry first                                       |
+//! | `smooth N`      | Moving average over N entries, wrapping like playback  |
+//!
+//! Stages work on unclamped values, so `offset -1000 | scale 2` is exact;
+//! only the finished table is rounded and clamped to 0-65535. Values are
+//! decimal or `0x` hex. The shell (`fill`), Lua scripts (`dac.fill`), the TUI
+//! (**K**) and `csv1 fill` all take the same text.
+//!
+//! A [`TableShadow`] remembers what a client wrote to each entry, so a table
+//! edited again only needs the entries that changed sent.
+
+use crate::error::{DacError, Result};
+use crate::protocol::{Command, TABLES, TABLE_LEN};
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+
+/// Largest entry value
+const FULL_SCALE: f64 = u16::MAX as f64;
+
+/// What a table starts out as
+#[derive(Debug, Clone, PartialEq)]
+pub enum Generator {
+    Ramp { from: u16, to: u16 },
+    Triangle,
+    Sine { cycles: f64, phase: f64 },
+    Gamma(f64),
+    SCurve(f64),
+    Steps(Vec<u16>),
+}
+
+/// A change applied to every entry, or to their order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    Scale(f64),
+    Offset(f64),
+    Invert,
+    Reverse,
+    Smooth(usize),
+}
+
+/// Position of entry `i` from 0.0 (first) to 1.0 (last)
+fn position(i: usize) -> f64 {
+    i as f64 / (TABLE_LEN - 1) as f64
+}
+
+impl Generator {
+    fn values(&self) -> Vec<f64> {
+        (0..TABLE_LEN)
+            .map(|i| match self {
+                Generator::Ramp { from, to } => {
+                    *from as f64 + (*to as f64 - *from as f64) * position(i)
+                }
+                Generator::Triangle => {
+                    FULL_SCALE * (1.0 - (2.0 * i as f64 / TABLE_LEN as f64 - 1.0).abs())
+                }
+                // Over i / TABLE_LEN, so whole cycles loop without a seam
+                Generator::Sine { cycles, phase } => {
+                    let angle = TAU * cycles * i as f64 / TABLE_LEN as f64 + phase.to_radians();
+                    FULL_SCALE * (0.5 + 0.5 * angle.sin())
+                }
+                Generator::Gamma(gamma) => FULL_SCALE * position(i).powf(*gamma),
+                Generator::SCurve(steepness) => {
+                    let logistic = |x: f64| 1.0 / (1.0 + (-steepness * (x - 0.5)).exp());
+                    let (low, high) = (logistic(0.0), logistic(1.0));
+                    FULL_SCALE * (logistic(position(i)) - low) / (high - low)
+                }
+                Generator::Steps(levels) => levels[i * levels.len() / TABLE_LEN] as f64,
+            })
+            .collect()
+    }
+}
+
+impl Transform {
+    fn apply(&self, values: &mut Vec<f64>) {
+        match *self {
+            Transform::Scale(factor) => values.iter_mut().for_each(|v| *v *= factor),
+            Transform::Offset(delta) => values.iter_mut().for_each(|v| *v += delta),
+            Transform::Invert => values.iter_mut().for_each(|v| *v = FULL_SCALE - *v),
+            Transform::Reverse => values.reverse(),
+            Transform::Smooth(window) => {
+                let len = values.len();
+                let before = (window - 1) / 2;
+                *values = (0..len)
+                    .map(|i| {
+                        let sum: f64 = (0..window)
+                            .map(|k| values[(i + len + k - before) % len])
+                            .sum();
+                        sum / window as f64
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+/// A generator and the transforms applied to its output, in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSpec {
+    generator: Generator,
+    transforms: Vec<Transform>,
+    /// The text parsed, for display
+    text: String,
+}
+
+impl TableSpec {
+    /// The [`TABLE_LEN`] entries the spec describes
+    pub fn generate(&self) -> Vec<u16> {
+        let mut values = self.generator.values();
+        for transform in &self.transforms {
+            transform.apply(&mut values);
+        }
+        values
+            .into_iter()
+            .map(|v| v.round().clamp(0.0, FULL_SCALE) as u16)
+            .collect()
+    }
+
+    /// Commands writing the generated entries to `table`
+    pub fn commands(&self, table: u8) -> Vec<Command> {
+        self.generate()
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| Command::TableWrite {
+                table,
+                index: index as u8,
+                value,
+            })
+            .collect()
+    }
+}
+
+/// The entries a client wrote to the device's tables, as far as it knows
+///
+/// Entries never written are unknown and always count as changed, as are
+/// all entries after [`clear`](Self::clear), e.g. once the device may have
+/// reset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableShadow {
+    entries: [[Option<u16>; TABLE_LEN]; TABLES],
+}
+
+impl Default for TableShadow {
+    fn default() -> Self {
+        Self {
+            entries: [[None; TABLE_LEN]; TABLES],
+        }
+    }
+}
+
+impl TableShadow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether entry `index` of `table` is known to hold `value`
+    pub fn holds(&self, table: u8, index: u8, value: u16) -> bool {
+        self.entries
+            .get(table as usize)
+            .is_some_and(|entries| entries[index as usize] == Some(value))
+    }
+
+    /// Writes for the entries of `values` (from entry 0) the table does not hold yet
+    pub fn changes(&self, table: u8, values: &[u16]) -> Vec<Command> {
+        values
+            .iter()
+            .take(TABLE_LEN)
+            .enumerate()
+            .filter(|&(index, &value)| !self.holds(table, index as u8, value))
+            .map(|(index, &value)| Command::TableWrite {
+                table,
+                index: index as u8,
+                value,
+            })
+            .collect()
+    }
+
+    /// Note a table write the device accepted; other commands are ignored
+    pub fn record(&mut self, cmd: &Command) {
+        if let Command::TableWrite {
+            table,
+            index,
+            value,
+        } = *cmd
+        {
+            if let Some(entries) = self.entries.get_mut(table as usize) {
+                entries[index as usize] = Some(value);
+            }
+        }
+    }
+
+    /// Forget every entry
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// A table entry that read back differently from what was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub index: u8,
+    pub expected: u16,
+    pub actual: u16,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "entry {}: wrote 0x{:04X}, read 0x{:04X}",
+            self.index, self.expected, self.actual
+        )
+    }
+}
+
+/// What reading a table back after an upload found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// Entries written again after reading back wrong, over all retries
+    pub rewritten: usize,
+    /// Entries still wrong after the last retry
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Verification {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+fn invalid(message: String) -> DacError {
+    DacError::InvalidArgument(message)
+}
+
+/// Decimal or `0x` hex entry value
+fn value(s: &str) -> Result<u16> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| invalid(format!("invalid table value '{}' (0-65535)", s)))
+}
+
+fn real(s: &str) -> Result<f64> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| invalid(format!("invalid number '{}'", s)))
+}
+
+fn positive(s: &str) -> Result<f64> {
+    real(s)
+        .ok()
+        .filter(|&v| v > 0.0)
+        .ok_or_else(|| invalid(format!("'{}' must be a number above zero", s)))
+}
+
+/// Signed offset, in decimal or `0x` hex
+fn delta(s: &str) -> Result<f64> {
+    match s.strip_prefix('-') {
+        Some(magnitude) => Ok(-(value(magnitude)? as f64)),
+        None => Ok(value(s.strip_prefix('+').unwrap_or(s))? as f64),
+    }
+}
+
+impl FromStr for Generator {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let generator = match words.as_slice() {
+            ["ramp"] => Generator::Ramp {
+                from: 0,
+                to: u16::MAX,
+            },
+            ["ramp", from, to] => Generator::Ramp {
+                from: value(from)?,
+                to: value(to)?,
+            },
+            ["triangle"] => Generator::Triangle,
+            ["sine"] => Generator::Sine {
+                cycles: 1.0,
+                phase: 0.0,
+            },
+            ["sine", cycles] => Generator::Sine {
+                cycles: positive(cycles)?,
+                phase: 0.0,
+            },
+            ["sine", cycles, phase] => Generator::Sine {
+                cycles: positive(cycles)?,
+                phase: real(phase)?,
+            },
+            ["gamma", gamma] => Generator::Gamma(positive(gamma)?),
+            ["scurve"] => Generator::SCurve(10.0),
+            ["scurve", steepness] => Generator::SCurve(positive(steepness)?),
+            ["steps", levels @ ..] if !levels.is_empty() && levels.len() <= TABLE_LEN => {
+                Generator::Steps(levels.iter().map(|v| value(v)).collect::<Result<_>>()?)
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "unknown generator '{}' (ramp, triangle, sine, gamma, scurve or steps)",
+                    s.trim()
+                )))
+            }
+        };
+        Ok(generator)
+    }
+}
+
+impl FromStr for Transform {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let transform = match words.as_slice() {
+            ["scale", factor] => Transform::Scale(real(factor)?),
+            ["offset", n] => Transform::Offset(delta(n)?),
+            ["invert"] => Transform::Invert,
+            ["reverse"] => Transform::Reverse,
+            ["smooth", n] => match n.parse::<usize>() {
+                Ok(window) if (1..=TABLE_LEN).contains(&window) => Transform::Smooth(window),
+                _ => {
+                    return Err(invalid(format!(
+                        "smooth window '{}' is not in 1-{}",
+                        n, TABLE_LEN
+                    )))
+                }
+            },
+            _ => {
+                return Err(invalid(format!(
+                    "unknown transform '{}' (scale, offset, invert, reverse or smooth)",
+                    s.trim()
+                )))
+            }
+        };
+        Ok(transform)
+    }
+}
+
+impl FromStr for TableSpec {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut stages = s.split('|');
+        let generator = stages.next().unwrap_or_default().parse()?;
+        let transforms = stages.map(str::parse).collect::<Result<_>>()?;
+        let text = s
+            .split('|')
+            .map(|stage| stage.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        Ok(Self {
+            generator,
+            transforms,
+            text,
+        })
+    }
+}
+
+impl fmt::Display for TableSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadow_sends_only_changed_entries() {
+        let mut shadow = TableShadow::new();
+        let values = vec![0x1000; TABLE_LEN];
+        let changes = shadow.changes(2, &values);
+        assert_eq!(changes.len(), TABLE_LEN);
+        for cmd in &changes {
+            shadow.record(cmd);
+        }
+        assert!(shadow.changes(2, &values).is_empty());
+        assert_eq!(shadow.changes(1, &values).len(), TABLE_LEN);
+
+        let mut edited = values.clone();
+        edited[7] = 0x2000;
+        assert_eq!(
+            shadow.changes(2, &edited),
+            vec![Command::TableWrite {
+                table: 2,
+                index: 7,
+                value: 0x2000
+            }]
+        );
+
+        shadow.clear();
+        assert_eq!(shadow.changes(2, &values).len(), TABLE_LEN);
+    }
+
+    #[test]
+    fn shadow_ignores_other_commands_and_tables() {
+        let mut shadow = TableShadow::new();
+        shadow.record(&Command::DacWrite {
+            channel: 0,
+            value: 5,
+        });
+        assert_eq!(shadow, TableShadow::new());
+        assert!(!shadow.holds(TABLES as u8, 0, 0));
+    }
+}
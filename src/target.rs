@@ -0,0 +1,335 @@
+//! Connection target parsing.
+//!
+//! Every binary accepts the same target syntax:
+//!
+//! | Target                            | Transport                              |
+//! |-----------------------------------|----------------------------------------|
+//! | `serial:/dev/ttyACM0?baud=115200` | Serial device, optional baud rate      |
+//! | `pty:/dev/pts/3`                  | Pseudo-terminal (socat, simulators)    |
+//! | `tcp:host:port`                   | TCP                                    |
+//! | `udp:host:port`                   | UDP, one datagram per write            |
+//! | `tls:host:port?ca=cert.pem`       | TCP with TLS (feature `tls`)           |
+//! | `scheme://...`                    | [`TransportRegistry`] scheme           |
+//! | `host:port`, `[::1]:port`         | TCP (bare form)                        |
+//! | anything else                     | Serial device path (bare form)         |
+//!
+//! A bare target is only taken as TCP if it ends in `:<port>`, so device paths
+//! containing a colon still open as serial ports.
+//!
+//! [`TransportRegistry`]: crate::transport::TransportRegistry
+
+use crate::error::{DacError, Result};
+use crate::transport::split_scheme;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Baud rate used when a serial target does not name one
+pub const DEFAULT_BAUD: u32 = 115_200;
+
+/// A parsed connection target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Serial {
+        path: String,
+        baud: u32,
+    },
+    Pty {
+        path: String,
+    },
+    Tcp {
+        address: String,
+    },
+    Udp {
+        address: String,
+    },
+    Tls {
+        address: String,
+        ca: Option<PathBuf>,
+    },
+    /// `scheme://rest`, opened through the transport registry
+    Url {
+        scheme: String,
+        rest: String,
+    },
+}
+
+fn invalid(target: &str, reason: impl fmt::Display) -> DacError {
+    DacError::InvalidArgument(format!("Invalid target '{}': {}", target, reason))
+}
+
+/// Whether `s` is `host:port` or `[v6]:port`
+fn is_address(s: &str) -> bool {
+    match s.rsplit_once(':') {
+        Some((host, port)) => {
+            !host.is_empty() && !host.contains('/') && port.parse::<u16>().is_ok()
+        }
+        None => false,
+    }
+}
+
+/// Split `rest?key=value&key=value` into `rest` and its options
+fn split_options(s: &str) -> (&str, Vec<(&str, &str)>) {
+    match s.split_once('?') {
+        Some((head, query)) => (
+            head,
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+                .collect(),
+        ),
+        None => (s, Vec::new()),
+    }
+}
+
+/// Parse `path?baud=N` as used by `serial:` and `serial://` targets
+pub fn parse_serial(target: &str, rest: &str) -> Result<(String, u32)> {
+    let (path, options) = split_options(rest);
+    if path.is_empty() {
+        return Err(invalid(target, "missing device path"));
+    }
+    let mut baud = DEFAULT_BAUD;
+    for (key, value) in options {
+        match key {
+            "baud" => {
+                baud = value
+                    .parse()
+                    .map_err(|_| invalid(target, format!("bad baud rate '{}'", value)))?
+            }
+            _ => return Err(invalid(target, format!("unknown option '{}'", key))),
+        }
+    }
+    Ok((path.to_string(), baud))
+}
+
+fn parse_address(target: &str, rest: &str) -> Result<String> {
+    if !is_address(rest) {
+        return Err(invalid(target, "expected host:port"));
+    }
+    Ok(rest.to_string())
+}
+
+impl FromStr for Target {
+    type Err = DacError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((scheme, rest)) = split_scheme(s) {
+            return Ok(Target::Url {
+                scheme: scheme.to_ascii_lowercase(),
+                rest: rest.to_string(),
+            });
+        }
+
+        if let Some((scheme, rest)) = s.split_once(':') {
+            let no_options = |rest: &str| {
+                if rest.contains('?') {
+                    Err(invalid(s, format!("{}: targets take no options", scheme)))
+                } else {
+                    Ok(())
+                }
+            };
+            match scheme.to_ascii_lowercase().as_str() {
+                "serial" => {
+                    let (path, baud) = parse_serial(s, rest)?;
+                    return Ok(Target::Serial { path, baud });
+                }
+                "pty" => {
+                    no_options(rest)?;
+                    if rest.is_empty() {
+                        return Err(invalid(s, "missing device path"));
+                    }
+                    return Ok(Target::Pty {
+                        path: rest.to_string(),
+                    });
+                }
+                "tcp" => {
+                    no_options(rest)?;
+                    return Ok(Target::Tcp {
+                        address: parse_address(s, rest)?,
+                    });
+                }
+                "udp" => {
+                    no_options(rest)?;
+                    return Ok(Target::Udp {
+                        address: parse_address(s, rest)?,
+                    });
+                }
+                "tls" => {
+                    let (address, options) = split_options(rest);
+                    let mut ca = None;
+                    for (key, value) in options {
+                        match key {
+                            "ca" => ca = Some(PathBuf::from(value)),
+                            _ => return Err(invalid(s, format!("unknown option '{}'", key))),
+                        }
+                    }
+                    return Ok(Target::Tls {
+                        address: parse_address(s, address)?,
+                        ca,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // Bare forms
+        if s.is_empty() {
+            Err(invalid(s, "empty target"))
+        } else if is_address(s) {
+            Ok(Target::Tcp {
+                address: s.to_string(),
+            })
+        } else {
+            Ok(Target::Serial {
+                path: s.to_string(),
+                baud: DEFAULT_BAUD,
+            })
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Serial { path, baud } if *baud == DEFAULT_BAUD => write!(f, "serial:{}", path),
+            Target::Serial { path, baud } => write!(f, "serial:{}?baud={}", path, baud),
+            Target::Pty { path } => write!(f, "pty:{}", path),
+            Target::Tcp { address } => write!(f, "tcp:{}", address),
+            Target::Udp { address } => write!(f, "udp:{}", address),
+            Target::Tls { address, ca: None } => write!(f, "tls:{}", address),
+            Target::Tls {
+                address,
+                ca: Some(ca),
+            } => write!(f, "tls:{}?ca={}", address, ca.display()),
+            Target::Url { scheme, rest } => write!(f, "{}://{}", scheme, rest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Target {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn bare_targets_are_tcp_only_with_a_port() {
+        let tcp = |address: &str| Target::Tcp {
+            address: address.to_string(),
+        };
+        let serial = |path: &str| Target::Serial {
+            path: path.to_string(),
+            baud: DEFAULT_BAUD,
+        };
+        assert_eq!(parse("lab-pi:8080"), tcp("lab-pi:8080"));
+        assert_eq!(parse("[::1]:8080"), tcp("[::1]:8080"));
+        assert_eq!(parse("/dev/ttyACM0"), serial("/dev/ttyACM0"));
+        assert_eq!(parse("COM3"), serial("COM3"));
+        assert_eq!(parse("lab-pi:http"), serial("lab-pi:http"));
+        assert_eq!(
+            parse("/dev/serial/by-path/pci-0000:00:14.0-usb-0:1:1.0"),
+            serial("/dev/serial/by-path/pci-0000:00:14.0-usb-0:1:1.0")
+        );
+    }
+
+    #[test]
+    fn prefixed_targets() {
+        assert_eq!(
+            parse("serial:/dev/ttyUSB0?baud=9600"),
+            Target::Serial {
+                path: "/dev/ttyUSB0".to_string(),
+                baud: 9600
+            }
+        );
+        assert_eq!(
+            parse("PTY:/dev/pts/3"),
+            Target::Pty {
+                path: "/dev/pts/3".to_string()
+            }
+        );
+        assert_eq!(
+            parse("tcp:[fe80::1]:8080"),
+            Target::Tcp {
+                address: "[fe80::1]:8080".to_string()
+            }
+        );
+        assert_eq!(
+            parse("udp:10.0.0.2:9000"),
+            Target::Udp {
+                address: "10.0.0.2:9000".to_string()
+            }
+        );
+        assert_eq!(
+            parse("tls:lab:8443?ca=certs/lab.pem"),
+            Target::Tls {
+                address: "lab:8443".to_string(),
+                ca: Some(PathBuf::from("certs/lab.pem"))
+            }
+        );
+    }
+
+    #[test]
+    fn url_targets_go_to_the_registry_as_they_are() {
+        assert_eq!(
+            parse("TLS://lab:8443?ca=lab.pem"),
+            Target::Url {
+                scheme: "tls".to_string(),
+                rest: "lab:8443?ca=lab.pem".to_string()
+            }
+        );
+        assert_eq!(
+            parse("serial:///dev/ttyACM0?baud=9600"),
+            Target::Url {
+                scheme: "serial".to_string(),
+                rest: "/dev/ttyACM0?baud=9600".to_string()
+            }
+        );
+        assert_eq!(
+            parse("carrier-pigeon://coop/1"),
+            Target::Url {
+                scheme: "carrier-pigeon".to_string(),
+                rest: "coop/1".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn bad_targets_are_rejected() {
+        for target in [
+            "",
+            "serial:",
+            "serial:/dev/ttyACM0?baud=fast",
+            "serial:/dev/ttyACM0?parity=odd",
+            "pty:",
+            "pty:/dev/pts/3?baud=9600",
+            "tcp:lab-pi",
+            "tcp:lab-pi:99999",
+            "udp:/dev/ttyACM0:1",
+            "tls:lab:8443?key=lab.key",
+        ] {
+            assert!(target.parse::<Target>().is_err(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn display_parses_back_to_the_same_target() {
+        for target in [
+            "serial:/dev/ttyACM0",
+            "serial:/dev/ttyACM0?baud=9600",
+            "pty:/dev/pts/3",
+            "tcp:[::1]:8080",
+            "udp:lab:9000",
+            "tls:lab:8443",
+            "tls:lab:8443?ca=lab.pem",
+            "ssh://pi@lab/dev/ttyACM0",
+        ] {
+            assert_eq!(parse(target).to_string(), target);
+            assert_eq!(parse(&parse(target).to_string()), parse(target));
+        }
+        assert_eq!(parse("lab:8080").to_string(), "tcp:lab:8080");
+        assert_eq!(parse("/dev/ttyACM0").to_string(), "serial:/dev/ttyACM0");
+    }
+}
@@ -0,0 +1,1435 @@
+//! Transport links selected by URL scheme.
+//!
+//! [`open_target`] opens any parsed [`Target`]; a target of the form
+//! `scheme://rest` is looked up in a [`TransportRegistry`]. The registry knows
+//! `tcp://`, `udp://`, `serial://`, `pty://` and `ssh://` and can be extended
+//! in code with [`TransportRegistry::register`] or, with the `plugins`
+//! feature, by shared libraries listed in `SERIALTEST_PLUGINS`.
+//!
+//! A link only moves raw bytes; [`FramedLink`] adds the CRC and stream framing
+//! of a [`Codec`] and traffic counters on top.
+
+use crate::channels::ChannelLinks;
+use crate::clock::{self, SharedClock};
+use crate::discovery;
+use crate::error::{DacError, Result};
+use crate::framing::Codec;
+use crate::stats::SharedStats;
+use crate::target::{parse_serial, Target};
+use crate::wake;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Environment variable listing transport plugin libraries (path-separated)
+pub const PLUGIN_ENV: &str = "SERIALTEST_PLUGINS";
+
+/// Symbol every plugin library exports, a `fn(&mut TransportRegistry)`
+pub const PLUGIN_SYMBOL: &[u8] = b"serialtest_register_transports";
+
+/// A byte stream to the device
+pub trait Link: Read + Write + Send {
+    /// Short name shown in status output, e.g. "TCP"
+    fn kind(&self) -> &'static str;
+
+    /// Open a second handle on the same link for a separate reader thread
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>>;
+}
+
+impl Link for TcpStream {
+    fn kind(&self) -> &'static str {
+        "TCP"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+}
+
+impl Link for Box<dyn serialport::SerialPort> {
+    fn kind(&self) -> &'static str {
+        "Serial"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+}
+
+/// Write every byte of `slices`, as few vectored writes as the writer allows
+///
+/// Batched commands are many 4-byte frames; on a socket this submits them with
+/// one `writev` instead of a write each. Writers without vectored writes get
+/// one write per slice, as before.
+pub fn write_all_vectored<W: Write + ?Sized>(
+    writer: &mut W,
+    mut slices: &mut [IoSlice<'_>],
+) -> io::Result<()> {
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Vectored write for serial ports, which have no `writev` of their own
+///
+/// The slices are joined and written at once, so a batch still costs one
+/// system call and the port's write timeout still applies.
+fn write_gathered(
+    port: &mut dyn serialport::SerialPort,
+    slices: &[IoSlice<'_>],
+) -> io::Result<usize> {
+    match slices {
+        [] => Ok(0),
+        [slice] => port.write(slice),
+        slices => port.write(
+            &slices
+                .iter()
+                .flat_map(|s| s.iter().copied())
+                .collect::<Vec<u8>>(),
+        ),
+    }
+}
+
+/// Bytes buffered by default before a [`CoalescingWriter`] flushes on its own
+pub const DEFAULT_COALESCE_CAPACITY: usize = 1024;
+
+/// Collects small writes and passes them on in fewer, larger ones
+///
+/// Pending bytes go out once `capacity` is reached, on a write made after
+/// `max_delay` has passed since the oldest pending byte, on [`flush`] or
+/// [`flush_if_due`], and before every read so a response is never awaited
+/// while its command is still buffered. With a zero `max_delay` every write
+/// goes straight through.
+///
+/// [`flush`]: Write::flush
+/// [`flush_if_due`]: CoalescingWriter::flush_if_due
+#[derive(Debug)]
+pub struct CoalescingWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    capacity: usize,
+    max_delay: Duration,
+    oldest: Option<Instant>,
+    clock: SharedClock,
+}
+
+impl<W: Write> CoalescingWriter<W> {
+    pub fn new(inner: W, max_delay: Duration) -> Self {
+        Self::with_capacity(inner, DEFAULT_COALESCE_CAPACITY, max_delay)
+    }
+
+    pub fn with_capacity(inner: W, capacity: usize, max_delay: Duration) -> Self {
+        Self {
+            inner,
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            max_delay,
+            oldest: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    pub fn set_max_delay(&mut self, max_delay: Duration) {
+        self.max_delay = max_delay;
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes written but not yet passed on
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Time until the pending bytes are due, or `None` if nothing is pending
+    pub fn due_in(&self) -> Option<Duration> {
+        self.oldest
+            .map(|t| self.max_delay.saturating_sub(self.clock.elapsed(t)))
+    }
+
+    /// Flush if the oldest pending byte has waited `max_delay`; returns
+    /// whether anything was written
+    pub fn flush_if_due(&mut self) -> io::Result<bool> {
+        if self.due_in() == Some(Duration::ZERO) {
+            self.flush()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            // On error the bytes are dropped: retrying a partial frame would
+            // desynchronise the device
+            let result = self.inner.write_all(&self.buffer);
+            self.buffer.clear();
+            self.oldest = None;
+            result?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for CoalescingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(data)])
+    }
+
+    fn write_vectored(&mut self, slices: &[IoSlice<'_>]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            self.oldest = Some(self.clock.now());
+        }
+        let mut len = 0;
+        for slice in slices {
+            self.buffer.extend_from_slice(slice);
+            len += slice.len();
+        }
+        if self.buffer.len() >= self.capacity || self.due_in() == Some(Duration::ZERO) {
+            self.flush()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Read + Write> Read for CoalescingWriter<W> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if !self.buffer.is_empty() {
+            self.flush()?;
+        }
+        self.inner.read(buffer)
+    }
+}
+
+/// A link with its codec and traffic counters, for programs that move bytes
+/// themselves rather than through a [`CommandStream`](crate::stream::CommandStream)
+///
+/// Writes go out through the codec and a [`CoalescingWriter`]; reads come
+/// back with CRC and stream framing undone. A read timeout reads as no data,
+/// and read errors that do not lose the link are counted and skipped.
+pub struct FramedLink {
+    link: CoalescingWriter<Box<dyn Link>>,
+    codec: Codec,
+    links: ChannelLinks,
+    stats: SharedStats,
+}
+
+impl FramedLink {
+    pub fn new(link: Box<dyn Link>, codec: Codec) -> Self {
+        Self {
+            link: CoalescingWriter::new(link, Duration::ZERO),
+            codec,
+            links: ChannelLinks::new(),
+            stats: SharedStats::new(),
+        }
+    }
+
+    /// Open `target` and frame it with `codec`
+    pub fn open(target: &Target, options: &LinkOptions, codec: Codec) -> Result<Self> {
+        Ok(Self::new(open_target(target, options)?, codec))
+    }
+
+    /// Hold writes back for up to `max_delay` to send them together
+    pub fn with_coalescing(mut self, max_delay: Duration) -> Self {
+        self.link.set_max_delay(max_delay);
+        self
+    }
+
+    /// Short name of the underlying link, e.g. "TCP"
+    pub fn kind(&self) -> &'static str {
+        self.link.get_ref().kind()
+    }
+
+    pub fn codec(&self) -> &Codec {
+        &self.codec
+    }
+
+    /// Counters shared by every handle of this link
+    pub fn stats(&self) -> SharedStats {
+        self.stats.clone()
+    }
+
+    /// A second handle on the same link and counters, for a separate thread
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            link: CoalescingWriter::new(
+                self.link.get_ref().try_clone_link()?,
+                self.link.max_delay(),
+            ),
+            codec: self.codec.clone(),
+            links: self.links,
+            stats: self.stats.clone(),
+        })
+    }
+
+    /// Keep complement channels at `65535 - master` in every
+    /// [`write_commands`](Self::write_commands)
+    pub fn with_links(mut self, links: ChannelLinks) -> Self {
+        self.links = links;
+        self
+    }
+
+    /// Check, frame and send `data`, each DAC write with the rest of its
+    /// complement pair; returns the bytes put on the link
+    pub fn write_commands(&mut self, data: &[u8]) -> Result<usize> {
+        self.codec.check(data)?;
+        self.write_unchecked(&self.links.apply(data))
+    }
+
+    /// Frame and send `data` as it is, without the codec's padding and strict
+    /// checks or the complement pairs
+    pub fn write_unchecked(&mut self, data: &[u8]) -> Result<usize> {
+        let framed = self.codec.encode_unchecked(data);
+        if let Err(e) = self.link.write_all(&framed) {
+            self.stats.update(|s| s.record_error());
+            return Err(DacError::Transport(e));
+        }
+        self.stats.update(|s| s.record_write(framed.len()));
+        Ok(framed.len())
+    }
+
+    /// Read into `buffer` and decode the responses in place, returning their
+    /// length; 0 when nothing arrived within the read timeout
+    pub fn read_responses(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        match self.link.read(buffer) {
+            Ok(0) => {
+                self.stats.update(|s| s.record_error());
+                Err(DacError::Transport(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed by peer",
+                )))
+            }
+            Ok(n) => {
+                self.stats.update(|s| s.record_read(n));
+                let decoded = self.codec.decode_responses(&buffer[..n])?;
+                buffer[..decoded.len()].copy_from_slice(&decoded);
+                Ok(decoded.len())
+            }
+            Err(e)
+                if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock =>
+            {
+                self.stats.update(|s| s.record_timeout());
+                Ok(0)
+            }
+            Err(e) if wake::is_link_lost(&e) => {
+                self.stats.update(|s| s.record_error());
+                Err(DacError::Transport(e))
+            }
+            Err(_) => {
+                self.stats.update(|s| s.record_error());
+                Ok(0)
+            }
+        }
+    }
+
+    /// Send any coalesced commands now
+    pub fn flush(&mut self) -> Result<()> {
+        self.link.flush().map_err(|e| {
+            self.stats.update(|s| s.record_error());
+            DacError::Transport(e)
+        })
+    }
+
+    /// Time until coalesced commands are due, or `None` if nothing is pending
+    pub fn flush_due_in(&self) -> Option<Duration> {
+        self.link.due_in()
+    }
+}
+
+/// Settings every link factory receives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkOptions {
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+}
+
+/// Opens a link from the part of the target after `scheme://`
+pub type LinkFactory = fn(&str, &LinkOptions) -> Result<Box<dyn Link>>;
+
+/// Split `scheme://rest`, or `None` for targets without a scheme
+pub fn split_scheme(target: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = target.split_once("://")?;
+    let valid = !scheme.is_empty()
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    valid.then_some((scheme, rest))
+}
+
+/// URL schemes and the factories that open them
+#[derive(Clone, Default)]
+pub struct TransportRegistry {
+    schemes: Vec<(String, LinkFactory)>,
+}
+
+impl TransportRegistry {
+    /// A registry without any schemes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with `tcp://`, `udp://`, `serial://`, `pty://`, `ssh://`
+    /// and, with the `tls` feature `tls://`, with the `bluetooth` feature on
+    /// Linux `bt://` and with the `usb` feature `usb://`
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("tcp", open_tcp);
+        registry.register("udp", open_udp);
+        registry.register("serial", open_serial);
+        registry.register("pty", open_pty);
+        registry.register("ssh", open_ssh);
+        #[cfg(feature = "tls")]
+        registry.register("tls", open_tls);
+        #[cfg(all(feature = "bluetooth", target_os = "linux"))]
+        registry.register("bt", open_rfcomm);
+        #[cfg(feature = "usb")]
+        registry.register("usb", open_usb);
+        registry
+    }
+
+    /// Add or replace the factory for `scheme` (matched case-insensitively)
+    pub fn register(&mut self, scheme: &str, factory: LinkFactory) {
+        let scheme = scheme.to_ascii_lowercase();
+        self.schemes.retain(|(s, _)| *s != scheme);
+        self.schemes.push((scheme, factory));
+    }
+
+    /// Registered scheme names, in registration order
+    pub fn schemes(&self) -> impl Iterator<Item = &str> {
+        self.schemes.iter().map(|(s, _)| s.as_str())
+    }
+
+    /// Open a `scheme://` target, or return `None` for targets without a scheme
+    pub fn open(&self, target: &str, options: &LinkOptions) -> Result<Option<Box<dyn Link>>> {
+        match split_scheme(target) {
+            Some((scheme, rest)) => self.open_scheme(scheme, rest, options).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Open the part of a target after `scheme://` with the factory for `scheme`
+    pub fn open_scheme(
+        &self,
+        scheme: &str,
+        rest: &str,
+        options: &LinkOptions,
+    ) -> Result<Box<dyn Link>> {
+        let factory = self
+            .schemes
+            .iter()
+            .find(|(s, _)| s.eq_ignore_ascii_case(scheme))
+            .map(|(_, factory)| *factory)
+            .ok_or_else(|| {
+                DacError::InvalidArgument(format!(
+                    "Unknown transport scheme '{}' (known: {})",
+                    scheme,
+                    self.schemes().collect::<Vec<_>>().join(", ")
+                ))
+            })?;
+        factory(rest, options)
+    }
+
+    /// Register the transports of every library listed in `SERIALTEST_PLUGINS`
+    ///
+    /// Each library must export [`PLUGIN_SYMBOL`] as a Rust
+    /// `fn(&mut TransportRegistry)` and be built with the same compiler and
+    /// `serialtest` version. Libraries stay loaded for the rest of the process.
+    #[cfg(feature = "plugins")]
+    pub fn load_plugins(&mut self) -> Result<()> {
+        let Some(paths) = std::env::var_os(PLUGIN_ENV) else {
+            return Ok(());
+        };
+        for path in std::env::split_paths(&paths).filter(|p| !p.as_os_str().is_empty()) {
+            let plugin_error = |e: libloading::Error| {
+                DacError::InvalidArgument(format!("Transport plugin {}: {}", path.display(), e))
+            };
+            // SAFETY: plugins are trusted code built against this crate, and the
+            // library is never unloaded so its factories stay valid
+            unsafe {
+                let library = libloading::Library::new(&path).map_err(plugin_error)?;
+                let register: libloading::Symbol<fn(&mut TransportRegistry)> =
+                    library.get(PLUGIN_SYMBOL).map_err(plugin_error)?;
+                register(self);
+                std::mem::forget(library);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Built-in schemes plus, with the `plugins` feature, any plugin transports
+pub fn default_registry() -> Result<TransportRegistry> {
+    #[allow(unused_mut)]
+    let mut registry = TransportRegistry::with_builtins();
+    #[cfg(feature = "plugins")]
+    registry.load_plugins()?;
+    Ok(registry)
+}
+
+/// Open any target: `scheme:` forms directly, `scheme://` forms through
+/// [`default_registry`]
+pub fn open_target(target: &Target, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    match target {
+        Target::Serial { path, baud } => open_serial_port(path, *baud, options),
+        Target::Pty { path } => open_pty(path, options),
+        Target::Tcp { address } => open_tcp(address, options),
+        Target::Udp { address } => open_udp(address, options),
+        #[cfg(feature = "tls")]
+        Target::Tls { address, ca } => open_tls_stream(address, ca.as_deref(), options),
+        #[cfg(not(feature = "tls"))]
+        Target::Tls { .. } => Err(DacError::InvalidArgument(
+            "tls: targets need a build with the `tls` feature".to_string(),
+        )),
+        Target::Url { scheme, rest } => default_registry()?.open_scheme(scheme, rest, options),
+    }
+}
+
+fn open_tcp(address: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| DacError::InvalidArgument(format!("Could not resolve {}", address)))?;
+    let stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(options.read_timeout))?;
+    stream.set_write_timeout(Some(options.write_timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(Box::new(stream))
+}
+
+fn open_serial(target: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    let (path, baud) = parse_serial(target, target)?;
+    open_serial_port(&path, baud, options)
+}
+
+fn open_serial_port(path: &str, baud: u32, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    let path = discovery::resolve_serial(path)?;
+    let port = serialport::new(&path, baud)
+        .timeout(options.read_timeout)
+        .open()
+        .map_err(|e| DacError::Transport(e.into()))?;
+    Ok(Box::new(SerialLink(port)))
+}
+
+/// A serial port, with batched writes gathered into one
+struct SerialLink(Box<dyn serialport::SerialPort>);
+
+impl Read for SerialLink {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buffer)
+    }
+}
+
+impl Write for SerialLink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.write(data)
+    }
+
+    fn write_vectored(&mut self, slices: &[IoSlice<'_>]) -> io::Result<usize> {
+        write_gathered(&mut *self.0, slices)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Link for SerialLink {
+    fn kind(&self) -> &'static str {
+        "Serial"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(SerialLink(self.0.try_clone()?)))
+    }
+}
+
+/// A pseudo-terminal, e.g. one end of a socat pair or a simulator's pty
+struct PtyLink(Box<dyn serialport::SerialPort>);
+
+impl Read for PtyLink {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buffer)
+    }
+}
+
+impl Write for PtyLink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.write(data)
+    }
+
+    fn write_vectored(&mut self, slices: &[IoSlice<'_>]) -> io::Result<usize> {
+        write_gathered(&mut *self.0, slices)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Link for PtyLink {
+    fn kind(&self) -> &'static str {
+        "PTY"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(PtyLink(self.0.try_clone()?)))
+    }
+}
+
+fn open_pty(path: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    // A pty has no line speed; the baud rate is only stored in its termios
+    let port = serialport::new(path, crate::target::DEFAULT_BAUD)
+        .timeout(options.read_timeout)
+        .open()
+        .map_err(|e| DacError::Transport(e.into()))?;
+    Ok(Box::new(PtyLink(port)))
+}
+
+/// A connected UDP socket; every write goes out as one datagram
+struct UdpLink(UdpSocket);
+
+impl Read for UdpLink {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buffer)
+    }
+}
+
+impl Write for UdpLink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.send(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Link for UdpLink {
+    fn kind(&self) -> &'static str {
+        "UDP"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(UdpLink(self.0.try_clone()?)))
+    }
+}
+
+fn open_udp(address: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| DacError::InvalidArgument(format!("Could not resolve {}", address)))?;
+    let local = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(addr)?;
+    socket.set_read_timeout(Some(options.read_timeout))?;
+    socket.set_write_timeout(Some(options.write_timeout))?;
+    Ok(Box::new(UdpLink(socket)))
+}
+
+/// Remote shell command relaying a serial device over stdin/stdout
+///
+/// The background reader is killed when the session ends. `stty` works on
+/// the device as its stdin, since Linux names that `-F` and BSD/macOS `-f`.
+fn ssh_bridge_command(device: &str, baud: u32) -> String {
+    let device = format!("'{}'", device.replace('\'', "'\\''"));
+    format!(
+        "stty {baud} raw -echo < {dev} && {{ cat {dev} & trap \"kill $!\" EXIT HUP TERM; cat > {dev}; }}",
+        baud = baud,
+        dev = device
+    )
+}
+
+/// Split `[user@]host[:port]/device[?baud=N]` into ssh destination, port,
+/// device path and baud rate
+fn parse_ssh_target(target: &str) -> Result<(&str, Option<&str>, String, u32)> {
+    let slash = target.find('/').ok_or_else(|| {
+        DacError::InvalidArgument(format!(
+            "Expected ssh://[user@]host[:port]/device, got ssh://{}",
+            target
+        ))
+    })?;
+    let (authority, device) = target.split_at(slash);
+    let (destination, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => {
+            (host, Some(port))
+        }
+        _ => (authority, None),
+    };
+    if destination.is_empty() || device.len() < 2 || device.starts_with("/?") {
+        return Err(DacError::InvalidArgument(format!(
+            "Expected ssh://[user@]host[:port]/device, got ssh://{}",
+            target
+        )));
+    }
+    let (device, baud) = parse_serial(&format!("ssh://{}", target), device)?;
+    Ok((destination, port, device, baud))
+}
+
+/// `ssh` child process that is killed once every handle is gone
+struct SshSession(Child);
+
+impl Drop for SshSession {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Bytes from the session's stdout, filled by a reader thread
+struct SshIncoming {
+    rx: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+/// Most of ssh's own messages kept to explain why a session ended
+const SSH_STDERR_LIMIT: usize = 4096;
+
+/// How often and how many times an ended session is checked for its exit status
+const SSH_EXIT_POLL: Duration = Duration::from_millis(10);
+const SSH_EXIT_POLLS: usize = 50;
+
+/// Serial device on a remote host, reached through the `ssh` command
+#[derive(Clone)]
+struct SshLink {
+    session: Arc<Mutex<SshSession>>,
+    stdin: Arc<Mutex<ChildStdin>>,
+    incoming: Arc<Mutex<SshIncoming>>,
+    /// What ssh printed on stderr, complete once stdout has closed
+    stderr: Arc<Mutex<Vec<u8>>>,
+    read_timeout: Duration,
+}
+
+impl SshLink {
+    /// Error for a session that has ended, with ssh's exit status and messages
+    fn ended(&self) -> io::Error {
+        let status = self
+            .exit_status()
+            .map_or_else(|| "still running".to_string(), |status| status.to_string());
+        let stderr = self.stderr.lock().unwrap();
+        let stderr = String::from_utf8_lossy(&stderr);
+        let message = match stderr.trim() {
+            "" => format!("ssh session ended ({})", status),
+            stderr => format!("ssh session ended ({}): {}", status, stderr),
+        };
+        io::Error::new(io::ErrorKind::UnexpectedEof, message)
+    }
+
+    /// Exit status of ssh, which closes its output just before it exits
+    fn exit_status(&self) -> Option<ExitStatus> {
+        let mut session = self.session.lock().unwrap();
+        for _ in 0..SSH_EXIT_POLLS {
+            match session.0.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) => thread::sleep(SSH_EXIT_POLL),
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+impl Read for SshLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut incoming = self.incoming.lock().unwrap();
+        if incoming.pending.is_empty() {
+            match incoming.rx.recv_timeout(self.read_timeout) {
+                Ok(data) => incoming.pending = data,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "ssh read timed out",
+                    ))
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(self.ended()),
+            }
+        }
+        let n = buf.len().min(incoming.pending.len());
+        buf[..n].copy_from_slice(&incoming.pending[..n]);
+        incoming.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for SshLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.stdin.lock().unwrap().write(buf);
+        result.map_err(|e| match e.kind() {
+            io::ErrorKind::BrokenPipe => self.ended(),
+            _ => e,
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.lock().unwrap().flush()
+    }
+}
+
+impl Link for SshLink {
+    fn kind(&self) -> &'static str {
+        "SSH"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+/// `ssh://[user@]host[:port]/device`: run a stdin/stdout bridge on the remote
+/// host with the system `ssh` client
+///
+/// Authentication must work without prompting (keys or an agent), since
+/// clients may already have taken over the terminal. ssh's messages (e.g. a
+/// refused key or an unknown host key) are kept and returned in the error
+/// once the session ends.
+fn open_ssh(target: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    let (destination, port, device, baud) = parse_ssh_target(target)?;
+
+    let mut command = Command::new("ssh");
+    command.args(["-o", "BatchMode=yes", "-T"]);
+    if let Some(port) = port {
+        command.args(["-p", port]);
+    }
+    command
+        .arg(destination)
+        .arg(ssh_bridge_command(&device, baud));
+    relay_child(command, options)
+}
+
+/// Link through the stdin/stdout of `command`, e.g. an `ssh` session
+fn relay_child(mut command: Command, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdin = child.stdin.take().expect("piped stdin");
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    let mut stderr = child.stderr.take().expect("piped stderr");
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let stderr_reader = {
+        let messages = messages.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            while let Ok(n @ 1..) = stderr.read(&mut buf) {
+                let mut messages = messages.lock().unwrap();
+                let room = SSH_STDERR_LIMIT.saturating_sub(messages.len());
+                messages.extend_from_slice(&buf[..n.min(room)]);
+            }
+        })
+    };
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        while let Ok(n @ 1..) = stdout.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                return;
+            }
+        }
+        // Readers see the end of the session only once its messages are in
+        let _ = stderr_reader.join();
+    });
+
+    Ok(Box::new(SshLink {
+        session: Arc::new(Mutex::new(SshSession(child))),
+        stdin: Arc::new(Mutex::new(stdin)),
+        incoming: Arc::new(Mutex::new(SshIncoming {
+            rx,
+            pending: Vec::new(),
+        })),
+        stderr: messages,
+        read_timeout: options.read_timeout,
+    }))
+}
+
+/// Bluetooth serial port (RFCOMM) socket
+#[cfg(all(feature = "bluetooth", target_os = "linux"))]
+struct RfcommLink(std::fs::File);
+
+#[cfg(all(feature = "bluetooth", target_os = "linux"))]
+impl Read for RfcommLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[cfg(all(feature = "bluetooth", target_os = "linux"))]
+impl Write for RfcommLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(all(feature = "bluetooth", target_os = "linux"))]
+impl Link for RfcommLink {
+    fn kind(&self) -> &'static str {
+        "Bluetooth"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(RfcommLink(self.0.try_clone()?)))
+    }
+}
+
+/// `struct sockaddr_rc` from BlueZ
+#[cfg(all(feature = "bluetooth", target_os = "linux"))]
+#[repr(C)]
+struct SockaddrRc {
+    rc_family: libc::sa_family_t,
+    rc_bdaddr: [u8; 6],
+    rc_channel: u8,
+}
+
+/// Parse `AA:BB:CC:DD:EE:FF` into BlueZ byte order (least significant first)
+#[cfg(all(feature = "bluetooth", target_os = "linux"))]
+fn parse_bdaddr(mac: &str) -> Result<[u8; 6]> {
+    let invalid = || DacError::InvalidArgument(format!("Invalid Bluetooth address '{}'", mac));
+    let mut bdaddr = [0u8; 6];
+    let mut parts = mac.split(':');
+    for byte in bdaddr.iter_mut().rev() {
+        let part = parts.next().filter(|p| p.len() == 2).ok_or_else(invalid)?;
+        *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+    }
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(bdaddr)
+}
+
+/// `bt://AA:BB:CC:DD:EE:FF[/channel]`: Bluetooth serial (RFCOMM), channel 1
+/// unless given
+///
+/// The device must already be paired; BLE UART services are not supported.
+#[cfg(all(feature = "bluetooth", target_os = "linux"))]
+fn open_rfcomm(target: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    const BTPROTO_RFCOMM: libc::c_int = 3;
+
+    let (mac, channel) = match target.split_once('/') {
+        Some((mac, channel)) => {
+            let channel = channel
+                .parse::<u8>()
+                .ok()
+                .filter(|c| (1..=30).contains(c))
+                .ok_or_else(|| {
+                    DacError::InvalidArgument(format!(
+                        "Invalid RFCOMM channel '{}' (1-30)",
+                        channel
+                    ))
+                })?;
+            (mac, channel)
+        }
+        None => (target, 1),
+    };
+    let addr = SockaddrRc {
+        rc_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+        rc_bdaddr: parse_bdaddr(mac)?,
+        rc_channel: channel,
+    };
+
+    // SAFETY: plain socket calls; the descriptor is owned by `OwnedFd` as soon
+    // as it exists and `addr` outlives the connect call
+    let socket = unsafe {
+        let fd = libc::socket(
+            libc::AF_BLUETOOTH,
+            libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+            BTPROTO_RFCOMM,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+        if libc::connect(
+            socket.as_raw_fd(),
+            &addr as *const SockaddrRc as *const libc::sockaddr,
+            std::mem::size_of::<SockaddrRc>() as libc::socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error().into());
+        }
+        socket
+    };
+
+    for (option, timeout) in [
+        (libc::SO_RCVTIMEO, options.read_timeout),
+        (libc::SO_SNDTIMEO, options.write_timeout),
+    ] {
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        // SAFETY: `tv` is a valid timeval for the duration of the call
+        let rc = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                option,
+                &tv as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(Box::new(RfcommLink(std::fs::File::from(socket))))
+}
+
+/// Largest bulk read; a multiple of every USB max packet size
+#[cfg(feature = "usb")]
+const USB_READ_SIZE: usize = 512;
+
+/// Bulk endpoints of a USB device, used without the OS serial driver
+#[cfg(feature = "usb")]
+struct UsbLink {
+    handle: Arc<rusb::DeviceHandle<rusb::GlobalContext>>,
+    endpoint_in: u8,
+    endpoint_out: u8,
+    options: LinkOptions,
+    /// Rest of the last bulk read that did not fit the caller's buffer
+    pending: Vec<u8>,
+}
+
+#[cfg(feature = "usb")]
+fn usb_error(e: rusb::Error) -> io::Error {
+    let kind = match e {
+        rusb::Error::Timeout => io::ErrorKind::TimedOut,
+        rusb::Error::NoDevice => io::ErrorKind::NotConnected,
+        rusb::Error::Access => io::ErrorKind::PermissionDenied,
+        rusb::Error::NotFound => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
+
+#[cfg(feature = "usb")]
+impl Read for UsbLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let mut packet = [0u8; USB_READ_SIZE];
+            let n = self
+                .handle
+                .read_bulk(self.endpoint_in, &mut packet, self.options.read_timeout)
+                .map_err(usb_error)?;
+            if n == 0 {
+                // A zero-length packet is not the end of the stream
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.pending.extend_from_slice(&packet[..n]);
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "usb")]
+impl Write for UsbLink {
+    /// The whole buffer goes out as one bulk transfer
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.handle
+            .write_bulk(self.endpoint_out, buf, self.options.write_timeout)
+            .map_err(usb_error)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "usb")]
+impl Link for UsbLink {
+    fn kind(&self) -> &'static str {
+        "USB"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(UsbLink {
+            handle: self.handle.clone(),
+            endpoint_in: self.endpoint_in,
+            endpoint_out: self.endpoint_out,
+            options: self.options,
+            pending: Vec::new(),
+        }))
+    }
+}
+
+/// `usb://VID:PID[/serial]`: claim the device's bulk endpoints with libusb
+///
+/// IDs are hexadecimal. The CDC-ACM kernel driver is detached while the link
+/// is open, and DTR is raised on the CDC control interface so firmware that
+/// waits for a terminal starts answering.
+#[cfg(feature = "usb")]
+fn open_usb(target: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    use rusb::{Direction, TransferType, UsbContext};
+
+    const CLASS_CDC_CONTROL: u8 = 0x02;
+    const CDC_SET_CONTROL_LINE_STATE: u8 = 0x22;
+    const CDC_DTR_RTS: u16 = 0x0003;
+
+    let invalid = || {
+        DacError::InvalidArgument(format!(
+            "Expected usb://VID:PID[/serial] with hex IDs, got usb://{}",
+            target
+        ))
+    };
+    let (ids, serial) = match target.split_once('/') {
+        Some((ids, serial)) => (ids, Some(serial)),
+        None => (target, None),
+    };
+    let (vid, pid) = ids.split_once(':').ok_or_else(invalid)?;
+    let vid = u16::from_str_radix(vid, 16).map_err(|_| invalid())?;
+    let pid = u16::from_str_radix(pid, 16).map_err(|_| invalid())?;
+    let open_error = |e: rusb::Error| DacError::Transport(usb_error(e));
+
+    let mut found = None;
+    for device in rusb::GlobalContext::default()
+        .devices()
+        .map_err(open_error)?
+        .iter()
+    {
+        let descriptor = device.device_descriptor().map_err(open_error)?;
+        if descriptor.vendor_id() != vid || descriptor.product_id() != pid {
+            continue;
+        }
+        let handle = device.open().map_err(open_error)?;
+        if let Some(serial) = serial {
+            if handle
+                .read_serial_number_string_ascii(&descriptor)
+                .ok()
+                .as_deref()
+                != Some(serial)
+            {
+                continue;
+            }
+        }
+        found = Some((device, handle));
+        break;
+    }
+    let (device, handle) = found.ok_or_else(|| {
+        DacError::Transport(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No USB device {:04x}:{:04x} found", vid, pid),
+        ))
+    })?;
+
+    // First interface with a bulk IN and a bulk OUT endpoint carries the data
+    let config = device.active_config_descriptor().map_err(open_error)?;
+    let mut control_interface = None;
+    let mut data = None;
+    for interface in config.interfaces() {
+        for setting in interface.descriptors() {
+            if setting.class_code() == CLASS_CDC_CONTROL {
+                control_interface.get_or_insert(setting.interface_number());
+            }
+            let bulk = |direction| {
+                setting
+                    .endpoint_descriptors()
+                    .find(|e| e.transfer_type() == TransferType::Bulk && e.direction() == direction)
+                    .map(|e| e.address())
+            };
+            if let (None, Some(endpoint_in), Some(endpoint_out)) =
+                (data, bulk(Direction::In), bulk(Direction::Out))
+            {
+                data = Some((setting.interface_number(), endpoint_in, endpoint_out));
+            }
+        }
+    }
+    let (data_interface, endpoint_in, endpoint_out) = data.ok_or_else(|| {
+        DacError::Transport(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("USB device {:04x}:{:04x} has no bulk endpoints", vid, pid),
+        ))
+    })?;
+
+    // Not every platform can detach kernel drivers; claiming reports the real problem
+    let _ = handle.set_auto_detach_kernel_driver(true);
+    handle.claim_interface(data_interface).map_err(open_error)?;
+    if let Some(control) = control_interface.filter(|&i| i != data_interface) {
+        if handle.claim_interface(control).is_ok() {
+            let _ = handle.write_control(
+                rusb::request_type(
+                    Direction::Out,
+                    rusb::RequestType::Class,
+                    rusb::Recipient::Interface,
+                ),
+                CDC_SET_CONTROL_LINE_STATE,
+                CDC_DTR_RTS,
+                control as u16,
+                &[],
+                options.write_timeout,
+            );
+        }
+    }
+
+    Ok(Box::new(UsbLink {
+        handle: Arc::new(handle),
+        endpoint_in,
+        endpoint_out,
+        options: *options,
+        pending: Vec::new(),
+    }))
+}
+
+/// How long a TLS read holds the stream before letting a writer in
+#[cfg(feature = "tls")]
+const TLS_POLL: Duration = Duration::from_millis(10);
+
+#[cfg(feature = "tls")]
+type TlsStream = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+
+/// TCP with TLS
+///
+/// A TLS session cannot be split into independent halves, so clones share
+/// it; reads poll in short slices so a writer on another thread is never
+/// held up for a whole read timeout.
+#[cfg(feature = "tls")]
+struct TlsLink {
+    stream: Arc<Mutex<TlsStream>>,
+    read_timeout: Duration,
+}
+
+#[cfg(feature = "tls")]
+impl Read for TlsLink {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let start = std::time::Instant::now();
+        loop {
+            match self.stream.lock().unwrap().read(buffer) {
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    if start.elapsed() >= self.read_timeout {
+                        return Err(e);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Write for TlsLink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.stream.lock().unwrap().write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.lock().unwrap().flush()
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Link for TlsLink {
+    fn kind(&self) -> &'static str {
+        "TLS"
+    }
+
+    fn try_clone_link(&self) -> io::Result<Box<dyn Link>> {
+        Ok(Box::new(TlsLink {
+            stream: self.stream.clone(),
+            read_timeout: self.read_timeout,
+        }))
+    }
+}
+
+#[cfg(feature = "tls")]
+fn open_tls(target: &str, options: &LinkOptions) -> Result<Box<dyn Link>> {
+    open_target(&format!("tls:{}", target).parse()?, options)
+}
+
+/// Connect and complete the handshake, trusting the web PKI roots and `ca`
+#[cfg(feature = "tls")]
+fn open_tls_stream(
+    address: &str,
+    ca: Option<&std::path::Path>,
+    options: &LinkOptions,
+) -> Result<Box<dyn Link>> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, ServerName};
+
+    let tls_error = |e: &dyn std::fmt::Display| DacError::Protocol(format!("TLS: {}", e));
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(ca) = ca {
+        for cert in CertificateDer::pem_file_iter(ca).map_err(|e| tls_error(&e))? {
+            roots
+                .add(cert.map_err(|e| tls_error(&e))?)
+                .map_err(|e| tls_error(&e))?;
+        }
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| tls_error(&e))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let host = address
+        .rsplit_once(':')
+        .map_or(address, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let name = ServerName::try_from(host.to_string()).map_err(|e| tls_error(&e))?;
+    let mut connection =
+        rustls::ClientConnection::new(Arc::new(config), name).map_err(|e| tls_error(&e))?;
+
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| DacError::InvalidArgument(format!("Could not resolve {}", address)))?;
+    let mut socket = TcpStream::connect(addr)?;
+    socket.set_nodelay(true)?;
+    socket.set_read_timeout(Some(options.read_timeout))?;
+    socket.set_write_timeout(Some(options.write_timeout))?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut socket)?;
+    }
+    socket.set_read_timeout(Some(TLS_POLL))?;
+
+    Ok(Box::new(TlsLink {
+        stream: Arc::new(Mutex::new(rustls::StreamOwned::new(connection, socket))),
+        read_timeout: options.read_timeout,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::{append_crc, StreamFraming};
+    use crate::mock::MockTransport;
+    use crate::target::DEFAULT_BAUD;
+
+    #[test]
+    fn framed_link_sends_and_decodes_through_the_codec() {
+        let with_crc = |frame: &[u8]| {
+            let mut frame = frame.to_vec();
+            append_crc(&mut frame);
+            frame
+        };
+        let mock = MockTransport::new();
+        mock.push_response(&with_crc(&[0x00, 0x00]));
+        let mut link =
+            FramedLink::new(Box::new(mock.clone()), Codec::new(true, StreamFraming::Raw));
+        assert_eq!(link.write_commands(&[0x01, 0x12, 0x34, 0x00]).unwrap(), 6);
+        assert_eq!(mock.written(), vec![with_crc(&[0x01, 0x12, 0x34, 0x00])]);
+        let mut buffer = [0u8; 16];
+        assert_eq!(link.read_responses(&mut buffer).unwrap(), 2);
+        assert_eq!(&buffer[..2], &[0x00, 0x00]);
+        let stats = link.stats().snapshot();
+        assert_eq!((stats.bytes_out, stats.bytes_in), (6, 4));
+    }
+
+    #[test]
+    fn framed_link_reads_a_timeout_as_no_data_and_eof_as_lost() {
+        let mock = MockTransport::new();
+        let mut link = FramedLink::new(Box::new(mock.clone()), Codec::default());
+        let mut buffer = [0u8; 16];
+        assert_eq!(link.read_responses(&mut buffer).unwrap(), 0);
+        mock.disconnect();
+        let error = link.read_responses(&mut buffer).unwrap_err();
+        assert!(matches!(error, DacError::Transport(ref e) if wake::is_link_lost(e)));
+    }
+
+    #[test]
+    fn ssh_bridge_configures_the_device_through_stdin() {
+        let command = ssh_bridge_command("/dev/cu.usbmodem1", 9600);
+        assert!(command.starts_with("stty 9600 raw -echo < '/dev/cu.usbmodem1' && "));
+        assert!(!command.contains("-F"));
+    }
+
+    #[test]
+    fn ssh_bridge_quotes_the_device() {
+        let command = ssh_bridge_command("/dev/it's", DEFAULT_BAUD);
+        assert!(command.contains("< '/dev/it'\\''s'"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ended_session_reports_its_messages() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo 'Permission denied (publickey).' >&2; exit 255"]);
+        let options = LinkOptions {
+            read_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(5),
+        };
+        let mut link = relay_child(command, &options).unwrap();
+        let error = link.read(&mut [0u8; 16]).unwrap_err();
+        assert!(wake::is_link_lost(&error));
+        let message = error.to_string();
+        assert!(message.contains("255"), "{}", message);
+        assert!(
+            message.contains("Permission denied (publickey)."),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn ssh_target_parts() {
+        assert_eq!(
+            parse_ssh_target("pi@lab:2222/dev/ttyACM0?baud=9600").unwrap(),
+            ("pi@lab", Some("2222"), "/dev/ttyACM0".to_string(), 9600)
+        );
+        assert_eq!(
+            parse_ssh_target("lab/dev/ttyUSB0").unwrap(),
+            ("lab", None, "/dev/ttyUSB0".to_string(), DEFAULT_BAUD)
+        );
+        assert!(parse_ssh_target("lab").is_err());
+        assert!(parse_ssh_target("lab/dev/ttyUSB0?baud=fast").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Low-latency tuning of serial ports.
+//!
+//! USB serial drivers hold received bytes back to fill larger transfers: an
+//! FTDI adapter waits up to its 16 ms latency timer, and the tty layer hands
+//! data on from a work queue. Each adds milliseconds of jitter to a command's
+//! round trip. [`SerialTuning`] trades that buffering for latency:
+//!
+//! | Setting         | How                                                          |
+//! |-----------------|--------------------------------------------------------------|
+//! | Latency timer   | `/sys/bus/usb-serial/devices/ttyUSBn/latency_timer` (Linux, FTDI) |
+//! | No buffering    | The driver's `low_latency` flag, as `setserial low_latency` (Linux) |
+//! | VMIN / VTIME    | Raw termios: bytes a read waits for, and the inter-byte timeout |
+//!
+//! The latency timer file is usually writable by root only; a udev rule can
+//! set it instead.
+
+use crate::error::{DacError, Result};
+use std::fmt;
+use std::io;
+use std::os::fd::RawFd;
+
+/// Driver settings applied to an open serial port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SerialTuning {
+    /// FTDI latency timer in milliseconds (1-255)
+    pub latency_timer: Option<u8>,
+    /// Have the driver pass received bytes on at once
+    pub low_latency: bool,
+    /// Termios VMIN: bytes a read waits for
+    pub vmin: Option<u8>,
+    /// Termios VTIME: tenths of a second a read waits between bytes
+    pub vtime: Option<u8>,
+}
+
+impl SerialTuning {
+    /// Whether the port is left as the driver set it up
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Apply the settings to the port at `path`, open on `fd`
+    ///
+    /// Fails with [`DacError::Unsupported`] if the port or the system has no
+    /// such setting, e.g. a latency timer on a CDC ACM device.
+    pub fn apply(&self, path: &str, fd: RawFd) -> Result<()> {
+        if let Some(ms) = self.latency_timer {
+            set_latency_timer(path, ms)?;
+        }
+        if self.low_latency {
+            set_low_latency(path, fd)?;
+        }
+        if self.vmin.is_some() || self.vtime.is_some() {
+            set_vmin_vtime(fd, self.vmin, self.vtime)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SerialTuning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(ms) = self.latency_timer {
+            settings.push(format!("latency timer {} ms", ms));
+        }
+        if self.low_latency {
+            settings.push("low_latency".to_string());
+        }
+        if let Some(vmin) = self.vmin {
+            settings.push(format!("VMIN={}", vmin));
+        }
+        if let Some(vtime) = self.vtime {
+            settings.push(format!("VTIME={}", vtime));
+        }
+        if settings.is_empty() {
+            write!(f, "driver defaults")
+        } else {
+            write!(f, "{}", settings.join(", "))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_latency_timer(path: &str, ms: u8) -> Result<()> {
+    // /dev/serial/by-id/... links to the ttyUSBn the driver knows
+    let device = std::fs::canonicalize(path)?;
+    let name = device
+        .file_name()
+        .ok_or_else(|| DacError::InvalidArgument(format!("{} is not a device", path)))?;
+    let file = std::path::Path::new("/sys/bus/usb-serial/devices")
+        .join(name)
+        .join("latency_timer");
+    if !file.exists() {
+        return Err(DacError::Unsupported(format!(
+            "{} has no latency timer (only FTDI and similar usb-serial adapters do)",
+            path
+        )));
+    }
+    std::fs::write(&file, format!("{}\n", ms)).map_err(|e| {
+        DacError::Transport(io::Error::new(
+            e.kind(),
+            format!("{}: {} (needs root or a udev rule)", file.display(), e),
+        ))
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_latency_timer(_path: &str, _ms: u8) -> Result<()> {
+    Err(DacError::Unsupported(
+        "setting the latency timer (Linux only)".to_string(),
+    ))
+}
+
+/// `struct serial_struct` of `<linux/serial.h>`
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct SerialStruct {
+    kind: libc::c_int,
+    line: libc::c_int,
+    port: libc::c_uint,
+    irq: libc::c_int,
+    flags: libc::c_int,
+    xmit_fifo_size: libc::c_int,
+    custom_divisor: libc::c_int,
+    baud_base: libc::c_int,
+    close_delay: libc::c_ushort,
+    io_type: libc::c_char,
+    reserved_char: [libc::c_char; 1],
+    hub6: libc::c_int,
+    closing_wait: libc::c_ushort,
+    closing_wait2: libc::c_ushort,
+    iomem_base: *mut libc::c_uchar,
+    iomem_reg_shift: libc::c_ushort,
+    port_high: libc::c_uint,
+    iomap_base: libc::c_ulong,
+}
+
+#[cfg(target_os = "linux")]
+fn set_low_latency(path: &str, fd: RawFd) -> Result<()> {
+    const ASYNC_LOW_LATENCY: libc::c_int = 1 << 13;
+
+    // SAFETY: `serial` is a zeroed serial_struct the ioctls read and fill;
+    // the kernel does not keep the pointer
+    unsafe {
+        let mut serial: SerialStruct = std::mem::zeroed();
+        if libc::ioctl(fd, libc::TIOCGSERIAL, &mut serial) < 0 {
+            let e = io::Error::last_os_error();
+            return Err(match e.raw_os_error() {
+                Some(libc::ENOTTY | libc::EINVAL) => DacError::Unsupported(format!(
+                    "low_latency on {} (its driver has no serial settings)",
+                    path
+                )),
+                _ => e.into(),
+            });
+        }
+        serial.flags |= ASYNC_LOW_LATENCY;
+        if libc::ioctl(fd, libc::TIOCSSERIAL, &serial) < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_low_latency(_path: &str, _fd: RawFd) -> Result<()> {
+    Err(DacError::Unsupported(
+        "low_latency (Linux only)".to_string(),
+    ))
+}
+
+fn set_vmin_vtime(fd: RawFd, vmin: Option<u8>, vtime: Option<u8>) -> Result<()> {
+    // SAFETY: `termios` is filled by tcgetattr before it is changed and
+    // written back; both calls only use it for their duration
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        if let Some(vmin) = vmin {
+            termios.c_cc[libc::VMIN] = vmin as libc::cc_t;
+        }
+        if let Some(vtime) = vtime {
+            termios.c_cc[libc::VTIME] = vtime as libc::cc_t;
+        }
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! Device twin: the device state as seen by a bridge.
+//!
+//! A [`Twin`] applies every command the bridge forwards to a [`DeviceState`],
+//! so clients and dashboards can read the current outputs without asking the
+//! hardware. It starts from the power-on state and only knows about commands
+//! that went through this bridge.
+//!
+//! [`serve_http`] answers `GET` requests with the state as JSON:
+//!
+//! | Path                 | Body                                           |
+//! |----------------------|------------------------------------------------|
+//! | `/state`             | Everything (`/` is the same)                   |
+//! | `/state/dac`         | One field: `dac`, `gpio`, `tables`, ...        |
+//! | `/state/dac/3`       | One element of an array field                  |
+//! | `/snapshot`          | Outputs as a [`Snapshot`] (`text/plain`)       |
+
+use crate::device::DeviceState;
+use crate::error::Result;
+use crate::protocol::Command;
+use crate::report::{self, Value};
+use crate::snapshot::Snapshot;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Default)]
+struct TwinState {
+    device: DeviceState,
+    updated: Option<SystemTime>,
+}
+
+/// Device state derived from forwarded commands, shared between client handlers
+#[derive(Debug, Default)]
+pub struct Twin {
+    state: Mutex<TwinState>,
+}
+
+impl Twin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply the 4-byte commands in `commands`; unknown commands are skipped
+    pub fn apply<'a>(&self, commands: impl IntoIterator<Item = &'a [u8]>) {
+        let mut state = self.state.lock().unwrap();
+        for cmd in commands {
+            if let Some(cmd) = Command::decode(cmd) {
+                state.device.apply(&cmd);
+                state.updated = Some(SystemTime::now());
+            }
+        }
+    }
+
+    /// Last value written to register `reg`, if any
+    pub fn register(&self, reg: u8) -> Option<u16> {
+        self.state
+            .lock()
+            .unwrap()
+            .device
+            .registers
+            .get(&reg)
+            .copied()
+    }
+
+    /// Entry `index` of `table` as last written (0 before)
+    pub fn table_entry(&self, table: u8, index: u8) -> u16 {
+        self.state.lock().unwrap().device.tables[table as usize][index as usize]
+    }
+
+    /// Commands that bring a device that lost its state back to the twin's
+    pub fn restore_commands(&self) -> Vec<Command> {
+        self.state.lock().unwrap().device.restore_commands()
+    }
+
+    /// The current outputs as a [`Snapshot`]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::from_state(&self.state.lock().unwrap().device)
+    }
+
+    /// The current state as a JSON-ready [`Value`]
+    pub fn to_value(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        let device = &state.device;
+        let registers = device
+            .registers
+            .iter()
+            .fold(Value::object(), |registers, (reg, value)| {
+                registers.with(&reg.to_string(), *value)
+            });
+
+        Value::object()
+            .with("updated", state.updated.map(report::utc_timestamp))
+            .with("commands", device.commands)
+            .with("dac", device.dac.to_vec())
+            .with("loaded", device.loaded.to_vec())
+            .with("hold_until_ldac", device.hold_until_ldac)
+            .with("gpio", device.gpio.to_vec())
+            .with("attached", device.attached.to_vec())
+            .with("table_offset", device.table_offset)
+            .with(
+                "tables",
+                device.tables.iter().map(|t| t.to_vec()).collect::<Vec<_>>(),
+            )
+            .with("registers", registers)
+            .with("keepalives", device.keepalives)
+            .with("ldac_count", device.ldac_count)
+    }
+}
+
+/// Answer HTTP requests for the twin's state until `shutdown_flag` is set
+pub fn serve_http(listener: TcpListener, twin: &Twin, shutdown_flag: &AtomicBool) -> Result<()> {
+    // Non-blocking so the shutdown flag is noticed without a connection
+    listener.set_nonblocking(true)?;
+    while !shutdown_flag.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = answer(stream, twin) {
+                    eprintln!("Twin request failed: {}", e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => {
+                eprintln!("Twin accept failed: {}", e);
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read one request and write its response
+fn answer(stream: TcpStream, twin: &Twin) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip the headers; requests have no body
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("");
+
+    let json = |(status, body): (&'static str, Value)| {
+        (status, "application/json", format!("{:#}\n", body))
+    };
+    let (status, content_type, body) = if method != "GET" {
+        json(("405 Method Not Allowed", error("only GET is supported")))
+    } else {
+        let state = twin.to_value();
+        let mut segments = path.trim_matches('/').split('/').filter(|s| !s.is_empty());
+        match segments.next() {
+            None | Some("state") => json(
+                match segments.try_fold(&state, |value, key| value.get(key)) {
+                    Some(value) => ("200 OK", value.clone()),
+                    None => ("404 Not Found", error("no such field")),
+                },
+            ),
+            Some("snapshot") => ("200 OK", "text/plain", twin.snapshot().to_string()),
+            Some(_) => json(("404 Not Found", error("no such path"))),
+        }
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn error(message: &str) -> Value {
+    Value::object().with("error", message)
+}
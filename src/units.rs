@@ -0,0 +1,83 @@
+//! Conversion between DAC codes and output voltages.
+//!
+//! A [`VoltageRange`] holds the voltage a board outputs at code 0 and at code
+//! 65535; codes in between map linearly. Boards with an inverting output
+//! stage give a `min` above `max`.
+
+use crate::error::{DacError, Result};
+
+/// Output voltage at code 0 and at code 65535
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoltageRange {
+    min: f64,
+    max: f64,
+}
+
+/// 0 V at code 0 to 10 V at code 65535
+impl Default for VoltageRange {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: 10.0,
+        }
+    }
+}
+
+impl VoltageRange {
+    /// `min` volts at code 0 and `max` volts at code 65535
+    ///
+    /// The ends must be finite and differ, or no code would map to a voltage.
+    pub fn new(min: f64, max: f64) -> Result<Self> {
+        if !min.is_finite() || !max.is_finite() || min == max {
+            return Err(DacError::InvalidArgument(format!(
+                "Voltage range {} V to {} V needs two different finite ends",
+                min, max
+            )));
+        }
+        Ok(Self { min, max })
+    }
+
+    /// Voltage at code 0
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    /// Voltage at code 65535
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// The code closest to `volts`, if it lies within the range
+    pub fn to_code(&self, volts: f64) -> Option<u16> {
+        let (low, high) = (self.min.min(self.max), self.min.max(self.max));
+        if !(low..=high).contains(&volts) {
+            return None;
+        }
+        Some(((volts - self.min) / (self.max - self.min) * 65535.0).round() as u16)
+    }
+
+    pub fn to_volts(&self, code: u16) -> f64 {
+        self.min + (self.max - self.min) * code as f64 / 65535.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_and_volts_map_both_ways() {
+        let range = VoltageRange::new(5.0, -5.0).unwrap();
+        assert_eq!(range.to_code(5.0), Some(0));
+        assert_eq!(range.to_code(-5.0), Some(65535));
+        assert_eq!(range.to_code(6.0), None);
+        assert_eq!(range.to_volts(65535), -5.0);
+    }
+
+    #[test]
+    fn degenerate_ranges_are_rejected() {
+        assert!(VoltageRange::new(1.0, 1.0).is_err());
+        assert!(VoltageRange::new(0.0, f64::NAN).is_err());
+        assert!(VoltageRange::new(f64::NEG_INFINITY, 0.0).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Version and build details of the tools.
+//!
+//! Every binary parses its arguments through [`parse_args`], which adds
+//! `-V`/`--version` (short form) and `--version-full`:
+//!
+//! ```text
+//! serialtest 0.0.0 (git 1408cc9-dirty)
+//! Built: 2026-10-17T09:12:44Z
+//! Features: lua, tls
+//! Protocol: 1
+//! ```
+//!
+//! `--version` alone prints the same details as `--version-full`, as clap
+//! does for long flags. Reports carry them as [`build_value`].
+
+use crate::protocol::{Hello, PROTOCOL_VERSION};
+use crate::report::{self, Value, GIT_VERSION, VERSION};
+use clap::{Arg, ArgAction, Parser};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch when the tools were built
+const BUILD_TIME: &str = env!("SERIALTEST_BUILD_TIME");
+
+/// Optional features, as named in `Cargo.toml`, and whether this build has them
+const FEATURES: [(&str, bool); 10] = [
+    ("lua", cfg!(feature = "lua")),
+    ("plugins", cfg!(feature = "plugins")),
+    ("bluetooth", cfg!(feature = "bluetooth")),
+    ("usb", cfg!(feature = "usb")),
+    ("tls", cfg!(feature = "tls")),
+    ("mdns", cfg!(feature = "mdns")),
+    ("gui", cfg!(feature = "gui")),
+    ("dbus", cfg!(feature = "dbus")),
+    ("mqtt", cfg!(feature = "mqtt")),
+    ("clipboard", cfg!(feature = "clipboard")),
+];
+
+/// When the tools were built (`SOURCE_DATE_EPOCH` for reproducible builds)
+pub fn build_time() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(BUILD_TIME.parse().unwrap_or_default())
+}
+
+/// The optional features compiled in
+pub fn features() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Features as a comma-separated list, or "none"
+pub fn features_text() -> String {
+    let features = features();
+    if features.is_empty() {
+        "none".to_string()
+    } else {
+        features.join(", ")
+    }
+}
+
+/// Version and git revision on one line
+pub fn short_version() -> String {
+    format!("{} (git {})", VERSION, GIT_VERSION)
+}
+
+/// Version, git revision, build time, features and protocol revision
+pub fn long_version() -> String {
+    format!(
+        "{}\nBuilt: {}\nFeatures: {}\nProtocol: {}",
+        short_version(),
+        report::utc_timestamp(build_time()),
+        features_text(),
+        PROTOCOL_VERSION
+    )
+}
+
+/// The build details for a report
+pub fn build_value() -> Value {
+    Value::object()
+        .with("version", VERSION)
+        .with("git", GIT_VERSION)
+        .with("built", report::utc_timestamp(build_time()))
+        .with("features", features_text())
+        .with("protocol", PROTOCOL_VERSION)
+}
+
+/// What a device answered to a hello, for a version line
+pub fn device_text(hello: &Hello) -> String {
+    if hello.version == 0 {
+        "no hello support (legacy firmware)".to_string()
+    } else {
+        format!(
+            "protocol version {}, features: {}",
+            hello.version, hello.features
+        )
+    }
+}
+
+/// Parse the command line into `T` with the version flags added
+///
+/// Exits with clap's usage message on errors, like `T::parse()`.
+pub fn parse_args<T: Parser>() -> T {
+    static SHORT: OnceLock<String> = OnceLock::new();
+    static LONG: OnceLock<String> = OnceLock::new();
+    let mut command = T::command()
+        .version(SHORT.get_or_init(short_version).as_str())
+        .long_version(LONG.get_or_init(long_version).as_str())
+        .disable_version_flag(true)
+        .propagate_version(true)
+        .arg(
+            Arg::new("version")
+                .short('V')
+                .long("version")
+                .visible_alias("version-full")
+                .global(true)
+                .action(ArgAction::Version)
+                .help("Print version (the long forms with build date, features and protocol)"),
+        );
+    command
+        .try_get_matches_from_mut(std::env::args_os())
+        .and_then(|mut matches| T::from_arg_matches_mut(&mut matches))
+        .unwrap_or_else(|e| e.format(&mut command).exit())
+}
@@ -0,0 +1,154 @@
+//! Recovery from host sleep.
+//!
+//! When a laptop sleeps, its USB serial adapters lose power and re-enumerate
+//! on wake, and TCP peers time the connection out. The open port or socket
+//! is dead afterwards: reads and writes fail with `ENXIO`, `EIO`, a broken
+//! pipe or a reset connection. Programs that should survive a lid close tell
+//! those errors apart with [`is_link_lost`], reopen the link on a [`Backoff`]
+//! until the device is back, and replay their state to it since the device
+//! may have reset meanwhile.
+//!
+//! The monotonic clock stops while the host sleeps, the system clock does
+//! not; a [`SleepDetector`] notices the gap, e.g. to reopen a link that still
+//! looks fine but whose peer has long given up on it.
+
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Gap between the clocks taken for a sleep rather than scheduling jitter
+pub const DEFAULT_SLEEP_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Wait after the first failed reopen; adapters take a few seconds to come
+/// back after a wake
+pub const DEFAULT_REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait between two reopen attempts
+pub const MAX_REOPEN_DELAY: Duration = Duration::from_secs(16);
+
+/// `ENXIO`, `ENODEV` and `EIO` as the serialport crate reports them: by their
+/// description only, without the error code
+#[cfg(unix)]
+const SERIALPORT_LOST: [&str; 3] = ["No such device or address", "No such device", "I/O error"];
+
+/// Whether `error` means the port or connection is gone for good, as after the
+/// host slept or the adapter was unplugged, and has to be reopened
+pub fn is_link_lost(error: &io::Error) -> bool {
+    if matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    if error.kind() == io::ErrorKind::Other
+        && error.raw_os_error().is_none()
+        && SERIALPORT_LOST.contains(&error.to_string().as_str())
+    {
+        return true;
+    }
+    #[cfg(unix)]
+    let lost = [libc::ENXIO, libc::ENODEV, libc::EIO];
+    // ERROR_GEN_FAILURE, ERROR_OPERATION_ABORTED, ERROR_DEVICE_NOT_CONNECTED
+    #[cfg(windows)]
+    let lost = [31, 995, 1167];
+    #[cfg(not(any(unix, windows)))]
+    let lost: [i32; 0] = [];
+    error
+        .raw_os_error()
+        .is_some_and(|code| lost.contains(&code))
+}
+
+/// Notices that the host slept between two checks
+#[derive(Debug, Clone)]
+pub struct SleepDetector {
+    threshold: Duration,
+    monotonic: Instant,
+    wall: SystemTime,
+}
+
+impl SleepDetector {
+    /// Report sleeps longer than [`DEFAULT_SLEEP_THRESHOLD`]
+    pub fn new() -> Self {
+        Self {
+            threshold: DEFAULT_SLEEP_THRESHOLD,
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// Report only sleeps longer than `threshold`
+    pub fn with_threshold(mut self, threshold: Duration) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// How long the host slept since the last check, if it did
+    ///
+    /// A step of the system clock forward (e.g. by NTP) looks the same.
+    pub fn check(&mut self) -> Option<Duration> {
+        let (monotonic, wall) = (Instant::now(), SystemTime::now());
+        let awake = monotonic - self.monotonic;
+        let passed = wall.duration_since(self.wall).unwrap_or_default();
+        self.monotonic = monotonic;
+        self.wall = wall;
+        let slept = passed.saturating_sub(awake);
+        (slept > self.threshold).then_some(slept)
+    }
+}
+
+impl Default for SleepDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Retry delays doubling from a first to a longest one
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    first: Duration,
+    max: Duration,
+    delay: Duration,
+    next: Instant,
+}
+
+impl Backoff {
+    /// First retry at once, then after `first`, doubling up to `max`
+    pub fn new(first: Duration, max: Duration) -> Self {
+        Self {
+            first,
+            max,
+            delay: first,
+            next: Instant::now(),
+        }
+    }
+
+    /// Time left until the next attempt is due, zero once it is
+    pub fn due_in(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+
+    /// Record a failed attempt and return the wait before the next one
+    pub fn failed(&mut self) -> Duration {
+        let delay = self.delay;
+        self.next = Instant::now() + delay;
+        self.delay = (delay * 2).min(self.max);
+        delay
+    }
+
+    /// Start over from the first delay, with the next attempt due at once
+    pub fn reset(&mut self) {
+        self.delay = self.first;
+        self.next = Instant::now();
+    }
+}
+
+impl Default for Backoff {
+    /// Reopen delays, from [`DEFAULT_REOPEN_DELAY`] up to [`MAX_REOPEN_DELAY`]
+    fn default() -> Self {
+        Self::new(DEFAULT_REOPEN_DELAY, MAX_REOPEN_DELAY)
+    }
+}
@@ -0,0 +1,246 @@
+//! Analog output reconstruction for offline waveform checks.
+//!
+//! The simulator reports every change of the DAC outputs to a
+//! [`WaveformRecorder`], which resamples them as they arrive (holding each
+//! value until the next change, like the real DAC) and streams the samples
+//! straight into a CSV or multi-channel WAV file. Only the current outputs are
+//! kept in memory, however long the recording runs.
+
+use crate::clock::{self, SharedClock};
+use crate::error::{DacError, Result};
+use crate::protocol::DAC_CHANNELS;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+enum Sink {
+    /// One row per sample: time in seconds followed by the code of every channel
+    Csv(Box<dyn Write + Send>),
+    /// 16-bit PCM with one WAV channel per DAC; code 0x8000 maps to silence
+    Wav(hound::WavWriter<BufWriter<File>>),
+}
+
+/// DAC outputs resampled and written to a file while they are recorded
+pub struct WaveformRecorder {
+    sink: Sink,
+    sample_rate: u32,
+    clock: SharedClock,
+    start: Instant,
+    outputs: [u16; DAC_CHANNELS],
+    written: u64,
+    changes: usize,
+    /// First write error; nothing more is written after it
+    error: Option<DacError>,
+}
+
+impl fmt::Debug for WaveformRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WaveformRecorder")
+            .field("sample_rate", &self.sample_rate)
+            .field("outputs", &self.outputs)
+            .field("written", &self.written)
+            .field("changes", &self.changes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WaveformRecorder {
+    /// Record to `path`, choosing CSV or WAV from the file extension
+    pub fn create(path: &Path, sample_rate: u32, initial: [u16; DAC_CHANNELS]) -> Result<Self> {
+        check_sample_rate(sample_rate)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => {
+                Self::csv(BufWriter::new(File::create(path)?), sample_rate, initial)
+            }
+            Some(ext) if ext.eq_ignore_ascii_case("wav") => {
+                let spec = hound::WavSpec {
+                    channels: DAC_CHANNELS as u16,
+                    sample_rate,
+                    bits_per_sample: 16,
+                    sample_format: hound::SampleFormat::Int,
+                };
+                let writer = hound::WavWriter::create(path, spec).map_err(wav_error)?;
+                Ok(Self::new(Sink::Wav(writer), sample_rate, initial))
+            }
+            _ => Err(DacError::InvalidArgument(format!(
+                "Unsupported export format for {} (use .csv or .wav)",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Record as CSV rows written to `out`
+    pub fn csv(
+        mut out: impl Write + Send + 'static,
+        sample_rate: u32,
+        initial: [u16; DAC_CHANNELS],
+    ) -> Result<Self> {
+        check_sample_rate(sample_rate)?;
+        write!(out, "time_s")?;
+        for ch in 0..DAC_CHANNELS {
+            write!(out, ",dac{}", ch)?;
+        }
+        writeln!(out)?;
+        Ok(Self::new(Sink::Csv(Box::new(out)), sample_rate, initial))
+    }
+
+    fn new(sink: Sink, sample_rate: u32, initial: [u16; DAC_CHANNELS]) -> Self {
+        let clock = clock::system();
+        Self {
+            sink,
+            sample_rate,
+            start: clock.now(),
+            clock,
+            outputs: initial,
+            written: 0,
+            changes: 0,
+            error: None,
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock; the recording starts now
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.start = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// Note the current outputs; unchanged outputs are not a change
+    ///
+    /// Samples up to now are written with the previous outputs. A write
+    /// error stops the recording and is returned by [`finish`](Self::finish).
+    pub fn record(&mut self, outputs: &[u16; DAC_CHANNELS]) {
+        if self.outputs != *outputs {
+            self.write_until(self.clock.elapsed(self.start));
+            self.outputs = *outputs;
+            self.changes += 1;
+        }
+    }
+
+    /// Number of recorded output changes
+    pub fn changes(&self) -> usize {
+        self.changes
+    }
+
+    /// Number of samples written so far
+    pub fn samples(&self) -> u64 {
+        self.written
+    }
+
+    /// Write the samples up to now and close the file, returning the sample count
+    pub fn finish(mut self) -> Result<u64> {
+        self.write_until(self.clock.elapsed(self.start));
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        match self.sink {
+            Sink::Csv(mut out) => out.flush()?,
+            Sink::Wav(writer) => writer.finalize().map_err(wav_error)?,
+        }
+        Ok(self.written)
+    }
+
+    /// Write every sample taken before `elapsed` with the current outputs
+    fn write_until(&mut self, elapsed: Duration) {
+        let due = (elapsed.as_nanos() * self.sample_rate as u128).div_ceil(NANOS_PER_SEC) as u64;
+        while self.error.is_none() && self.written < due {
+            if let Err(e) = self.write_sample() {
+                self.error = Some(e);
+            }
+            self.written += 1;
+        }
+    }
+
+    fn write_sample(&mut self) -> Result<()> {
+        match &mut self.sink {
+            Sink::Csv(out) => {
+                write!(out, "{:.6}", self.written as f64 / self.sample_rate as f64)?;
+                for value in self.outputs {
+                    write!(out, ",{}", value)?;
+                }
+                writeln!(out)?;
+            }
+            Sink::Wav(writer) => {
+                for value in self.outputs {
+                    writer
+                        .write_sample((value as i32 - 0x8000) as i16)
+                        .map_err(wav_error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_sample_rate(sample_rate: u32) -> Result<()> {
+    if sample_rate == 0 {
+        return Err(DacError::InvalidArgument(
+            "Sample rate must be at least 1 Hz".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn wav_error(e: hound::Error) -> DacError {
+    match e {
+        hound::Error::IoError(e) => e.into(),
+        e => DacError::InvalidArgument(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn samples_hold_each_value_until_the_next_change() {
+        let clock = ManualClock::new();
+        let out = Shared::default();
+        let mut recorder = WaveformRecorder::csv(out.clone(), 10, [0; DAC_CHANNELS])
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        clock.advance(Duration::from_millis(250));
+        recorder.record(&[7; DAC_CHANNELS]);
+        recorder.record(&[7; DAC_CHANNELS]);
+        assert_eq!(recorder.samples(), 3);
+        clock.advance(Duration::from_millis(150));
+        assert_eq!(recorder.changes(), 1);
+        assert_eq!(recorder.finish().unwrap(), 4);
+
+        let csv = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert!(rows[0].starts_with("time_s,dac0,"));
+        assert!(rows[1].starts_with("0.000000,0,"));
+        assert!(rows[3].starts_with("0.200000,0,"));
+        assert!(rows[4].starts_with("0.300000,7,"));
+        assert_eq!(rows.len(), 5);
+    }
+
+    #[test]
+    fn zero_sample_rate_is_rejected() {
+        assert!(WaveformRecorder::csv(Vec::new(), 0, [0; DAC_CHANNELS]).is_err());
+        let path = Path::new("unused.wav");
+        assert!(WaveformRecorder::create(path, 0, [0; DAC_CHANNELS]).is_err());
+        assert!(!path.exists());
+    }
+}
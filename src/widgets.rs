@@ -0,0 +1,308 @@
+//! ratatui widgets shared by the TUI diagnostic tool and the simulator dashboard.
+
+use crate::units::VoltageRange;
+use clap::ValueEnum;
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph},
+    Frame,
+};
+
+/// Built-in color themes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ThemeName {
+    #[default]
+    Default,
+    /// Bright colors plus reverse video for selection and active pins
+    HighContrast,
+    /// Blue/yellow/cyan only, distinguishable with red-green color blindness
+    ColorBlind,
+    /// No colors; state is shown with bold, reverse and dim text
+    Monochrome,
+}
+
+impl ThemeName {
+    /// The theme after this one, for cycling at runtime
+    pub fn next(self) -> Self {
+        match self {
+            ThemeName::Default => ThemeName::HighContrast,
+            ThemeName::HighContrast => ThemeName::ColorBlind,
+            ThemeName::ColorBlind => ThemeName::Monochrome,
+            ThemeName::Monochrome => ThemeName::Default,
+        }
+    }
+
+    pub fn theme(self) -> Theme {
+        let bold = Modifier::BOLD;
+        let marked = Modifier::BOLD | Modifier::REVERSED;
+        match self {
+            ThemeName::Default => Theme {
+                title: Style::default().fg(Color::Cyan).add_modifier(bold),
+                selected: Style::default().fg(Color::Red).add_modifier(bold),
+                normal: Style::default().fg(Color::Blue),
+                on: Style::default().fg(Color::Green).add_modifier(bold),
+                off: Style::default().fg(Color::Gray),
+                info: Style::default().fg(Color::Yellow),
+                status: Style::default().fg(Color::Green),
+                alert: Style::default().fg(Color::Red).add_modifier(bold),
+                text: Style::default().fg(Color::White),
+            },
+            ThemeName::HighContrast => Theme {
+                title: Style::default().fg(Color::White).add_modifier(bold),
+                selected: Style::default().fg(Color::LightYellow).add_modifier(marked),
+                normal: Style::default().fg(Color::White),
+                on: Style::default().fg(Color::White).add_modifier(marked),
+                off: Style::default().fg(Color::Gray),
+                info: Style::default().fg(Color::LightYellow).add_modifier(bold),
+                status: Style::default().fg(Color::White).add_modifier(bold),
+                alert: Style::default().fg(Color::LightRed).add_modifier(marked),
+                text: Style::default().fg(Color::White),
+            },
+            ThemeName::ColorBlind => Theme {
+                title: Style::default().fg(Color::Cyan).add_modifier(bold),
+                selected: Style::default().fg(Color::Yellow).add_modifier(bold),
+                normal: Style::default().fg(Color::Blue),
+                on: Style::default().fg(Color::Cyan).add_modifier(marked),
+                off: Style::default().fg(Color::DarkGray),
+                info: Style::default().fg(Color::Yellow),
+                status: Style::default().fg(Color::Cyan),
+                alert: Style::default().fg(Color::Yellow).add_modifier(marked),
+                text: Style::default().fg(Color::White),
+            },
+            ThemeName::Monochrome => Theme {
+                title: Style::default().add_modifier(bold),
+                selected: Style::default().add_modifier(marked),
+                normal: Style::default(),
+                on: Style::default().add_modifier(marked),
+                off: Style::default().add_modifier(Modifier::DIM),
+                info: Style::default(),
+                status: Style::default(),
+                alert: Style::default().add_modifier(marked),
+                text: Style::default(),
+            },
+        }
+    }
+}
+
+/// Styles for every themed element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub title: Style,
+    /// Selected DAC channel
+    pub selected: Style,
+    /// Unselected DAC channels
+    pub normal: Style,
+    /// GPIO pin ON
+    pub on: Style,
+    /// GPIO pin OFF
+    pub off: Style,
+    /// Informational panes (table offset, keepalive)
+    pub info: Style,
+    /// Last command and response
+    pub status: Style,
+    /// Conditions needing attention, such as paused keepalives
+    pub alert: Style,
+    /// Help and log text
+    pub text: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        ThemeName::default().theme()
+    }
+}
+
+/// How DAC values are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ValueDisplay {
+    /// Decimal code, 0-65535
+    #[default]
+    Raw,
+    /// Code in hex, 0x0000-0xFFFF
+    Hex,
+    /// Percent of full scale
+    Percent,
+    /// Output voltage through the calibrated range
+    Volts,
+}
+
+impl ValueDisplay {
+    /// The display after this one, for cycling at runtime
+    pub fn next(self) -> Self {
+        match self {
+            ValueDisplay::Raw => ValueDisplay::Hex,
+            ValueDisplay::Hex => ValueDisplay::Percent,
+            ValueDisplay::Percent => ValueDisplay::Volts,
+            ValueDisplay::Volts => ValueDisplay::Raw,
+        }
+    }
+}
+
+/// A value display together with the voltage range it converts through
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ValueFormat {
+    pub display: ValueDisplay,
+    pub range: VoltageRange,
+}
+
+impl ValueFormat {
+    /// `code` as shown in gauges and status lines, at most 9 characters
+    pub fn format(&self, code: u16) -> String {
+        match self.display {
+            ValueDisplay::Raw => code.to_string(),
+            ValueDisplay::Hex => format!("0x{:04X}", code),
+            ValueDisplay::Percent => format!("{:.1}%", code as f64 / 65535.0 * 100.0),
+            ValueDisplay::Volts => format!("{:.3} V", self.range.to_volts(code)),
+        }
+    }
+}
+
+/// Per-channel flags shown next to a DAC value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DacMarks {
+    pub ganged: bool,
+    /// Outside its alarm thresholds
+    pub alarm: bool,
+}
+
+/// Title of a DAC pane: `[G]` for ganged channels, `[!]` for alarms, `[G!]` for both
+fn dac_label(channel: usize, marks: &[DacMarks]) -> String {
+    let marks = marks.get(channel).copied().unwrap_or_default();
+    match (marks.ganged, marks.alarm) {
+        (false, false) => format!("DAC{}", channel),
+        (true, false) => format!("DAC{} [G]", channel),
+        (false, true) => format!("DAC{} [!]", channel),
+        (true, true) => format!("DAC{} [G!]", channel),
+    }
+}
+
+fn is_alarm(channel: usize, marks: &[DacMarks]) -> bool {
+    marks.get(channel).is_some_and(|marks| marks.alarm)
+}
+
+/// Render one vertical-bar gauge per DAC channel, highlighting `selected` and alarms
+pub fn render_dac_gauges(
+    f: &mut Frame,
+    area: Rect,
+    values: &[u16],
+    selected: Option<usize>,
+    marks: &[DacMarks],
+    format: &ValueFormat,
+    theme: &Theme,
+) {
+    let constraints = vec![Constraint::Percentage(12); values.len()];
+    let slider_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(constraints)
+        .split(area);
+
+    for (i, chunk) in slider_chunks.iter().enumerate() {
+        let value = values[i];
+        let percentage = (value as f64 / 65535.0 * 100.0) as u16;
+
+        let style = if Some(i) == selected {
+            theme.selected
+        } else {
+            theme.normal
+        };
+
+        let gauge = Gauge::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(dac_label(i, marks))
+                    .border_style(style),
+            )
+            .gauge_style(if is_alarm(i, marks) {
+                theme.alert
+            } else {
+                style
+            })
+            .percent(percentage)
+            .label(format.format(value));
+
+        f.render_widget(gauge, *chunk);
+    }
+}
+
+/// Render an ON/OFF indicator per GPIO pin
+pub fn render_gpio_states(f: &mut Frame, area: Rect, states: &[bool], theme: &Theme) {
+    let constraints = vec![Constraint::Percentage(12); states.len()];
+    let gpio_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(constraints)
+        .split(area);
+
+    for (i, chunk) in gpio_chunks.iter().enumerate() {
+        let state = states[i];
+        let style = if state { theme.on } else { theme.off };
+
+        let gpio_widget = Paragraph::new(if state { "ON" } else { "OFF" })
+            .style(style)
+            .alignment(Alignment::Center)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("GPIO{}", i))
+                    .border_style(style),
+            );
+
+        f.render_widget(gpio_widget, *chunk);
+    }
+}
+
+/// Render DAC channels as table rows with a text bar, for small terminals
+pub fn render_dac_table(
+    f: &mut Frame,
+    area: Rect,
+    values: &[u16],
+    selected: Option<usize>,
+    marks: &[DacMarks],
+    format: &ValueFormat,
+    theme: &Theme,
+) {
+    // "> DAC0 [G!] -10.000 V " plus the borders
+    let bar_width = area.width.saturating_sub(24) as usize;
+    let items: Vec<ListItem> = values
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| {
+            let filled = (value as usize * bar_width + 32767) / 65535;
+            let is_selected = Some(i) == selected;
+            let line = format!(
+                "{} {:9} {:>9} {}{}",
+                if is_selected { ">" } else { " " },
+                dac_label(i, marks),
+                format.format(value),
+                "█".repeat(filled),
+                "·".repeat(bar_width - filled)
+            );
+            let style = if is_alarm(i, marks) {
+                theme.alert
+            } else if is_selected {
+                theme.selected
+            } else {
+                theme.normal
+            };
+            ListItem::new(line).style(style)
+        })
+        .collect();
+
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title("DACs"));
+    f.render_widget(list, area);
+}
+
+/// Render all GPIO pins on a single line
+pub fn render_gpio_line(f: &mut Frame, area: Rect, states: &[bool], theme: &Theme) {
+    let mut spans = vec![Span::styled("GPIO", theme.text)];
+    for (i, &state) in states.iter().enumerate() {
+        spans.push(Span::raw(" "));
+        spans.push(Span::styled(
+            format!("{}:{}", i, if state { "ON " } else { "OFF" }),
+            if state { theme.on } else { theme.off },
+        ));
+    }
+    f.render_widget(Paragraph::new(Line::from(spans)), area);
+}